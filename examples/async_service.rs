@@ -0,0 +1,49 @@
+//! Uses a database from async tasks on a tokio runtime, the way a service
+//! embedding kronk would. Run with `--features aio`.
+
+use kronk::aio::Database;
+use kronk::table::schema::{TableDescriptor, ColumnDataType};
+use kronk::table::store::database_dir;
+use kronk::table::value::Value;
+
+const DB_NAME: &str = "example_async_service";
+
+async fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let db = Database::open(DB_NAME).await?;
+    db.add_table(TableDescriptor::new("visits", vec![
+        ("id", ColumnDataType::SerialId),
+        ("path", ColumnDataType::Byte(32)),
+        ("millis", ColumnDataType::Int32)
+    ])?).await?;
+
+    // each request handler gets its own clone
+    let handlers = ["/", "/about", "/"].into_iter().enumerate().map(|(i, path)| {
+        let db = db.clone();
+        tokio::spawn(async move { db.insert_columns("visits", &[("path", path), ("millis", &(10 * i).to_string())]).await })
+    }).collect::<Vec<_>>();
+    for handler in handlers {
+        assert_eq!(handler.await.map_err(|e| e.to_string())??.rows_affected, 1);
+    }
+
+    let inserted = db.insert_rows("visits", &[vec![("path", "/about"), ("millis", "5")], vec![("path", "/"), ("millis", "7")]]).await?;
+    assert_eq!(inserted.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![3, 4]);
+    assert!(db.insert_rows("visits", &[vec![("path", "/"), ("millis", "1")], vec![("path", "/"), ("millis", "slow")]]).await.is_err());
+
+    let result = db.query("select count(*) from visits where path == \"/\"").await?;
+    assert_eq!(result.rows[0].value("count"), Some(&Value::UInt64(3)));
+
+    drop(db);
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(run()).unwrap();
+}
+
+#[test]
+fn async_service() {
+    main();
+}
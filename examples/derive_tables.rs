@@ -0,0 +1,71 @@
+//! Keeps structs in tables with `#[derive(KronkTable)]`, instead of writing
+//! out column lists and converting values by hand. Run with
+//! `--features derive`.
+
+use kronk::{Database, KronkTable, SelectQuery, database_dir};
+
+const DB_NAME: &str = "example_derive_tables";
+
+#[derive(Debug, Clone, PartialEq, KronkTable)]
+struct Book {
+    #[kronk(serial_id)]
+    id: u64,
+    #[kronk(size = 32)]
+    author: String,
+    title: String,
+    year_published: i32,
+    in_print: bool
+}
+
+#[derive(Debug, PartialEq, KronkTable)]
+#[kronk(table = "loans")]
+struct BookLoan {
+    #[kronk(serial_id)]
+    id: u64,
+    book_id: u64,
+    days: u32,
+    #[kronk(row_version)]
+    version: u64
+}
+
+fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let mut db = Database::new(DB_NAME);
+    db.add_table(Book::table_descriptor()?)?;
+    db.add_table(BookLoan::table_descriptor()?)?;
+    assert_eq!(Book::TABLE_NAME, "book");
+
+    let books = vec![
+        Book { id: 0, author: "Stink Williams".to_owned(), title: "Singing for Frogs".to_owned(), year_published: 1921, in_print: false },
+        Book { id: 1, author: "joseph".to_owned(), title: "My Lumps My Bumps".to_owned(), year_published: 1917, in_print: true }
+    ];
+    for book in &books {
+        db.insert_record(book)?;
+    }
+    assert!(db.insert_record(&Book { author: "x".repeat(40), ..books[0].clone() }).is_err());
+    db.insert_record(&BookLoan { id: 0, book_id: 1, days: 14, version: 0 })?;
+
+    let query = SelectQuery::parse_raw_query_against_db("select * from book order by id", &db)?;
+    assert_eq!(db.query_records::<Book>(&query)?, books);
+
+    db.update_versioned("loans", 0, 0, &[("days", "21")]).map_err(|e| e.to_string())?;
+    let query = SelectQuery::parse_raw_query_against_db("select * from loans", &db)?;
+    assert_eq!(db.query_records::<BookLoan>(&query)?, vec![BookLoan { id: 0, book_id: 1, days: 21, version: 1 }]);
+
+    // the rows have to hold every field
+    let query = SelectQuery::parse_raw_query_against_db("select title from book", &db)?;
+    assert!(db.query_records::<Book>(&query).is_err());
+
+    drop(db);
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn derive_tables() {
+    main();
+}
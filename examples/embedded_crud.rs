@@ -0,0 +1,75 @@
+//! Creates a database in-process, inserts a handful of rows and queries them
+//! back, both through the query parser and by binding a parsed statement,
+//! then updates and deletes some of them.
+
+use kronk::table::db::Database;
+use kronk::table::query::{SelectQuery, WherePredicate, WhereCondition, builder::Filter};
+use kronk::table::schema::{TableDescriptor, ColumnDataType, GetTableDescriptor};
+use kronk::table::store::database_dir;
+
+const DB_NAME: &str = "example_embedded_crud";
+
+fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let mut db = Database::new(DB_NAME);
+    db.add_table(TableDescriptor::new("books", vec![
+        ("id", ColumnDataType::SerialId),
+        ("author", ColumnDataType::Byte(64)),
+        ("title", ColumnDataType::Byte(64)),
+        ("year_published", ColumnDataType::Int32),
+        ("us_based_publisher", ColumnDataType::Boolean)
+    ])?)?;
+
+    let insertions: Vec<Vec<(&str, &str)>> = vec![
+        vec![("author", "Billy Bob"), ("title", "How to Sting Like a Bee"), ("year_published", "1932")],
+        vec![("author", "Stink Williams"), ("title", "Singing for Frogs"), ("year_published", "1921")],
+        vec![("author", "Stink Williams"), ("title", "Dancing for the Everyday Man"), ("year_published", "1937"), ("us_based_publisher", "true")],
+        vec![("author", "joseph"), ("title", "My Lumps My Bumps"), ("year_published", "1917")]
+    ];
+    for ins in insertions {
+        let (_, row) = db.insert_returning("books", &ins[..])?;
+        println!("inserted {:?}", row);
+    }
+
+    let query = SelectQuery::parse_raw_query_against_db("select title, author from books where year_published >= 1930", &db)?;
+    for row in db.query(&query).map_err(|e| e.to_string())? {
+        let row = row.map_err(|e| e.to_string())?;
+        println!("{} by {}", row.get::<String>("title")?, row.get::<String>("author")?);
+    }
+
+    // the same kind of select, built in code
+    let rows = db.select("books").columns(&["title"])
+        .filter(Filter::ge("year_published", 1920).and(!Filter::eq("author", "joseph")))
+        .order_by_desc("year_published")
+        .run()?.rows;
+    assert_eq!(rows.len(), 3);
+
+    let mut published_since = db.prepare("select title from books where year_published >= ?")?;
+    assert_eq!(published_since.execute(&["1930"])?.count(), 2);
+
+    // the predicate borrows its columns, so build it against a copy of the
+    // table's descriptor while the database is borrowed mutably
+    let books = db.table_with_name("books").unwrap().clone();
+    let predicate = WherePredicate::Condition(WhereCondition::new(books.column_for_name("author").unwrap(), "==", "Stink Williams")?);
+    assert_eq!(db.update_columns("books", &[("author", "Stinky Williams")], &predicate)?.rows_affected, 2);
+    let predicate = WherePredicate::Condition(WhereCondition::new(books.column_for_name("year_published").unwrap(), "<", "1920")?);
+    assert_eq!(db.delete_where("books", &predicate)?.rows_affected, 1);
+
+    let query = SelectQuery::parse_raw_query_against_db("select author, count(*) from books group by author", &db)?;
+    for row in db.query_result_set(&query).map_err(|e| e.to_string())?.rows {
+        println!("{}: {} books", row.get::<String>("author")?, row.get::<u64>("count")?);
+    }
+
+    drop(db);
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn embedded_crud() {
+    main();
+}
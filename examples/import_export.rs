@@ -0,0 +1,113 @@
+//! Loads rows from csv text into a table and writes query results back out
+//! as csv and json through the result formatter.
+
+use itertools::Itertools;
+use kronk::table::db::Database;
+use kronk::table::format::{FormatOptions, FormatColumn, OutputFormat, ResultFormatter};
+use kronk::table::query::SelectQuery;
+use kronk::table::schema::{TableDescriptor, ColumnDataType};
+use kronk::table::store::database_dir;
+
+const DB_NAME: &str = "example_import_export";
+
+const CITIES_CSV: &str = "\
+name,country,population
+Lagos,NG,15388000
+Osaka,JP,19059856
+Lima,PE,10883000
+Oslo,NO,709037
+";
+
+/// Inserts every line after the header, matching fields to columns by the
+/// header's names. Returns the number of rows imported. The lines go in as
+/// one batch, so if any of them can't be inserted none are.
+fn import_csv(db: &mut Database, table_name: &str, csv: &str) -> Result<usize, String> {
+    let mut lines = csv.lines();
+    let header = lines.next().ok_or("csv input is empty")?.split(',').collect_vec();
+    let mut rows = Vec::new();
+    for line in lines {
+        let fields = line.split(',').collect_vec();
+        if fields.len() != header.len() {
+            return Err(format!("expected {} fields but line has {}: {}", header.len(), fields.len(), line));
+        }
+        rows.push(header.iter().copied().zip(fields).collect_vec());
+    }
+    Ok(db.insert_rows(table_name, &rows)?.len())
+}
+
+fn export(db: &Database, query: &str, format: OutputFormat) -> Result<String, String> {
+    let query = SelectQuery::parse_raw_query_against_db(query, db)?;
+    let rows = db.query(&query).map_err(|e| e.to_string())?.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+    let columns = query.columns[..].iter()
+        .map(|c| FormatColumn { name: c.name.as_str(), datatype: c.datatype() })
+        .collect_vec();
+    let cells = rows[..].iter()
+        .map(|row| row.columns[..].iter().map(|(_, v)| (!v.is_null()).then(|| v.to_string())).collect_vec())
+        .collect_vec();
+    let values = cells[..].iter()
+        .map(|row| row[..].iter().map(|v| v.as_deref()).collect_vec())
+        .collect_vec();
+
+    let options = FormatOptions { format, ..FormatOptions::default() };
+    let mut out: Vec<u8> = Vec::new();
+    ResultFormatter::new(&options).write(&mut out, &columns, &values).map_err(|e| e.to_string())?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+/// Like `export`, but writes the rows out as they're read instead of
+/// collecting them first.
+fn export_streamed(db: &Database, query: &str, format: OutputFormat) -> Result<String, String> {
+    let query = SelectQuery::parse_raw_query_against_db(query, db)?;
+    let options = FormatOptions { format, ..FormatOptions::default() };
+    let mut out: Vec<u8> = Vec::new();
+    db.query_to_writer(&query, &mut out, &options)?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let mut db = Database::new(DB_NAME);
+    db.add_table(TableDescriptor::new("cities", vec![
+        ("id", ColumnDataType::SerialId),
+        ("name", ColumnDataType::Byte(32)),
+        ("country", ColumnDataType::Byte(4)),
+        ("population", ColumnDataType::Int64)
+    ])?)?;
+
+    let imported = import_csv(&mut db, "cities", CITIES_CSV)?;
+    println!("imported {} rows", imported);
+    assert_eq!(imported, 4);
+    assert!(import_csv(&mut db, "cities", "name,country,population\nCairo,EG,22183000\nDelhi,IN,lots\n").is_err());
+
+    let csv = export(&db, "select name, population from cities where population > 1000000 order by population desc", OutputFormat::Csv)?;
+    print!("{}", csv);
+    assert_eq!(csv, "name,population\nOsaka,19059856\nLagos,15388000\nLima,10883000\n");
+
+    let json = export(&db, "select name, country from cities where population < 1000000", OutputFormat::Json)?;
+    print!("{}", json);
+    assert_eq!(json, "[\n  {\"name\": \"Oslo\", \"country\": \"NO\"}\n]\n");
+    // streamed csv and json come out the same as when the rows are collected
+    let all_cities = "select name, country, population from cities order by name";
+    assert_eq!(export_streamed(&db, all_cities, OutputFormat::Csv)?, export(&db, all_cities, OutputFormat::Csv)?);
+    assert_eq!(export_streamed(&db, all_cities, OutputFormat::Json)?, export(&db, all_cities, OutputFormat::Json)?);
+    assert_eq!(export_streamed(&db, "select name from cities where country == \"EG\"", OutputFormat::Json)?, "[\n]\n");
+    // streamed text columns are only as wide as their names
+    assert_eq!(export_streamed(&db, "select name, population from cities where name == \"Oslo\"", OutputFormat::Text)?,
+        "name | population\n-----+-----------\nOslo |     709037\n(1 row)\n");
+
+    // the bad import left nothing behind
+    assert_eq!(export(&db, "select name from cities where country == \"EG\"", OutputFormat::Csv)?, "name\n");
+
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn import_export() {
+    main();
+}
@@ -0,0 +1,52 @@
+//! Sorts and groups more rows than their memory budgets hold. With
+//! `--no-default-features` nothing touches the filesystem: the tables and
+//! the rows the sort and group spill are all kept in memory.
+
+use kronk::{Database, SelectQuery, TableDescriptor, ColumnDataType, database_dir};
+use kronk::table::exec::DefaultExecutor;
+
+const DB_NAME: &str = "example_in_memory";
+
+fn run() -> Result<(), String> {
+    if cfg!(feature = "file") {
+        let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+    }
+
+    // budgets of a byte make every row spill
+    let executor = DefaultExecutor::default().with_sort_memory(1).with_group_memory(1);
+    let mut db = Database::with_executor(DB_NAME, Box::new(executor));
+    db.add_table(TableDescriptor::new("readings", vec![
+        ("id", ColumnDataType::SerialId),
+        ("sensor", ColumnDataType::Byte(8)),
+        ("celsius", ColumnDataType::Int32)
+    ])?)?;
+    let rows = (0..40).map(|i| vec![("sensor", ["north", "south", "east"][i % 3]), ("celsius", ["12", "-3", "7", "30"][i % 4])]).collect::<Vec<_>>();
+    db.insert_rows("readings", &rows)?;
+
+    let query = SelectQuery::parse_raw_query_against_db("select celsius from readings order by celsius desc", &db)?;
+    let sorted = db.query_result_set(&query).map_err(|e| e.to_string())?.rows.into_iter().map(|row| row.get::<i32>("celsius")).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(sorted.len(), 40);
+    assert!(sorted.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    let query = SelectQuery::parse_raw_query_against_db("select sensor, count(*) from readings group by sensor", &db)?;
+    let mut counts = db.query_result_set(&query).map_err(|e| e.to_string())?.rows.into_iter()
+        .map(|row| Ok((row.get::<String>("sensor")?, row.get::<u64>("count")?)))
+        .collect::<Result<Vec<_>, kronk::KronkError>>()?;
+    counts.sort();
+    assert_eq!(counts, vec![("east".to_owned(), 13), ("north".to_owned(), 14), ("south".to_owned(), 13)]);
+
+    drop(db);
+    if cfg!(feature = "file") {
+        std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn in_memory() {
+    main();
+}
@@ -0,0 +1,50 @@
+//! Evolves a table's layout in place with `alter_table`, showing that rows
+//! written under the old layout are carried over into the new one.
+
+use kronk::{Database, SelectQuery, TableDescriptor, ColumnDataType, AlterTableOperation, Value, database_dir};
+
+const DB_NAME: &str = "example_migrations";
+
+fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let mut db = Database::new(DB_NAME);
+    db.add_table(TableDescriptor::new("users", vec![
+        ("id", ColumnDataType::SerialId),
+        ("name", ColumnDataType::Byte(32)),
+        ("age", ColumnDataType::Int32)
+    ])?)?;
+    db.insert_columns("users", &[("name", "ada"), ("age", "36")])?;
+    db.insert_columns("users", &[("name", "grace"), ("age", "45")])?;
+
+    // v2: widen age, add an email column and rename name
+    db.alter_table_with_progress("users", &[
+        AlterTableOperation::AlterColumnType("age".to_owned(), ColumnDataType::Int64),
+        AlterTableOperation::AddColumn("email".to_owned(), ColumnDataType::Byte(64)),
+        AlterTableOperation::RenameColumn("name".to_owned(), "username".to_owned())
+    ], &mut |progress| println!("{}", progress))?;
+
+    db.insert_columns("users", &[("username", "linus"), ("age", "28"), ("email", "linus@example.com")])?;
+
+    // v3: drop the column again
+    db.alter_table("users", &[AlterTableOperation::DropColumn("email".to_owned())])?;
+
+    let query = SelectQuery::parse_raw_query_against_db("select username, age from users where age > 30", &db)?;
+    let rows = db.query(&query).map_err(|e| e.to_string())?.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+    for row in &rows {
+        println!("{:?}", row.columns);
+    }
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].value("username"), Some(&Value::Str("ada".to_owned())));
+
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn migrations() {
+    main();
+}
@@ -0,0 +1,64 @@
+//! Reads query results into plain serde structs, picking out columns by
+//! name, and writes them out as json. Run with `--features serde`.
+
+use serde::Deserialize;
+
+use kronk::{Database, SelectQuery, TableDescriptor, ColumnDataType, database_dir};
+
+const DB_NAME: &str = "example_serde_rows";
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Score {
+    player: String,
+    // an Int32 column reads into any integer type it fits
+    total: i64,
+    finished: bool,
+    // not selected, so None
+    rank: Option<u32>
+}
+
+fn run() -> Result<(), String> {
+    let _ = std::fs::remove_dir_all(database_dir(DB_NAME));
+
+    let mut db = Database::new(DB_NAME);
+    db.add_table(TableDescriptor::new("scores", vec![
+        ("id", ColumnDataType::SerialId),
+        ("player", ColumnDataType::Byte(32)),
+        ("points", ColumnDataType::Int32),
+        ("finished", ColumnDataType::Boolean)
+    ])?)?;
+    db.insert_rows("scores", &[
+        vec![("player", "ada"), ("points", "120"), ("finished", "true")],
+        vec![("player", "grace"), ("points", "95"), ("finished", "false")]
+    ])?;
+
+    let query = SelectQuery::parse_raw_query_against_db("select id, player, points as total, finished from scores order by points", &db)?;
+    let scores = db.query_result_set(&query).map_err(|e| e.to_string())?.deserialize::<Score>()?;
+    assert_eq!(scores, vec![
+        Score { player: "grace".to_owned(), total: 95, finished: false, rank: None },
+        Score { player: "ada".to_owned(), total: 120, finished: true, rank: None }
+    ]);
+
+    // a string can't be read into an integer field
+    let query = SelectQuery::parse_raw_query_against_db("select player, player as total, finished from scores", &db)?;
+    assert!(db.query_result_set(&query).map_err(|e| e.to_string())?.deserialize::<Score>().is_err());
+
+    // and out again as json, a map per row
+    let query = SelectQuery::parse_raw_query_against_db("select player, points, finished from scores where points > 100", &db)?;
+    let result = db.query_result_set(&query).map_err(|e| e.to_string())?;
+    assert_eq!(serde_json::to_string(&result).map_err(|e| e.to_string())?, r#"[{"player":"ada","points":120,"finished":true}]"#);
+    let row = serde_json::to_value(&result.rows[0]).map_err(|e| e.to_string())?;
+    assert_eq!(row["points"], 120);
+
+    drop(db);
+    std::fs::remove_dir_all(database_dir(DB_NAME)).map_err(|e| e.to_string())
+}
+
+fn main() {
+    run().unwrap();
+}
+
+#[test]
+fn serde_rows() {
+    main();
+}
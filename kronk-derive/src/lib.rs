@@ -0,0 +1,128 @@
+//! `#[derive(KronkTable)]`, re-exported by kronk's `derive` feature. See
+//! `kronk::table::record::KronkTable` for what the derived impl does.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr};
+
+#[proc_macro_derive(KronkTable, attributes(kronk))]
+pub fn derive_kronk_table(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+enum ColumnKind {
+    SerialId,
+    RowVersion,
+    Sized(usize),
+    Plain
+}
+
+struct Column<'a> {
+    field: &'a syn::Ident,
+    ty: &'a syn::Type,
+    kind: ColumnKind
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let struct_name = &input.ident;
+    let mut table_name = snake_case(&struct_name.to_string());
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("kronk")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table_name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"name\"`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(struct_name, "KronkTable needs a struct with named fields"))
+        },
+        _ => return Err(syn::Error::new_spanned(struct_name, "KronkTable can only be derived for structs"))
+    };
+
+    let mut columns = Vec::new();
+    for field in fields {
+        let mut kind = ColumnKind::Plain;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("kronk")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("serial_id") {
+                    kind = ColumnKind::SerialId;
+                } else if meta.path.is_ident("row_version") {
+                    kind = ColumnKind::RowVersion;
+                } else if meta.path.is_ident("size") {
+                    kind = ColumnKind::Sized(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+                } else {
+                    return Err(meta.error("expected `serial_id`, `row_version` or `size = n`"));
+                }
+                Ok(())
+            })?;
+        }
+        columns.push(Column { field: field.ident.as_ref().unwrap(), ty: &field.ty, kind });
+    }
+
+    let kronk = quote!(::kronk::table);
+    let datatypes = columns.iter().map(|c| {
+        let name = c.field.to_string();
+        let ty = c.ty;
+        let datatype = match c.kind {
+            ColumnKind::SerialId => quote!(#kronk::schema::ColumnDataType::SerialId),
+            ColumnKind::RowVersion => quote!(#kronk::schema::ColumnDataType::RowVersion),
+            ColumnKind::Sized(size) => quote!(#kronk::schema::ColumnDataType::Byte(#size)),
+            ColumnKind::Plain => quote!(<#ty as #kronk::record::ColumnValue>::DATATYPE)
+        };
+        quote!((#name, #datatype))
+    });
+    let inserted = columns.iter().filter(|c| matches!(c.kind, ColumnKind::Sized(_) | ColumnKind::Plain)).map(|c| {
+        let name = c.field.to_string();
+        let field = c.field;
+        quote!((#name, #kronk::record::ColumnValue::to_column(&self.#field)))
+    });
+    let read = columns.iter().map(|c| {
+        let name = c.field.to_string();
+        let field = c.field;
+        match c.kind {
+            ColumnKind::SerialId => quote!(#field: row.id),
+            _ => quote!(#field: row.get(#name)?)
+        }
+    });
+
+    Ok(quote! {
+        impl #kronk::record::KronkTable for #struct_name {
+            const TABLE_NAME: &'static str = #table_name;
+
+            fn table_descriptor() -> ::std::result::Result<#kronk::schema::TableDescriptor, #kronk::error::KronkError> {
+                #kronk::schema::TableDescriptor::new(Self::TABLE_NAME, vec![#(#datatypes),*])
+            }
+
+            fn to_columns(&self) -> ::std::vec::Vec<(&'static str, ::std::string::String)> {
+                vec![#(#inserted),*]
+            }
+
+            fn from_row(row: &#kronk::value::Row) -> ::std::result::Result<Self, #kronk::error::KronkError> {
+                Ok(#struct_name { #(#read),* })
+            }
+        }
+    })
+}
+
+/// `BookReview` to `book_review`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
@@ -0,0 +1,98 @@
+//! An async api over a shared database, for embedding kronk in tokio
+//! services. Kronk's storage is blocking, and this isn't an async store:
+//! each call runs the blocking api through a `DatabaseHandle` on tokio's
+//! blocking thread pool, holding one of its threads until it's done, and
+//! the task awaiting it is free to do other work meanwhile. A call that
+//! panics panics in the awaiting task too. Needs a tokio runtime to be
+//! running.
+
+use crate::table::{db::{self, ExecResult, InsertedRow}, handle::DatabaseHandle, schema::TableDescriptor, value::ResultSet, error::KronkError};
+
+/// A database that can be used from async code. Clones are cheap and refer
+/// to the same database, as with `DatabaseHandle`.
+#[derive(Clone)]
+pub struct Database {
+    handle: DatabaseHandle
+}
+
+impl Database {
+    /// Opens the database, failing if another process already has it open.
+    pub async fn open(db_name: &str) -> Result<Database, KronkError> {
+        let db_name = db_name.to_owned();
+        let db = run_blocking(move || db::Database::open(&db_name)).await?;
+        Ok(Database::from_handle(DatabaseHandle::new(db)))
+    }
+
+    /// Shares a database already being used through `handle`.
+    pub fn from_handle(handle: DatabaseHandle) -> Database {
+        Database { handle }
+    }
+
+    /// The handle to the database, for blocking code, or calls with no
+    /// async counterpart here.
+    pub fn handle(&self) -> &DatabaseHandle {
+        &self.handle
+    }
+
+    pub async fn add_table(&self, descriptor: TableDescriptor) -> Result<(), KronkError> {
+        let handle = self.handle.clone();
+        run_blocking(move || handle.write().add_table(descriptor)).await
+    }
+
+    pub async fn insert_columns(&self, table_name: &str, columns: &[(&str, &str)]) -> Result<ExecResult, KronkError> {
+        let handle = self.handle.clone();
+        let table_name = table_name.to_owned();
+        let columns = owned_columns(columns);
+        run_blocking(move || handle.insert_columns(&table_name, &borrowed_columns(&columns))).await
+    }
+
+    /// Inserts a batch of rows, returning them as stored. Either every row
+    /// is inserted or none are.
+    pub async fn insert_rows(&self, table_name: &str, rows: &[Vec<(&str, &str)>]) -> Result<Vec<InsertedRow>, KronkError> {
+        let handle = self.handle.clone();
+        let table_name = table_name.to_owned();
+        let rows = rows.iter().map(|columns| owned_columns(columns)).collect::<Vec<_>>();
+        run_blocking(move || {
+            let rows = rows.iter().map(|columns| borrowed_columns(columns)).collect::<Vec<_>>();
+            handle.insert_rows(&table_name, &rows)
+        }).await
+    }
+
+    /// Runs a select to the end.
+    pub async fn query(&self, query: &str) -> Result<ResultSet, KronkError> {
+        let handle = self.handle.clone();
+        let query = query.to_owned();
+        run_blocking(move || handle.query(&query)).await
+    }
+}
+
+/// Runs `f` on tokio's blocking thread pool.
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, KronkError> + Send + 'static) -> Result<T, KronkError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        // the runtime is shutting down
+        Err(e) => Err(KronkError::Io(std::io::Error::new(std::io::ErrorKind::Interrupted, e)))
+    }
+}
+
+fn owned_columns(columns: &[(&str, &str)]) -> Vec<(String, String)> {
+    columns.iter().map(|(column, value)| (column.to_string(), value.to_string())).collect()
+}
+
+fn borrowed_columns(columns: &[(String, String)]) -> Vec<(&str, &str)> {
+    columns.iter().map(|(column, value)| (column.as_str(), value.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_reach_the_awaiting_task() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let panicked = std::panic::catch_unwind(|| runtime.block_on(run_blocking(|| -> Result<(), KronkError> { panic!("store blew up") })));
+        let payload = panicked.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"store blew up"));
+    }
+}
@@ -0,0 +1,25 @@
+//! An embedded table database. The types most programs need are exported
+//! here; the rest of the engine is under `table`, by module.
+//!
+//! Without the default `file` feature, tables live only in memory and
+//! nothing is read from or written to the filesystem.
+
+pub mod table;
+#[cfg(feature = "aio")]
+pub mod aio;
+
+pub use table::db::{Database, ExecResult, UpdateError, TransactionGuard};
+pub use table::error::KronkError;
+pub use table::handle::DatabaseHandle;
+pub use table::schema::{TableDescriptor, ColumnDataType, AlterTableOperation, GetTableDescriptor};
+pub use table::query::{SelectQuery, WherePredicate, WhereCondition, builder::{SelectBuilder, Filter}};
+pub use table::query::types::{ParsingError, LexingError};
+pub use table::exec::{Executor, QueryError, CancelToken};
+pub use table::lock::{LockError, LockWait, Transaction};
+pub use table::record::KronkTable;
+#[cfg(feature = "derive")]
+pub use kronk_derive::KronkTable;
+pub use table::value::{Value, Row, FromValue, ResultColumn, ResultSet};
+pub use table::store::{ByteStore, database_dir};
+#[cfg(feature = "file")]
+pub use table::store::object::ObjectStore;
@@ -1,88 +1,51 @@
-mod table;
-
-use std::io::{prelude::*, BufReader};
-use std::fs::File;
-use std::path::Path;
+use std::io::prelude::*;
 
 use itertools::Itertools;
-use table::schema::{TableDescriptor, ColumnDataType, DatabaseDescriptor};
-use table::store::InMemoryByteStore;
-use table::query::{SelectQuery};
-use table::bytes::{ToNativeType};
-use table::query::types::RawSelectQuery;
-
-use crate::table::db::Database;
-use crate::table::query::parse::RawParse;
-use crate::table::query::types::RawDbCommand;
-
-fn run_db() {
-    let mut db = Database::new("my_db");
-    db.add_table(TableDescriptor::new("books", vec![
-        ("id", ColumnDataType::SerialId),
-        ("author", ColumnDataType::Byte(64)),
-        ("title", ColumnDataType::Byte(64)),
-        ("year_published", ColumnDataType::Int32),
-        ("us_based_publisher", ColumnDataType::Boolean)
-    ]).unwrap()).unwrap();
-
-    let insertions: Vec<Vec<(&str, &str)>> = vec![
-        vec![
-            ("author", "Billy Bob"),
-            ("title", "How to Sting Like a Bee"),
-            ("year_published", "1932")
-        ],
-        vec![
-            ("author", "Stink Williams"),
-            ("title", "Singing for Frogs"),
-            ("year_published", "1921")
-        ],
-        vec![
-            ("author", "Stink Williams"),
-            ("title", "Singing for Woodland Creatures"),
-            ("year_published", "1923")
-        ],
-        vec![
-            ("author", "Stink Williams"),
-            ("title", "Dancing for the Everyday Man"),
-            ("year_published", "1937"),
-            ("us_based_publisher", "true")
-        ],
-        vec![
-            ("author", "Stink Williams"),
-            ("title", "O My Friend, How Art Thee"),
-            ("year_published", "1924")
-        ],
-        vec![
-            ("author", "Stink Williams"),
-            ("title", "Singing for Woodland Creatures"),
-            ("year_published", "1923")
-        ],
-        vec![
-            ("author", "joseph"),
-            ("title", "My Lumps My Bumps"),
-            ("year_published", "1917")
-        ]
-    ];
+use kronk::table::schema::{TableDescriptor, ColumnDataType};
+use kronk::table::db::Database;
+use kronk::table::instance::Instance;
+use kronk::table::session::{Session, CommandOutput};
+use kronk::table::format::{FormatOptions, FormatColumn, ResultFormatter};
 
-    for ins in insertions {
-        db.insert_columns("books", &ins[..]).unwrap();
+fn run_meta_command(cmd: &str, options: &mut FormatOptions) -> Result<(), String> {
+    let args = cmd.split_whitespace().collect_vec();
+    match args[..] {
+        [".format", format] => { options.format = str::parse(format)?; },
+        [".null", display] => { options.null_display = display.to_owned(); },
+        [".bool", true_display, false_display] => {
+            options.true_display = true_display.to_owned();
+            options.false_display = false_display.to_owned();
+        },
+        _ => return Err(format!("Unknown command '{}'. Try .format <text|json|csv>, .null <display>, or .bool <true> <false>", cmd))
     }
-
-    let select_query = SelectQuery::parse_raw_query_against_db("select title, author from books where year_published >= 1935", &db).unwrap();
-    let res = db.query(&select_query);
-    dbg!(res);
+    Ok(())
 }
 
-fn reader () {
-    let mut buf: [u8; 64] = [0u8; 64];
-    buf[2] = 8u8;
-    let mut buf_reader = BufReader::new(buf.as_slice());
-    let mut dest_buf = [0u8; 1024];
-    let bytes_read = buf_reader.read(&mut dest_buf).unwrap();
-    dbg!(bytes_read);
+fn run_command(q: &str, session: &mut Session, instance: &mut Instance, options: &FormatOptions) -> Result<(), String> {
+    let formatter = ResultFormatter::new(options);
+    let mut stdout = std::io::stdout();
+
+    match session.execute(instance, q)? {
+        CommandOutput::Inserted(row) => {
+            let columns = row.iter()
+                .map(|(name, datatype, _)| FormatColumn { name: name.as_str(), datatype: Some(datatype) })
+                .collect_vec();
+            let values = vec![row.iter().map(|(_, _, v)| Some(v.as_str())).collect_vec()];
+            formatter.write(&mut stdout, &columns, &values).map_err(|e| e.to_string())
+        },
+        CommandOutput::Rows(result) => formatter.write_result_set(&mut stdout, &result).map_err(|e| e.to_string()),
+        CommandOutput::Plan(plan) => {
+            let values = plan[..].iter().map(|line| vec![Some(line.as_str())]).collect_vec();
+            formatter.write(&mut stdout, &[FormatColumn { name: "plan", datatype: None }], &values).map_err(|e| e.to_string())
+        },
+        CommandOutput::Message(message) => {
+            println!("{}", message);
+            Ok(())
+        }
+    }
 }
 
-fn run_select_query() {
+fn run_repl() {
     let mut db = Database::new("my_db");
     db.add_table(TableDescriptor::new("books", vec![
         ("id", ColumnDataType::SerialId),
@@ -92,33 +55,33 @@ fn run_select_query() {
         ("us_based_publisher", ColumnDataType::Boolean)
     ]).unwrap()).unwrap();
 
-    let mut q = String::new();
-    std::io::stdin().read_line(&mut q).unwrap();
+    let mut instance = Instance::new();
+    instance.add_database(db).unwrap();
+    let mut session = Session::new(&instance);
 
-    let cmd = RawParse::parse(q.as_str());
+    let mut options = FormatOptions::default();
 
-    match cmd {
-        Ok(c) => match c {
-            RawDbCommand::Insert(i) => {
-                let mapped_args = i.values.into_iter()
-                    .map(|(c, v)| (c.as_str(), v.as_str()))
-                    .collect_vec()
-                    .as_slice();
-                db.insert_columns(&i.table_name, mapped_args).unwrap();
-            },
-            RawDbCommand::Select(s) => {
-                let select_query = SelectQuery::parse_raw_query_against_db(q.trim(), &db).unwrap();
-                let res = db.query(&select_query);
-                dbg!(res);
-            }
-        },
-        Err(e) => {
-            dbg!(e);
+    loop {
+        print!("kronk> ");
+        std::io::stdout().flush().unwrap();
+
+        let mut q = String::new();
+        if std::io::stdin().read_line(&mut q).unwrap() == 0 { break; }
+        let q = q.trim();
+        if q.is_empty() { continue; }
+
+        let res = if q.starts_with('.') {
+            run_meta_command(q, &mut options)
+        } else {
+            run_command(q, &mut session, &mut instance, &options)
+        };
+
+        if let Err(e) = res {
+            println!("error: {}", e);
         }
     }
 }
 
 fn main() {
-    // run_db()
-    run_select_query();
-}
\ No newline at end of file
+    run_repl();
+}
@@ -0,0 +1,150 @@
+use std::{collections::VecDeque, sync::{Condvar, Mutex}};
+
+use super::error::KronkError;
+
+#[derive(Debug)]
+struct AdmissionState {
+    running: usize,
+    next_ticket: u64,
+    waiting: VecDeque<u64>
+}
+
+/// Bounded FIFO admission control for query execution. At most
+/// `max_concurrent` holders run at once; up to `max_queued` more wait in
+/// line and are told their position as it changes. Anything past that is
+/// rejected outright. See `DatabaseHandle::with_admission`.
+#[derive(Debug)]
+pub struct AdmissionQueue {
+    max_concurrent: usize,
+    max_queued: usize,
+    state: Mutex<AdmissionState>,
+    turn: Condvar
+}
+
+/// Held while a query runs; dropping it admits the next one in line.
+pub struct AdmissionPermit<'a> {
+    queue: &'a AdmissionQueue
+}
+
+impl AdmissionQueue {
+    pub fn new(max_concurrent: usize, max_queued: usize) -> AdmissionQueue {
+        AdmissionQueue {
+            max_concurrent: max_concurrent.max(1),
+            max_queued,
+            state: Mutex::new(AdmissionState { running: 0, next_ticket: 0, waiting: VecDeque::new() }),
+            turn: Condvar::new()
+        }
+    }
+
+    /// Blocks until the caller may run. `on_position` is called with the
+    /// caller's 1-based place in line each time it changes while waiting.
+    /// Fails with `KronkError::Overloaded` if the line is full.
+    pub fn acquire(&self, on_position: &mut dyn FnMut(usize)) -> Result<AdmissionPermit<'_>, KronkError> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.running < self.max_concurrent && state.waiting.is_empty() {
+            state.running += 1;
+            return Ok(AdmissionPermit { queue: self });
+        }
+
+        if state.waiting.len() >= self.max_queued {
+            return Err(KronkError::Overloaded { queued: state.waiting.len() });
+        }
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push_back(ticket);
+
+        let mut last_position = 0usize;
+        loop {
+            let position = state.waiting.iter().position(|t| *t == ticket).unwrap() + 1;
+            if position == 1 && state.running < self.max_concurrent {
+                state.waiting.pop_front();
+                state.running += 1;
+                // others in line just moved up
+                self.turn.notify_all();
+                return Ok(AdmissionPermit { queue: self });
+            }
+
+            if position != last_position {
+                // report outside the lock, then re-check our place in line
+                drop(state);
+                on_position(position);
+                last_position = position;
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state = self.turn.wait(state).unwrap();
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+}
+
+impl Drop for AdmissionPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.running -= 1;
+        self.queue.turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+
+    fn wait_until_queued(queue: &AdmissionQueue, queued: usize) {
+        while queue.queued() < queued {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn waiters_are_admitted_in_order() {
+        let queue = AdmissionQueue::new(1, 3);
+        let admitted = Mutex::new(Vec::new());
+        let running = queue.acquire(&mut |_| {}).unwrap();
+        std::thread::scope(|s| {
+            let waiters = (0..3).map(|i| {
+                let (queue, admitted) = (&queue, &admitted);
+                let waiter = s.spawn(move || {
+                    let mut positions = Vec::new();
+                    let _permit = queue.acquire(&mut |p| positions.push(p)).unwrap();
+                    admitted.lock().unwrap().push(i);
+                    positions
+                });
+                wait_until_queued(queue, i + 1);
+                waiter
+            }).collect::<Vec<_>>();
+            assert_eq!(queue.running(), 1);
+            drop(running);
+            // each is told where it joined the line; a waiter admitted as
+            // soon as it reaches the front isn't told it got there
+            let joined_at = waiters.into_iter().map(|w| w.join().unwrap()[0]).collect::<Vec<_>>();
+            assert_eq!(joined_at, vec![1, 2, 3]);
+        });
+        assert_eq!(admitted.into_inner().unwrap(), vec![0, 1, 2]);
+        assert_eq!((queue.running(), queue.queued()), (0, 0));
+    }
+
+    #[test]
+    fn callers_past_the_line_are_rejected() {
+        let queue = AdmissionQueue::new(1, 1);
+        let running = queue.acquire(&mut |_| {}).unwrap();
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| queue.acquire(&mut |_| {}).map(drop));
+            wait_until_queued(&queue, 1);
+            assert!(matches!(queue.acquire(&mut |_| {}), Err(KronkError::Overloaded { queued: 1 })));
+            drop(running);
+            waiter.join().unwrap().unwrap();
+        });
+    }
+}
@@ -0,0 +1,121 @@
+use super::{exec::QueryError, query::{SelectColumn, SelectExpression, types::AggregateFunction}, schema::TableColumn, value::Value};
+
+/// Running state of one aggregate, updated a row at a time.
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+    Count(u64),
+    /// Integer sums are kept wide enough that they can't overflow partway
+    /// through, and checked against the result type at the end.
+    Sum { total: Option<i128>, signed: bool },
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg { total: i128, count: u64 }
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction, column: &TableColumn) -> Accumulator {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum { total: None, signed: column.datatype.is_signed_integer() },
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+            AggregateFunction::Avg => Accumulator::Avg { total: 0, count: 0 }
+        }
+    }
+
+    pub fn update(&mut self, value: Value) {
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum { total, .. } => *total = Some(total.unwrap_or(0) + Self::integer(&value)),
+            Accumulator::Min(min) => if min.as_ref().is_none_or(|m| value < *m) { *min = Some(value) },
+            Accumulator::Max(max) => if max.as_ref().is_none_or(|m| value > *m) { *max = Some(value) },
+            Accumulator::Avg { total, count } => {
+                *total += Self::integer(&value);
+                *count += 1;
+            }
+        }
+    }
+
+    /// Sums and averages are only bound over integer columns.
+    fn integer(value: &Value) -> i128 {
+        match value {
+            Value::Int32(i) => *i as i128,
+            Value::UInt32(u) => *u as i128,
+            Value::Int64(i) => *i as i128,
+            Value::UInt64(u) => *u as i128,
+            _ => unreachable!("only integer columns are summed")
+        }
+    }
+
+    /// The aggregate's value. Everything but a count is null over no rows.
+    pub fn finish(self, column: &TableColumn) -> Result<Value, QueryError> {
+        let overflow = || QueryError::Overflow { column_name: column.name.to_owned() };
+        Ok(match self {
+            Accumulator::Count(n) => Value::UInt64(n),
+            Accumulator::Sum { total: None, .. } => Value::Null,
+            Accumulator::Sum { total: Some(total), signed: true } => Value::Int64(i64::try_from(total).map_err(|_| overflow())?),
+            Accumulator::Sum { total: Some(total), signed: false } => Value::UInt64(u64::try_from(total).map_err(|_| overflow())?),
+            Accumulator::Min(v) | Accumulator::Max(v) => v.unwrap_or(Value::Null),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { total, count } => Value::Float64(total as f64 / count as f64)
+        })
+    }
+}
+
+/// Folds rows into one result row for a select list with aggregates in it.
+/// Each aggregate column gets an accumulator, `count(*)` counts the rows,
+/// and plain columns take their value from the first row, which is what
+/// every row of a group has in them.
+pub struct Aggregates<'q> {
+    columns: &'q [SelectColumn<'q>],
+    accumulators: Vec<Option<Accumulator>>,
+    rows: u64,
+    first_row: Option<Vec<u8>>
+}
+
+impl<'q> Aggregates<'q> {
+    pub fn new(columns: &'q [SelectColumn<'q>]) -> Aggregates<'q> {
+        let accumulators = columns.iter()
+            .map(|c| match &c.expression {
+                SelectExpression::Aggregate(f, column) => Some(Accumulator::new(*f, column)),
+                _ => None
+            })
+            .collect();
+        Aggregates { columns, accumulators, rows: 0, first_row: None }
+    }
+
+    pub fn update(&mut self, row: &[u8]) -> Result<(), QueryError> {
+        for (c, accumulator) in self.columns.iter().zip(&mut self.accumulators) {
+            if let (SelectExpression::Aggregate(_, column), Some(accumulator)) = (&c.expression, accumulator) {
+                accumulator.update(decode(column, row)?);
+            }
+        }
+        if self.first_row.is_none() {
+            self.first_row = Some(row.to_vec());
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Vec<(String, Value)>, QueryError> {
+        let Aggregates { columns, accumulators, rows, first_row } = self;
+        columns.iter().zip(accumulators)
+            .map(|(c, accumulator)| Ok((c.name.to_owned(), match (&c.expression, accumulator) {
+                (SelectExpression::Aggregate(_, column), Some(accumulator)) => accumulator.finish(column)?,
+                (SelectExpression::CountAll, _) => Value::UInt64(rows),
+                (SelectExpression::Constant(v), _) => Value::from_literal(v),
+                (SelectExpression::Column(column), _) => match &first_row {
+                    Some(row) => decode(column, row)?,
+                    None => Value::Null
+                },
+                (SelectExpression::Aggregate(..), None) => unreachable!("every aggregate column has an accumulator"),
+                (SelectExpression::JoinedColumn(_), _) => unreachable!("joined columns can't be grouped by")
+            })))
+            .collect()
+    }
+}
+
+fn decode(column: &TableColumn, row: &[u8]) -> Result<Value, QueryError> {
+    Value::from_bytes(&column.datatype, &row[column.offset..])
+        .map_err(|e| QueryError::Decode { column_name: column.name.to_owned(), source: Box::new(e) })
+}
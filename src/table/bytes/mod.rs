@@ -5,6 +5,11 @@ pub trait FromSlice: Sized {
     fn from_slice(buf: &[u8]) -> Result<Self, Self::Err>;
 }
 
+/// The inverse of `FromSlice`: the bytes a value is stored as.
+pub trait ToBytes {
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
 pub trait ToNativeType<T> {
     type Err;
     fn to_native_type(&self) -> Result<T, Self::Err>;
@@ -26,28 +31,28 @@ fn to_native_type<T, const SZ: usize>(buf: &[u8], to_type: fn ([u8; SZ]) -> T) -
 impl ToNativeType<i32> for [u8] {
     type Err = SizedTypeConversionError;
     fn to_native_type(&self) -> Result<i32, Self::Err> {
-        to_native_type::<i32, 4>(self, |b| i32::from_le_bytes(b))
+        to_native_type::<i32, 4>(self, i32::from_le_bytes)
     }
 }
 
 impl ToNativeType<u32> for [u8] {
     type Err = SizedTypeConversionError;
     fn to_native_type(&self) -> Result<u32, Self::Err> {
-        to_native_type::<u32, 4>(self, |b| u32::from_le_bytes(b))
+        to_native_type::<u32, 4>(self, u32::from_le_bytes)
     }
 }
 
 impl ToNativeType<u64> for [u8] {
     type Err = SizedTypeConversionError;
     fn to_native_type(&self) -> Result<u64, Self::Err> {
-        to_native_type::<u64, 8>(self, |b| u64::from_le_bytes(b))
+        to_native_type::<u64, 8>(self, u64::from_le_bytes)
     }
 }
 
 impl ToNativeType<i64> for [u8] {
     type Err = SizedTypeConversionError;
     fn to_native_type(&self) -> Result<i64, Self::Err> {
-        to_native_type::<i64, 8>(self, |b| i64::from_le_bytes(b))
+        to_native_type::<i64, 8>(self, i64::from_le_bytes)
     }
 }
 
@@ -77,4 +82,28 @@ impl FromSlice for u64 {
     fn from_slice(buf: &[u8]) -> Result<Self, Self::Err> {
         buf.to_native_type()
     }
-}
\ No newline at end of file
+}
+
+impl ToBytes for i32 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+}
+
+impl ToBytes for u32 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+}
+
+impl ToBytes for i64 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+}
+
+impl ToBytes for u64 {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+}
@@ -0,0 +1,88 @@
+//! The virtual tables `select`s can read the database's own description
+//! from: `kronk_tables`, with a row per table, and `kronk_columns`, with a
+//! row per column of each. They sit alongside `kronk_stats`, and like it
+//! are filled in afresh for each query and can't be written or joined.
+
+use itertools::Itertools;
+
+use super::{error::KronkError, literal, schema::{ColumnDataType, DatabaseDescriptor, TableDescriptor, ident::MAX_IDENTIFIER_LENGTH}, stats::{TableStats, STATS_TABLE_NAME}, store::{ByteStore, InMemoryByteStore}};
+
+pub const TABLES_TABLE_NAME: &str = "kronk_tables";
+pub const COLUMNS_TABLE_NAME: &str = "kronk_columns";
+
+/// Whether `table_name`, in canonical form, is one of the virtual tables.
+/// No real table can take their names.
+pub fn is_virtual(table_name: &str) -> bool {
+    [STATS_TABLE_NAME, TABLES_TABLE_NAME, COLUMNS_TABLE_NAME].contains(&table_name)
+}
+
+/// A catalog of the virtual tables, for binding queries on them.
+pub fn virtual_catalog(db_name: &str) -> Result<DatabaseDescriptor, KronkError> {
+    DatabaseDescriptor::new(db_name, vec![TableStats::descriptor(), tables_descriptor(), columns_descriptor()])
+}
+
+/// Layout of the `kronk_tables` table.
+pub fn tables_descriptor() -> TableDescriptor {
+    TableDescriptor::new(TABLES_TABLE_NAME, vec![
+        // byte columns hold strings at least two bytes shorter than they are
+        ("table_name", ColumnDataType::Byte(MAX_IDENTIFIER_LENGTH + 2)),
+        ("column_count", ColumnDataType::UInt32),
+        ("row_size", ColumnDataType::UInt32)
+    ]).expect("kronk_tables descriptor should be valid")
+}
+
+/// Layout of the `kronk_columns` table.
+pub fn columns_descriptor() -> TableDescriptor {
+    TableDescriptor::new(COLUMNS_TABLE_NAME, vec![
+        ("table_name", ColumnDataType::Byte(MAX_IDENTIFIER_LENGTH + 2)),
+        ("column_name", ColumnDataType::Byte(MAX_IDENTIFIER_LENGTH + 2)),
+        // where the column comes in its table, counting from 0
+        ("position", ColumnDataType::UInt32),
+        ("datatype", ColumnDataType::Byte(32)),
+        ("size", ColumnDataType::UInt32),
+        ("primary_key", ColumnDataType::Boolean)
+    ]).expect("kronk_columns descriptor should be valid")
+}
+
+/// A store holding a `kronk_tables` row for each of `database`'s tables, in
+/// the order they were added.
+pub fn tables_store(database: &DatabaseDescriptor) -> Result<InMemoryByteStore, KronkError> {
+    let descriptor = tables_descriptor();
+    let mut store = InMemoryByteStore::new(&descriptor);
+    for table in &database.tables {
+        let columns = [
+            ("table_name", table.table_name.to_owned()),
+            ("column_count", literal::format_integer(table.columns.len())),
+            ("row_size", literal::format_integer(table.total_row_size()))
+        ];
+        insert(&mut store, &descriptor, &columns)?;
+    }
+    Ok(store)
+}
+
+/// A store holding a `kronk_columns` row for each column of `database`'s
+/// tables, table by table and in row order within each.
+pub fn columns_store(database: &DatabaseDescriptor) -> Result<InMemoryByteStore, KronkError> {
+    let descriptor = columns_descriptor();
+    let mut store = InMemoryByteStore::new(&descriptor);
+    for table in &database.tables {
+        for (position, column) in table.columns.iter().enumerate() {
+            let columns = [
+                ("table_name", table.table_name.to_owned()),
+                ("column_name", column.name.to_owned()),
+                ("position", literal::format_integer(position)),
+                ("datatype", column.datatype.to_string()),
+                ("size", literal::format_integer(column.datatype.size_in_bytes())),
+                ("primary_key", literal::format_boolean(table.primary_key.contains(&column.name)).to_owned())
+            ];
+            insert(&mut store, &descriptor, &columns)?;
+        }
+    }
+    Ok(store)
+}
+
+fn insert(store: &mut InMemoryByteStore, descriptor: &TableDescriptor, columns: &[(&str, String)]) -> Result<(), KronkError> {
+    let literals = columns.iter().map(|(n, v)| (*n, v.as_str())).collect_vec();
+    store.insert(descriptor, &descriptor.encode_literals(&literals)?)?;
+    Ok(())
+}
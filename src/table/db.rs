@@ -1,84 +1,2159 @@
-use std::collections::HashMap;
-use std::io::prelude::*;
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, io::Write, ops::Bound, sync::{Arc, Mutex}};
+#[cfg(feature = "file")]
+use std::{fs::File, path::Path};
 use itertools::Itertools;
+use thiserror::Error;
 
-use super::{schema::{DatabaseDescriptor, TableDescriptor, GetTableDescriptor}, store::{InMemoryByteStore, ByteStore, FileByteStore}, query::SelectQuery};
+use super::{bytes::FromSlice, schema::{ident, DatabaseDescriptor, TableDescriptor, TableColumn, OnDelete, AlterTableOperation, GetTableDescriptor, ColumnDataType, EncodedRow, config::StorageSettings}, progress::OperationProgress, stats::{TableStats, ColumnHistogram, STATS_TABLE_NAME}, catalog::{self, TABLES_TABLE_NAME, COLUMNS_TABLE_NAME}, store::{ByteStore, InMemoryByteStore, index::IndexKind, version::{VersionClock, ReadSnapshot}}, query::{SelectQuery, WherePredicate, WhereCondition, builder::SelectBuilder, parse::RawParse, types::{RawSelectQuery, RawDbCommand}}, exec::{Executor, DefaultExecutor, CancelToken, Cursor, QueryError, RowIterator, TableStores}, plan::AccessPath, prepared::PreparedStatement, format::{FormatOptions, FormatColumn, ResultFormatter}, value::{Value, Row, ResultSet}, lock::{LockError, LockManager, LockWait, Transaction}, record::KronkTable, hooks::Hooks, error::KronkError};
+#[cfg(feature = "file")]
+use super::{schema::config::{StorageBackend, Compression, DEFAULT_BUFFER_POOL_MEMORY_CAP}, store::{FileByteStore, pool::{BufferPool, BufferPoolStats, PageCache}, wal::WriteAheadLog, snapshot, object::{ObjectStore, RemoteSegments}, database_dir, table_path, lock_database}};
+#[cfg(feature = "mmap")]
+use super::store::mmap::MmapByteStore;
 
 pub struct Database {
     descriptor: DatabaseDescriptor,
-    table_stores: HashMap<String, Box<dyn ByteStore>>
+    table_stores: TableStores,
+    executor: Box<dyn Executor>,
+    #[cfg(feature = "file")]
+    buffer_pool: Arc<Mutex<BufferPool>>,
+    #[cfg(feature = "file")]
+    wal: Arc<Mutex<WriteAheadLog>>,
+    #[cfg(feature = "file")]
+    object_store: Option<Arc<dyn ObjectStore>>,
+    /// Holds the virtual tables, `kronk_stats` and those in `catalog`, for
+    /// binding queries on them.
+    virtual_catalog: DatabaseDescriptor,
+    /// Numbers writes, for reading at a `ReadSnapshot`.
+    clock: Arc<Mutex<VersionClock>>,
+    locks: Arc<LockManager>,
+    hooks: Hooks,
+    /// Set by `close`, so dropping the database doesn't close it again.
+    closed: bool,
+    /// Keeps other processes from opening the database while this one has
+    /// it open.
+    #[cfg(feature = "file")]
+    lock: File
 }
 
+/// What an insert, update or delete did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecResult {
+    pub rows_affected: u64,
+    /// The id of the last row inserted, if any were.
+    pub last_insert_id: Option<u64>
+}
+
+/// Why `update_versioned` didn't update a row.
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    /// The row was updated since it was read at version `expected`. Read it
+    /// again and retry.
+    #[error("row {id} of '{table_name}' was changed since it was read: it's at version {current}, not {expected}")]
+    VersionConflict { table_name: String, id: u64, expected: u64, current: u64 },
+
+    #[error(transparent)]
+    Failed(#[from] KronkError)
+}
+
+/// A row number, and the bytes to overwrite the row with, or none to
+/// delete it.
+type PendingRow = (u64, Option<Vec<u8>>);
+
+/// A row as inserted: its id, and each column's name and value.
+pub type InsertedRow = (u64, Vec<(String, String)>);
+
+/// The rows an update or delete is going to change, found without changing
+/// anything yet.
+#[derive(Debug)]
+pub struct PendingWrite {
+    /// Each table's rows, with the bytes to overwrite them with, or none
+    /// to delete them.
+    rows: BTreeMap<String, Vec<PendingRow>>,
+    rows_affected: u64
+}
+
+impl PendingWrite {
+    pub fn rows_affected(&self) -> u64 {
+        self.rows_affected
+    }
+
+    /// Locks the rows the write changes for `transaction`, waiting for
+    /// them as the database's `LockWait` says.
+    pub fn lock(&self, transaction: &Transaction) -> Result<(), LockError> {
+        for (table_name, rows) in &self.rows {
+            transaction.lock_rows(table_name, &rows.iter().map(|(row_number, _)| *row_number).collect_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Whether `transaction` already holds every row the write changes.
+    pub fn is_locked_by(&self, transaction: &Transaction) -> bool {
+        self.rows.iter().all(|(table_name, rows)| transaction.holds(table_name, rows.iter().map(|(row_number, _)| *row_number)))
+    }
+}
+
+/// Once the write-ahead log grows past this, the next insert checkpoints it.
+#[cfg(feature = "file")]
+pub const WAL_CHECKPOINT_SIZE: u64 = 16 * 1024 * 1024;
+
 impl Database {
+    /// Like `open`, panicking if the database can't be opened.
     pub fn new(db_name: &str) -> Database {
-        Database { 
-            descriptor: DatabaseDescriptor { 
-                db_name: db_name.to_owned(), 
-                tables: Vec::new() 
-            }, 
-            table_stores: HashMap::new()
+        Self::with_executor(db_name, Box::new(DefaultExecutor::default()))
+    }
+
+    /// Like `open_with_executor`, panicking if the database can't be opened.
+    pub fn with_executor(db_name: &str, executor: Box<dyn Executor>) -> Database {
+        Self::open_with_executor(db_name, executor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Opens the database, failing if another process already has it open.
+    /// Without the `file` feature, the database starts out empty every time
+    /// and is only ever in memory.
+    pub fn open(db_name: &str) -> Result<Database, KronkError> {
+        Self::open_with_executor(db_name, Box::new(DefaultExecutor::default()))
+    }
+
+    pub fn open_with_executor(db_name: &str, executor: Box<dyn Executor>) -> Result<Database, KronkError> {
+        let descriptor = DatabaseDescriptor::new(db_name, Vec::new())?;
+        let virtual_catalog = catalog::virtual_catalog(db_name)?;
+        #[cfg(feature = "file")]
+        let (lock, wal) = Self::open_files(&descriptor.db_name)?;
+        Ok(Database { 
+            descriptor,
+            table_stores: HashMap::new(),
+            executor,
+            #[cfg(feature = "file")]
+            buffer_pool: Arc::new(Mutex::new(BufferPool::new(DEFAULT_BUFFER_POOL_MEMORY_CAP))),
+            #[cfg(feature = "file")]
+            wal,
+            #[cfg(feature = "file")]
+            object_store: None,
+            virtual_catalog,
+            clock: Arc::new(Mutex::new(VersionClock::default())),
+            locks: Arc::new(LockManager::new(LockWait::FailFast)),
+            hooks: Hooks::default(),
+            closed: false,
+            #[cfg(feature = "file")]
+            lock
+        })
+    }
+
+    /// Takes the lock on the database's directory and opens its write-ahead
+    /// log.
+    #[cfg(feature = "file")]
+    fn open_files(db_name: &str) -> Result<(File, Arc<Mutex<WriteAheadLog>>), KronkError> {
+        let db_dir = database_dir(db_name);
+        let lock = lock_database(&db_dir).map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock => KronkError::Storage(format!("Database '{}' is already open, in this or another process", db_name)),
+            _ => KronkError::storage_io(format!("failed locking database '{}'", db_name), e)
+        })?;
+        let wal = Arc::new(Mutex::new(WriteAheadLog::open(&db_dir).map_err(|e| KronkError::storage_io("failed opening write-ahead log", e))?));
+        WriteAheadLog::start_flusher(&wal).map_err(|e| KronkError::storage_io("failed starting write-ahead log flusher", e))?;
+        Ok((lock, wal))
+    }
+
+    /// Syncs every table file and drops their records from the write-ahead
+    /// log. Happens on its own when the log gets large, before tables are
+    /// rewritten or dropped, and when the database is closed.
+    pub fn checkpoint(&mut self) -> Result<(), KronkError> {
+        for (table_name, store) in self.table_stores.iter() {
+            store.sync().map_err(|e| KronkError::storage_io(format!("failed syncing table '{}'", table_name), e))?;
+        }
+        #[cfg(feature = "file")]
+        {
+            let table_names = self.table_stores.keys().map(|n| n.as_str()).collect_vec();
+            self.wal.lock().unwrap().checkpoint(&table_names).map_err(|e| KronkError::storage_io("failed checkpointing write-ahead log", e))?;
+        }
+        Ok(())
+    }
+
+    /// Closes the database, syncing every table file, id counters and
+    /// deleted rows included, and checkpointing the write-ahead log, so the
+    /// next open has nothing to replay. Dropping the database does the same
+    /// but can only report failures on stderr.
+    pub fn close(mut self) -> Result<(), KronkError> {
+        self.closed = true;
+        self.checkpoint()
+    }
+
+    /// Checkpoints once the write-ahead log has grown past
+    /// `WAL_CHECKPOINT_SIZE`.
+    fn checkpoint_if_large(&mut self) -> Result<(), KronkError> {
+        #[cfg(feature = "file")]
+        if self.wal.lock().unwrap().len()? > WAL_CHECKPOINT_SIZE {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Copies the database's files into a new directory at `dest_dir`, while
+    /// it stays open. Tables are synced first and the write-ahead log is
+    /// copied along with them, so the snapshot holds every write made before
+    /// this call.
+    #[cfg(feature = "file")]
+    pub fn snapshot(&self, dest_dir: &Path) -> Result<(), KronkError> {
+        self.snapshot_with_progress(dest_dir, &mut |_| {})
+    }
+
+    /// Like `snapshot`, calling `on_progress` after each file is copied.
+    #[cfg(feature = "file")]
+    pub fn snapshot_with_progress(&self, dest_dir: &Path, on_progress: &mut dyn FnMut(&OperationProgress)) -> Result<(), KronkError> {
+        for (table_name, store) in self.table_stores.iter() {
+            store.sync().map_err(|e| KronkError::storage_io(format!("failed syncing table '{}'", table_name), e))?;
         }
+        // holding the log keeps anything from being logged mid-copy
+        let mut wal = self.wal.lock().unwrap();
+        wal.sync().map_err(|e| KronkError::storage_io("failed syncing write-ahead log", e))?;
+        let db_dir = database_dir(&self.descriptor.db_name);
+        let failed = |e| KronkError::storage_io(format!("Failed writing snapshot to {}", dest_dir.display()), e);
+        let mut progress = OperationProgress::for_files("snapshot", &self.descriptor.db_name, snapshot::size(&db_dir).map_err(failed)?);
+        snapshot::write(&db_dir, dest_dir, &mut |entry| progress.copied(entry.len, on_progress)).map_err(failed)?;
+        Ok(())
+    }
+
+    /// Replaces the database's files with the snapshot at `src_dir`, once
+    /// every file in it checks out. A database with tables open or data on
+    /// disk is only replaced with `force`. Open tables are closed; add them
+    /// again to use the restored data.
+    #[cfg(feature = "file")]
+    pub fn restore(&mut self, src_dir: &Path, force: bool) -> Result<(), KronkError> {
+        self.restore_with_progress(src_dir, force, &mut |_| {})
+    }
+
+    /// Like `restore`, calling `on_progress` after each file of the snapshot
+    /// is checked, and again after each is copied into place. The two
+    /// passes are told apart by their `operation`.
+    #[cfg(feature = "file")]
+    pub fn restore_with_progress(&mut self, src_dir: &Path, force: bool, on_progress: &mut dyn FnMut(&OperationProgress)) -> Result<(), KronkError> {
+        let db_dir = database_dir(&self.descriptor.db_name);
+        let failed = |e: std::io::Error| KronkError::storage_io(format!("Failed restoring snapshot from {}", src_dir.display()), e);
+        // the manifest has to be in a format this version writes
+        let entries = snapshot::read_manifest(src_dir).map_err(failed)?;
+        let size = entries.iter().map(|entry| entry.len).sum();
+        let mut checked = OperationProgress::for_files("verify snapshot", &self.descriptor.db_name, size);
+        snapshot::verify(src_dir, &entries, &mut |entry| checked.copied(entry.len, on_progress)).map_err(failed)?;
+        if !force && (!self.table_stores.is_empty() || snapshot::has_data(&db_dir).map_err(failed)?) {
+            return Err(KronkError::Storage(format!("Database '{}' already has data; restore with force to replace it", self.descriptor.db_name)));
+        }
+
+        for table_name in self.table_stores.keys().cloned().collect_vec() {
+            self.descriptor.remove_table(&table_name)?;
+            self.buffer_pool.lock().unwrap().invalidate_table(&table_name);
+        }
+        self.table_stores.clear();
+        let mut restored = OperationProgress::for_files("restore", &self.descriptor.db_name, size);
+        snapshot::install(src_dir, &entries, &db_dir, &mut |entry| restored.copied(entry.len, on_progress)).map_err(failed)?;
+        // the lock file went with the replaced directory
+        self.lock = lock_database(&db_dir).map_err(failed)?;
+        self.wal.lock().unwrap().reopen().map_err(failed)?;
+        Ok(())
+    }
+
+    /// Caps the memory held by the buffer pool shared by all of this
+    /// database's tables, evicting pages right away if it is over the new cap.
+    #[cfg(feature = "file")]
+    pub fn set_buffer_pool_memory_cap(&mut self, memory_cap: usize) {
+        self.buffer_pool.lock().unwrap().set_memory_cap(memory_cap);
+    }
+
+    #[cfg(feature = "file")]
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.lock().unwrap().stats()
     }
 
-    pub fn add_table(&mut self, descriptor: TableDescriptor) -> Result<(), String> {
+    /// Keeps the sealed segments of tables added from now on in `objects`
+    /// as well as on disk, downloading any a table is missing when it is
+    /// added. Only tables with a `segment_size` are sealed into segments.
+    #[cfg(feature = "file")]
+    pub fn set_object_store(&mut self, objects: Arc<dyn ObjectStore>) {
+        self.object_store = Some(objects);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.descriptor.db_name
+    }
+
+    /// Changes the database-wide settings. A change of storage backend only
+    /// applies to tables added afterwards.
+    pub fn set_default_settings(&mut self, settings: StorageSettings) {
+        self.descriptor.settings = settings;
+        for (table_name, store) in self.table_stores.iter_mut() {
+            if let Some(table_settings) = self.descriptor.settings_for(table_name) {
+                store.apply_settings(&table_settings);
+            }
+        }
+    }
+
+    pub fn settings_for(&self, table_name: &str) -> Option<StorageSettings> {
+        self.descriptor.settings_for(table_name)
+    }
+
+    /// The database's tables, in the order they were added. The virtual
+    /// `kronk_stats` table isn't one of them.
+    pub fn tables(&self) -> impl Iterator<Item = &TableDescriptor> {
+        self.descriptor.tables.iter()
+    }
+
+    /// The table named `table_name`: its columns, their types and where
+    /// they sit in a row, and its keys and constraints.
+    pub fn describe(&self, table_name: &str) -> Result<&TableDescriptor, KronkError> {
+        self.table_with_name(table_name).ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", ident::canonical(table_name))))
+    }
+
+    /// The columns of the table named `table_name`, in row order.
+    pub fn columns(&self, table_name: &str) -> Result<impl Iterator<Item = &TableColumn>, KronkError> {
+        Ok(self.describe(table_name)?.columns.iter())
+    }
+
+    pub fn add_table(&mut self, descriptor: TableDescriptor) -> Result<(), KronkError> {
+        self.descriptor.validate_new_table(&descriptor)?;
         let n = descriptor.table_name.clone();
-        let fbs = FileByteStore::new(&descriptor).unwrap();
-        self.table_stores.insert(n,  Box::new(fbs));
+        let store = self.open_store(&descriptor)?;
+        self.table_stores.insert(n, store);
         self.descriptor.add_table(descriptor)?;
 
         Ok(())
     }
 
-    pub fn insert_columns(&mut self, table_name: &str, columns: &[(&str, &str)]) -> Result<(), String> {
+    /// Opens a table whose file is already on disk, e.g. one written by an
+    /// earlier run, checking its stored layout against `descriptor` and
+    /// carrying on from its id counter. Unlike `add_table`, this fails
+    /// rather than creating an empty table when there's no file.
+    #[cfg(feature = "file")]
+    pub fn attach_table(&mut self, descriptor: TableDescriptor) -> Result<(), KronkError> {
+        if !table_path(&self.descriptor.db_name, &descriptor.table_name).exists() {
+            return Err(KronkError::Schema(format!("No stored table '{}' to attach in database '{}'", descriptor.table_name, self.descriptor.db_name)));
+        }
+        self.add_table(descriptor)
+    }
+
+    /// Opens the table's files, creating them if there are none, in the
+    /// storage backend its settings pick.
+    #[cfg(feature = "file")]
+    fn open_store(&self, descriptor: &TableDescriptor) -> Result<Box<dyn ByteStore>, KronkError> {
+        let n = &descriptor.table_name;
+        let settings = self.descriptor.settings.with_overrides(&descriptor.overrides);
+        if let Some(objects) = &self.object_store {
+            RemoteSegments::download_missing(objects.as_ref(), &database_dir(&self.descriptor.db_name), n)
+                .map_err(|e| KronkError::storage_io(format!("Failed downloading segments for table '{}'", n), e))?;
+        }
+        let mut fbs = FileByteStore::new(&self.descriptor.db_name, descriptor)
+            .and_then(|fbs| fbs.with_object_store(self.object_store.clone()))
+            .map_err(|e| KronkError::storage_io(format!("Failed opening storage for table '{}'", n), e))?
+            .with_wal(self.wal.clone(), settings.durability);
+        if settings.compression != Compression::None && settings.segment_size.is_some() {
+            return Err(KronkError::Schema(format!("Table '{}' can't be both compressed and split into segments", n)));
+        }
+        match settings.backend {
+            StorageBackend::File => {
+                fbs = fbs.with_page_cache(PageCache::new(self.buffer_pool.clone(), &settings))
+                    .with_compression(settings.compression, settings.page_size)
+                    .and_then(|fbs| fbs.with_segments(settings.segment_size))
+                    .map_err(|e| KronkError::storage_io(format!("Failed opening storage for table '{}'", n), e))?;
+            },
+            StorageBackend::Mmap if settings.compression != Compression::None => {
+                return Err(KronkError::Schema(format!("Table '{}' can't be compressed with the mmap storage backend", n)));
+            },
+            StorageBackend::Mmap if settings.segment_size.is_some() => {
+                return Err(KronkError::Schema(format!("Table '{}' can't be split into segments with the mmap storage backend", n)));
+            },
+            StorageBackend::Mmap => {}
+        }
+        let mut fbs = fbs.with_checksums(settings.on_corruption)
+            .map_err(|e| KronkError::storage_io(format!("Failed opening checksums for table '{}'", n), e))?;
+        fbs.recover().map_err(|e| KronkError::storage_io(format!("Failed replaying write-ahead log for table '{}'", n), e))?;
+        let fbs = fbs.with_id_index(descriptor)
+            .and_then(|fbs| fbs.with_column_indexes(descriptor))
+            .map_err(|e| KronkError::storage_io(format!("Failed opening indexes for table '{}'", n), e))?;
+        Ok(match settings.backend {
+            StorageBackend::File => Box::new(fbs),
+            #[cfg(feature = "mmap")]
+            StorageBackend::Mmap => Box::new(MmapByteStore::new(fbs)
+                .map_err(|e| KronkError::storage_io(format!("Failed mapping storage for table '{}'", n), e))?),
+            #[cfg(not(feature = "mmap"))]
+            StorageBackend::Mmap => return Err(KronkError::Schema(format!("Table '{}' uses the mmap storage backend, which needs the mmap feature", n)))
+        })
+    }
+
+    /// Without the `file` feature, every table is kept in memory.
+    #[cfg(not(feature = "file"))]
+    fn open_store(&self, descriptor: &TableDescriptor) -> Result<Box<dyn ByteStore>, KronkError> {
+        Ok(Box::new(InMemoryByteStore::new(descriptor)))
+    }
+
+    /// Removes a table and releases its storage. With `keep_backup`, the
+    /// table's data is archived rather than deleted.
+    pub fn drop_table(&mut self, table_name: &str, keep_backup: bool) -> Result<(), KronkError> {
+        let table_name = &ident::canonical(table_name);
+        self.checkpoint()?;
+        self.descriptor.remove_table(table_name)?;
+        #[cfg(feature = "file")]
+        self.buffer_pool.lock().unwrap().invalidate_table(table_name);
+        let store = self.table_stores.remove(table_name).ok_or_else(|| no_such_table(table_name))?;
+        store.release(keep_backup)
+    }
+
+    /// Changes a table's layout, rewriting every stored row into the new
+    /// layout. Rows are converted into a fresh copy of the table that replaces
+    /// the old one only after every row converted, so a failed conversion
+    /// leaves the table as it was.
+    pub fn alter_table(&mut self, table_name: &str, operations: &[AlterTableOperation]) -> Result<(), KronkError> {
+        self.alter_table_with_progress(table_name, operations, &mut |_| {})
+    }
+
+    /// Like `alter_table`, calling `on_progress` periodically while rows are
+    /// rewritten and once more when the rewrite is done.
+    pub fn alter_table_with_progress(&mut self, table_name: &str, operations: &[AlterTableOperation], on_progress: &mut dyn FnMut(&OperationProgress)) -> Result<(), KronkError> {
+        let table_name = &ident::canonical(table_name);
+        // logged writes are in the old layout, so get them out of the log first
+        self.checkpoint()?;
+        let table = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let (altered, sources) = table.altered(operations)?;
+
+        let store = Self::store_mut(&mut self.table_stores, table_name)?;
+        let rows_total = store.row_count().map_err(|e| KronkError::storage_io(format!("failed reading table '{}'", table_name), e))?;
+        let mut progress = OperationProgress::new("alter table", table_name, Some(rows_total));
+        store.rewrite_rows(&altered, &mut |row| {
+            let converted = altered.convert_row_from(table, &sources, row)?;
+            progress.advance(converted.len(), on_progress);
+            Ok(converted)
+        })?;
+        on_progress(&progress);
+
+        #[cfg(feature = "file")]
+        self.buffer_pool.lock().unwrap().invalidate_table(table_name);
+        self.descriptor.replace_table(altered)
+    }
+
+    /// Indexes a column, so where clauses comparing it no longer scan the
+    /// whole table. The index is kept up to date as rows are inserted.
+    pub fn create_index(&mut self, table_name: &str, column_name: &str, kind: IndexKind) -> Result<(), KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let table = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let store = Self::store_mut(&mut self.table_stores, table_name)?;
+        store.create_index(table, column_name, kind)
+    }
+
+    /// Samples a table into a histogram per column, which queries estimate
+    /// how many rows a condition matches from when picking between an index
+    /// and a scan, or how to join. Histograms aren't saved with the table or
+    /// kept up to date as rows change, so analyze again after big changes.
+    pub fn analyze(&mut self, table_name: &str) -> Result<(), KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let table = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let store = Self::store_mut(&mut self.table_stores, table_name)?;
+        let histograms = ColumnHistogram::for_store(table, store.as_ref())
+            .map_err(|e| KronkError::storage_io(format!("failed reading table '{}'", table_name), e))?;
+        store.set_histograms(histograms)
+    }
+
+    pub fn insert_columns(&mut self, table_name: &str, columns: &[(&str, &str)]) -> Result<ExecResult, KronkError> {
+        let (id, _) = self.insert_returning(table_name, columns)?;
+        Ok(ExecResult { rows_affected: 1, last_insert_id: Some(id) })
+    }
+
+    /// Inserts `record` into its struct's table.
+    pub fn insert_record<T: KronkTable>(&mut self, record: &T) -> Result<ExecResult, KronkError> {
+        let columns = record.to_columns();
+        self.insert_columns(T::TABLE_NAME, &columns.iter().map(|(n, v)| (*n, v.as_str())).collect_vec())
+    }
+
+    /// Inserts a row and returns it as stored, with generated values (serial
+    /// id, uuids for omitted uuid columns) filled in.
+    pub fn insert_returning(&mut self, table_name: &str, columns: &[(&str, &str)]) -> Result<InsertedRow, KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let row = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?.encode_literals(columns)?;
+        self.insert_encoded(table_name, row)
+    }
+
+    /// Like `insert_columns`, taking natively typed values rather than
+    /// literals, so they're stored without being formatted and parsed.
+    /// Integers can be given as any integer type that fits the column.
+    pub fn insert_values(&mut self, table_name: &str, columns: &[(&str, Value)]) -> Result<ExecResult, KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let row = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?.encode_values(columns)?;
+        let (id, _) = self.insert_encoded(table_name, row)?;
+        Ok(ExecResult { rows_affected: 1, last_insert_id: Some(id) })
+    }
+
+    /// Inserts an already serialized row into the table named `table_name`,
+    /// in canonical form.
+    fn insert_encoded(&mut self, table_name: &str, row: EncodedRow) -> Result<InsertedRow, KronkError> {
+        let table_descriptor = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        Self::check_unique_constraints(table_descriptor, Self::store(&self.table_stores, table_name)?, std::slice::from_ref(&row))?;
+        self.check_foreign_keys(table_descriptor, &row)?;
+
+        let version = Self::begin_write(&self.clock, &mut self.table_stores);
+        let backing_store = Self::store_mut(&mut self.table_stores, table_name)?;
+        let (row_number, bytes) = backing_store.insert(table_descriptor, &row)?;
+        if let (Some(version), Some(versions)) = (version, backing_store.row_versions_mut()) {
+            versions.record_insert(row_number, version);
+        }
+        self.hooks.inserted(table_descriptor, row_number, &bytes)?;
+        let row = table_descriptor.parse_row(&bytes, row_number)?;
+
+        self.checkpoint_if_large()?;
+        Ok(row)
+    }
+
+    /// Inserts a batch of rows with a single write, returning them as stored.
+    /// Constraints are checked for the whole batch first, including between
+    /// rows of the batch, so either every row is inserted or none are.
+    pub fn insert_rows(&mut self, table_name: &str, rows: &[Vec<(&str, &str)>]) -> Result<Vec<InsertedRow>, KronkError> {
+        self.insert_rows_with_progress(table_name, rows, &mut |_| {})
+    }
+
+    /// Like `insert_rows`, calling `on_progress` periodically while the rows
+    /// are encoded, and once more when they're written.
+    pub fn insert_rows_with_progress(&mut self, table_name: &str, rows: &[Vec<(&str, &str)>], on_progress: &mut dyn FnMut(&OperationProgress)) -> Result<Vec<InsertedRow>, KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let table_descriptor = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let mut progress = OperationProgress::new("insert rows", table_name, Some(rows.len() as u64));
+        let batch = rows.iter()
+            .map(|columns| {
+                let row = table_descriptor.encode_literals(columns)?;
+                progress.advance(table_descriptor.total_row_size(), on_progress);
+                Ok(row)
+            })
+            .collect::<Result<Vec<_>, KronkError>>()?;
+        Self::check_unique_constraints(table_descriptor, Self::store(&self.table_stores, table_name)?, &batch)?;
+        for row in &batch {
+            self.check_foreign_keys(table_descriptor, row)?;
+        }
+
+        let version = Self::begin_write(&self.clock, &mut self.table_stores);
+        let backing_store = Self::store_mut(&mut self.table_stores, table_name)?;
+        let inserted = backing_store.insert_many(table_descriptor, &batch)?;
+        if let (Some(version), Some(versions)) = (version, backing_store.row_versions_mut()) {
+            for (row_number, _) in &inserted {
+                versions.record_insert(*row_number, version);
+            }
+        }
+        on_progress(&progress);
+        for (row_number, bytes) in &inserted {
+            self.hooks.inserted(table_descriptor, *row_number, bytes)?;
+        }
+        let inserted = inserted.into_iter()
+            .map(|(row_number, bytes)| table_descriptor.parse_row(&bytes, row_number))
+            .collect::<Result<Vec<_>, KronkError>>()?;
+
+        self.checkpoint_if_large()?;
+        Ok(inserted)
+    }
+
+    /// Sets `set`'s columns to new values in every row matching `predicate`,
+    /// returning how many rows were updated. The predicate's columns are
+    /// looked up by name, so it can be built against a copy of the table's
+    /// descriptor. Constraints are checked for every updated row first, so
+    /// either all the matching rows are updated or none are.
+    pub fn update_columns(&mut self, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let transaction = self.begin_transaction();
+        self.update_columns_in(&transaction, table_name, set, predicate)
+    }
+
+    /// Like `update_columns`, locking the updated rows for `transaction`.
+    /// Fails without updating anything if one of them is locked by another
+    /// transaction.
+    pub fn update_columns_in(&mut self, transaction: &Transaction, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let write = self.prepare_update(table_name, set, predicate)?;
+        self.apply_write(transaction, write)
+    }
+
+    /// Updates the row with serial id `id`, as long as its row version is
+    /// still `version`, the one it was read at. If another write updated it
+    /// since, nothing is updated and `UpdateError::VersionConflict` is
+    /// returned. The table needs a `RowVersion` column.
+    pub fn update_versioned(&mut self, table_name: &str, id: u64, version: u64, set: &[(&str, &str)]) -> Result<ExecResult, UpdateError> {
+        let table_name = ident::canonical(table_name);
+        // the predicate borrows its columns from the descriptor, so use a
+        // copy while the database is borrowed mutably
+        let table = self.descriptor.table_with_name(&table_name).cloned()
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let version_column = table.version_column()
+            .ok_or_else(|| KronkError::Schema(format!("Table '{}' has no row version column", table_name)))?;
+        let id_column = table.id_column()
+            .ok_or_else(|| KronkError::Schema(format!("Table '{}' has no serial id column", table_name)))?;
+
+        let store = Self::store(&self.table_stores, &table_name)?;
+        let row = store.find_row_by_id(&table, id)
+            .map_err(|e| KronkError::storage_io(format!("failed reading table '{}'", table_name), e))?
+            .ok_or_else(|| KronkError::Schema(format!("No row with id {} in '{}'", id, table_name)))?;
+        let current = u64::from_slice(&row[version_column.offset..]).unwrap();
+        if current != version {
+            return Err(UpdateError::VersionConflict { table_name, id, expected: version, current });
+        }
+
+        let predicate = WherePredicate::Condition(WhereCondition::new(id_column, "==", &id.to_string()).map_err(KronkError::Parse)?)
+            .and(WherePredicate::Condition(WhereCondition::new(version_column, "==", &version.to_string()).map_err(KronkError::Parse)?));
+        Ok(self.update_columns(&table_name, set, &predicate)?)
+    }
+
+    /// Finds the rows `update_columns` would update and checks their
+    /// constraints, leaving the table as it is.
+    pub fn prepare_update(&self, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<PendingWrite, KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let table_descriptor = self.descriptor.table_with_name(table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+
+        let assignments = set.iter()
+            .map(|(column_name, value)| {
+                let column = table_descriptor.column_for_name(column_name)
+                    .ok_or_else(|| KronkError::Schema(format!("No column '{}' in table '{}'", column_name, table_name)))?;
+                Ok((column, column.datatype.parse_string(value)?))
+            })
+            .collect::<Result<Vec<_>, KronkError>>()?;
+
+        let store = Self::store(&self.table_stores, table_name)?;
+        let updated = Self::matching_rows(table_descriptor, store, predicate)?.into_iter()
+            .map(|(row_number, mut row)| {
+                for (column, bytes) in &assignments {
+                    row[column.offset..(column.offset + bytes.len())].copy_from_slice(bytes);
+                }
+                if let Some(version) = table_descriptor.version_column() {
+                    let bumped = u64::from_slice(&row[version.offset..]).unwrap().wrapping_add(1);
+                    row[version.offset..(version.offset + 8)].copy_from_slice(&bumped.to_le_bytes());
+                }
+                (row_number, row)
+            })
+            .collect::<Vec<_>>();
+
+        let assigned = assignments.iter().map(|(column, _)| *column).collect::<Vec<_>>();
+        Self::check_unique_updates(table_descriptor, store, &assigned, &updated)?;
+        for (fk_column, references, _) in table_descriptor.foreign_keys() {
+            if let Some((_, bytes)) = assignments.iter().find(|(c, _)| c.name == fk_column.name) {
+                self.check_foreign_key(fk_column, references, bytes)?;
+            }
+        }
+
+        let rows_affected = updated.len() as u64;
+        let rows = updated.into_iter().map(|(row_number, bytes)| (row_number, Some(bytes))).collect();
+        Ok(PendingWrite { rows: BTreeMap::from([(table_name.to_owned(), rows)]), rows_affected })
+    }
+
+    /// Deletes every row matching `predicate`, returning how many were
+    /// deleted. Rows in other tables whose foreign keys reference a deleted
+    /// row are deleted along with it when the key is `on delete cascade`
+    /// (and aren't counted); when it's `on delete restrict`, nothing is
+    /// deleted and an error is returned instead.
+    pub fn delete_where(&mut self, table_name: &str, predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let transaction = self.begin_transaction();
+        self.delete_where_in(&transaction, table_name, predicate)
+    }
+
+    /// Like `delete_where`, locking the deleted rows, cascades included, for
+    /// `transaction`. Fails without deleting anything if one of them is
+    /// locked by another transaction.
+    pub fn delete_where_in(&mut self, transaction: &Transaction, table_name: &str, predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let write = self.prepare_delete(table_name, predicate)?;
+        self.apply_write(transaction, write)
+    }
+
+    /// Finds the rows `delete_where` would delete, cascades included, and
+    /// checks that nothing restricts deleting them, leaving the tables as
+    /// they are.
+    pub fn prepare_delete(&self, table_name: &str, predicate: &WherePredicate) -> Result<PendingWrite, KronkError> {
+        let table_name = &ident::canonical(table_name);
         let table_descriptor = self.descriptor.table_with_name(table_name)
-            .ok_or_else(|| format!("No table '{}' exists", table_name))?;
-        let backing_store = self.table_stores.get_mut(table_name).expect("Table backig store should be present here");
-        backing_store.insert(table_descriptor, columns)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+        let store = Self::store(&self.table_stores, table_name)?;
+        let matching = Self::matching_rows(table_descriptor, store, predicate)?;
+        let deleted = matching.len() as u64;
+
+        // row numbers to delete in each table, cascades included
+        let mut doomed: HashMap<String, BTreeSet<u64>> = HashMap::new();
+        doomed.insert(table_name.to_owned(), matching.iter().map(|(row_number, _)| *row_number).collect());
+        let mut restricted: Vec<(&str, u64, &str, u64)> = Vec::new();
+        let mut pending = vec![(table_descriptor, matching)];
+        while let Some((table, rows)) = pending.pop() {
+            if table.id_column().is_none() { continue; }
+            let ids = rows.iter().map(|(row_number, row)| table.row_id(row, *row_number)).collect::<HashSet<_>>();
+
+            for referencing in &self.descriptor.tables {
+                for (fk_column, references, on_delete) in referencing.foreign_keys() {
+                    if references != table.table_name { continue; }
+                    let referencing_store = Self::store(&self.table_stores, &referencing.table_name)?;
+                    let already = doomed.entry(referencing.table_name.to_owned()).or_default();
+                    let mut found: Vec<(u64, Vec<u8>)> = Vec::new();
+                    referencing_store.scan_rows(&mut |row_number, row| {
+                        let id = u64::from_slice(&row[fk_column.offset..]).unwrap();
+                        if ids.contains(&id) && !already.contains(&row_number) {
+                            found.push((row_number, row.to_vec()));
+                        }
+                        true
+                    }).map_err(|e| KronkError::storage_io(format!("failed reading table '{}' while checking foreign keys", referencing.table_name), e))?;
+
+                    match on_delete {
+                        OnDelete::Restrict => restricted.extend(found.iter().map(|(row_number, row)| {
+                            (referencing.table_name.as_str(), *row_number, references, u64::from_slice(&row[fk_column.offset..]).unwrap())
+                        })),
+                        OnDelete::Cascade if !found.is_empty() => {
+                            already.extend(found.iter().map(|(row_number, _)| *row_number));
+                            pending.push((referencing, found));
+                        },
+                        OnDelete::Cascade => {}
+                    }
+                }
+            }
+        }
+
+        // a restricting row is fine if a cascade deletes it too
+        if let Some((referencing, row_number, references, id)) = restricted.into_iter()
+            .find(|(t, row_number, _, _)| !doomed.get(*t).is_some_and(|rows| rows.contains(row_number))) {
+            return Err(KronkError::Constraint(format!("Foreign key violated: row {} of '{}' references the row in '{}' with id {}", row_number, referencing, references, id)));
+        }
+
+        let rows = doomed.into_iter()
+            .map(|(doomed_table, row_numbers)| (doomed_table, row_numbers.into_iter().map(|row_number| (row_number, None)).collect()))
+            .collect();
+        Ok(PendingWrite { rows, rows_affected: deleted })
+    }
+
+    /// Makes the changes of an update or delete, locking the rows it
+    /// changes for `transaction` first. Nothing is changed if one of them
+    /// is locked by another transaction.
+    pub fn apply_write(&mut self, transaction: &Transaction, write: PendingWrite) -> Result<ExecResult, KronkError> {
+        write.lock(transaction)?;
+        let version = Self::begin_write(&self.clock, &mut self.table_stores);
+        for (table_name, rows) in write.rows {
+            let table = self.descriptor.table_with_name(&table_name).ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+            let store = Self::store_mut(&mut self.table_stores, &table_name)?;
+            for (row_number, bytes) in rows {
+                if let Some(version) = version {
+                    Self::keep_earlier_version(store.as_mut(), row_number, version)?;
+                }
+                match bytes {
+                    Some(bytes) => {
+                        store.write_row(row_number, &bytes)?;
+                        self.hooks.updated(table, row_number, &bytes)?;
+                    },
+                    None => {
+                        // the row can't be read once it's deleted
+                        let deleted = match self.hooks.has_delete_listeners() {
+                            true => Some(store.read_row(row_number)?),
+                            false => None
+                        };
+                        store.delete_row(row_number)?;
+                        if let Some(deleted) = deleted {
+                            self.hooks.deleted(table, row_number, &deleted)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.checkpoint_if_large()?;
+        Ok(ExecResult { rows_affected: write.rows_affected, last_insert_id: None })
+    }
+
+    /// The tables a write to `table_name` can change or depends on staying
+    /// as they are: the table itself, and those whose foreign keys reference
+    /// it, directly or through other tables, which deletes cascade to or
+    /// are restricted by.
+    pub fn write_path_tables(&self, table_name: &str) -> BTreeSet<String> {
+        let mut tables = BTreeSet::from([ident::canonical(table_name)]);
+        let mut pending = vec![ident::canonical(table_name)];
+        while let Some(table_name) = pending.pop() {
+            for referencing in &self.descriptor.tables {
+                if referencing.foreign_keys().any(|(_, references, _)| references == table_name) && tables.insert(referencing.table_name.to_owned()) {
+                    pending.push(referencing.table_name.to_owned());
+                }
+            }
+        }
+        tables
+    }
+
+    /// The store backing a table. The stores are passed in rather than
+    /// borrowed through `self` so the descriptor can be held alongside.
+    fn store<'a>(table_stores: &'a TableStores, table_name: &str) -> Result<&'a dyn ByteStore, KronkError> {
+        table_stores.get(table_name).map(|store| store.as_ref()).ok_or_else(|| no_such_table(table_name))
+    }
+
+    fn store_mut<'a>(table_stores: &'a mut TableStores, table_name: &str) -> Result<&'a mut Box<dyn ByteStore>, KronkError> {
+        table_stores.get_mut(table_name).ok_or_else(|| no_such_table(table_name))
+    }
+
+    /// Takes a version for a write about to be made, letting go of the row
+    /// history no open snapshot needs any more. `None` when there are no
+    /// snapshots, so the rows the write changes needn't be kept as they
+    /// were.
+    fn begin_write(clock: &Mutex<VersionClock>, table_stores: &mut TableStores) -> Option<u64> {
+        let (version, oldest) = clock.lock().unwrap().advance();
+        for store in table_stores.values_mut() {
+            if let Some(versions) = store.row_versions_mut() {
+                versions.prune(oldest);
+            }
+        }
+        oldest.map(|_| version)
+    }
+
+    /// Keeps a row's bytes as they are before the write at `version`
+    /// replaces them, for the snapshots taken before it.
+    fn keep_earlier_version(store: &mut dyn ByteStore, row_number: u64, version: u64) -> Result<(), KronkError> {
+        let bytes = store.read_row(row_number).map_err(|e| KronkError::storage_io(format!("failed reading row {} before writing it", row_number), e))?;
+        if let Some(versions) = store.row_versions_mut() {
+            versions.record_replace(row_number, version, bytes);
+        }
+        Ok(())
+    }
+
+    /// The rows of `store` matching `predicate`, by row number. The
+    /// predicate's columns are looked up by name in `table`.
+    fn matching_rows(table: &TableDescriptor, store: &dyn ByteStore, predicate: &WherePredicate) -> Result<Vec<(u64, Vec<u8>)>, KronkError> {
+        let predicate = predicate.against(table).map_err(KronkError::Parse)?;
+        let read_error = |e| KronkError::storage_io(format!("failed reading table '{}'", table.table_name), e);
+        let matches = |row: &[u8]| predicate.is_true(row);
+
+        // `where id == 42` and the like only read the rows the index points at
+        if let Some(mut row_numbers) = AccessPath::choose(Some(&predicate), store).into_rows() {
+            row_numbers.sort_unstable();
+            let mut matching = Vec::new();
+            for row_number in row_numbers {
+                let row = store.read_row(row_number).map_err(read_error)?;
+                if matches(&row) {
+                    matching.push((row_number, row));
+                }
+            }
+            return Ok(matching);
+        }
+
+        let mut matching = Vec::new();
+        store.scan_rows(&mut |row_number, row| {
+            if matches(row) {
+                matching.push((row_number, row.to_vec()));
+            }
+            true
+        }).map_err(read_error)?;
+        Ok(matching)
+    }
+}
+
+impl Database {
+    /// Row counts and storage sizes for a table, read from its store as it
+    /// is now. The same figures for every table can be queried from the
+    /// `kronk_stats` virtual table.
+    pub fn table_stats(&self, table_name: &str) -> Result<TableStats, KronkError> {
+        let table_name = &ident::canonical(table_name);
+        let store = Self::store(&self.table_stores, table_name)?;
+        TableStats::for_store(table_name, store)
+            .map_err(|e| KronkError::storage_io(format!("failed reading storage of table '{}'", table_name), e))
+    }
+
+    /// A store holding a `kronk_stats` row for each table, in name order.
+    fn stats_store(&self) -> Result<InMemoryByteStore, KronkError> {
+        let descriptor = TableStats::descriptor();
+        let mut store = InMemoryByteStore::new(&descriptor);
+        for table_name in self.table_stores.keys().sorted() {
+            let stats = self.table_stats(table_name)?;
+            let columns = stats.columns();
+            store.insert(&descriptor, &descriptor.encode_literals(&columns.iter().map(|(n, v)| (*n, v.as_str())).collect_vec())?)?;
+        }
+        Ok(store)
+    }
+
+    /// The stores a query on a virtual table reads, filled in afresh, or
+    /// `None` for a query on the database's own tables.
+    fn virtual_stores(&self, query: &SelectQuery) -> Result<Option<TableStores>, KronkError> {
+        let Some(table) = query.table else { return Ok(None) };
+        let store = match table.table_name.as_str() {
+            STATS_TABLE_NAME => self.stats_store()?,
+            TABLES_TABLE_NAME => catalog::tables_store(&self.descriptor)?,
+            COLUMNS_TABLE_NAME => catalog::columns_store(&self.descriptor)?,
+            _ => return Ok(None)
+        };
+        Ok(Some(HashMap::from([(table.table_name.to_owned(), Box::new(store) as Box<dyn ByteStore>)])))
+    }
+
+    /// Pre-loads the given tables' pages, so the first queries after startup
+    /// don't pay for cold reads. Returns the total number of bytes touched.
+    pub fn warmup(&self, tables: &[&str]) -> Result<u64, KronkError> {
+        self.warmup_with_progress(tables, &mut |_| {})
+    }
+
+    /// Like `warmup`, calling `on_progress` periodically while each table is
+    /// read and once more when that table is done.
+    pub fn warmup_with_progress(&self, tables: &[&str], on_progress: &mut dyn FnMut(&OperationProgress)) -> Result<u64, KronkError> {
+        let mut touched = 0u64;
+        for table_name in tables {
+            let table_name = &ident::canonical(table_name);
+            self.descriptor.table_with_name(table_name)
+                .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+            let store = Self::store(&self.table_stores, table_name)?;
+            let failed = |e| KronkError::storage_io(format!("failed warming up table '{}'", table_name), e);
+
+            let mut progress = OperationProgress::new("warmup", table_name, Some(store.row_count().map_err(failed)?));
+            store.scan_rows(&mut |_, row| {
+                progress.advance(row.len(), on_progress);
+                true
+            }).map_err(failed)?;
+            on_progress(&progress);
+            touched += progress.bytes_processed;
+        }
+        Ok(touched)
+    }
+
+    /// Every foreign key column must be given and must name an existing row
+    /// in the referenced table.
+    fn check_foreign_keys(&self, table: &TableDescriptor, row: &EncodedRow) -> Result<(), KronkError> {
+        for (fk_column, references, _) in table.foreign_keys() {
+            let bytes = row.get(&fk_column.name)
+                .ok_or_else(|| KronkError::Constraint(format!("Foreign key column '{}' requires a value", fk_column.name)))?;
+            self.check_foreign_key(fk_column, references, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// `bytes` is the serialized value of `fk_column` being written.
+    fn check_foreign_key(&self, fk_column: &TableColumn, references: &str, bytes: &[u8]) -> Result<(), KronkError> {
+        let value = Value::from_bytes(&fk_column.datatype, bytes)?;
+        let id = match &value {
+            Value::UInt64(id) => Some(*id),
+            other => other.as_i64().and_then(|i| u64::try_from(i).ok())
+        }.ok_or_else(|| KronkError::Constraint(format!("Foreign key column '{}' must hold a serial id, got '{}'", fk_column.name, value)))?;
+
+        let referenced_table = self.descriptor.table_with_name(references)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", references)))?;
+        let referenced_store = Self::store(&self.table_stores, references)?;
+        let found = referenced_store.find_row_by_id(referenced_table, id)
+            .map_err(|e| KronkError::storage_io(format!("failed reading table '{}' while checking foreign keys", references), e))?;
+
+        if found.is_none() {
+            return Err(KronkError::Constraint(format!("Foreign key violated: no row in '{}' with id {}", references, id)));
+        }
+        Ok(())
+    }
+
+    /// The table's unique columns, and its primary key if it has one.
+    fn unique_keys(table: &TableDescriptor) -> Vec<Vec<&TableColumn>> {
+        table.unique_columns()
+            .map(|c| vec![c])
+            .chain(std::iter::once(table.primary_key_columns().collect::<Vec<_>>()))
+            .filter(|key| !key.is_empty())
+            .collect::<Vec<_>>()
+    }
+
+    /// Scans the table for rows that already hold the values being written to
+    /// a unique column or to the primary key, and checks the rows being
+    /// written against each other. Omitted columns are checked against their
+    /// zeroed default, except uuid columns, which get a freshly generated
+    /// value, and serial ids, which are always fresh.
+    fn check_unique_constraints(table: &TableDescriptor, store: &dyn ByteStore, rows: &[EncodedRow]) -> Result<(), KronkError> {
+        let keys = Self::unique_keys(table);
+
+        // (key, the key's column values laid end to end)
+        let mut candidates: HashSet<(usize, Vec<u8>)> = HashSet::new();
+        for row in rows {
+            'keys: for (k, key) in keys.iter().enumerate() {
+                let mut value = vec![];
+                for kc in key {
+                    match row.get(&kc.name) {
+                        Some(bytes) => value.extend(bytes),
+                        None if kc.datatype == ColumnDataType::UuidV4 || kc.datatype == ColumnDataType::SerialId => continue 'keys,
+                        None => value.extend(vec![0u8; kc.datatype.size_in_bytes()])
+                    };
+                }
+                if !candidates.insert((k, value.clone())) {
+                    let (names, values) = Self::describe_key(&keys[k], &value)?;
+                    return Err(KronkError::Constraint(format!("Unique constraint violated: ({}) would contain ({}) twice", names, values)));
+                }
+            }
+        }
+
+        Self::check_existing_keys(&keys, store, &candidates, &HashSet::new())
+    }
+
+    /// Like `check_unique_constraints`, for rows being overwritten in place
+    /// with new values in the `assigned` columns. Only keys that include an
+    /// assigned column can change, and the rows' old values don't count.
+    fn check_unique_updates(table: &TableDescriptor, store: &dyn ByteStore, assigned: &[&TableColumn], rows: &[(u64, Vec<u8>)]) -> Result<(), KronkError> {
+        let keys = Self::unique_keys(table).into_iter()
+            .filter(|key| key.iter().any(|kc| assigned.iter().any(|c| c.name == kc.name)))
+            .collect::<Vec<_>>();
+
+        let mut candidates: HashSet<(usize, Vec<u8>)> = HashSet::new();
+        for (_, row) in rows {
+            for (k, key) in keys.iter().enumerate() {
+                let value = Self::key_value(key, row);
+                if !candidates.insert((k, value.clone())) {
+                    let (names, values) = Self::describe_key(&keys[k], &value)?;
+                    return Err(KronkError::Constraint(format!("Unique constraint violated: ({}) would contain ({}) twice", names, values)));
+                }
+            }
+        }
+
+        let overwritten = rows.iter().map(|(row_number, _)| *row_number).collect::<HashSet<_>>();
+        Self::check_existing_keys(&keys, store, &candidates, &overwritten)
+    }
+
+    /// Fails if a row of the table, other than the `skipped` ones, holds one
+    /// of the `candidates` key values. A key with an indexed column has its
+    /// values looked up in the index; the table is only scanned for the
+    /// others.
+    fn check_existing_keys(keys: &[Vec<&TableColumn>], store: &dyn ByteStore, candidates: &HashSet<(usize, Vec<u8>)>, skipped: &HashSet<u64>) -> Result<(), KronkError> {
+        if candidates.is_empty() { return Ok(()); }
+
+        let held = |row_number: u64, k: usize, value: &[u8]| -> Result<bool, KronkError> {
+            if skipped.contains(&row_number) || store.is_deleted(row_number) { return Ok(false); }
+            let row = store.read_row(row_number).map_err(|e| KronkError::storage_io("failed reading row while checking unique constraints", e))?;
+            Ok(Self::key_value(&keys[k], &row) == value)
+        };
+        let mut violation = None;
+        let mut scanned = HashSet::new();
+        for (k, value) in candidates.iter().map(|(k, value)| (*k, value)) {
+            match Self::indexed_rows(&keys[k], store, value) {
+                Some(rows) => for row_number in rows {
+                    if held(row_number, k, value)? {
+                        violation = Some((k, value.clone()));
+                        break;
+                    }
+                },
+                None => { scanned.insert(k); }
+            }
+            if violation.is_some() { break; }
+        }
+
+        if violation.is_none() && !scanned.is_empty() {
+            store.scan_rows(&mut |row_number, row| {
+                if skipped.contains(&row_number) { return true; }
+                violation = keys.iter().enumerate()
+                    .filter(|(k, _)| scanned.contains(k))
+                    .map(|(k, key)| (k, Self::key_value(key, row)))
+                    .find(|candidate| candidates.contains(candidate));
+                violation.is_none()
+            }).map_err(|e| KronkError::storage_io("failed reading table while checking unique constraints", e))?;
+        }
+
+        if let Some((k, value)) = violation {
+            let (names, values) = Self::describe_key(&keys[k], &value)?;
+            return Err(KronkError::Constraint(format!("Unique constraint violated: ({}) already contains ({})", names, values)));
+        }
+
+        Ok(())
+    }
+
+    /// The rows whose value in one of the key's indexed columns matches
+    /// `value`'s, or `None` if none of its columns are indexed.
+    fn indexed_rows(key: &[&TableColumn], store: &dyn ByteStore, value: &[u8]) -> Option<Vec<u64>> {
+        let mut offset = 0;
+        key.iter().find_map(|c| {
+            let index_key = c.datatype.index_key(&value[offset..]);
+            offset += c.datatype.size_in_bytes();
+            store.rows_in_key_range(&c.name, (Bound::Included(index_key.clone()), Bound::Included(index_key)))
+        })
+    }
+
+    /// The key's column values in `row`, laid end to end.
+    fn key_value(key: &[&TableColumn], row: &[u8]) -> Vec<u8> {
+        key.iter().flat_map(|c| row[c.offset..(c.offset + c.datatype.size_in_bytes())].to_vec()).collect()
+    }
+
+    /// The key's column names and its values, for error messages.
+    fn describe_key(key: &[&TableColumn], value: &[u8]) -> Result<(String, String), KronkError> {
+        let names = key.iter().map(|c| c.name.as_str()).join(", ");
+        let mut offset = 0;
+        let values = key.iter()
+            .map(|c| {
+                offset += c.datatype.size_in_bytes();
+                c.datatype.parse_bytes(&value[(offset - c.datatype.size_in_bytes())..offset])
+            })
+            .collect::<Result<Vec<_>, KronkError>>()?
+            .join(", ");
+        Ok((names, values))
     }
 }
 
 impl GetTableDescriptor for Database {
     fn table_with_name<'a>(&'a self, table_name: &str) -> Option<&'a TableDescriptor> {
         self.descriptor.table_with_name(table_name)
+            .or_else(|| self.virtual_catalog.table_with_name(table_name))
     }
 }
 
 impl Database {
-    pub fn query(&self, query: &SelectQuery) -> Vec<(u64, Vec<(String, String)>)> {
-        let backing_store = self.table_stores.get(&query.table.table_name).expect("backing store here shold be populated");
+    /// Starts a select of every column of `table_name`, built up in code
+    /// rather than written out as text.
+    pub fn select(&self, table_name: &str) -> SelectBuilder<'_> {
+        SelectBuilder::new(self, table_name)
+    }
+
+    pub fn bind<'a>(&'a self, query: &RawSelectQuery) -> Result<SelectQuery<'a>, KronkError> {
+        let bound = self.bind_with_parameters(query)?;
+        if !bound.parameters.is_empty() {
+            return Err(KronkError::Parse("Invalid query: '?' placeholders only take values in a prepared statement".to_owned()));
+        }
+        Ok(bound)
+    }
+
+    /// Parses and binds a select once, for running again with different
+    /// values for its `?` placeholders.
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement<'_>, KronkError> {
+        let query = match RawParse::parse(sql)? {
+            RawDbCommand::Select(s) => s,
+            _ => return Err(KronkError::Parse("Only select statements can be prepared".to_owned()))
+        };
+        Ok(PreparedStatement::new(self, self.bind_with_parameters(&query)?))
+    }
+
+    fn bind_with_parameters<'a>(&'a self, query: &RawSelectQuery) -> Result<SelectQuery<'a>, KronkError> {
+        if let (Some(database_name), Some(table_name)) = (&query.database_name, &query.table_name) {
+            if ident::canonical(database_name) != self.descriptor.db_name {
+                return Err(KronkError::Parse(format!("Invalid query: '{}.{}' is not in database '{}'", database_name, table_name, self.descriptor.db_name)));
+            }
+        }
+        if query.table_name.as_ref().is_some_and(|t| catalog::is_virtual(&ident::canonical(t))) {
+            return self.executor.bind(query, &self.virtual_catalog);
+        }
+        self.executor.bind(query, &self.descriptor)
+    }
+
+    /// Runs a bound query, reading its rows as the returned iterator is
+    /// advanced.
+    pub fn query<'a>(&'a self, query: &'a SelectQuery) -> Result<RowIterator<'a>, QueryError> {
+        self.query_with_cancel(query, CancelToken::new())
+    }
+
+    /// Starts a transaction whose writes are undone when it's dropped,
+    /// unless it's committed first. Write through it while it's open. Row
+    /// listeners hear about its writes when it commits, and not at all if
+    /// it's rolled back.
+    pub fn begin(&mut self) -> TransactionGuard<'_> {
+        let transaction = self.begin_transaction();
+        self.hooks.hold();
+        TransactionGuard { db: self, transaction, undo: Vec::new() }
+    }
+
+    /// Puts back the rows a `TransactionGuard` changed, most recent change
+    /// first.
+    fn undo_writes(&mut self, undo: Vec<(String, u64, Option<Vec<u8>>)>) -> Result<(), KronkError> {
+        if undo.is_empty() {
+            return Ok(());
+        }
+        let version = Self::begin_write(&self.clock, &mut self.table_stores);
+        for (table_name, row_number, bytes) in undo.into_iter().rev() {
+            let store = Self::store_mut(&mut self.table_stores, &table_name)?;
+            if store.is_deleted(row_number) {
+                // deleting left the bytes where they were
+                store.restore_row(row_number)?;
+                continue;
+            }
+            if let Some(version) = version {
+                Self::keep_earlier_version(store.as_mut(), row_number, version)?;
+            }
+            match bytes {
+                Some(bytes) => store.write_row(row_number, &bytes)?,
+                None => store.delete_row(row_number)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a transaction for `update_columns_in` and `delete_where_in`.
+    /// The rows it changes stay locked until it's dropped.
+    pub fn begin_transaction(&self) -> Transaction {
+        LockManager::begin(&self.locks)
+    }
+
+    /// What writers do when a row they'd change is locked by another
+    /// transaction. They fail right away unless this is changed.
+    pub fn set_lock_wait(&mut self, on_conflict: LockWait) {
+        self.locks.set_on_conflict(on_conflict);
+    }
+
+    /// Calls `listener` with each row inserted, after it's stored.
+    pub fn on_insert(&mut self, listener: impl Fn(&str, &Row) + Send + Sync + 'static) {
+        self.hooks.on_insert(Box::new(listener));
+    }
+
+    /// Calls `listener` with each row an update changes, as it is after.
+    pub fn on_update(&mut self, listener: impl Fn(&str, &Row) + Send + Sync + 'static) {
+        self.hooks.on_update(Box::new(listener));
+    }
+
+    /// Calls `listener` with each row deleted, cascades included, as it was
+    /// before.
+    pub fn on_delete(&mut self, listener: impl Fn(&str, &Row) + Send + Sync + 'static) {
+        self.hooks.on_delete(Box::new(listener));
+    }
+
+    /// Calls `listener` with each query run, before its first row is read.
+    pub fn on_query(&mut self, listener: impl Fn(&SelectQuery) + Send + Sync + 'static) {
+        self.hooks.on_query(Box::new(listener));
+    }
+
+    /// A view of the database as it is now, for queries given it with
+    /// `SelectQuery::with_snapshot`. They keep seeing it as it was, however
+    /// it's written to after, for as long as the snapshot is held. Changes
+    /// to tables' columns aren't versioned: rows written before an
+    /// `alter_table` are read in the new layout.
+    pub fn begin_read(&self) -> ReadSnapshot {
+        ReadSnapshot::new(&self.clock)
+    }
+
+    /// Runs the query to the end, returning its rows along with what their
+    /// columns are.
+    pub fn query_result_set(&self, query: &SelectQuery) -> Result<ResultSet, QueryError> {
+        let rows = self.query(query)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(ResultSet { columns: query.result_columns(), rows })
+    }
+
+    /// Runs the query, writing its rows to `out` in the options' format as
+    /// they're read instead of collecting them first, e.g. to export a whole
+    /// table to a file or socket. Returns the number of rows written.
+    pub fn query_to_writer(&self, query: &SelectQuery, out: &mut impl Write, options: &FormatOptions) -> Result<usize, KronkError> {
+        let result_columns = query.result_columns();
+        let columns = result_columns.iter()
+            .map(|c| FormatColumn { name: c.label(), datatype: c.datatype.as_ref() })
+            .collect_vec();
+        let rows = self.query(query)?
+            .map(|row| Ok(row?.columns.into_iter().map(|(_, v)| (!v.is_null()).then(|| v.to_string())).collect_vec()));
+        ResultFormatter::new(options).write_streamed::<KronkError>(out, &columns, rows)
+    }
+
+    /// Runs a select of a `KronkTable`'s columns to the end, reading each row
+    /// back as the struct.
+    pub fn query_records<T: KronkTable>(&self, query: &SelectQuery) -> Result<Vec<T>, KronkError> {
+        let mut records = Vec::new();
+        for row in self.query(query)? {
+            records.push(T::from_row(&row?)?);
+        }
+        Ok(records)
+    }
+
+    /// Like `query`, handing the rows out `batch_size` at a time.
+    pub fn query_cursor<'a>(&'a self, query: &'a SelectQuery, batch_size: usize) -> Result<Cursor<'a>, QueryError> {
+        self.resume_cursor(query, batch_size, 0)
+    }
+
+    /// Picks up where a cursor over the same query left off, `position` rows
+    /// in. The rows before it are read again and passed over, so they should
+    /// come back in the same order: the query should have an `order by`, or
+    /// the table not have changed in between.
+    pub fn resume_cursor<'a>(&'a self, query: &'a SelectQuery, batch_size: usize, position: u64) -> Result<Cursor<'a>, QueryError> {
+        let mut rows = self.query(query)?;
+        for row in rows.by_ref().take(usize::try_from(position).unwrap_or(usize::MAX)) {
+            row?;
+        }
+        Ok(Cursor::new(rows, batch_size, position))
+    }
+
+    /// Like `query`, stopping with `QueryError::Cancelled` once `cancel` is
+    /// cancelled, e.g. from another thread, or its deadline passes.
+    pub fn query_with_cancel<'a>(&'a self, query: &'a SelectQuery, cancel: CancelToken) -> Result<RowIterator<'a>, QueryError> {
+        self.hooks.queried(query);
+        let virtual_stores = self.virtual_stores(query)
+            .map_err(|e| QueryError::Io { table_name: query.table.map(|t| t.table_name.to_owned()).unwrap_or_default(), source: std::io::Error::other(e) })?;
+        if let Some(stores) = virtual_stores {
+            // there are few enough virtual rows to collect before the stores
+            // go away
+            let rows = self.executor.execute_cancellable(query, &stores, cancel).collect::<Result<Vec<_>, _>>()?;
+            return Ok(RowIterator::new(rows.into_iter().map(Ok)));
+        }
+        Ok(self.executor.execute_cancellable(query, &self.table_stores, cancel))
+    }
+
+    /// The plan a bound query runs with, a line of text per operator: how
+    /// each table is read, where the where clause is checked, and how rows
+    /// are joined, sorted and grouped.
+    pub fn explain(&self, query: &SelectQuery) -> Result<Vec<String>, KronkError> {
+        if let Some(stores) = self.virtual_stores(query)? {
+            return Ok(self.executor.explain(query, &stores));
+        }
+        Ok(self.executor.explain(query, &self.table_stores))
+    }
+}
+
+/// Writes made through it are undone when it's dropped, unless `commit` is
+/// called first. Started with `Database::begin`. The rows it updates or
+/// deletes stay locked until then, and the database can be read through it,
+/// its writes included. Rolling back an insert deletes the row, so its
+/// serial id isn't given out again.
+pub struct TransactionGuard<'a> {
+    db: &'a mut Database,
+    transaction: Transaction,
+    /// Each row written, with its bytes from before, or none for a row
+    /// that was inserted.
+    undo: Vec<(String, u64, Option<Vec<u8>>)>
+}
+
+impl<'a> TransactionGuard<'a> {
+    pub fn insert_columns(&mut self, table_name: &str, columns: &[(&str, &str)]) -> Result<ExecResult, KronkError> {
+        let row_number = self.next_row_number(table_name)?;
+        let result = self.db.insert_columns(table_name, columns)?;
+        self.undo.push((ident::canonical(table_name), row_number, None));
+        Ok(result)
+    }
+
+    pub fn insert_values(&mut self, table_name: &str, columns: &[(&str, Value)]) -> Result<ExecResult, KronkError> {
+        let row_number = self.next_row_number(table_name)?;
+        let result = self.db.insert_values(table_name, columns)?;
+        self.undo.push((ident::canonical(table_name), row_number, None));
+        Ok(result)
+    }
+
+    pub fn insert_record<T: KronkTable>(&mut self, record: &T) -> Result<ExecResult, KronkError> {
+        let columns = record.to_columns();
+        self.insert_columns(T::TABLE_NAME, &columns.iter().map(|(n, v)| (*n, v.as_str())).collect_vec())
+    }
+
+    pub fn update_columns(&mut self, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let write = self.db.prepare_update(table_name, set, predicate)?;
+        self.apply_write(write)
+    }
+
+    pub fn delete_where(&mut self, table_name: &str, predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        let write = self.db.prepare_delete(table_name, predicate)?;
+        self.apply_write(write)
+    }
+
+    /// Keeps the transaction's writes.
+    pub fn commit(mut self) {
+        self.undo.clear();
+        self.db.hooks.release();
+    }
+
+    /// Undoes the transaction's writes, like dropping it does, returning
+    /// what went wrong if they couldn't all be undone.
+    pub fn rollback(mut self) -> Result<(), KronkError> {
+        let undo = std::mem::take(&mut self.undo);
+        self.db.undo_writes(undo)
+    }
+
+    /// The row an insert into `table_name` is about to get: rows are
+    /// numbered in the order they're inserted.
+    fn next_row_number(&self, table_name: &str) -> Result<u64, KronkError> {
+        let table_name = ident::canonical(table_name);
+        let store = Database::store(&self.db.table_stores, &table_name)?;
+        store.row_count().map_err(|e| KronkError::storage_io(format!("failed reading table '{}'", table_name), e))
+    }
+
+    /// Locks the rows `write` changes, so no other transaction changes them
+    /// before they're put back, and keeps their bytes before applying it.
+    fn apply_write(&mut self, write: PendingWrite) -> Result<ExecResult, KronkError> {
+        write.lock(&self.transaction)?;
+        let mut undo = Vec::new();
+        for (table_name, rows) in &write.rows {
+            let store = Database::store(&self.db.table_stores, table_name)?;
+            for (row_number, _) in rows {
+                let bytes = store.read_row(*row_number).map_err(|e| KronkError::storage_io(format!("failed reading row {} before writing it", row_number), e))?;
+                undo.push((table_name.to_owned(), *row_number, Some(bytes)));
+            }
+        }
+        self.undo.extend(undo);
+        self.db.apply_write(&self.transaction, write)
+    }
+}
+
+impl<'a> std::ops::Deref for TransactionGuard<'a> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db
+    }
+}
+
+impl<'a> Drop for TransactionGuard<'a> {
+    fn drop(&mut self) {
+        let undo = std::mem::take(&mut self.undo);
+        if let Err(e) = self.db.undo_writes(undo) {
+            eprintln!("warning: rolling back a transaction on '{}': {}", self.db.descriptor.db_name, e);
+        }
+        self.db.hooks.discard();
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.checkpoint() {
+            eprintln!("warning: closing database '{}': {}", self.descriptor.db_name, e);
+        }
+    }
+}
+
+fn no_such_table(table_name: &str) -> KronkError {
+    KronkError::Schema(format!("No table '{}' exists", table_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{query::builder::Filter, schema::{TableConstraint, config::{TableOverrides, Durability, CachePriority}}, value::ResultColumn, testing::ScratchDir};
+
+    fn books(db_name: &str) -> Database {
+        let mut db = Database::new(db_name);
+        db.add_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int32)]).unwrap()).unwrap();
+        db.insert_rows("books", &[vec![("year", "1950")], vec![("year", "1960")]]).unwrap();
+        db
+    }
+
+    fn years(db: &Database, snapshot: &ReadSnapshot) -> Vec<i32> {
+        let query = SelectQuery::parse_raw_query_against_db("select year from books", db).unwrap().with_snapshot(snapshot);
+        db.query(&query).unwrap().map(|row| row.unwrap().get::<i32>("year").unwrap()).collect()
+    }
+
+    // without the file feature this reads in-memory tables
+    #[test]
+    fn snapshots_see_rows_as_they_were() {
+        let _dir = ScratchDir::new("test_db_snapshot");
+        let mut db = books("test_db_snapshot");
+        let books = db.table_with_name("books").unwrap().clone();
+        let snapshot = db.begin_read();
+        let first = WherePredicate::Condition(WhereCondition::new(books.column_for_name("id").unwrap(), "==", "0").unwrap());
+        db.update_columns("books", &[("year", "1951")], &first).unwrap();
+        let second = WherePredicate::Condition(WhereCondition::new(books.column_for_name("id").unwrap(), "==", "1").unwrap());
+        db.delete_where("books", &second).unwrap();
+        db.insert_columns("books", &[("year", "1970")]).unwrap();
+
+        assert_eq!(years(&db, &snapshot), vec![1950, 1960]);
+        drop(snapshot);
+        assert_eq!(years(&db, &db.begin_read()), vec![1951, 1970]);
+    }
+
+    /// Buffer pool lookups made by `f`.
+    #[cfg(feature = "file")]
+    fn page_lookups<T>(db: &mut Database, f: impl FnOnce(&mut Database) -> T) -> (u64, T) {
+        let before = db.buffer_pool_stats();
+        let result = f(db);
+        let after = db.buffer_pool_stats();
+        (after.hits + after.misses - before.hits - before.misses, result)
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn unique_checks_look_keys_up_in_their_index() {
+        use crate::table::schema::TableConstraint;
+
+        let _dir = ScratchDir::new("test_db_unique_index");
+        let mut db = Database::new("test_db_unique_index");
+        let table = TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("isbn", ColumnDataType::UInt64)]).unwrap()
+            .with_constraint(TableConstraint::Unique("isbn".to_owned())).unwrap();
+        db.add_table(table).unwrap();
+        let isbns = (0..2000).map(|isbn| isbn.to_string()).collect::<Vec<_>>();
+        db.insert_rows("books", &isbns.iter().map(|isbn| vec![("isbn", isbn.as_str())]).collect::<Vec<_>>()).unwrap();
+
+        let (scanned, _) = page_lookups(&mut db, |db| db.insert_columns("books", &[("isbn", "2000")]).unwrap());
+        db.create_index("books", "isbn", IndexKind::Hash).unwrap();
+        let (looked_up, duplicate) = page_lookups(&mut db, |db| db.insert_columns("books", &[("isbn", "1500")]));
+        assert!(matches!(duplicate, Err(KronkError::Constraint(_))), "{:?}", duplicate);
+        assert!(looked_up < 3 && scanned > 5, "{} lookups with the index, {} without", looked_up, scanned);
+
+        // a row's own value doesn't count against it, and deleted rows' don't either
+        let books = db.table_with_name("books").unwrap().clone();
+        let isbn = |isbn| WherePredicate::Condition(WhereCondition::new(books.column_for_name("isbn").unwrap(), "==", isbn).unwrap());
+        db.update_columns("books", &[("isbn", "1500")], &isbn("1500")).unwrap();
+        assert!(matches!(db.update_columns("books", &[("isbn", "1500")], &isbn("1501")), Err(KronkError::Constraint(_))));
+        db.delete_where("books", &isbn("1500")).unwrap();
+        db.insert_columns("books", &[("isbn", "1500")]).unwrap();
+    }
+
+    #[test]
+    fn bulk_inserts_report_progress() {
+        let _dir = ScratchDir::new("test_db_insert_progress");
+        let mut db = books("test_db_insert_progress");
+        let years = (0..5000).map(|year| year.to_string()).collect::<Vec<_>>();
+        let rows = years.iter().map(|year| vec![("year", year.as_str())]).collect::<Vec<_>>();
+        let mut reports = Vec::new();
+        db.insert_rows_with_progress("books", &rows, &mut |p| reports.push((p.rows_processed, p.fraction_done()))).unwrap();
+        assert_eq!(reports, vec![(4096, Some(4096.0 / 5000.0)), (5000, Some(1.0))]);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn backups_report_progress() {
+        let (_dir, _snapshot_dir, _restored_dir) = (ScratchDir::new("test_db_backup_progress"), ScratchDir::new("test_db_backup_snapshot"), ScratchDir::new("test_db_backup_restored"));
+        let db = books("test_db_backup_progress");
+        let snapshot_dir = crate::table::store::database_dir("test_db_backup_snapshot");
+        let mut reports = Vec::new();
+        let mut record = |p: &OperationProgress| reports.push((p.operation, p.bytes_processed, p.bytes_total.unwrap()));
+        db.snapshot_with_progress(&snapshot_dir, &mut record).unwrap();
+        Database::new("test_db_backup_restored").restore_with_progress(&snapshot_dir, false, &mut record).unwrap();
 
-        let row_size = query.table.total_row_size();
+        // each pass ends having gone through all of its bytes
+        for operation in ["snapshot", "verify snapshot", "restore"] {
+            let last = reports.iter().rfind(|(o, _, _)| *o == operation).unwrap();
+            assert!(last.1 > 0 && last.1 == last.2, "{:?}", reports);
+        }
+    }
+
+    /// Books and their reviews.
+    fn library(db_name: &str) -> Database {
+        let mut db = Database::new(db_name);
+        db.add_table(TableDescriptor::new("books", vec![
+            ("id", ColumnDataType::SerialId),
+            ("author", ColumnDataType::Byte(64)),
+            ("title", ColumnDataType::Byte(64)),
+            ("year_published", ColumnDataType::Int32),
+            ("us_based_publisher", ColumnDataType::Boolean)
+        ]).unwrap()).unwrap();
+        db.insert_rows("books", &[
+            vec![("author", "Billy Bob"), ("title", "How to Sting Like a Bee"), ("year_published", "1932")],
+            vec![("author", "Stink Williams"), ("title", "Singing for Frogs"), ("year_published", "1921")],
+            vec![("author", "Stink Williams"), ("title", "Dancing for the Everyday Man"), ("year_published", "1937"), ("us_based_publisher", "true")],
+            vec![("author", "joseph"), ("title", "My Lumps My Bumps"), ("year_published", "1917")]
+        ]).unwrap();
+        db.add_table(TableDescriptor::new("reviews", vec![
+            ("id", ColumnDataType::SerialId),
+            ("book_id", ColumnDataType::UInt64),
+            ("stars", ColumnDataType::Int32)
+        ]).unwrap()).unwrap();
+        db.insert_rows("reviews", &[vec![("book_id", "1"), ("stars", "3")], vec![("book_id", "2"), ("stars", "5")], vec![("book_id", "2"), ("stars", "4")]]).unwrap();
+        db
+    }
+
+    fn select(db: &Database, sql: &str) -> Vec<Row> {
+        let query = SelectQuery::parse_raw_query_against_db(sql, db).unwrap();
+        db.query(&query).unwrap().collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    fn titles(rows: &[Row]) -> Vec<String> {
+        rows.iter().map(|row| row.get::<String>("title").unwrap()).collect()
+    }
 
-        let mut reader = backing_store.get_reader();
-        let mut dest_vec: Vec<u8> = Vec::new();
-        dest_vec.extend(std::iter::repeat(0u8).take(row_size));
-        let bytes = dest_vec.as_mut_slice();
+    #[test]
+    fn the_schema_can_be_looked_up() {
+        let _dir = ScratchDir::new("test_db_schema");
+        let mut db = library("test_db_schema");
+        assert_eq!(db.tables().map(|t| t.table_name.as_str()).collect::<Vec<_>>(), vec!["books", "reviews"]);
+        assert_eq!(db.describe("Books").unwrap().column_names().collect::<Vec<_>>(), vec!["id", "author", "title", "year_published", "us_based_publisher"]);
+        assert_eq!(db.columns("books").unwrap().map(|c| &c.datatype).nth(3), Some(&ColumnDataType::Int32));
+        assert!(matches!(db.describe("novels"), Err(KronkError::Schema(_))));
 
-        let mut out: Vec<(u64, Vec<(String, String)>)> = vec![];
+        let tables = select(&db, "select table_name, column_count from kronk_tables");
+        assert_eq!((tables[0].get::<String>("table_name").unwrap(), tables[0].get::<u32>("column_count").unwrap()), ("books".to_owned(), 5));
+        let columns = select(&db, "select column_name, datatype, primary_key from kronk_columns where table_name == \"books\" and position < 2").into_iter()
+            .map(|row| (row.get::<String>("column_name").unwrap(), row.get::<String>("datatype").unwrap(), row.get::<bool>("primary_key").unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(columns, vec![("id".to_owned(), "serial_id".to_owned(), true), ("author".to_owned(), "byte(64)".to_owned(), false)]);
+        assert!(db.add_table(TableDescriptor::new("kronk_columns", vec![("id", ColumnDataType::SerialId)]).unwrap()).is_err());
+    }
 
-        loop {
-            let bytes_read = reader.read(bytes).unwrap();
-            if bytes_read == 0 { break; }
-            if bytes_read != row_size { panic!("woah buddy, file size ain't right") }
+    #[test]
+    fn inserts_are_checked_against_the_schema() {
+        let _dir = ScratchDir::new("test_db_insert_checks");
+        let mut db = library("test_db_insert_checks");
+        assert!(matches!(db.insert_columns("novels", &[("title", "Frogs")]), Err(KronkError::Schema(_))));
+        assert!(matches!(db.insert_columns("books", &[("year_published", "soon")]), Err(KronkError::Schema(_))));
+        // native values are stored as they are; integers only have to fit
+        assert_eq!(db.insert_values("reviews", &[("stars", Value::Int64(2))]).unwrap().last_insert_id, Some(3));
+        assert!(db.insert_values("reviews", &[("stars", Value::Str("2".to_owned()))]).is_err());
+        assert!(db.insert_values("reviews", &[("stars", Value::Int64(i64::MAX))]).is_err());
+    }
 
-            let id_column = query.table.id_column();
-            let row_id: u64 = str::parse(id_column.datatype.parse_bytes(&bytes[id_column.offset..]).unwrap().as_str()).unwrap();
+    #[test]
+    fn typed_getters_convert_what_fits() {
+        let _dir = ScratchDir::new("test_db_getters");
+        let db = library("test_db_getters");
+        let rows = select(&db, "select title, year_published from books where id == 2");
+        assert_eq!(rows[0].get::<String>("title").unwrap(), "Dancing for the Everyday Man");
+        assert_eq!(rows[0].get::<i64>("year_published").unwrap(), 1937);
+        assert!(rows[0].get::<i32>("title").is_err());
+        assert!(rows[0].get::<String>("year_published").is_err());
+    }
+
+    #[test]
+    fn parsed_and_built_selects_agree() {
+        let _dir = ScratchDir::new("test_db_builder");
+        let db = library("test_db_builder");
+        let query = SelectQuery::parse_raw_query_against_db("select title from books where year_published >= 1920 and not author == \"joseph\" order by year_published desc limit 2", &db).unwrap();
+        let built = || db.select("books").columns(&["title"])
+            .filter(Filter::ge("year_published", 1920).and(!Filter::eq("author", "joseph")))
+            .order_by_desc("year_published")
+            .limit(2);
+        assert_eq!(db.explain(&built().build().unwrap()).unwrap(), db.explain(&query).unwrap());
+        let rows = built().run().unwrap().rows;
+        assert_eq!(rows, db.query_result_set(&query).unwrap().rows);
+        assert_eq!(titles(&rows), vec!["Dancing for the Everyday Man", "How to Sting Like a Bee"]);
+        assert!(db.select("books").filter(Filter::eq("year_published", "soon")).run().is_err());
+
+        let rows = select(&db, "select title from books where year_published < 1920 or (author == \"Stink Williams\" and not us_based_publisher == true)");
+        assert_eq!(titles(&rows), vec!["Singing for Frogs", "My Lumps My Bumps"]);
+    }
 
-            let where_cond = match &query.where_predicate {
-                Some(predicate) => predicate.conditions[..].into_iter()
-                    .all(|wc| wc.comparison.is_true(&bytes[wc.column.offset..])),
-                None => true
-            };
+    #[test]
+    fn cursors_resume_where_they_left_off() {
+        let _dir = ScratchDir::new("test_db_cursor");
+        let db = library("test_db_cursor");
+        let query = SelectQuery::parse_raw_query_against_db("select title from books order by title", &db).unwrap();
+        let mut cursor = db.query_cursor(&query, 3).unwrap();
+        assert_eq!(cursor.next_page().unwrap().map(|page| page.len()), Some(3));
+        let mut cursor = db.resume_cursor(&query, 3, cursor.position()).unwrap();
+        assert_eq!(titles(&cursor.next_page().unwrap().unwrap()), vec!["Singing for Frogs"]);
+        assert!(cursor.next_page().unwrap().is_none());
+    }
 
-            if !where_cond { continue; }
+    #[test]
+    fn prepared_statements_bind_each_execution() {
+        let _dir = ScratchDir::new("test_db_prepared");
+        let db = library("test_db_prepared");
+        let mut published_since = db.prepare("select title from books where year_published >= ?").unwrap();
+        assert_eq!(published_since.execute(&["1930"]).unwrap().count(), 2);
+        assert_eq!(published_since.execute(&["1920"]).unwrap().count(), 3);
+        let mut by_either = db.prepare("select title from books where author == ? or year_published == ?").unwrap();
+        assert_eq!(by_either.execute(&["joseph", "1932"]).unwrap().count(), 2);
+    }
 
-            let column_data = query.columns[..].into_iter()
-                .map(|c| (c.name.to_owned(), c.datatype.parse_bytes(&bytes[c.offset..]).unwrap()))
-                .collect_vec();
+    #[test]
+    fn versioned_updates_fail_on_a_stale_version() {
+        let _dir = ScratchDir::new("test_db_versioned");
+        let mut db = Database::new("test_db_versioned");
+        db.add_table(TableDescriptor::new("accounts", vec![("id", ColumnDataType::SerialId), ("balance", ColumnDataType::Int64), ("version", ColumnDataType::RowVersion)]).unwrap()).unwrap();
+        db.insert_values("accounts", &[("balance", Value::Int32(100))]).unwrap();
+        // two read-modify-write cycles read the account at version 0; only
+        // the first to write wins
+        assert_eq!(db.update_versioned("accounts", 0, 0, &[("balance", "70")]).unwrap().rows_affected, 1);
+        assert!(matches!(db.update_versioned("accounts", 0, 0, &[("balance", "120")]), Err(UpdateError::VersionConflict { current: 1, .. })));
+        assert!(db.update_versioned("accounts", 0, 1, &[("version", "5")]).is_err());
+        let rows = select(&db, "select balance, version from accounts where version == 1");
+        assert_eq!(rows[0].value("balance"), Some(&Value::Int64(70)));
+    }
 
-            out.push((row_id, column_data));
+    #[test]
+    fn dropped_transactions_are_rolled_back() {
+        let _dir = ScratchDir::new("test_db_rollback");
+        let mut db = library("test_db_rollback");
+        let reviews = db.table_with_name("reviews").unwrap().clone();
+        let first = WherePredicate::Condition(WhereCondition::new(reviews.column_for_name("id").unwrap(), "==", "0").unwrap());
+        {
+            let mut tx = db.begin();
+            assert_eq!(tx.update_columns("reviews", &[("stars", "1")], &first).unwrap().rows_affected, 1);
+            tx.insert_values("reviews", &[("stars", Value::Int64(5))]).unwrap();
+            assert_eq!(tx.select("reviews").run().unwrap().rows.len(), 4);
+            assert_eq!(tx.delete_where("reviews", &first).unwrap().rows_affected, 1);
         }
+        let rows = db.select("reviews").run().unwrap().rows;
+        assert_eq!(rows.iter().map(|row| row.get::<i32>("stars").unwrap()).collect::<Vec<_>>(), vec![3, 5, 4]);
+
+        let mut tx = db.begin();
+        assert_eq!(tx.insert_values("reviews", &[("stars", Value::Int64(5))]).unwrap().last_insert_id, Some(4));
+        tx.commit();
+        assert_eq!(db.select("reviews").run().unwrap().rows.len(), 4);
+    }
+
+    #[test]
+    fn aggregates_over_no_rows_are_null() {
+        let _dir = ScratchDir::new("test_db_aggregates");
+        let db = library("test_db_aggregates");
+        let years = &select(&db, "select min(year_published), max(year_published), avg(year_published) from books where year_published > 1920")[0];
+        assert_eq!((years.value("min"), years.value("max")), (Some(&Value::Int32(1921)), Some(&Value::Int32(1937))));
+        assert_eq!(years.get::<f64>("avg").unwrap(), 1930.0);
+        assert_eq!(years.get_opt::<i64>("min").unwrap(), Some(1921));
+        let none = &select(&db, "select min(year_published) from books where year_published > 2000")[0];
+        assert_eq!(none.get_opt::<i32>("min").unwrap(), None);
+        assert!(none.get::<i32>("min").is_err());
+
+        let query = SelectQuery::parse_raw_query_against_db("select min(year_published), avg(year_published), title as name from books group by title", &db).unwrap();
+        let result = db.query_result_set(&query).unwrap();
+        assert_eq!((&result.columns[0].datatype, result.columns[0].nullable), (&Some(ColumnDataType::Int32), true));
+        assert_eq!(result.columns[1].datatype, None);
+        assert_eq!(result.columns[2], ResultColumn { name: "title".to_owned(), alias: Some("name".to_owned()), datatype: Some(ColumnDataType::Byte(64)), nullable: false });
+
+        let authors = select(&db, "select author, count(*) from books group by author");
+        assert!(authors.iter().any(|row| row.get::<String>("author").unwrap() == "Stink Williams" && row.get::<u64>("count").unwrap() == 2));
+    }
+
+    #[test]
+    fn joins_match_rows_across_tables() {
+        let _dir = ScratchDir::new("test_db_join");
+        let db = library("test_db_join");
+        let rows = select(&db, "select b.title, r.stars from books b join reviews r on b.id == r.book_id where year_published >= 1930");
+        assert_eq!(titles(&rows), vec!["Dancing for the Everyday Man"; 2]);
+    }
+
+    #[test]
+    fn cancelled_queries_stop() {
+        let _dir = ScratchDir::new("test_db_cancel");
+        let db = library("test_db_cancel");
+        let query = SelectQuery::parse_raw_query_against_db("select count(*) from books", &db).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(db.query_with_cancel(&query, cancel).unwrap().next(), Some(Err(QueryError::Cancelled))));
+    }
+
+    #[test]
+    fn listeners_hear_each_write_and_query() {
+        let _dir = ScratchDir::new("test_db_hooks");
+        let mut db = library("test_db_hooks");
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let inserts = Arc::clone(&log);
+        db.on_insert(move |table, row| inserts.lock().unwrap().push(format!("insert {} {}", table, row.id)));
+        let updates = Arc::clone(&log);
+        db.on_update(move |table, row| updates.lock().unwrap().push(format!("update {} {}", table, row.id)));
+        let deletes = Arc::clone(&log);
+        db.on_delete(move |table, row| deletes.lock().unwrap().push(format!("delete {} {} {}", table, row.id, row.value("stars").unwrap())));
+        let queries = Arc::clone(&log);
+        db.on_query(move |query| queries.lock().unwrap().push(format!("query {}", query.result_columns().len())));
 
-        out
+        let reviews = db.table_with_name("reviews").unwrap().clone();
+        let id = db.insert_values("reviews", &[("stars", Value::Int64(1))]).unwrap().last_insert_id.unwrap();
+        let review = WherePredicate::Condition(WhereCondition::new(reviews.column_for_name("id").unwrap(), "==", &id.to_string()).unwrap());
+        db.update_columns("reviews", &[("stars", "2")], &review).unwrap();
+        db.delete_where("reviews", &review).unwrap();
+        db.select("reviews").columns(&["stars"]).run().unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["insert reviews 3", "update reviews 3", "delete reviews 3 2", "query 1"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn listeners_hear_transactions_once_theyre_committed() {
+        let _dir = ScratchDir::new("test_db_tx_hooks");
+        let mut db = library("test_db_tx_hooks");
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let inserts = Arc::clone(&log);
+        db.on_insert(move |table, row| inserts.lock().unwrap().push(format!("insert {} {}", table, row.id)));
+        let deletes = Arc::clone(&log);
+        db.on_delete(move |table, row| deletes.lock().unwrap().push(format!("delete {} {}", table, row.id)));
+        let reviews = db.table_with_name("reviews").unwrap().clone();
+        let first = WherePredicate::Condition(WhereCondition::new(reviews.column_for_name("id").unwrap(), "==", "0").unwrap());
+
+        {
+            let mut tx = db.begin();
+            tx.insert_values("reviews", &[("stars", Value::Int64(1))]).unwrap();
+            tx.delete_where("reviews", &first).unwrap();
+            assert!(log.lock().unwrap().is_empty());
+        }
+        assert!(log.lock().unwrap().is_empty());
+        let rows = db.select("reviews").run().unwrap().rows;
+        assert_eq!(rows.iter().map(|row| row.get::<i32>("stars").unwrap()).collect::<Vec<_>>(), vec![3, 5, 4]);
+
+        let mut tx = db.begin();
+        tx.insert_values("reviews", &[("stars", Value::Int64(2))]).unwrap();
+        tx.delete_where("reviews", &first).unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        tx.commit();
+        assert_eq!(*log.lock().unwrap(), vec!["insert reviews 4", "delete reviews 0"]);
+        // writes outside a transaction are heard straight away again
+        db.insert_values("reviews", &[("stars", Value::Int64(3))]).unwrap();
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn analyzed_id_lookups_use_the_index() {
+        let _dir = ScratchDir::new("test_db_explain");
+        let mut db = library("test_db_explain");
+        db.analyze("books").unwrap();
+        let query = SelectQuery::parse_raw_query_against_db("select title from books where id == 2", &db).unwrap();
+        assert_eq!(db.explain(&query).unwrap().last().map(|line| line.trim()), Some("Scan books using id index (1 rows)"));
+    }
+
+    // a table file left as it was mid-insert, its header's id counter behind
+    // the rows, doesn't give out an id again when it's opened
+    #[cfg(feature = "file")]
+    #[test]
+    fn ids_arent_reused_after_a_torn_insert() {
+        let _dir = ScratchDir::new("test_db_torn_insert");
+        let shelves = || TableDescriptor::new("shelves", vec![("id", ColumnDataType::SerialId), ("label", ColumnDataType::Byte(16))]).unwrap();
+        let mut db = Database::new("test_db_torn_insert");
+        db.add_table(shelves()).unwrap();
+        db.insert_columns("shelves", &[("label", "a")]).unwrap();
+        db.close().unwrap();
+
+        let mut db = Database::open("test_db_torn_insert").unwrap();
+        db.attach_table(shelves()).unwrap();
+        assert_eq!(db.insert_columns("shelves", &[("label", "b")]).unwrap().last_insert_id, Some(1));
+        let shelves_path = table_path("test_db_torn_insert", "shelves");
+        let mid_insert = std::fs::read(&shelves_path).unwrap();
+        db.close().unwrap();
+        std::fs::write(&shelves_path, mid_insert).unwrap();
+
+        let mut db = Database::open("test_db_torn_insert").unwrap();
+        db.attach_table(shelves()).unwrap();
+        assert_eq!(db.insert_columns("shelves", &[("label", "c")]).unwrap().last_insert_id, Some(2));
+    }
+
+    #[test]
+    fn ids_are_found_after_the_columns_before_them_are_dropped() {
+        let _dir = ScratchDir::new("test_db_alter_id_offset");
+        let mut db = Database::new("test_db_alter_id_offset");
+        db.add_table(TableDescriptor::new("books", vec![("code", ColumnDataType::Int64), ("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int32)]).unwrap()).unwrap();
+        db.insert_rows("books", &[vec![("code", "7"), ("year", "1950")], vec![("code", "8"), ("year", "1960")], vec![("code", "9"), ("year", "1970")]]).unwrap();
+        db.alter_table("books", &[AlterTableOperation::DropColumn("code".to_owned())]).unwrap();
+
+        let books = db.table_with_name("books").unwrap().clone();
+        let id = |id: &str| WherePredicate::Condition(WhereCondition::new(books.column_for_name("id").unwrap(), "==", id).unwrap());
+        assert_eq!(db.update_columns("books", &[("year", "1961")], &id("1")).unwrap().rows_affected, 1);
+        assert_eq!(db.delete_where("books", &id("2")).unwrap().rows_affected, 1);
+        assert!(select(&db, "select year from books where id == 2").is_empty());
+        assert_eq!(select(&db, "select year from books where id == 1")[0].get::<i32>("year").unwrap(), 1961);
+        assert_eq!(db.insert_columns("books", &[("year", "1980")]).unwrap().last_insert_id, Some(3));
+        let rows = select(&db, "select id, year from books");
+        assert_eq!(rows.iter().map(|row| (row.get::<u64>("id").unwrap(), row.get::<i32>("year").unwrap())).collect::<Vec<_>>(), vec![(0, 1950), (1, 1961), (3, 1980)]);
+    }
+
+    /// Books with a deleted row in the middle.
+    fn shelf(db_name: &str) -> Database {
+        let mut db = Database::new(db_name);
+        db.add_table(TableDescriptor::new("books", vec![("code", ColumnDataType::Int64), ("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16)), ("year", ColumnDataType::Int32), ("stars", ColumnDataType::Int32)]).unwrap()).unwrap();
+        db.insert_rows("books", &[
+            vec![("code", "7"), ("title", "Frogs"), ("year", "1950"), ("stars", "3")],
+            vec![("code", "8"), ("title", "Bees"), ("year", "1960"), ("stars", "4")],
+            vec![("code", "9"), ("title", "Lumps"), ("year", "1970"), ("stars", "5")]
+        ]).unwrap();
+        let books = db.table_with_name("books").unwrap().clone();
+        db.delete_where("books", &WherePredicate::Condition(WhereCondition::new(books.column_for_name("id").unwrap(), "==", "1").unwrap())).unwrap();
+        db
+    }
+
+    /// The rows the index on `column_name` has for `value`, or `None` if
+    /// the column isn't indexed.
+    #[cfg(feature = "file")]
+    fn indexed(db: &Database, column_name: &str, value: &str) -> Option<Vec<u64>> {
+        let column = db.table_with_name("books").unwrap().column_for_name(column_name).unwrap();
+        let key = column.datatype.index_key(&column.datatype.parse_string(value).unwrap());
+        db.table_stores.get("books").unwrap().rows_in_key_range(column_name, (Bound::Included(key.clone()), Bound::Included(key)))
+    }
+
+    /// Every slot of the table's row data, deleted ones included.
+    fn slots(db: &Database, table_name: &str) -> Vec<Vec<u8>> {
+        let store = db.table_stores.get(table_name).unwrap();
+        let mut data = Vec::new();
+        store.get_reader().unwrap().read_to_end(&mut data).unwrap();
+        data.chunks(store.row_size()).map(|slot| slot.to_vec()).collect()
+    }
+
+    #[test]
+    fn altered_tables_convert_their_rows() {
+        let _dir = ScratchDir::new("test_db_alter");
+        let mut db = shelf("test_db_alter");
+        db.alter_table("books", &[
+            AlterTableOperation::AddColumn("pages".to_owned(), ColumnDataType::Int32),
+            AlterTableOperation::DropColumn("stars".to_owned()),
+            AlterTableOperation::RenameColumn("title".to_owned(), "name".to_owned()),
+            AlterTableOperation::AlterColumnType("year".to_owned(), ColumnDataType::Int64)
+        ]).unwrap();
+
+        assert_eq!(db.describe("books").unwrap().column_names().collect::<Vec<_>>(), vec!["code", "id", "name", "year", "pages"]);
+        let rows = select(&db, "select id, name, year, pages from books");
+        let rows = rows.iter().map(|row| (row.get::<u64>("id").unwrap(), row.get::<String>("name").unwrap(), row.value("year").cloned().unwrap(), row.get::<i32>("pages").unwrap())).collect::<Vec<_>>();
+        assert_eq!(rows, vec![(0, "Frogs".to_owned(), Value::Int64(1950), 0), (2, "Lumps".to_owned(), Value::Int64(1970), 0)]);
+        // the deleted row's slot is there, zeroed, so rows keep their positions
+        let slots = slots(&db, "books");
+        assert_eq!(slots.len(), 3);
+        assert!(slots[1].iter().all(|b| *b == 0));
+        assert!(db.select("books").columns(&["stars"]).run().is_err());
+    }
+
+    #[test]
+    fn failed_alters_leave_the_table_as_it_was() {
+        let _dir = ScratchDir::new("test_db_alter_failed");
+        let mut db = shelf("test_db_alter_failed");
+        db.checkpoint().unwrap();
+        #[cfg(feature = "file")]
+        let file = std::fs::read(table_path("test_db_alter_failed", "books")).unwrap();
+        let before = slots(&db, "books");
+
+        // titles aren't numbers
+        let e = db.alter_table("books", &[AlterTableOperation::AlterColumnType("title".to_owned(), ColumnDataType::Int32)]).unwrap_err();
+        assert!(matches!(e, KronkError::Schema(_)), "{:?}", e);
+        #[cfg(feature = "file")]
+        assert_eq!(std::fs::read(table_path("test_db_alter_failed", "books")).unwrap(), file);
+        assert_eq!(slots(&db, "books"), before);
+        assert_eq!(db.describe("books").unwrap().column_names().count(), 5);
+        assert_eq!(titles(&select(&db, "select title from books")), vec!["Frogs", "Lumps"]);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn indexes_answer_for_the_altered_table() {
+        let _dir = ScratchDir::new("test_db_alter_indexes");
+        let mut db = shelf("test_db_alter_indexes");
+        db.create_index("books", "year", IndexKind::BTree).unwrap();
+        db.create_index("books", "title", IndexKind::Hash).unwrap();
+        db.create_index("books", "stars", IndexKind::BTree).unwrap();
+        db.alter_table("books", &[
+            AlterTableOperation::DropColumn("code".to_owned()),
+            AlterTableOperation::AlterColumnType("title".to_owned(), ColumnDataType::Byte(32)),
+            AlterTableOperation::DropColumn("stars".to_owned())
+        ]).unwrap();
+
+        assert_eq!(indexed(&db, "year", "1970"), Some(vec![2]));
+        assert_eq!(indexed(&db, "title", "Lumps"), None);
+        assert_eq!(db.table_stores.get("books").unwrap().rows_in_key_range("stars", (Bound::Unbounded, Bound::Unbounded)), None);
+        assert_eq!(db.table_stores.get("books").unwrap().rows_in_id_range(0..=2), Some(vec![0, 2]));
+        assert_eq!(titles(&select(&db, "select title from books where year == 1970")), vec!["Lumps"]);
+        assert_eq!(titles(&select(&db, "select title from books where year > 1940")), vec!["Frogs", "Lumps"]);
+        assert_eq!(titles(&select(&db, "select title from books where title == \"Lumps\"")), vec!["Lumps"]);
+        assert_eq!(titles(&select(&db, "select title from books where id == 2")), vec!["Lumps"]);
+        assert!(select(&db, "select title from books where id == 1").is_empty());
+
+        // and keep up with writes made after it
+        db.insert_columns("books", &[("title", "Moths"), ("year", "1970")]).unwrap();
+        assert_eq!(titles(&select(&db, "select title from books where year == 1970")), vec!["Lumps", "Moths"]);
+        assert_eq!(titles(&select(&db, "select title from books where id == 3")), vec!["Moths"]);
+        assert_eq!(indexed(&db, "year", "1970"), Some(vec![2, 3]));
+        // a new index on a column whose old one was dropped starts from the rows as they are
+        db.create_index("books", "title", IndexKind::Hash).unwrap();
+        assert_eq!(indexed(&db, "title", "Moths"), Some(vec![3]));
+    }
+
+    /// Authors, their books, which cascade, and loans of the books, which
+    /// restrict.
+    fn lending(db_name: &str) -> Database {
+        let mut db = Database::new(db_name);
+        db.add_table(TableDescriptor::new("authors", vec![("id", ColumnDataType::SerialId), ("name", ColumnDataType::Byte(16))]).unwrap()).unwrap();
+        db.add_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("author_id", ColumnDataType::UInt64), ("title", ColumnDataType::Byte(16))]).unwrap()
+            .with_constraint(TableConstraint::ForeignKey { column: "author_id".to_owned(), references: "authors".to_owned(), on_delete: OnDelete::Cascade }).unwrap()).unwrap();
+        db.add_table(TableDescriptor::new("loans", vec![("id", ColumnDataType::SerialId), ("book_id", ColumnDataType::UInt64)]).unwrap()
+            .with_constraint(TableConstraint::ForeignKey { column: "book_id".to_owned(), references: "books".to_owned(), on_delete: OnDelete::Restrict }).unwrap()).unwrap();
+        db.insert_rows("authors", &[vec![("name", "Billy")], vec![("name", "Stink")]]).unwrap();
+        db.insert_rows("books", &[vec![("author_id", "0"), ("title", "Bees")], vec![("author_id", "1"), ("title", "Frogs")], vec![("author_id", "1"), ("title", "Dancing")]]).unwrap();
+        db
+    }
+
+    fn id_is(table: &TableDescriptor, id: u64) -> WherePredicate<'_> {
+        WherePredicate::Condition(WhereCondition::new(table.column_for_name("id").unwrap(), "==", &id.to_string()).unwrap())
+    }
+
+    #[test]
+    fn foreign_keys_must_reference_a_row() {
+        let _dir = ScratchDir::new("test_db_fk_insert");
+        let mut db = lending("test_db_fk_insert");
+        let e = db.insert_columns("books", &[("author_id", "7"), ("title", "Moths")]).unwrap_err();
+        assert!(matches!(e, KronkError::Constraint(_)), "{:?}", e);
+        assert!(db.insert_rows("loans", &[vec![("book_id", "0")], vec![("book_id", "3")]]).is_err());
+        assert!(select(&db, "select id from loans").is_empty());
+        db.insert_columns("loans", &[("book_id", "2")]).unwrap();
+    }
+
+    #[test]
+    fn deletes_cascade_unless_restricted() {
+        let _dir = ScratchDir::new("test_db_fk_delete");
+        let mut db = lending("test_db_fk_delete");
+        db.insert_columns("loans", &[("book_id", "2")]).unwrap();
+
+        let authors = db.table_with_name("authors").unwrap().clone();
+        let loans = db.table_with_name("loans").unwrap().clone();
+
+        // Stink's books cascade, but one of them is lent out
+        let stink = id_is(&authors, 1);
+        let e = db.delete_where("authors", &stink).unwrap_err();
+        assert!(matches!(e, KronkError::Constraint(_)), "{:?}", e);
+        assert_eq!(select(&db, "select id from authors").len(), 2);
+        assert_eq!(titles(&select(&db, "select title from books")), vec!["Bees", "Frogs", "Dancing"]);
+
+        // a restricting row is fine if the same delete removes it
+        let billy = id_is(&authors, 0);
+        assert_eq!(db.delete_where("authors", &billy).unwrap().rows_affected, 1);
+        assert_eq!(titles(&select(&db, "select title from books")), vec!["Frogs", "Dancing"]);
+
+        let loan = id_is(&loans, 0);
+        assert_eq!(db.delete_where("loans", &loan).unwrap().rows_affected, 1);
+        db.delete_where("authors", &stink).unwrap();
+        assert!(select(&db, "select title from books").is_empty());
+        assert_eq!(db.delete_where("authors", &stink).unwrap().rows_affected, 0);
+    }
+
+    #[test]
+    fn referenced_tables_cant_be_dropped() {
+        let _dir = ScratchDir::new("test_db_drop_referenced");
+        let mut db = lending("test_db_drop_referenced");
+        for table_name in ["authors", "books"] {
+            let e = db.drop_table(table_name, false).unwrap_err();
+            assert!(matches!(e, KronkError::Schema(_)), "{:?}", e);
+            assert!(db.describe(table_name).is_ok());
+        }
+        assert_eq!(titles(&select(&db, "select title from books where author_id == 0")), vec!["Bees"]);
+
+        db.drop_table("loans", false).unwrap();
+        db.drop_table("books", false).unwrap();
+        db.drop_table("authors", false).unwrap();
+        assert_eq!(db.tables().count(), 0);
+        assert!(db.drop_table("authors", false).is_err());
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn dropped_tables_release_or_archive_their_files() {
+        let _dir = ScratchDir::new("test_db_drop");
+        let mut db = lending("test_db_drop");
+        db.drop_table("loans", false).unwrap();
+        assert!(!table_path("test_db_drop", "loans").exists());
+        db.drop_table("books", true).unwrap();
+        assert!(!table_path("test_db_drop", "books").exists());
+        let archived = std::fs::read_dir(database_dir("test_db_drop").join("archive")).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(archived.len(), 1);
+        assert!(archived[0].starts_with("books"), "{:?}", archived);
+
+        // the name is free again, for a table that starts out empty
+        db.add_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap()).unwrap();
+        assert!(select(&db, "select title from books").is_empty());
+    }
+
+    #[test]
+    fn composite_primary_keys_are_unique() {
+        let _dir = ScratchDir::new("test_db_composite_key");
+        let mut db = Database::new("test_db_composite_key");
+        let places = TableDescriptor::new("places", vec![("shelf", ColumnDataType::Int32), ("slot", ColumnDataType::Int32), ("title", ColumnDataType::Byte(16))]).unwrap()
+            .with_primary_key(&["shelf", "slot"]).unwrap();
+        assert!(places.id_column().is_none());
+        db.add_table(places).unwrap();
+        db.insert_rows("places", &[vec![("shelf", "1"), ("slot", "1"), ("title", "Bees")], vec![("shelf", "1"), ("slot", "2"), ("title", "Frogs")], vec![("shelf", "2"), ("slot", "1"), ("title", "Lumps")]]).unwrap();
+
+        let e = db.insert_columns("places", &[("shelf", "2"), ("slot", "1"), ("title", "Moths")]).unwrap_err();
+        assert!(matches!(e, KronkError::Constraint(_)), "{:?}", e);
+        // keys are checked within a batch too, and a failed batch inserts nothing
+        assert!(db.insert_rows("places", &[vec![("shelf", "3"), ("slot", "1")], vec![("shelf", "3"), ("slot", "1")]]).is_err());
+        let places = db.table_with_name("places").unwrap().clone();
+        let frogs = WherePredicate::Condition(WhereCondition::new(places.column_for_name("title").unwrap(), "==", "Frogs").unwrap());
+        assert!(db.update_columns("places", &[("slot", "1")], &frogs).is_err());
+        assert_eq!(db.update_columns("places", &[("slot", "3")], &frogs).unwrap().rows_affected, 1);
+        assert_eq!(titles(&select(&db, "select title from places where shelf == 1 and slot == 3")), vec!["Frogs"]);
+        assert_eq!(select(&db, "select title from places").len(), 3);
+
+        assert!(TableDescriptor::new("places", vec![("shelf", ColumnDataType::Int32)]).unwrap().with_primary_key(&["slot"]).is_err());
+        let e = db.add_table(TableDescriptor::new("keyless", vec![("shelf", ColumnDataType::Int32)]).unwrap()).unwrap_err();
+        assert!(matches!(e, KronkError::Schema(_)), "{:?}", e);
+    }
+
+    #[test]
+    fn tables_can_override_the_default_settings() {
+        let _dir = ScratchDir::new("test_db_overrides");
+        let mut db = Database::new("test_db_overrides");
+        let overrides = TableOverrides { page_size: Some(1024), cache_priority: Some(CachePriority::High), ..TableOverrides::default() };
+        db.add_table(TableDescriptor::new("hot", vec![("id", ColumnDataType::SerialId)]).unwrap().with_overrides(overrides).unwrap()).unwrap();
+        db.add_table(TableDescriptor::new("cold", vec![("id", ColumnDataType::SerialId)]).unwrap()).unwrap();
+        db.set_default_settings(StorageSettings { durability: Durability::EveryWrite, ..StorageSettings::default() });
+
+        let hot = db.settings_for("hot").unwrap();
+        assert_eq!((hot.page_size, hot.cache_priority, hot.durability), (1024, CachePriority::High, Durability::EveryWrite));
+        let cold = db.settings_for("cold").unwrap();
+        assert_eq!(cold, StorageSettings { durability: Durability::EveryWrite, ..StorageSettings::default() });
+        assert!(db.settings_for("lukewarm").is_none());
+
+        for overrides in [TableOverrides { page_size: Some(1000), ..TableOverrides::default() }, TableOverrides { page_size: Some(256), ..TableOverrides::default() }, TableOverrides { segment_size: Some(0), ..TableOverrides::default() }] {
+            let e = TableDescriptor::new("bad", vec![("id", ColumnDataType::SerialId)]).unwrap().with_overrides(overrides).unwrap_err();
+            assert!(matches!(e, KronkError::Schema(_)), "{:?}", e);
+        }
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn databases_can_only_be_open_once() {
+        let _dir = ScratchDir::new("test_db_open_twice");
+        let db = Database::open("test_db_open_twice").unwrap();
+        let e = Database::open("test_db_open_twice").err().unwrap();
+        assert!(matches!(&e, KronkError::Storage(message) if message.contains("already open")), "{:?}", e);
+        db.close().unwrap();
+        Database::open("test_db_open_twice").unwrap();
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn reopened_tables_must_match_their_stored_layout() {
+        let _dir = ScratchDir::new("test_db_header");
+        let db = books("test_db_header");
+        db.close().unwrap();
+
+        let mut db = Database::new("test_db_header");
+        let mismatched = |e: KronkError| matches!(e, KronkError::StorageIo { source, .. } if source.kind() == std::io::ErrorKind::InvalidData);
+        assert!(mismatched(db.attach_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int64)]).unwrap()).unwrap_err()));
+        assert!(mismatched(db.add_table(TableDescriptor::new("books", vec![("year", ColumnDataType::Int32), ("id", ColumnDataType::SerialId)]).unwrap()).unwrap_err()));
+        assert!(db.describe("books").is_err());
+        assert!(db.attach_table(TableDescriptor::new("pages", vec![("id", ColumnDataType::SerialId)]).unwrap()).is_err());
+
+        // with the same layout, the rows and the id counter carry on
+        db.attach_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int32)]).unwrap()).unwrap();
+        assert_eq!(db.insert_columns("books", &[("year", "1970")]).unwrap().last_insert_id, Some(2));
+        let rows = select(&db, "select year from books");
+        assert_eq!(rows.iter().map(|row| row.get::<i32>("year").unwrap()).collect::<Vec<_>>(), vec![1950, 1960, 1970]);
+    }
+
+    #[test]
+    fn table_stats_count_live_and_dead_rows() {
+        let _dir = ScratchDir::new("test_db_stats");
+        let mut db = library("test_db_stats");
+        let reviews = db.table_with_name("reviews").unwrap().clone();
+        let review = id_is(&reviews, 1);
+        db.delete_where("reviews", &review).unwrap();
+
+        let stats = db.table_stats("Reviews").unwrap();
+        assert_eq!((stats.table_name.as_str(), stats.row_count, stats.live_rows, stats.dead_rows), ("reviews", 3, 2, 1));
+        assert!(db.table_stats("loans").is_err());
+
+        let rows = select(&db, "select table_name, live_rows, dead_rows from kronk_stats");
+        let rows = rows.iter().map(|row| (row.get::<String>("table_name").unwrap(), row.get::<u64>("live_rows").unwrap(), row.get::<u64>("dead_rows").unwrap())).collect::<Vec<_>>();
+        assert_eq!(rows, vec![("books".to_owned(), 4, 0), ("reviews".to_owned(), 2, 1)]);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn table_stats_count_file_and_index_bytes() {
+        let _dir = ScratchDir::new("test_db_stats_bytes");
+        let mut db = library("test_db_stats_bytes");
+        db.checkpoint().unwrap();
+        let before = db.table_stats("books").unwrap();
+        assert!(before.file_bytes >= 4 * db.describe("books").unwrap().total_row_size() as u64);
+        db.create_index("books", "year_published", IndexKind::BTree).unwrap();
+        let after = db.table_stats("books").unwrap();
+        assert!(after.index_bytes > before.index_bytes, "{:?} {:?}", before, after);
+        assert_eq!(after.file_bytes, before.file_bytes);
+    }
+
+    /// Overwrites the first copy of `from` in the table's file with `to`,
+    /// behind the database's back.
+    #[cfg(feature = "file")]
+    fn corrupt(db_name: &str, table_name: &str, from: &[u8], to: &[u8]) {
+        let path = table_path(db_name, table_name);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let at = bytes.windows(from.len()).position(|w| w == from).unwrap();
+        bytes[at..(at + to.len())].copy_from_slice(to);
+        std::fs::write(&path, bytes).unwrap();
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn corrupt_rows_are_handled_by_the_tables_policy() {
+        use crate::table::schema::config::CorruptionPolicy;
+        let _dir = ScratchDir::new("test_db_corruption");
+        let titles_table = |on_corruption| TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap()
+            .with_overrides(TableOverrides { on_corruption: Some(on_corruption), ..TableOverrides::default() }).unwrap();
+        let mut db = Database::new("test_db_corruption");
+        db.add_table(titles_table(CorruptionPolicy::Error)).unwrap();
+        db.insert_rows("books", &[vec![("title", "Bees")], vec![("title", "Frogs")], vec![("title", "Lumps")]]).unwrap();
+        db.close().unwrap();
+        corrupt("test_db_corruption", "books", b"Frogs", b"Frogz");
+
+        // the log was checkpointed on close, so there's nothing to repair from
+        for on_corruption in [CorruptionPolicy::Error, CorruptionPolicy::Repair] {
+            let mut db = Database::new("test_db_corruption");
+            db.add_table(titles_table(on_corruption)).unwrap();
+            let query = SelectQuery::parse_raw_query_against_db("select title from books", &db).unwrap();
+            let e = db.query(&query).unwrap().collect::<Result<Vec<_>, _>>().unwrap_err();
+            assert!(e.to_string().contains("failed its checksum"), "{}", e);
+        }
+
+        let mut db = Database::new("test_db_corruption");
+        db.add_table(titles_table(CorruptionPolicy::Skip)).unwrap();
+        assert_eq!(titles(&select(&db, "select title from books")), vec!["Bees", "Lumps"]);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn corrupt_rows_are_repaired_from_the_log() {
+        use crate::table::schema::config::CorruptionPolicy;
+        let _dir = ScratchDir::new("test_db_repair");
+        let mut db = Database::new("test_db_repair");
+        // nothing is cached, so reads see the file as it is
+        db.set_buffer_pool_memory_cap(0);
+        db.add_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap()
+            .with_overrides(TableOverrides { on_corruption: Some(CorruptionPolicy::Repair), ..TableOverrides::default() }).unwrap()).unwrap();
+        db.insert_rows("books", &[vec![("title", "Bees")], vec![("title", "Frogs")]]).unwrap();
+        db.table_stores.get("books").unwrap().sync().unwrap();
+        corrupt("test_db_repair", "books", b"Frogs", b"Frogz");
+
+        assert_eq!(titles(&select(&db, "select title from books")), vec!["Bees", "Frogs"]);
+        // and the repaired row was written back
+        let file = std::fs::read(table_path("test_db_repair", "books")).unwrap();
+        assert!(file.windows(5).any(|w| w == b"Frogs"));
+    }
+}
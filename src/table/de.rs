@@ -0,0 +1,107 @@
+//! Reading query results into serde types, with the `serde` feature.
+
+use serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, MapAccess, Visitor};
+use thiserror::Error;
+
+use super::{error::KronkError, value::{ResultSet, Row, Value}};
+
+impl ResultSet {
+    /// Reads each row into a `T`, matching the row's columns to `T`'s fields
+    /// by name, e.g. a `#[derive(Deserialize)]` struct. Columns `T` has no
+    /// field for are skipped; a field with no column fails unless it's an
+    /// `Option` or has a default. Integer columns read into any integer
+    /// field they fit in, and null values into `Option` fields as `None`.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<Vec<T>, KronkError> {
+        self.rows.iter()
+            .map(|row| T::deserialize(RowDeserializer { row }).map_err(|e| KronkError::Schema(format!("Could not read row {}: {}", row.id, e))))
+            .collect()
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct DeserializeError(String);
+
+impl de::Error for DeserializeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> DeserializeError {
+        DeserializeError(msg.to_string())
+    }
+}
+
+struct RowDeserializer<'a> {
+    row: &'a Row
+}
+
+impl<'de, 'a> Deserializer<'de> for RowDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_map(ColumnAccess { columns: self.row.columns.iter(), value: None })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Hands out a row's columns as a map of name to value.
+struct ColumnAccess<'a> {
+    columns: std::slice::Iter<'a, (String, Value)>,
+    value: Option<&'a Value>
+}
+
+impl<'de, 'a> MapAccess<'de> for ColumnAccess<'a> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DeserializeError> {
+        match self.columns.next() {
+            Some((name, value)) => {
+                self.value = Some(value);
+                seed.deserialize(name.as_str().into_deserializer()).map(Some)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeserializeError> {
+        let value = self.value.take().expect("next_value_seed is only called after next_key_seed");
+        seed.deserialize(ValueDeserializer { value })
+    }
+}
+
+struct ValueDeserializer<'a> {
+    value: &'a Value
+}
+
+impl<'de, 'a> Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match self.value {
+            Value::Int32(i) => visitor.visit_i32(*i),
+            Value::UInt32(u) => visitor.visit_u32(*u),
+            Value::Int64(i) => visitor.visit_i64(*i),
+            Value::UInt64(u) => visitor.visit_u64(*u),
+            Value::Float64(x) => visitor.visit_f64(*x),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Uuid(u) => visitor.visit_string(u.to_string()),
+            Value::Str(s) => visitor.visit_string(s.clone()),
+            Value::Null => visitor.visit_none()
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
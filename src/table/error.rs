@@ -0,0 +1,89 @@
+use thiserror::Error;
+
+use super::{exec::QueryError, lock::LockError, query::types::{LexingError, ParsingError}};
+
+/// Why a database, schema or store call failed.
+#[derive(Debug, Error)]
+pub enum KronkError {
+    /// A table, column or value doesn't fit the database's schema, e.g. a
+    /// table that doesn't exist or a value that doesn't parse as its
+    /// column's type.
+    #[error("{0}")]
+    Schema(String),
+
+    /// A table's storage couldn't be read or written, or doesn't support
+    /// what was asked of it.
+    #[error("{0}")]
+    Storage(String),
+
+    /// Reading or writing a table's files failed partway through doing
+    /// `context`.
+    #[error("{context}: {source}")]
+    StorageIo { context: String, source: std::io::Error },
+
+    /// A query or command couldn't be bound to the database's tables, e.g.
+    /// a column that doesn't exist, or a value that doesn't fit where it's
+    /// compared.
+    #[error("{0}")]
+    Parse(String),
+
+    /// A query or command isn't valid syntax.
+    #[error(transparent)]
+    Syntax(#[from] ParsingError),
+
+    /// A stored string isn't valid utf-8, e.g. in a corrupted table file.
+    #[error("stored string isn't valid utf-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+
+    /// A write would break a unique or foreign key constraint.
+    #[error("{0}")]
+    Constraint(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Query(#[from] QueryError),
+
+    #[error(transparent)]
+    Lock(#[from] LockError),
+
+    /// A query was turned away because as many queries as are let wait
+    /// already are.
+    #[error("Query rejected: {queued} queries are already queued")]
+    Overloaded { queued: usize }
+}
+
+impl KronkError {
+    /// A `StorageIo` error, for `map_err` on a store's I/O calls.
+    pub fn storage_io(context: impl Into<String>, source: std::io::Error) -> KronkError {
+        KronkError::StorageIo { context: context.into(), source }
+    }
+}
+
+impl From<LexingError> for KronkError {
+    fn from(e: LexingError) -> KronkError {
+        KronkError::Syntax(e.into())
+    }
+}
+
+impl From<KronkError> for String {
+    fn from(e: KronkError) -> String {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::KronkError;
+
+    #[test]
+    fn storage_io_keeps_its_source() {
+        let e = KronkError::storage_io("failed reading table file", std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(e.to_string().starts_with("failed reading table file: "));
+        let source = e.source().and_then(|s| s.downcast_ref::<std::io::Error>()).unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
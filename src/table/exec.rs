@@ -0,0 +1,489 @@
+use std::{borrow::Cow, collections::HashMap, io::Read, iter::FusedIterator, ops::Range, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+use itertools::Itertools;
+use thiserror::Error;
+
+use super::{error::KronkError, schema::{DatabaseDescriptor, TableDescriptor, TableColumn}, value::{Row, Value}, sort::{SortedRows, DEFAULT_SORT_MEMORY}, aggregate::Aggregates, group::{GroupedRows, DEFAULT_GROUP_MEMORY}, join::JoinedRows, plan::{PlanNode, AccessPath}, store::{ByteStore, checksum::RowCheck, version::{RowVersions, RowVisibility}}, query::{SelectQuery, SelectColumn, SelectExpression, WherePredicate, types::RawSelectQuery}};
+
+pub type TableStores = HashMap<String, Box<dyn ByteStore>>;
+
+type TableRows<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<u8>), QueryError>> + 'a>;
+
+/// The query engine behind a `Database`. Binding resolves a raw query against
+/// the catalog, and execution runs the bound query over the table stores.
+/// Alternative engines implement this trait and are handed to
+/// `Database::with_executor`.
+pub trait Executor: Send + Sync {
+    fn bind<'a>(&self, query: &RawSelectQuery, catalog: &'a DatabaseDescriptor) -> Result<SelectQuery<'a>, KronkError> {
+        SelectQuery::parse_query_against_db(query, catalog).map_err(KronkError::Parse)
+    }
+
+    fn execute<'a>(&self, query: &'a SelectQuery, stores: &'a TableStores) -> RowIterator<'a>;
+
+    /// Like `execute`, stopping with `QueryError::Cancelled` once `cancel`
+    /// is cancelled or its deadline passes. By default that's only checked
+    /// between result rows, so engines that read many rows per result row
+    /// should check as they read.
+    fn execute_cancellable<'a>(&self, query: &'a SelectQuery, stores: &'a TableStores, cancel: CancelToken) -> RowIterator<'a> {
+        let rows = self.execute(query, stores);
+        RowIterator::new(rows.map(move |row| {
+            cancel.check()?;
+            row
+        }))
+    }
+
+    /// How `execute` would run the query, a line of text per step.
+    fn explain(&self, query: &SelectQuery, stores: &TableStores) -> Vec<String> {
+        PlanNode::for_query(query, stores).explain()
+    }
+}
+
+/// Why a query couldn't be run, or stopped partway through its rows.
+#[derive(Debug, Error)]
+pub enum QueryError {
+    #[error("failed reading table '{table_name}': {source}")]
+    Io { table_name: String, source: std::io::Error },
+
+    /// The table's storage ends partway through a row, e.g. after a crash
+    /// while the row was being written or a copy that was cut short.
+    #[error("table '{table_name}' is truncated: its data ends partway through row {row_number}")]
+    TruncatedTable { table_name: String, row_number: u64 },
+
+    #[error("failed decoding column '{column_name}': {source}")]
+    Decode { column_name: String, source: Box<KronkError> },
+
+    #[error("failed spilling rows to disk: {0}")]
+    Spill(std::io::Error),
+
+    #[error("sum of column '{column_name}' doesn't fit in its result type")]
+    Overflow { column_name: String },
+
+    #[error("query was cancelled")]
+    Cancelled
+}
+
+/// Rows a scan reads between checks of its `CancelToken`.
+const CANCEL_CHECK_ROWS: u64 = 1024;
+
+/// Lets a running query be stopped from another thread, or once a deadline
+/// passes. Clones share their cancelled flag, so one can be kept to cancel
+/// the query with while another is handed to it.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> CancelToken {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> CancelToken {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    pub fn check(&self) -> Result<(), QueryError> {
+        match self.is_cancelled() {
+            true => Err(QueryError::Cancelled),
+            false => Ok(())
+        }
+    }
+}
+
+/// A query's result rows. Rows are read from the table as the iterator is
+/// advanced, so a large result never has to fit in memory at once. A row
+/// that can't be read comes back as an error, and the rows after it aren't
+/// read: the iterator is done after an error, as it is after the last row.
+pub struct RowIterator<'a> {
+    rows: Box<dyn Iterator<Item = Result<Row, QueryError>> + 'a>
+}
+
+impl<'a> RowIterator<'a> {
+    pub fn new(rows: impl Iterator<Item = Result<Row, QueryError>> + 'a) -> RowIterator<'a> {
+        // stop at the first error rather than carrying on past a bad row
+        RowIterator { rows: Box::new(rows.scan(false, |failed, row| match *failed {
+            true => None,
+            false => {
+                *failed = row.is_err();
+                Some(row)
+            }
+        }).fuse()) }
+    }
+}
+
+impl Iterator for RowIterator<'_> {
+    type Item = Result<Row, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl FusedIterator for RowIterator<'_> {}
+
+/// A query's result rows handed out a page at a time, for clients that
+/// can't hold the whole result. Only a page's rows are read ahead. A cursor
+/// that's been dropped can be picked up again at its `position` with
+/// `Database::resume_cursor`.
+pub struct Cursor<'a> {
+    rows: RowIterator<'a>,
+    batch_size: usize,
+    position: u64
+}
+
+impl<'a> Cursor<'a> {
+    /// Pages of up to `batch_size` rows, the first holding the row at
+    /// `position`.
+    pub fn new(rows: RowIterator<'a>, batch_size: usize, position: u64) -> Cursor<'a> {
+        Cursor { rows, batch_size: batch_size.max(1), position }
+    }
+
+    /// Rows handed out so far, those before the cursor was resumed included.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The next page, or `None` once every row was handed out. A page is
+    /// only short at the end of the result.
+    pub fn next_page(&mut self) -> Result<Option<Vec<Row>>, QueryError> {
+        let page = self.rows.by_ref().take(self.batch_size).collect::<Result<Vec<_>, _>>()?;
+        self.position += page.len() as u64;
+        Ok((!page.is_empty()).then_some(page))
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<Vec<Row>, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_page().transpose()
+    }
+}
+
+/// Row-at-a-time engine that walks the query's `PlanNode` tree: scans the
+/// table, or just the rows an index points at when the where clause
+/// compares the serial id or an indexed column, and filters each row.
+/// Ordered results are sorted within `sort_memory` bytes, and grouped
+/// results grouped within `group_memory` bytes, spilling to temporary files
+/// past that.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultExecutor {
+    pub sort_memory: usize,
+    pub group_memory: usize
+}
+
+impl Default for DefaultExecutor {
+    fn default() -> Self {
+        DefaultExecutor { sort_memory: DEFAULT_SORT_MEMORY, group_memory: DEFAULT_GROUP_MEMORY }
+    }
+}
+
+impl DefaultExecutor {
+    pub fn with_sort_memory(mut self, sort_memory: usize) -> DefaultExecutor {
+        self.sort_memory = sort_memory;
+        self
+    }
+
+    pub fn with_group_memory(mut self, group_memory: usize) -> DefaultExecutor {
+        self.group_memory = group_memory;
+        self
+    }
+
+    /// Evaluates the projection for one row of a query without aggregates.
+    /// Joined columns sit after the first table's row, `left_row_size` bytes in.
+    fn project_row(columns: &[SelectColumn], left_row_size: usize, id: u64, bytes: &[u8]) -> Result<Row, QueryError> {
+        let columns = columns.iter()
+            .map(|c| Ok((c.name.to_owned(), match &c.expression {
+                SelectExpression::Column(tc) => Value::from_bytes(&tc.datatype, &bytes[tc.offset..])
+                    .map_err(|e| QueryError::Decode { column_name: tc.name.to_owned(), source: Box::new(e) })?,
+                SelectExpression::JoinedColumn(tc) => Value::from_bytes(&tc.datatype, &bytes[(left_row_size + tc.offset)..])
+                    .map_err(|e| QueryError::Decode { column_name: tc.name.to_owned(), source: Box::new(e) })?,
+                SelectExpression::Constant(v) => Value::from_literal(v),
+                SelectExpression::CountAll | SelectExpression::Aggregate(..) => unreachable!("aggregates are folded by Aggregates")
+            })))
+            .collect::<Result<Vec<_>, QueryError>>()?;
+        Ok(Row { id, columns })
+    }
+
+    /// Runs a plan node that passes up table rows, as row numbers and bytes.
+    fn table_rows_for<'a>(&self, node: PlanNode<'a>, stores: &'a TableStores, cancel: &CancelToken) -> TableRows<'a> {
+        let store = |table: &TableDescriptor| stores.get(&table.table_name).expect("backing store here shold be populated").as_ref();
+        match node {
+            PlanNode::Empty => Box::new(std::iter::once(Ok((0, Vec::new())))),
+            PlanNode::Scan { table, access, columns, snapshot } => Self::table_rows(table, store(table), access, None, &columns, snapshot, cancel.clone()),
+            PlanNode::Filter { input, predicate } => match *input {
+                // checked as the scan reads them, so rows that don't pass
+                // are never copied out
+                PlanNode::Scan { table, access, columns, snapshot } => Self::table_rows(table, store(table), access, Some(predicate), &columns, snapshot, cancel.clone()),
+                input => Box::new(self.table_rows_for(input, stores, cancel).filter(move |row| row.as_ref().map_or(true, |(_, bytes)| predicate.is_true(bytes))))
+            },
+            PlanNode::Sort { input, order_by } => {
+                let row_size = input.row_size();
+                Box::new(SortedRows::new(self.table_rows_for(*input, stores, cancel), order_by, row_size, self.sort_memory))
+            },
+            PlanNode::Join { left, right, join, strategy } => {
+                let executor = *self;
+                let right = *right;
+                let right_cancel = cancel.clone();
+                let joined_rows = Box::new(move || executor.table_rows_for(right.clone(), stores, &right_cancel));
+                Box::new(JoinedRows::new(self.table_rows_for(*left, stores, cancel), joined_rows, join, strategy))
+            },
+            PlanNode::Aggregate { .. } | PlanNode::Project { .. } | PlanNode::Limit { .. } => unreachable!("result rows are only made at the top of a plan")
+        }
+    }
+
+    /// Runs a plan node that passes up result rows.
+    fn result_rows<'a>(&self, node: PlanNode<'a>, stores: &'a TableStores, cancel: &CancelToken) -> Box<dyn Iterator<Item = Result<Row, QueryError>> + 'a> {
+        match node {
+            PlanNode::Limit { input, limit } => Box::new(self.result_rows(*input, stores, cancel).take(usize::try_from(limit).unwrap_or(usize::MAX))),
+            PlanNode::Aggregate { input, columns, group_by } if !group_by.is_empty() => {
+                let row_size = input.row_size();
+                Box::new(GroupedRows::new(self.table_rows_for(*input, stores, cancel), columns, group_by, row_size, self.group_memory))
+            },
+            PlanNode::Aggregate { input, columns, .. } => {
+                let rows = self.table_rows_for(*input, stores, cancel);
+                Box::new(std::iter::once_with(move || {
+                    let mut aggregates = Aggregates::new(columns);
+                    for row in rows {
+                        aggregates.update(&row?.1)?;
+                    }
+                    Ok(Row { id: 0, columns: aggregates.finish()? })
+                }))
+            },
+            // rows are only read as they're asked for, so without an
+            // ordering the scan stops once the limit is reached
+            PlanNode::Project { input, table, columns } => {
+                let left_row_size = table.map_or(0, |t| t.total_row_size());
+                Box::new(self.table_rows_for(*input, stores, cancel).map(move |row| {
+                    let (row_number, bytes) = row?;
+                    let id = table.map_or(0, |t| t.row_id(&bytes, row_number));
+                    Self::project_row(columns, left_row_size, id, &bytes)
+                }))
+            },
+            node => unreachable!("{:?} doesn't make result rows", node)
+        }
+    }
+
+    /// A table's rows that pass `predicate`, in the order a scan reads them,
+    /// as of `snapshot` if there is one. Only the bytes of `columns` are sure
+    /// to be filled in; scans of mapped tables leave the rest of each row
+    /// zeroed.
+    fn table_rows<'a>(table: &'a TableDescriptor, backing_store: &'a dyn ByteStore, access: AccessPath, predicate: Option<&'a WherePredicate<'a>>, columns: &[&TableColumn], snapshot: Option<u64>, cancel: CancelToken) -> TableRows<'a> {
+        let mut scan = ScanRows::new(table.table_name.as_str(), backing_store, predicate, column_ranges(columns), cancel);
+        if let Some(snapshot) = snapshot {
+            scan = scan.with_snapshot(snapshot);
+        }
+        if let Some(mut row_numbers) = access.into_rows() {
+            // the index has rows' current values, so rows the snapshot sees
+            // an earlier version of are checked too
+            if let Some((_, versions)) = scan.snapshot {
+                row_numbers.extend(versions.replaced_rows());
+            }
+            // keep the order a scan would produce
+            row_numbers.sort_unstable();
+            row_numbers.dedup();
+            scan = scan.with_row_numbers(row_numbers);
+        }
+        Box::new(scan)
+    }
+}
+
+impl Executor for DefaultExecutor {
+    fn execute<'a>(&self, query: &'a SelectQuery, stores: &'a TableStores) -> RowIterator<'a> {
+        self.execute_cancellable(query, stores, CancelToken::new())
+    }
+
+    fn execute_cancellable<'a>(&self, query: &'a SelectQuery, stores: &'a TableStores, cancel: CancelToken) -> RowIterator<'a> {
+        RowIterator::new(self.result_rows(PlanNode::for_query(query, stores), stores, &cancel))
+    }
+}
+
+fn read_error(table_name: &str, row_number: u64, e: std::io::Error) -> QueryError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => QueryError::TruncatedTable { table_name: table_name.to_owned(), row_number },
+        _ => QueryError::Io { table_name: table_name.to_owned(), source: e }
+    }
+}
+
+/// Checks a row read at `row_number` against its checksum and the where
+/// clause. `None` if the row isn't returned. A row the store repaired comes
+/// back in place of the one read.
+fn check_row<'r>(store: &dyn ByteStore, predicate: Option<&WherePredicate>, row_number: u64, bytes: &'r [u8]) -> std::io::Result<Option<Cow<'r, [u8]>>> {
+    let row = match store.verify_row(row_number, bytes)? {
+        RowCheck::Intact => Cow::Borrowed(bytes),
+        RowCheck::Repaired(row) => Cow::Owned(row),
+        RowCheck::Skip => return Ok(None)
+    };
+    Ok(predicate.is_none_or(|p| p.is_true(&row)).then_some(row))
+}
+
+/// The byte ranges `columns` take up in a row, sorted and merged.
+fn column_ranges(columns: &[&TableColumn]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for column in columns.iter().map(|c| c.offset..(c.offset + c.datatype.size_in_bytes())).sorted_by_key(|r| r.start) {
+        match ranges.last_mut() {
+            Some(last) if column.start <= last.end => last.end = last.end.max(column.end),
+            _ => ranges.push(column)
+        }
+    }
+    ranges
+}
+
+/// Reads a store's rows one at a time, passing over deleted ones and ones
+/// `check_row` turns away. Mapped stores are read in place, copying out just
+/// the `ranges` of each row returned, so the pages of columns the query
+/// doesn't use are never read in. Other stores are read through their reader,
+/// or a row at a time for rows an index found, into one buffer, and rows only
+/// copied out when they're returned. A row cut off by the end of the data
+/// means the table is truncated. Every so many rows the scan checks whether
+/// it was cancelled.
+struct ScanRows<'a> {
+    table_name: &'a str,
+    store: &'a dyn ByteStore,
+    predicate: Option<&'a WherePredicate<'a>>,
+    ranges: Vec<Range<usize>>,
+    mapped: Option<&'a [u8]>,
+    reader: Option<Box<dyn Read + 'a>>,
+    /// Rows an index narrowed the scan down to, read one at a time instead
+    /// of reading through the table. `None` scans every row.
+    row_numbers: Option<std::vec::IntoIter<u64>>,
+    /// The version rows are read as of, and the store's earlier versions
+    /// of them. `None` reads rows as they are.
+    snapshot: Option<(u64, &'a RowVersions)>,
+    row: Vec<u8>,
+    row_number: u64,
+    rows_read: u64,
+    cancel: CancelToken
+}
+
+impl<'a> ScanRows<'a> {
+    fn new(table_name: &'a str, store: &'a dyn ByteStore, predicate: Option<&'a WherePredicate<'a>>, ranges: Vec<Range<usize>>, cancel: CancelToken) -> ScanRows<'a> {
+        ScanRows { table_name, store, predicate, ranges, mapped: store.mapped_rows(), reader: None, row_numbers: None, snapshot: None, row: vec![0u8; store.row_size()], row_number: 0, rows_read: 0, cancel }
+    }
+
+    /// Reads rows as they were at `version`, for stores that keep earlier
+    /// versions of rows.
+    fn with_snapshot(mut self, version: u64) -> ScanRows<'a> {
+        self.snapshot = self.store.row_versions().filter(|v| !v.is_empty()).map(|v| (version, v));
+        self
+    }
+
+    /// Reads just the rows at `row_numbers`, in the order given.
+    fn with_row_numbers(mut self, row_numbers: Vec<u64>) -> ScanRows<'a> {
+        self.row_numbers = Some(row_numbers.into_iter());
+        self
+    }
+
+    /// Copies the used ranges of a mapped row into a new row.
+    fn copy_ranges(&self, mapped: &[u8]) -> Vec<u8> {
+        let mut row = vec![0u8; mapped.len()];
+        for range in &self.ranges {
+            row[range.clone()].copy_from_slice(&mapped[range.clone()]);
+        }
+        row
+    }
+
+    /// Fills the row buffer from the reader, returning how many bytes it got
+    /// before the data ran out.
+    fn read_row(&mut self) -> std::io::Result<usize> {
+        let reader = match &mut self.reader {
+            Some(reader) => reader,
+            None => self.reader.insert(self.store.get_reader()?)
+        };
+        let row = &mut self.row;
+        let mut filled = 0;
+        while filled < row.len() {
+            match reader.read(&mut row[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl Iterator for ScanRows<'_> {
+    type Item = Result<(u64, Vec<u8>), QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row_size = self.row.len();
+        let truncated = |row_number| Some(Err(read_error(self.table_name, row_number, std::io::ErrorKind::UnexpectedEof.into())));
+        loop {
+            if self.rows_read.is_multiple_of(CANCEL_CHECK_ROWS) {
+                if let Err(e) = self.cancel.check() {
+                    return Some(Err(e));
+                }
+            }
+            let (row_number, indexed) = match &mut self.row_numbers {
+                Some(row_numbers) => (row_numbers.next()?, true),
+                None => (self.row_number, false)
+            };
+            let mapped = match self.mapped {
+                Some(mapped) => {
+                    let start = row_number as usize * row_size;
+                    if start >= mapped.len() && !indexed {
+                        return None;
+                    }
+                    match mapped.get(start..(start + row_size)) {
+                        Some(row) => Some(row),
+                        None => return truncated(row_number)
+                    }
+                },
+                None if indexed => match self.store.is_deleted(row_number) {
+                    true => None,
+                    false => match self.store.read_row_into(row_number, &mut self.row) {
+                        Ok(()) => None,
+                        Err(e) => return Some(Err(read_error(self.table_name, row_number, e)))
+                    }
+                },
+                None => match self.read_row() {
+                    Ok(0) => return None,
+                    Ok(n) if n < row_size => return truncated(row_number),
+                    Ok(_) => None,
+                    Err(e) => return Some(Err(read_error(self.table_name, row_number, e)))
+                }
+            };
+            self.row_number += 1;
+            self.rows_read += 1;
+            if let Some((version, versions)) = self.snapshot {
+                match versions.visibility(row_number, version) {
+                    RowVisibility::Current => {},
+                    RowVisibility::Hidden => continue,
+                    // earlier versions aren't covered by the row's checksum
+                    RowVisibility::Earlier(row) => match self.predicate.is_none_or(|p| p.is_true(row)) {
+                        true => return Some(Ok((row_number, row.to_vec()))),
+                        false => continue
+                    }
+                }
+            }
+            if self.store.is_deleted(row_number) {
+                continue;
+            }
+            match check_row(self.store, self.predicate, row_number, mapped.unwrap_or(&self.row)) {
+                Ok(Some(Cow::Borrowed(row))) if mapped.is_some() => return Some(Ok((row_number, self.copy_ranges(row)))),
+                Ok(Some(row)) => return Some(Ok((row_number, row.into_owned()))),
+                Ok(None) => {},
+                Err(e) => return Some(Err(read_error(self.table_name, row_number, e)))
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::io::Write;
+
+use itertools::Itertools;
+
+use super::{literal, schema::ColumnDataType, value::ResultSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown output format '{}': expected text, json or csv", s))
+        }
+    }
+}
+
+/// How result sets are rendered. `null_display` and the boolean displays
+/// apply to the text and csv formats; json always uses its own literals.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub format: OutputFormat,
+    pub null_display: String,
+    pub true_display: String,
+    pub false_display: String
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            format: OutputFormat::Text,
+            null_display: "NULL".to_owned(),
+            true_display: "true".to_owned(),
+            false_display: "false".to_owned()
+        }
+    }
+}
+
+/// A result column as the formatter sees it. A `None` datatype renders the
+/// column's values as plain text.
+pub struct FormatColumn<'a> {
+    pub name: &'a str,
+    pub datatype: Option<&'a ColumnDataType>
+}
+
+pub struct ResultFormatter<'a> {
+    options: &'a FormatOptions
+}
+
+impl<'a> ResultFormatter<'a> {
+    pub fn new(options: &'a FormatOptions) -> ResultFormatter<'a> {
+        ResultFormatter { options }
+    }
+
+    fn is_numeric(datatype: Option<&ColumnDataType>) -> bool {
+        matches!(datatype, Some(ColumnDataType::SerialId | ColumnDataType::Int32 | ColumnDataType::UInt32 | ColumnDataType::Int64 | ColumnDataType::UInt64 | ColumnDataType::RowVersion))
+    }
+
+    /// Renders one value for the text and csv formats.
+    fn display_cell(&self, datatype: Option<&ColumnDataType>, value: Option<&str>) -> String {
+        match (datatype, value) {
+            (_, None) => self.options.null_display.to_owned(),
+            (Some(ColumnDataType::Boolean), Some("true")) => self.options.true_display.to_owned(),
+            (Some(ColumnDataType::Boolean), Some("false")) => self.options.false_display.to_owned(),
+            (_, Some(v)) => v.to_owned()
+        }
+    }
+
+    fn json_cell(datatype: Option<&ColumnDataType>, value: Option<&str>) -> String {
+        match (datatype, value) {
+            (_, None) => "null".to_owned(),
+            (Some(ColumnDataType::Boolean), Some(v)) => v.to_owned(),
+            (d, Some(v)) if Self::is_numeric(d) && literal::is_integer(v) => v.to_owned(),
+            (_, Some(v)) => Self::json_string(v)
+        }
+    }
+
+    fn json_string(s: &str) -> String {
+        let mut o = String::with_capacity(s.len() + 2);
+        o.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => o.push_str("\\\""),
+                '\\' => o.push_str("\\\\"),
+                '\n' => o.push_str("\\n"),
+                '\r' => o.push_str("\\r"),
+                '\t' => o.push_str("\\t"),
+                c if (c as u32) < 0x20 => o.push_str(&format!("\\u{:04x}", c as u32)),
+                c => o.push(c)
+            }
+        }
+        o.push('"');
+        o
+    }
+
+    fn csv_field(s: &str) -> String {
+        if s.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        } else {
+            s.to_owned()
+        }
+    }
+
+    /// Writes a whole result set. Each row holds one value per column, with
+    /// `None` standing for NULL.
+    pub fn write(&self, out: &mut impl Write, columns: &[FormatColumn], rows: &[Vec<Option<&str>>]) -> std::io::Result<()> {
+        match self.options.format {
+            OutputFormat::Text => self.write_text(out, columns, rows),
+            OutputFormat::Json => Self::write_json(out, columns, rows),
+            OutputFormat::Csv => self.write_csv(out, columns, rows)
+        }
+    }
+
+    /// Writes a query's result, typed and named after its columns.
+    pub fn write_result_set(&self, out: &mut impl Write, result: &ResultSet) -> std::io::Result<()> {
+        let columns = result.columns.iter()
+            .map(|c| FormatColumn { name: c.label(), datatype: c.datatype.as_ref() })
+            .collect_vec();
+        let cells = result.rows.iter()
+            .map(|row| row.columns.iter().map(|(_, v)| (!v.is_null()).then(|| v.to_string())).collect_vec())
+            .collect_vec();
+        let values = cells.iter()
+            .map(|row| row.iter().map(|v| v.as_deref()).collect_vec())
+            .collect_vec();
+        self.write(out, &columns, &values)
+    }
+
+    fn pad(s: &str, width: usize, right: bool) -> String {
+        let fill = " ".repeat(width.saturating_sub(s.chars().count()));
+        if right { format!("{}{}", fill, s) } else { format!("{}{}", s, fill) }
+    }
+
+    fn text_header(columns: &[FormatColumn], widths: &[usize]) -> String {
+        let names = columns.iter().zip(widths).map(|(c, w)| Self::pad(c.name, *w, false)).join(" | ");
+        format!("{}\n{}", names.trim_end(), widths.iter().map(|w| "-".repeat(*w)).join("-+-"))
+    }
+
+    fn text_row(columns: &[FormatColumn], widths: &[usize], cells: &[String]) -> String {
+        cells.iter().zip(columns.iter().zip(widths))
+            .map(|(v, (c, w))| Self::pad(v, *w, Self::is_numeric(c.datatype)))
+            .join(" | ").trim_end().to_owned()
+    }
+
+    fn text_footer(row_count: usize) -> String {
+        format!("({} {})", row_count, if row_count == 1 { "row" } else { "rows" })
+    }
+
+    fn json_row(columns: &[FormatColumn], row: &[Option<&str>]) -> String {
+        let fields = columns.iter().zip(row)
+            .map(|(c, v)| format!("{}: {}", Self::json_string(c.name), Self::json_cell(c.datatype, *v)))
+            .join(", ");
+        format!("  {{{}}}", fields)
+    }
+
+    fn csv_header(columns: &[FormatColumn]) -> String {
+        columns.iter().map(|c| Self::csv_field(c.name)).join(",")
+    }
+
+    fn csv_row(&self, columns: &[FormatColumn], row: &[Option<&str>]) -> String {
+        columns.iter().zip(row)
+            .map(|(c, v)| Self::csv_field(&self.display_cell(c.datatype, *v)))
+            .join(",")
+    }
+
+    fn write_text(&self, out: &mut impl Write, columns: &[FormatColumn], rows: &[Vec<Option<&str>>]) -> std::io::Result<()> {
+        let cells = rows.iter()
+            .map(|r| columns.iter().zip(r).map(|(c, v)| self.display_cell(c.datatype, *v)).collect_vec())
+            .collect_vec();
+
+        let widths = columns.iter().enumerate()
+            .map(|(i, c)| cells.iter().map(|r| r[i].chars().count()).chain(std::iter::once(c.name.chars().count())).max().unwrap_or(0))
+            .collect_vec();
+
+        writeln!(out, "{}", Self::text_header(columns, &widths))?;
+        for r in &cells {
+            writeln!(out, "{}", Self::text_row(columns, &widths, r))?;
+        }
+        writeln!(out, "{}", Self::text_footer(rows.len()))
+    }
+
+    fn write_json(out: &mut impl Write, columns: &[FormatColumn], rows: &[Vec<Option<&str>>]) -> std::io::Result<()> {
+        writeln!(out, "[")?;
+        for (i, r) in rows.iter().enumerate() {
+            writeln!(out, "{}{}", Self::json_row(columns, r), if i + 1 < rows.len() { "," } else { "" })?;
+        }
+        writeln!(out, "]")
+    }
+
+    fn write_csv(&self, out: &mut impl Write, columns: &[FormatColumn], rows: &[Vec<Option<&str>>]) -> std::io::Result<()> {
+        writeln!(out, "{}", Self::csv_header(columns))?;
+        for r in rows {
+            writeln!(out, "{}", self.csv_row(columns, r))?;
+        }
+        Ok(())
+    }
+
+    /// Writes rows as they're read, without holding more than the next one.
+    /// The text format can't size its columns to rows it hasn't seen yet,
+    /// so each is as wide as its name and longer values push the rest of
+    /// their row along. Returns the number of rows written.
+    pub fn write_streamed<E>(&self, out: &mut impl Write, columns: &[FormatColumn], rows: impl Iterator<Item = Result<Vec<Option<String>>, E>>) -> Result<usize, E>
+    where E: From<std::io::Error> {
+        let widths = columns.iter().map(|c| c.name.chars().count()).collect_vec();
+        match self.options.format {
+            OutputFormat::Text => writeln!(out, "{}", Self::text_header(columns, &widths))?,
+            OutputFormat::Json => writeln!(out, "[")?,
+            OutputFormat::Csv => writeln!(out, "{}", Self::csv_header(columns))?
+        }
+
+        let mut rows = rows.peekable();
+        let mut row_count = 0;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let row = row.iter().map(|v| v.as_deref()).collect_vec();
+            match self.options.format {
+                OutputFormat::Text => {
+                    let cells = columns.iter().zip(&row).map(|(c, v)| self.display_cell(c.datatype, *v)).collect_vec();
+                    writeln!(out, "{}", Self::text_row(columns, &widths, &cells))?
+                },
+                // json rows are separated by commas, so the next row is
+                // read before this one is written to know if it's the last
+                OutputFormat::Json => writeln!(out, "{}{}", Self::json_row(columns, &row), if rows.peek().is_some() { "," } else { "" })?,
+                OutputFormat::Csv => writeln!(out, "{}", self.csv_row(columns, &row))?
+            }
+            row_count += 1;
+        }
+
+        match self.options.format {
+            OutputFormat::Text => writeln!(out, "{}", Self::text_footer(row_count))?,
+            OutputFormat::Json => writeln!(out, "]")?,
+            OutputFormat::Csv => ()
+        }
+        Ok(row_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(options: &FormatOptions, rows: &[Vec<Option<&str>>]) -> String {
+        let columns = [
+            FormatColumn { name: "title", datatype: Some(&ColumnDataType::Byte(16)) },
+            FormatColumn { name: "year", datatype: Some(&ColumnDataType::Int32) },
+            FormatColumn { name: "us", datatype: Some(&ColumnDataType::Boolean) }
+        ];
+        let mut out = Vec::new();
+        ResultFormatter::new(options).write(&mut out, &columns, rows).unwrap();
+        let written = String::from_utf8(out).unwrap();
+
+        // streaming writes the same rows, if not always laid out the same
+        let mut streamed = Vec::new();
+        let owned = rows.iter().map(|row| Ok::<_, std::io::Error>(row.iter().map(|v| v.map(str::to_owned)).collect_vec()));
+        assert_eq!(ResultFormatter::new(options).write_streamed(&mut streamed, &columns, owned).unwrap(), rows.len());
+        if options.format != OutputFormat::Text {
+            assert_eq!(String::from_utf8(streamed).unwrap(), written);
+        }
+        written
+    }
+
+    fn rows() -> Vec<Vec<Option<&'static str>>> {
+        vec![
+            vec![Some("Bees"), Some("1950"), Some("true")],
+            vec![Some("Frogs, \"Toads\""), None, Some("false")],
+            vec![None, Some("-7"), None]
+        ]
+    }
+
+    #[test]
+    fn text_pads_columns_and_counts_rows() {
+        let options = FormatOptions { null_display: "-".to_owned(), true_display: "yes".to_owned(), ..FormatOptions::default() };
+        assert_eq!(render(&options, &rows()), concat!(
+            "title          | year | us\n",
+            "---------------+------+------\n",
+            "Bees           | 1950 | yes\n",
+            "Frogs, \"Toads\" |    - | false\n",
+            "-              |   -7 | -\n",
+            "(3 rows)\n"
+        ));
+        assert_eq!(render(&FormatOptions::default(), &[]), "title | year | us\n------+------+---\n(0 rows)\n");
+    }
+
+    #[test]
+    fn json_keeps_its_own_literals() {
+        let options = FormatOptions { format: OutputFormat::Json, null_display: "-".to_owned(), ..FormatOptions::default() };
+        assert_eq!(render(&options, &rows()), concat!(
+            "[\n",
+            "  {\"title\": \"Bees\", \"year\": 1950, \"us\": true},\n",
+            "  {\"title\": \"Frogs, \\\"Toads\\\"\", \"year\": null, \"us\": false},\n",
+            "  {\"title\": null, \"year\": -7, \"us\": null}\n",
+            "]\n"
+        ));
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let options = FormatOptions { format: OutputFormat::Csv, null_display: String::new(), ..FormatOptions::default() };
+        assert_eq!(render(&options, &rows()), "title,year,us\nBees,1950,true\n\"Frogs, \"\"Toads\"\"\",,false\n,-7,\n");
+        assert_eq!("csv".parse::<OutputFormat>(), Ok(OutputFormat::Csv));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::{HashMap, hash_map::DefaultHasher}, hash::{Hash, Hasher}, io::{BufReader, BufWriter, Read, Write}};
+
+use super::{aggregate::{Accumulator, Aggregates}, exec::QueryError, query::SelectColumn, schema::TableColumn, spill::SpillFile, value::Row};
+
+/// Memory a `group by` can hold groups in before it spills rows to disk.
+pub const DEFAULT_GROUP_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Partitions the rows of new groups are spread over once memory is full.
+const SPILL_PARTITIONS: usize = 16;
+
+/// Times a partition can be split again. A partition this deep is grouped
+/// in memory whatever its size, since its keys won't split any further.
+const MAX_SPILL_DEPTH: u32 = 4;
+
+type GroupInput<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<u8>), QueryError>> + 'a>;
+
+/// Result rows of a `group by`, a row per distinct combination of the
+/// grouping columns. Groups live in a hash map keyed on the grouping
+/// columns' encoded bytes. Once the groups take up the memory budget, rows
+/// of groups that aren't in memory yet are written out to partition files
+/// by their key's hash instead, and each partition is grouped on its own
+/// after the groups in memory are returned. Groups come back in the order
+/// their first row was read, partitions aside.
+pub struct GroupedRows<'a> {
+    columns: &'a [SelectColumn<'a>],
+    group_by: &'a [&'a TableColumn],
+    row_size: usize,
+    memory: usize,
+    input: Option<GroupInput<'a>>,
+    groups: std::vec::IntoIter<Aggregates<'a>>,
+    partitions: Vec<(SpillPartition, u32)>
+}
+
+impl<'a> GroupedRows<'a> {
+    /// Groups `rows`, each of `row_size` bytes, holding at most about
+    /// `memory` bytes of groups in memory.
+    pub fn new(rows: GroupInput<'a>, columns: &'a [SelectColumn<'a>], group_by: &'a [&'a TableColumn], row_size: usize, memory: usize) -> GroupedRows<'a> {
+        GroupedRows { columns, group_by, row_size, memory, input: Some(rows), groups: Vec::new().into_iter(), partitions: Vec::new() }
+    }
+
+    fn key(&self, row: &[u8]) -> Vec<u8> {
+        self.group_by.iter()
+            .flat_map(|c| &row[c.offset..(c.offset + c.datatype.size_in_bytes())])
+            .copied()
+            .collect()
+    }
+
+    /// Rough number of bytes a group with this key takes up.
+    fn group_size(&self, key: &[u8]) -> usize {
+        key.len() * 2 + self.row_size
+            + std::mem::size_of::<Aggregates>()
+            + self.columns.len() * std::mem::size_of::<Option<Accumulator>>()
+    }
+
+    /// Groups one pass worth of rows, at `depth` partitions deep.
+    fn group(&mut self, rows: impl Iterator<Item = Result<Vec<u8>, QueryError>>, depth: u32) -> Result<(), QueryError> {
+        let mut index: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut groups: Vec<Aggregates<'a>> = Vec::new();
+        let mut spilled: Vec<Option<SpillPartition>> = (0..SPILL_PARTITIONS).map(|_| None).collect();
+        let mut held = 0;
+        for row in rows {
+            let row = row?;
+            let key = self.key(&row);
+            if let Some(i) = index.get(&key) {
+                groups[*i].update(&row)?;
+                continue;
+            }
+            let size = self.group_size(&key);
+            if held + size > self.memory && depth < MAX_SPILL_DEPTH {
+                let partition = &mut spilled[partition_for(&key, depth)];
+                if partition.is_none() {
+                    *partition = Some(SpillPartition::create()?);
+                }
+                partition.as_mut().unwrap().write(&row)?;
+                continue;
+            }
+            held += size;
+            let mut group = Aggregates::new(self.columns);
+            group.update(&row)?;
+            index.insert(key, groups.len());
+            groups.push(group);
+        }
+
+        for partition in spilled.into_iter().flatten() {
+            self.partitions.push((partition.finish()?, depth + 1));
+        }
+        self.groups = groups.into_iter();
+        Ok(())
+    }
+
+    fn next_row(&mut self) -> Option<Result<Row, QueryError>> {
+        if let Some(rows) = self.input.take() {
+            if let Err(e) = self.group(rows.map(|row| row.map(|(_, bytes)| bytes)), 0) {
+                return Some(Err(e));
+            }
+        }
+        loop {
+            if let Some(group) = self.groups.next() {
+                return Some(group.finish().map(|columns| Row { id: 0, columns }));
+            }
+            let (mut partition, depth) = self.partitions.pop()?;
+            let row_size = self.row_size;
+            if let Err(e) = self.group(std::iter::from_fn(|| partition.read(row_size).transpose()), depth) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl Iterator for GroupedRows<'_> {
+    type Item = Result<Row, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.next_row();
+        if matches!(row, Some(Err(_))) {
+            self.groups = Vec::new().into_iter();
+            self.partitions.clear();
+        }
+        row
+    }
+}
+
+/// Which partition a key spills to. The depth goes into the hash so a
+/// partition that's split again spreads its keys out differently.
+fn partition_for(key: &[u8], depth: u32) -> usize {
+    let mut hasher = DefaultHasher::new();
+    depth.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+}
+
+/// Rows spilled from a `group by` to a spill file, written back to back.
+struct SpillPartition {
+    file: Option<BufWriter<SpillFile>>,
+    reader: Option<BufReader<SpillFile>>
+}
+
+impl SpillPartition {
+    fn create() -> Result<SpillPartition, QueryError> {
+        let file = SpillFile::create("kronk-group").map_err(QueryError::Spill)?;
+        Ok(SpillPartition { file: Some(BufWriter::new(file)), reader: None })
+    }
+
+    fn write(&mut self, row: &[u8]) -> Result<(), QueryError> {
+        self.file.as_mut().expect("partition should still be open for writing").write_all(row).map_err(QueryError::Spill)
+    }
+
+    /// Flushes the partition and readies it for reading back.
+    fn finish(mut self) -> Result<SpillPartition, QueryError> {
+        let file = self.file.take().expect("partition should only be finished once");
+        let mut file = file.into_inner().map_err(|e| QueryError::Spill(e.into_error()))?;
+        std::io::Seek::rewind(&mut file).map_err(QueryError::Spill)?;
+        self.reader = Some(BufReader::new(file));
+        Ok(self)
+    }
+
+    fn read(&mut self, row_size: usize) -> Result<Option<Vec<u8>>, QueryError> {
+        let reader = self.reader.as_mut().expect("partition should be finished before it's read");
+        let mut row = vec![0u8; row_size];
+        match reader.read_exact(&mut row) {
+            Ok(()) => Ok(Some(row)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(QueryError::Spill(e))
+        }
+    }
+}
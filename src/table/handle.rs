@@ -0,0 +1,242 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard}};
+use itertools::Itertools;
+
+use super::{admission::AdmissionQueue, db::{Database, ExecResult, InsertedRow, PendingWrite}, lock::Transaction, query::{SelectQuery, WherePredicate}, value::ResultSet, error::KronkError};
+
+/// A database shared between threads. Clones are cheap and all refer to the
+/// same database. Any number of threads can query it at once; writes, and
+/// changes to its tables, wait for the queries running to finish and hold
+/// off new ones until they're done.
+///
+/// Writes made through the handle's own methods also take their tables'
+/// write paths, so there's only ever one writer per table. They look for
+/// the rows to change while queries go on, and only hold queries off while
+/// they make the changes. An update or delete waiting for rows another
+/// transaction has locked waits holding neither, so queries, other writers
+/// and the transaction it waits on all go on meanwhile.
+#[derive(Clone)]
+pub struct DatabaseHandle {
+    db: Arc<RwLock<Database>>,
+    /// Held by the writer of each table.
+    write_paths: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    admission: Option<Arc<AdmissionQueue>>
+}
+
+impl DatabaseHandle {
+    pub fn new(db: Database) -> DatabaseHandle {
+        DatabaseHandle { db: Arc::new(RwLock::new(db)), write_paths: Arc::new(Mutex::new(HashMap::new())), admission: None }
+    }
+
+    /// Lets at most `max_concurrent` of the handle's queries run at once.
+    /// Up to `max_queued` more wait their turn, in the order they came, and
+    /// any past that fail with `KronkError::Overloaded`. Clones made after
+    /// share the same line.
+    pub fn with_admission(mut self, max_concurrent: usize, max_queued: usize) -> DatabaseHandle {
+        self.admission = Some(Arc::new(AdmissionQueue::new(max_concurrent, max_queued)));
+        self
+    }
+
+    /// The queue the handle's queries wait in, if it has one.
+    pub fn admission(&self) -> Option<&AdmissionQueue> {
+        self.admission.as_deref()
+    }
+
+    /// The database, for queries. Other threads can read it meanwhile, but
+    /// not write to it.
+    pub fn read(&self) -> RwLockReadGuard<'_, Database> {
+        self.db.read().unwrap()
+    }
+
+    /// The database, for writes. No other thread can use it meanwhile.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Database> {
+        self.db.write().unwrap()
+    }
+
+    /// Runs a select to the end, reading the database while it does.
+    pub fn query(&self, query: &str) -> Result<ResultSet, KronkError> {
+        self.query_with_position(query, &mut |_| {})
+    }
+
+    /// Like `query`, calling `on_position` with the query's place in line
+    /// each time it changes while it waits to be admitted.
+    pub fn query_with_position(&self, query: &str, on_position: &mut dyn FnMut(usize)) -> Result<ResultSet, KronkError> {
+        // wait in line before taking the database, so writers aren't held
+        // off by queries that aren't running yet
+        let _permit = self.admission.as_ref().map(|queue| queue.acquire(on_position)).transpose()?;
+        let db = self.read();
+        let query = SelectQuery::parse_raw_query_against_db(query, &*db)?;
+        Ok(db.query_result_set(&query)?)
+    }
+
+    pub fn insert_columns(&self, table_name: &str, columns: &[(&str, &str)]) -> Result<ExecResult, KronkError> {
+        self.with_write_path(table_name, || self.write().insert_columns(table_name, columns))
+    }
+
+    /// Inserts a batch of rows, returning them as stored. Either every row
+    /// is inserted or none are.
+    pub fn insert_rows(&self, table_name: &str, rows: &[Vec<(&str, &str)>]) -> Result<Vec<InsertedRow>, KronkError> {
+        self.with_write_path(table_name, || self.write().insert_rows(table_name, rows))
+    }
+
+    /// Starts a transaction for `update_columns_in` and `delete_where_in`.
+    /// The rows it changes stay locked until it's dropped.
+    pub fn begin_transaction(&self) -> Transaction {
+        self.read().begin_transaction()
+    }
+
+    pub fn update_columns(&self, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        self.update_columns_in(&self.begin_transaction(), table_name, set, predicate)
+    }
+
+    /// Like `update_columns`, locking the updated rows for `transaction`.
+    /// If one of them is locked by a transaction that's waiting, directly
+    /// or through others, on a row `transaction` holds, this fails with
+    /// `LockError::Deadlock` rather than waiting.
+    pub fn update_columns_in(&self, transaction: &Transaction, table_name: &str, set: &[(&str, &str)], predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        self.write_locked(transaction, table_name, |db| db.prepare_update(table_name, set, predicate))
+    }
+
+    pub fn delete_where(&self, table_name: &str, predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        self.delete_where_in(&self.begin_transaction(), table_name, predicate)
+    }
+
+    /// Like `delete_where`, locking the deleted rows, cascades included, for
+    /// `transaction`, as `update_columns_in` does.
+    pub fn delete_where_in(&self, transaction: &Transaction, table_name: &str, predicate: &WherePredicate) -> Result<ExecResult, KronkError> {
+        self.write_locked(transaction, table_name, |db| db.prepare_delete(table_name, predicate))
+    }
+
+    /// Finds the rows a write to `table_name` changes with `prepare`, locks
+    /// them for `transaction`, and makes the changes. Locking them waits
+    /// holding nothing else. Since the rows could have changed by the time
+    /// they're locked, they're found again under the write path, and if
+    /// any of them aren't locked yet, it all starts over.
+    fn write_locked(&self, transaction: &Transaction, table_name: &str, prepare: impl Fn(&Database) -> Result<PendingWrite, KronkError>) -> Result<ExecResult, KronkError> {
+        loop {
+            let write = prepare(&self.read())?;
+            write.lock(transaction)?;
+            let applied = self.with_write_path(table_name, || {
+                let write = prepare(&self.read())?;
+                if !write.is_locked_by(transaction) {
+                    return Ok(None);
+                }
+                self.write().apply_write(transaction, write).map(Some)
+            })?;
+            if let Some(result) = applied {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Runs `write` holding the write paths of every table a write to
+    /// `table_name` can touch. They're taken in name order, so two writers
+    /// never each wait on a path the other holds.
+    fn with_write_path<T>(&self, table_name: &str, write: impl FnOnce() -> T) -> T {
+        let tables = self.read().write_path_tables(table_name);
+        let paths = {
+            let mut write_paths = self.write_paths.lock().unwrap();
+            tables.into_iter().map(|table_name| write_paths.entry(table_name).or_default().clone()).collect_vec()
+        };
+        let _held = paths.iter().map(|path| path.lock().unwrap()).collect_vec();
+        write()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, time::{Duration, Instant}};
+
+    use super::*;
+    use crate::table::{lock::{LockError, LockWait}, query::WhereCondition, schema::{ColumnDataType, GetTableDescriptor, TableDescriptor}, testing::ScratchDir};
+
+    /// A handle to a database with an `accounts` table of two rows, whose
+    /// writers wait up to `wait` for locked rows.
+    fn accounts(db_name: &str, wait: Duration) -> DatabaseHandle {
+        let mut db = Database::new(db_name);
+        db.add_table(TableDescriptor::new("accounts", vec![("id", ColumnDataType::SerialId), ("balance", ColumnDataType::Int64)]).unwrap()).unwrap();
+        db.insert_rows("accounts", &[vec![("balance", "10")], vec![("balance", "20")]]).unwrap();
+        db.set_lock_wait(LockWait::Timeout(wait));
+        DatabaseHandle::new(db)
+    }
+
+    fn account(table: &TableDescriptor, id: u64) -> WherePredicate<'_> {
+        WherePredicate::Condition(WhereCondition::new(table.column_for_name("id").unwrap(), "==", &id.to_string()).unwrap())
+    }
+
+    #[test]
+    fn writers_wait_for_rows_without_holding_the_database() {
+        let _dir = ScratchDir::new("test_handle_waiting_writer");
+        let db = accounts("test_handle_waiting_writer", Duration::from_secs(30));
+        let table = db.read().table_with_name("accounts").unwrap().clone();
+        let holder = db.read().begin_transaction();
+        db.write().update_columns_in(&holder, "accounts", &[("balance", "11")], &account(&table, 0)).unwrap();
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| db.update_columns("accounts", &[("balance", "12")], &account(&table, 0)));
+            std::thread::sleep(Duration::from_millis(50));
+            // while it waits, queries go on, and so does the transaction it
+            // waits on
+            let started = Instant::now();
+            assert_eq!(db.query("select balance from accounts").unwrap().rows.len(), 2);
+            db.write().update_columns_in(&holder, "accounts", &[("balance", "13")], &account(&table, 0)).unwrap();
+            assert!(started.elapsed() < Duration::from_secs(10));
+            drop(holder);
+            assert_eq!(waiter.join().unwrap().unwrap().rows_affected, 1);
+        });
+        let rows = db.query("select balance from accounts where id == 0").unwrap().rows;
+        assert_eq!(rows[0].get::<i64>("balance").unwrap(), 12);
+    }
+
+    #[test]
+    fn transactions_waiting_on_each_other_are_deadlocked() {
+        let _dir = ScratchDir::new("test_handle_deadlock");
+        let db = accounts("test_handle_deadlock", Duration::from_secs(30));
+        let table = db.read().table_with_name("accounts").unwrap().clone();
+        let barrier = Barrier::new(2);
+        let started = Instant::now();
+        // each locks one account, then wants the other's
+        let results = std::thread::scope(|s| {
+            let writers = [(0, 1), (1, 0)].map(|(first, second)| {
+                let (db, table, barrier) = (&db, &table, &barrier);
+                s.spawn(move || {
+                    let transaction = db.begin_transaction();
+                    db.update_columns_in(&transaction, "accounts", &[("balance", "0")], &account(table, first))?;
+                    barrier.wait();
+                    db.update_columns_in(&transaction, "accounts", &[("balance", "0")], &account(table, second))
+                })
+            });
+            writers.map(|writer| writer.join().unwrap())
+        });
+        // the second to ask is told straight away, and once it lets go of
+        // its account the first gets it
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().any(|r| matches!(r, Err(KronkError::Lock(LockError::Deadlock { .. })))));
+    }
+
+    #[test]
+    fn queries_past_the_admitted_ones_wait_in_line() {
+        let _dir = ScratchDir::new("test_handle_admission");
+        let db = accounts("test_handle_admission", Duration::from_secs(30)).with_admission(1, 1);
+        let queue = db.admission().unwrap();
+        let wait_for = |running, queued| while (queue.running(), queue.queued()) != (running, queued) {
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        // a writer holds up the admitted query, so the next has to wait
+        let writing = db.write();
+        std::thread::scope(|s| {
+            let admitted = s.spawn(|| db.query("select balance from accounts"));
+            wait_for(1, 0);
+            let queued = s.spawn(|| {
+                let mut positions = Vec::new();
+                db.query_with_position("select balance from accounts", &mut |p| positions.push(p)).map(|r| (r, positions))
+            });
+            wait_for(1, 1);
+            assert!(matches!(db.query("select balance from accounts"), Err(KronkError::Overloaded { queued: 1 })));
+            drop(writing);
+            assert_eq!(admitted.join().unwrap().unwrap().rows.len(), 2);
+            let (rows, positions) = queued.join().unwrap().unwrap();
+            assert_eq!((rows.rows.len(), positions), (2, vec![1]));
+        });
+        assert_eq!((queue.running(), queue.queued()), (0, 0));
+    }
+}
@@ -0,0 +1,117 @@
+//! Callbacks a database runs as it's written to and queried, for keeping
+//! caches, audit logs or change feeds in step with it.
+
+use super::{error::KronkError, query::SelectQuery, schema::TableDescriptor, value::{Row, Value}};
+
+/// Called with the name of the table a row was written to and the row: as
+/// stored for inserts and updates, and as it was for deletes.
+pub type RowListener = Box<dyn Fn(&str, &Row) + Send + Sync>;
+
+/// Called with each query just before it runs.
+pub type QueryListener = Box<dyn Fn(&SelectQuery) + Send + Sync>;
+
+/// The listeners registered on a database, run in the order they were
+/// registered. Rows are only decoded for listeners when there are some.
+/// While writes are held, row listeners are called once they're released,
+/// and not at all if they're discarded.
+#[derive(Default)]
+pub struct Hooks {
+    inserted: Vec<RowListener>,
+    updated: Vec<RowListener>,
+    deleted: Vec<RowListener>,
+    queried: Vec<QueryListener>,
+    held: Option<Vec<(RowEvent, String, Row)>>
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RowEvent {
+    Inserted,
+    Updated,
+    Deleted
+}
+
+impl Hooks {
+    pub fn on_insert(&mut self, listener: RowListener) {
+        self.inserted.push(listener);
+    }
+
+    pub fn on_update(&mut self, listener: RowListener) {
+        self.updated.push(listener);
+    }
+
+    pub fn on_delete(&mut self, listener: RowListener) {
+        self.deleted.push(listener);
+    }
+
+    pub fn on_query(&mut self, listener: QueryListener) {
+        self.queried.push(listener);
+    }
+
+    pub fn has_delete_listeners(&self) -> bool {
+        !self.deleted.is_empty()
+    }
+
+    pub fn inserted(&mut self, table: &TableDescriptor, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        self.row_written(RowEvent::Inserted, table, row_number, bytes)
+    }
+
+    pub fn updated(&mut self, table: &TableDescriptor, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        self.row_written(RowEvent::Updated, table, row_number, bytes)
+    }
+
+    pub fn deleted(&mut self, table: &TableDescriptor, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        self.row_written(RowEvent::Deleted, table, row_number, bytes)
+    }
+
+    /// Holds back the rows written from now on, until `release` or
+    /// `discard`.
+    pub fn hold(&mut self) {
+        self.held = Some(Vec::new());
+    }
+
+    /// Calls the row listeners for the rows held back, in the order they
+    /// were written.
+    pub fn release(&mut self) {
+        for (event, table_name, row) in self.held.take().unwrap_or_default() {
+            for listener in self.listeners(event) {
+                listener(&table_name, &row);
+            }
+        }
+    }
+
+    /// Forgets the rows held back without calling any listeners.
+    pub fn discard(&mut self) {
+        self.held = None;
+    }
+
+    pub fn queried(&self, query: &SelectQuery) {
+        for listener in &self.queried {
+            listener(query);
+        }
+    }
+
+    fn listeners(&self, event: RowEvent) -> &[RowListener] {
+        match event {
+            RowEvent::Inserted => &self.inserted,
+            RowEvent::Updated => &self.updated,
+            RowEvent::Deleted => &self.deleted
+        }
+    }
+
+    fn row_written(&mut self, event: RowEvent, table: &TableDescriptor, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        if self.listeners(event).is_empty() {
+            return Ok(());
+        }
+        let columns = table.columns.iter()
+            .map(|c| Ok((c.name.to_owned(), Value::from_bytes(&c.datatype, &bytes[c.offset..])?)))
+            .collect::<Result<Vec<_>, KronkError>>()?;
+        let row = Row { id: table.row_id(bytes, row_number), columns };
+        match &mut self.held {
+            Some(held) => held.push((event, table.table_name.to_owned(), row)),
+            None => for listener in self.listeners(event) {
+                listener(&table.table_name, &row);
+            }
+        }
+        Ok(())
+    }
+}
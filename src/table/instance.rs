@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use super::{db::Database, schema::ident, error::KronkError};
+
+/// A set of named databases opened side by side, with a current database
+/// that unqualified table references resolve against.
+pub struct Instance {
+    databases: HashMap<String, Database>,
+    current: Option<String>
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Instance {
+    pub fn new() -> Instance {
+        Instance { databases: HashMap::new(), current: None }
+    }
+
+    /// Adds a database. The first database added becomes the current one.
+    pub fn add_database(&mut self, db: Database) -> Result<(), KronkError> {
+        let name = db.name().to_owned();
+        if self.databases.contains_key(&name) {
+            return Err(KronkError::Schema(format!("Cannot add database with duplicate name '{}'", name)));
+        }
+        self.databases.insert(name.clone(), db);
+        if self.current.is_none() { self.current = Some(name); }
+        Ok(())
+    }
+
+    pub fn use_database(&mut self, db_name: &str) -> Result<(), KronkError> {
+        let db_name = ident::canonical(db_name);
+        if !self.databases.contains_key(&db_name) {
+            return Err(KronkError::Schema(format!("No database '{}' exists", db_name)));
+        }
+        self.current = Some(db_name);
+        Ok(())
+    }
+
+    pub fn current_name(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn database(&self, db_name: &str) -> Option<&Database> {
+        self.databases.get(&ident::canonical(db_name))
+    }
+
+    pub fn database_mut(&mut self, db_name: &str) -> Option<&mut Database> {
+        self.databases.get_mut(&ident::canonical(db_name))
+    }
+
+    fn resolve_name(&self, db_name: Option<&str>) -> Result<String, KronkError> {
+        match db_name {
+            Some(n) => Ok(ident::canonical(n)),
+            None => self.current.clone().ok_or_else(|| KronkError::Schema("No database selected: run 'use <db>' first".to_owned()))
+        }
+    }
+
+    /// The database a (possibly database-qualified) table reference points at.
+    pub fn resolve(&self, db_name: Option<&str>) -> Result<&Database, KronkError> {
+        let n = self.resolve_name(db_name)?;
+        self.databases.get(&n).ok_or_else(|| KronkError::Schema(format!("No database '{}' exists", n)))
+    }
+
+    pub fn resolve_mut(&mut self, db_name: Option<&str>) -> Result<&mut Database, KronkError> {
+        let n = self.resolve_name(db_name)?;
+        self.databases.get_mut(&n).ok_or_else(|| KronkError::Schema(format!("No database '{}' exists", n)))
+    }
+}
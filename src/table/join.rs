@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use super::{exec::QueryError, query::Join, schema::{ColumnDataType, TableColumn}, value::Value};
+
+/// Most pairs of rows an equality join compares one by one. Past this a
+/// hash join is cheaper than scanning the joined table for every row.
+pub const NESTED_LOOP_MAX_PAIRS: u64 = 64 * 1024;
+
+type JoinInput<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<u8>), QueryError>> + 'a>;
+
+/// How a join pairs up rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinStrategy {
+    /// Scans the joined table again for every row of the first, comparing
+    /// each pair. Works with any comparison, and costs nothing up front.
+    NestedLoop,
+    /// Reads the joined table once into a hash table keyed on the join
+    /// column, then looks each row of the first table up in it. Only for
+    /// joins on `==`.
+    Hash,
+    /// Like `Hash` the other way around: reads the first table into the hash
+    /// table and looks each row of the joined table up in it, for when the
+    /// first table is the smaller one. Rows come back in the joined table's
+    /// order instead of the first table's.
+    HashFirst
+}
+
+impl JoinStrategy {
+    /// Picks a strategy from the estimated number of rows on each side. A
+    /// hash table is built from the smaller side, unless the rows have to
+    /// stay in the first table's order.
+    pub fn choose(join: &Join, left_rows: u64, right_rows: u64, keep_order: bool) -> JoinStrategy {
+        if !join.is_equality() || left_rows.saturating_mul(right_rows) <= NESTED_LOOP_MAX_PAIRS {
+            JoinStrategy::NestedLoop
+        } else if !keep_order && left_rows < right_rows {
+            JoinStrategy::HashFirst
+        } else {
+            JoinStrategy::Hash
+        }
+    }
+}
+
+/// The joined table's rows, keyed on their join column.
+struct HashTable {
+    rows: Vec<(u64, Vec<u8>)>,
+    index: HashMap<Vec<u8>, Vec<usize>>
+}
+
+/// Rows of the joined table left to pair with the current row.
+enum Matches<'a> {
+    Scan(JoinInput<'a>),
+    Hashed(std::vec::IntoIter<usize>)
+}
+
+/// A joined table row being paired up with the first table's rows it
+/// matches: the row, and the matching rows it hasn't been paired with yet.
+type Probe = (Vec<u8>, std::vec::IntoIter<usize>);
+
+/// Rows of the first table, each followed by a row of the joined table it
+/// pairs up with. Rows come back in the first table's order, and the pairs
+/// of one row in the joined table's order, except with
+/// `JoinStrategy::HashFirst`. The joined table is only read once there's a
+/// row of the first table to pair with it.
+pub struct JoinedRows<'a> {
+    left: JoinInput<'a>,
+    /// Starts a new read of the joined table's rows.
+    right: Box<dyn Fn() -> JoinInput<'a> + 'a>,
+    join: &'a Join<'a>,
+    strategy: JoinStrategy,
+    hash_table: Option<HashTable>,
+    current: Option<(u64, Vec<u8>, Matches<'a>)>,
+    /// With `JoinStrategy::HashFirst`, the joined table's rows, and the one
+    /// being paired up with the first table's rows it matches.
+    probe: Option<(JoinInput<'a>, Option<Probe>)>
+}
+
+impl<'a> JoinedRows<'a> {
+    pub fn new(left: JoinInput<'a>, right: Box<dyn Fn() -> JoinInput<'a> + 'a>, join: &'a Join<'a>, strategy: JoinStrategy) -> JoinedRows<'a> {
+        JoinedRows { left, right, join, strategy, hash_table: None, current: None, probe: None }
+    }
+
+    /// Reads `rows` into a hash table keyed on `column`.
+    fn build(rows: JoinInput<'a>, column: &TableColumn) -> Result<HashTable, QueryError> {
+        let mut table = HashTable { rows: Vec::new(), index: HashMap::new() };
+        for row in rows {
+            let (row_number, bytes) = row?;
+            table.index.entry(join_key(column, &bytes)).or_default().push(table.rows.len());
+            table.rows.push((row_number, bytes));
+        }
+        Ok(table)
+    }
+
+    /// Next row of a `JoinStrategy::HashFirst` join.
+    fn next_probed_row(&mut self) -> Option<Result<(u64, Vec<u8>), QueryError>> {
+        let join = self.join;
+        if self.probe.is_none() {
+            let left = std::mem::replace(&mut self.left, Box::new(std::iter::empty()));
+            match Self::build(left, join.left) {
+                Ok(table) => self.hash_table = Some(table),
+                Err(e) => return Some(Err(e))
+            }
+            self.probe = Some(((self.right)(), None));
+        }
+        let table = self.hash_table.as_ref().expect("hash table should be built before probing");
+        let (rows, current) = self.probe.as_mut().unwrap();
+        loop {
+            if let Some((right, matches)) = current {
+                if let Some(i) = matches.next() {
+                    let (row_number, left) = &table.rows[i];
+                    let mut row = left.clone();
+                    row.extend_from_slice(right);
+                    return Some(Ok((*row_number, row)));
+                }
+            }
+            let right = match rows.next()? {
+                Ok((_, right)) => right,
+                Err(e) => return Some(Err(e))
+            };
+            let matches = table.index.get(&join_key(join.right, &right)).cloned().unwrap_or_default().into_iter();
+            *current = Some((right, matches));
+        }
+    }
+
+    fn next_row(&mut self) -> Option<Result<(u64, Vec<u8>), QueryError>> {
+        if self.strategy == JoinStrategy::HashFirst {
+            return self.next_probed_row();
+        }
+        let join = self.join;
+        loop {
+            if let Some((row_number, left, matches)) = &mut self.current {
+                let right = match matches {
+                    Matches::Scan(rows) => loop {
+                        let right = match rows.next() {
+                            Some(Ok((_, right))) => right,
+                            Some(Err(e)) => return Some(Err(e)),
+                            None => break None
+                        };
+                        match pairs(join, left, &right) {
+                            Ok(true) => break Some(right),
+                            Ok(false) => {},
+                            Err(e) => return Some(Err(e))
+                        }
+                    },
+                    Matches::Hashed(rows) => rows.next()
+                        .map(|i| self.hash_table.as_ref().expect("hash table should be built before probing").rows[i].1.clone())
+                };
+                if let Some(right) = right {
+                    let mut row = left.clone();
+                    row.extend(right);
+                    return Some(Ok((*row_number, row)));
+                }
+                self.current = None;
+            }
+
+            let (row_number, left) = match self.left.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e))
+            };
+            let matches = match self.strategy {
+                JoinStrategy::NestedLoop => Matches::Scan((self.right)()),
+                JoinStrategy::HashFirst => unreachable!("hash first joins are probed by the joined table's rows"),
+                JoinStrategy::Hash => {
+                    if self.hash_table.is_none() {
+                        match Self::build((self.right)(), join.right) {
+                            Ok(table) => self.hash_table = Some(table),
+                            Err(e) => return Some(Err(e))
+                        }
+                    }
+                    let table = self.hash_table.as_ref().unwrap();
+                    Matches::Hashed(table.index.get(&join_key(join.left, &left)).cloned().unwrap_or_default().into_iter())
+                }
+            };
+            self.current = Some((row_number, left, matches));
+        }
+    }
+}
+
+impl Iterator for JoinedRows<'_> {
+    type Item = Result<(u64, Vec<u8>), QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.next_row();
+        if matches!(row, Some(Err(_))) {
+            self.current = None;
+            self.left = Box::new(std::iter::empty());
+            self.probe = Some((Box::new(std::iter::empty()), None));
+        }
+        row
+    }
+}
+
+/// The bytes a row is looked up by in a hash join. Strings are cut off at
+/// their end, so strings of columns with different lengths still match.
+fn join_key(column: &TableColumn, row: &[u8]) -> Vec<u8> {
+    let stored = &row[column.offset..(column.offset + column.datatype.size_in_bytes())];
+    match column.datatype {
+        ColumnDataType::Byte(_) => stored.iter().copied().take_while(|b| *b != 0u8).collect(),
+        _ => stored.to_vec()
+    }
+}
+
+/// Whether a row of the first table and one of the joined table pair up.
+fn pairs(join: &Join, left: &[u8], right: &[u8]) -> Result<bool, QueryError> {
+    let decode = |column: &TableColumn, row: &[u8]| Value::from_bytes(&column.datatype, &row[column.offset..])
+        .map_err(|e| QueryError::Decode { column_name: column.name.to_owned(), source: Box::new(e) });
+    Ok(join.matches(&decode(join.left, left)?, &decode(join.right, right)?))
+}
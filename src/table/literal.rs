@@ -0,0 +1,76 @@
+use std::{any::type_name, fmt::Display, str::FromStr};
+
+use super::error::KronkError;
+
+// Literal syntax shared by the lexer, value parsing and result rendering.
+// The rules are fixed rather than taken from the host locale, so a value
+// reads back exactly as it was written everywhere:
+//
+// - integers are an optional leading `-` followed by ASCII digits, with no
+//   `+` sign, thousands separators or surrounding whitespace
+// - booleans are exactly `true` or `false`
+// - there are no fractional types yet; when they land, the decimal point is
+//   always `.`
+
+pub fn is_digit(c: char) -> bool {
+    c.is_ascii_digit()
+}
+
+/// True if `s` is written as an integer literal, regardless of range.
+pub fn is_integer(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(is_digit)
+}
+
+pub fn parse_integer<T>(s: &str) -> Result<T, KronkError> where T: FromStr {
+    if !is_integer(s) {
+        return Err(KronkError::Schema(format!("'{}' is not an integer literal", s)));
+    }
+    // the digits are already checked, so the only way left to fail is range
+    str::parse::<T>(s).map_err(|_| KronkError::Schema(format!("'{}' is out of range for {}", s, type_name::<T>())))
+}
+
+pub fn parse_boolean(s: &str) -> Result<bool, KronkError> {
+    match s {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(KronkError::Schema(format!("'{}' is not a boolean literal", s)))
+    }
+}
+
+pub fn format_integer<T>(i: T) -> String where T: Display {
+    // integer Display never groups digits or localizes the sign
+    i.to_string()
+}
+
+pub fn format_boolean(b: bool) -> &'static str {
+    if b { "true" } else { "false" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_read_back_as_written() {
+        for i in [0i64, 7, -7, i64::MIN, i64::MAX] {
+            assert_eq!(parse_integer::<i64>(&format_integer(i)).unwrap(), i);
+        }
+        for b in [true, false] {
+            assert_eq!(parse_boolean(format_boolean(b)).unwrap(), b);
+        }
+    }
+
+    #[test]
+    fn only_plain_literals_are_accepted() {
+        for s in ["", "-", "+1", " 1", "1 ", "1,000", "1_000", "1.0", "0x10", "١٢"] {
+            assert!(parse_integer::<i64>(s).is_err(), "{:?}", s);
+        }
+        assert_eq!(parse_integer::<i32>("-0").unwrap(), 0);
+        assert!(parse_integer::<i8>("128").unwrap_err().to_string().contains("out of range"));
+        assert!(parse_integer::<u32>("-1").is_err());
+        for s in ["True", "TRUE", "1", "yes", ""] {
+            assert!(parse_boolean(s).is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Condvar, Mutex}, time::{Duration, Instant}};
+use thiserror::Error;
+
+/// What a writer does when a row it's about to change is locked by another
+/// transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockWait {
+    /// fail right away
+    FailFast,
+    /// wait for the other transaction to let go of the row, failing if it
+    /// hasn't by the time this is up
+    Timeout(Duration)
+}
+
+/// Why a transaction couldn't lock a row. Whatever the reason, the row
+/// was left to the transaction holding it: drop this transaction and try
+/// again.
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("row {row_number} of '{table_name}' is locked by another transaction")]
+    Locked { table_name: String, row_number: u64 },
+
+    #[error("timed out waiting for row {row_number} of '{table_name}', locked by another transaction")]
+    TimedOut { table_name: String, row_number: u64 },
+
+    /// The transaction holding the row is waiting, directly or through
+    /// others, on a row this one holds, so neither would ever go on.
+    #[error("deadlock: row {row_number} of '{table_name}' is locked by a transaction waiting on this one")]
+    Deadlock { table_name: String, row_number: u64 }
+}
+
+impl From<LockError> for String {
+    fn from(e: LockError) -> String {
+        e.to_string()
+    }
+}
+
+#[derive(Debug)]
+struct LockState {
+    /// The transaction holding each locked row, by table and row number.
+    owners: HashMap<(String, u64), u64>,
+    /// The transaction each waiting transaction waits on.
+    waits_for: HashMap<u64, u64>,
+    next_transaction: u64,
+    on_conflict: LockWait
+}
+
+impl LockState {
+    /// Whether `transaction` waiting on the transaction it waits for would
+    /// have it wait on itself, all the way round.
+    fn is_deadlocked(&self, transaction: u64) -> bool {
+        let mut seen = HashSet::new();
+        let mut waiting_on = self.waits_for.get(&transaction);
+        while let Some(other) = waiting_on {
+            if *other == transaction {
+                return true;
+            }
+            if !seen.insert(*other) {
+                return false;
+            }
+            waiting_on = self.waits_for.get(other);
+        }
+        false
+    }
+}
+
+/// Row locks held by a database's transactions. Rows of the same table
+/// locked by different transactions don't get in each other's way; a
+/// transaction wanting a row another holds waits or fails, depending on
+/// `LockWait`. One that would wait on a transaction waiting on it fails
+/// straight away.
+///
+/// A transaction waits holding nothing but its rows, so writers have to
+/// lock their rows before taking anything else the other transactions need
+/// to go on, such as `DatabaseHandle`'s write guard.
+#[derive(Debug)]
+pub struct LockManager {
+    state: Mutex<LockState>,
+    released: Condvar
+}
+
+/// Holds the rows it has locked until it's dropped.
+#[derive(Debug)]
+pub struct Transaction {
+    id: u64,
+    locks: Arc<LockManager>
+}
+
+impl LockManager {
+    pub fn new(on_conflict: LockWait) -> LockManager {
+        LockManager {
+            state: Mutex::new(LockState { owners: HashMap::new(), waits_for: HashMap::new(), next_transaction: 0, on_conflict }),
+            released: Condvar::new()
+        }
+    }
+
+    pub fn set_on_conflict(&self, on_conflict: LockWait) {
+        self.state.lock().unwrap().on_conflict = on_conflict;
+    }
+
+    pub fn begin(locks: &Arc<LockManager>) -> Transaction {
+        let mut state = locks.state.lock().unwrap();
+        let id = state.next_transaction;
+        state.next_transaction += 1;
+        Transaction { id, locks: locks.clone() }
+    }
+}
+
+impl Transaction {
+    /// Locks `row_numbers` of `table_name` for this transaction. Either all
+    /// of them are locked or, if one stays held by another transaction,
+    /// none are.
+    pub fn lock_rows(&self, table_name: &str, row_numbers: &[u64]) -> Result<(), LockError> {
+        let mut state = self.locks.state.lock().unwrap();
+        let deadline = match state.on_conflict {
+            LockWait::FailFast => None,
+            LockWait::Timeout(timeout) => Some(Instant::now() + timeout)
+        };
+        loop {
+            let held = row_numbers.iter()
+                .find_map(|row_number| state.owners.get(&(table_name.to_owned(), *row_number)).filter(|owner| **owner != self.id).map(|owner| (*row_number, *owner)));
+            let (row_number, owner) = match held {
+                Some(held) => held,
+                None => break
+            };
+            let deadline = match deadline {
+                Some(deadline) => deadline,
+                None => return Err(LockError::Locked { table_name: table_name.to_owned(), row_number })
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                state.waits_for.remove(&self.id);
+                return Err(LockError::TimedOut { table_name: table_name.to_owned(), row_number });
+            }
+            state.waits_for.insert(self.id, owner);
+            if state.is_deadlocked(self.id) {
+                state.waits_for.remove(&self.id);
+                return Err(LockError::Deadlock { table_name: table_name.to_owned(), row_number });
+            }
+            state = self.locks.released.wait_timeout(state, remaining).unwrap().0;
+        }
+        state.waits_for.remove(&self.id);
+        for row_number in row_numbers {
+            state.owners.insert((table_name.to_owned(), *row_number), self.id);
+        }
+        Ok(())
+    }
+
+    /// Whether this transaction holds every one of `row_numbers` of
+    /// `table_name`.
+    pub fn holds(&self, table_name: &str, mut row_numbers: impl Iterator<Item = u64>) -> bool {
+        let state = self.locks.state.lock().unwrap();
+        row_numbers.all(|row_number| state.owners.get(&(table_name.to_owned(), row_number)) == Some(&self.id))
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        let mut state = self.locks.state.lock().unwrap();
+        state.owners.retain(|_, owner| *owner != self.id);
+        state.waits_for.remove(&self.id);
+        self.locks.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait_until_waiting(locks: &LockManager, transaction: u64) {
+        while !locks.state.lock().unwrap().waits_for.contains_key(&transaction) {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn rows_of_a_table_lock_separately() {
+        let locks = Arc::new(LockManager::new(LockWait::FailFast));
+        let (a, b) = (LockManager::begin(&locks), LockManager::begin(&locks));
+        a.lock_rows("books", &[1, 2]).unwrap();
+        b.lock_rows("books", &[3]).unwrap();
+        b.lock_rows("reviews", &[1]).unwrap();
+        assert!(matches!(b.lock_rows("books", &[3, 2]), Err(LockError::Locked { row_number: 2, .. })));
+        // none of them were locked
+        assert!(!b.holds("books", [2].into_iter()));
+        assert!(a.holds("books", [1, 2].into_iter()));
+        // locking rows again is fine
+        a.lock_rows("books", &[1]).unwrap();
+        drop(a);
+        b.lock_rows("books", &[2]).unwrap();
+    }
+
+    #[test]
+    fn waiters_get_the_row_once_it_is_let_go() {
+        let locks = Arc::new(LockManager::new(LockWait::Timeout(Duration::from_secs(30))));
+        let a = LockManager::begin(&locks);
+        let b = LockManager::begin(&locks);
+        a.lock_rows("books", &[1]).unwrap();
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| b.lock_rows("books", &[1]));
+            wait_until_waiting(&locks, b.id);
+            drop(a);
+            waiting.join().unwrap().unwrap();
+        });
+        assert!(b.holds("books", [1].into_iter()));
+    }
+
+    #[test]
+    fn waiters_time_out() {
+        let locks = Arc::new(LockManager::new(LockWait::Timeout(Duration::from_millis(20))));
+        let (a, b) = (LockManager::begin(&locks), LockManager::begin(&locks));
+        a.lock_rows("books", &[1]).unwrap();
+        assert!(matches!(b.lock_rows("books", &[1]), Err(LockError::TimedOut { row_number: 1, .. })));
+        assert!(locks.state.lock().unwrap().waits_for.is_empty());
+    }
+
+    #[test]
+    fn waiting_on_a_waiter_is_a_deadlock() {
+        let locks = Arc::new(LockManager::new(LockWait::Timeout(Duration::from_secs(30))));
+        let (a, b, c) = (LockManager::begin(&locks), LockManager::begin(&locks), LockManager::begin(&locks));
+        a.lock_rows("books", &[1]).unwrap();
+        b.lock_rows("books", &[2]).unwrap();
+        c.lock_rows("books", &[3]).unwrap();
+        let (a_id, b_id) = (a.id, b.id);
+        std::thread::scope(|s| {
+            // a waits on b, and b on c
+            let a_waiting = s.spawn(move || a.lock_rows("books", &[2]));
+            wait_until_waiting(&locks, a_id);
+            let b_waiting = s.spawn(move || b.lock_rows("books", &[3]));
+            wait_until_waiting(&locks, b_id);
+            // so c can't wait on a
+            assert!(matches!(c.lock_rows("books", &[1]), Err(LockError::Deadlock { row_number: 1, .. })));
+            // once c goes, b gets its row and goes too, and then a gets its
+            drop(c);
+            b_waiting.join().unwrap().unwrap();
+            a_waiting.join().unwrap().unwrap();
+        });
+    }
+}
@@ -2,4 +2,32 @@ pub mod schema;
 pub mod query;
 pub mod store;
 pub mod db;
-pub mod bytes;
\ No newline at end of file
+pub mod bytes;
+pub mod exec;
+pub mod admission;
+pub mod format;
+pub mod literal;
+pub mod instance;
+pub mod session;
+pub mod progress;
+pub mod stats;
+pub mod catalog;
+pub mod value;
+pub mod spill;
+pub mod sort;
+pub mod aggregate;
+pub mod group;
+pub mod join;
+pub mod plan;
+pub mod prepared;
+pub mod lock;
+pub mod handle;
+pub mod record;
+pub mod hooks;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod error;
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,258 @@
+use std::{fmt::Display, ops::RangeInclusive};
+
+use itertools::Itertools;
+
+use super::{literal, exec::TableStores, join::JoinStrategy, query::{Join, OrderBy, SelectColumn, SelectQuery, WhereCondition, WherePredicate}, schema::{TableColumn, TableDescriptor}, store::ByteStore};
+
+/// Cost of reading a row through an index, relative to reading one as part
+/// of a scan. Index reads jump around the table instead of reading it in
+/// order.
+const RANDOM_READ_COST: f64 = 4.0;
+
+/// How a scan finds the rows it reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessPath {
+    /// Reads every row of the table.
+    FullScan,
+    /// Reads just the rows whose serial ids the where clause lets through,
+    /// found through the id index.
+    IdIndex { rows: Vec<u64> },
+    /// Reads just the rows an index on `column_name` points at.
+    ColumnIndex { column_name: String, rows: Vec<u64> }
+}
+
+impl AccessPath {
+    /// The cheapest way to find the rows that can pass `predicate`. Each
+    /// condition an index can narrow down is estimated from the table's row
+    /// count and histograms, most selective first, and an index is only used
+    /// if reading the rows it points at one by one costs less than scanning
+    /// the table. Conditions on columns without a histogram are tried last,
+    /// and every index's rows are counted before it's picked, in case the
+    /// estimate was off.
+    pub fn choose(predicate: Option<&WherePredicate>, store: &dyn ByteStore) -> AccessPath {
+        let predicate = match predicate {
+            Some(predicate) => predicate,
+            None => return AccessPath::FullScan
+        };
+        let table_rows = estimated_rows(store) as f64;
+        let cheaper = |rows: f64| rows * RANDOM_READ_COST <= table_rows;
+
+        let mut candidates: Vec<(Option<f64>, Option<&WhereCondition>)> = Vec::new();
+        if let Some(ids) = predicate.id_range() {
+            candidates.push((Some(id_selectivity(&ids, store) * table_rows), None));
+        }
+        for c in predicate.required_conditions() {
+            if let (Some(keys), false) = (c.key_range(), c.comparison.id_range().is_some()) {
+                candidates.push((store.histogram(&c.column.name).map(|h| h.selectivity(&keys) * table_rows), Some(c)));
+            }
+        }
+        // estimated conditions first, fewest rows first, and the rest in the
+        // order they were written
+        candidates.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            _ => a.is_none().cmp(&b.is_none())
+        });
+
+        for (estimate, condition) in candidates {
+            if estimate.is_some_and(|rows| !cheaper(rows)) {
+                continue;
+            }
+            let path = match condition {
+                None => predicate.id_range()
+                    .and_then(|ids| store.rows_in_id_range(ids))
+                    .map(|rows| AccessPath::IdIndex { rows }),
+                Some(c) => c.key_range()
+                    .and_then(|keys| store.rows_in_key_range(&c.column.name, keys))
+                    .map(|rows| AccessPath::ColumnIndex { column_name: c.column.name.to_owned(), rows })
+            };
+            if let Some(path) = path.filter(|p| cheaper(p.rows().len() as f64)) {
+                return path;
+            }
+        }
+        AccessPath::FullScan
+    }
+
+    fn rows(&self) -> &[u64] {
+        match self {
+            AccessPath::FullScan => &[],
+            AccessPath::IdIndex { rows } | AccessPath::ColumnIndex { rows, .. } => rows
+        }
+    }
+
+    /// Row numbers an index narrowed the scan down to, `None` for a full scan.
+    pub fn into_rows(self) -> Option<Vec<u64>> {
+        match self {
+            AccessPath::FullScan => None,
+            AccessPath::IdIndex { rows } | AccessPath::ColumnIndex { rows, .. } => Some(rows)
+        }
+    }
+}
+
+impl Display for AccessPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessPath::FullScan => write!(f, "(full scan)"),
+            AccessPath::IdIndex { rows } => write!(f, "using id index ({} rows)", literal::format_integer(rows.len())),
+            AccessPath::ColumnIndex { column_name, rows } => write!(f, "using index on {} ({} rows)", column_name, literal::format_integer(rows.len()))
+        }
+    }
+}
+
+/// One operator of a query plan. Scans, filters, sorts and joins pass table
+/// rows up the tree, as row numbers and bytes. Projections and aggregates
+/// turn those into result rows, and a limit cuts the result rows short.
+#[derive(Debug, Clone)]
+pub enum PlanNode<'a> {
+    /// A single empty row, for selects without a from clause.
+    Empty,
+    /// A table's rows, as of `snapshot` if there is one. Only the bytes of
+    /// `columns` are sure to be read.
+    Scan { table: &'a TableDescriptor, access: AccessPath, columns: Vec<&'a TableColumn>, snapshot: Option<u64> },
+    /// Rows of `input` that pass the where clause. Run over a scan, rows are
+    /// checked as they're read, before they're copied out.
+    Filter { input: Box<PlanNode<'a>>, predicate: &'a WherePredicate<'a> },
+    Sort { input: Box<PlanNode<'a>>, order_by: &'a [OrderBy<'a>] },
+    /// Each row of `left` followed by each row of `right` it pairs up with.
+    Join { left: Box<PlanNode<'a>>, right: Box<PlanNode<'a>>, join: &'a Join<'a>, strategy: JoinStrategy },
+    /// A result row per group, or a single one for the whole input when
+    /// `group_by` is empty.
+    Aggregate { input: Box<PlanNode<'a>>, columns: &'a [SelectColumn<'a>], group_by: &'a [&'a TableColumn] },
+    /// A result row per input row. `table` is what row ids are read from.
+    Project { input: Box<PlanNode<'a>>, table: Option<&'a TableDescriptor>, columns: &'a [SelectColumn<'a>] },
+    Limit { input: Box<PlanNode<'a>>, limit: u64 }
+}
+
+impl<'a> PlanNode<'a> {
+    /// Plans a bound query. Rows are filtered as they're scanned, and sorted
+    /// before they're joined, since a join keeps the first table's order.
+    /// Access paths and the join strategy are picked from the table stores.
+    pub fn for_query(query: &'a SelectQuery, stores: &TableStores) -> PlanNode<'a> {
+        let mut node = match query.table {
+            Some(table) => {
+                let store = stores.get(&table.table_name).expect("backing store here shold be populated").as_ref();
+                let access = AccessPath::choose(query.where_predicate.as_ref(), store);
+                let left_rows = match &access {
+                    AccessPath::FullScan => estimated_matches(query.where_predicate.as_ref(), store),
+                    AccessPath::IdIndex { rows } | AccessPath::ColumnIndex { rows, .. } => rows.len() as u64
+                };
+                let mut node = PlanNode::Scan { table, access, columns: query.columns_used(), snapshot: query.snapshot };
+                if let Some(predicate) = &query.where_predicate {
+                    node = PlanNode::Filter { input: Box::new(node), predicate };
+                }
+                if !query.order_by.is_empty() {
+                    node = PlanNode::Sort { input: Box::new(node), order_by: &query.order_by };
+                }
+                if let Some(join) = &query.join {
+                    let joined_store = stores.get(&join.table.table_name).expect("backing store here shold be populated").as_ref();
+                    let right = PlanNode::Scan { table: join.table, access: AccessPath::FullScan, columns: query.joined_columns_used(), snapshot: query.snapshot };
+                    let strategy = JoinStrategy::choose(join, left_rows, estimated_rows(joined_store), !query.order_by.is_empty());
+                    node = PlanNode::Join { left: Box::new(node), right: Box::new(right), join, strategy };
+                }
+                node
+            },
+            None => PlanNode::Empty
+        };
+
+        node = match query.is_aggregate() || !query.group_by.is_empty() {
+            true => PlanNode::Aggregate { input: Box::new(node), columns: &query.columns, group_by: &query.group_by },
+            false => PlanNode::Project { input: Box::new(node), table: query.table, columns: &query.columns }
+        };
+        if let Some(limit) = query.limit {
+            node = PlanNode::Limit { input: Box::new(node), limit };
+        }
+        node
+    }
+
+    /// The plan as text, a line per operator with its inputs indented below
+    /// it. Filters run as part of a scan say so.
+    pub fn explain(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.explain_into(0, &mut lines);
+        lines
+    }
+
+    fn explain_into(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        let names = |columns: &[SelectColumn]| columns.iter().map(|c| c.name.as_str()).join(", ");
+        lines.push(format!("{}{}", indent, match self {
+            PlanNode::Empty => "Empty row".to_owned(),
+            PlanNode::Scan { table, access, .. } => format!("Scan {} {}", table.table_name, access),
+            PlanNode::Filter { input, predicate } => match **input {
+                PlanNode::Scan { .. } => format!("Filter {} (during scan)", predicate),
+                _ => format!("Filter {}", predicate)
+            },
+            PlanNode::Sort { order_by, .. } => format!("Sort by {}", order_by.iter()
+                .map(|o| format!("{}{}", o.column.name, if o.descending { " desc" } else { "" }))
+                .join(", ")),
+            PlanNode::Join { join, strategy, .. } => format!("{} on {}", match strategy {
+                JoinStrategy::NestedLoop => "Nested loop join",
+                JoinStrategy::Hash => "Hash join",
+                JoinStrategy::HashFirst => "Hash join, hashing the first table,"
+            }, join),
+            PlanNode::Aggregate { columns, group_by: [], .. } => format!("Aggregate {}", names(columns)),
+            PlanNode::Aggregate { columns, group_by, .. } => format!("Group by {}: {}", group_by.iter().map(|c| c.name.as_str()).join(", "), names(columns)),
+            PlanNode::Project { columns, .. } => format!("Project {}", names(columns)),
+            PlanNode::Limit { limit, .. } => format!("Limit {}", literal::format_integer(limit))
+        }));
+        match self {
+            PlanNode::Empty | PlanNode::Scan { .. } => {},
+            PlanNode::Join { left, right, .. } => {
+                left.explain_into(depth + 1, lines);
+                right.explain_into(depth + 1, lines);
+            },
+            PlanNode::Filter { input, .. } | PlanNode::Sort { input, .. } | PlanNode::Aggregate { input, .. }
+                | PlanNode::Project { input, .. } | PlanNode::Limit { input, .. } => input.explain_into(depth + 1, lines)
+        }
+    }
+
+    /// Bytes in each row the node passes up. Only meaningful for nodes that
+    /// pass up table rows.
+    pub fn row_size(&self) -> usize {
+        match self {
+            PlanNode::Empty => 0,
+            PlanNode::Scan { table, .. } => table.total_row_size(),
+            PlanNode::Join { left, right, .. } => left.row_size() + right.row_size(),
+            PlanNode::Filter { input, .. } | PlanNode::Sort { input, .. } | PlanNode::Aggregate { input, .. }
+                | PlanNode::Project { input, .. } | PlanNode::Limit { input, .. } => input.row_size()
+        }
+    }
+}
+
+/// Live rows in a store, or as many as there could be if it can't say.
+fn estimated_rows(store: &dyn ByteStore) -> u64 {
+    store.row_count().map_or(u64::MAX, |n| n.saturating_sub(store.deleted_count()))
+}
+
+/// Share of a table's rows, from 0 to 1, with serial ids in `ids`. Ids are
+/// handed out from 0 up, so they run about as high as the row count.
+fn id_selectivity(ids: &RangeInclusive<u64>, store: &dyn ByteStore) -> f64 {
+    let row_count = match store.row_count() {
+        Ok(0) | Err(_) => return 1.0,
+        Ok(n) => n
+    };
+    let high = (*ids.end()).min(row_count - 1);
+    match *ids.start() <= high {
+        true => (high - ids.start() + 1) as f64 / row_count as f64,
+        false => 0.0
+    }
+}
+
+/// Live rows estimated to pass `predicate`, taking its required conditions
+/// to be independent of each other and the rest of it to let every row
+/// through. A condition on a column without a histogram
+/// is taken to let every row through.
+fn estimated_matches(predicate: Option<&WherePredicate>, store: &dyn ByteStore) -> u64 {
+    let rows = estimated_rows(store);
+    let conditions = match predicate {
+        Some(predicate) => predicate.required_conditions(),
+        None => return rows
+    };
+    let selectivity: f64 = conditions.into_iter()
+        .map(|c| match (c.comparison.id_range(), c.key_range()) {
+            (Some(ids), _) => id_selectivity(&ids, store),
+            (None, Some(keys)) => store.histogram(&c.column.name).map_or(1.0, |h| h.selectivity(&keys)),
+            (None, None) => 1.0
+        })
+        .product();
+    (rows as f64 * selectivity).ceil() as u64
+}
@@ -0,0 +1,41 @@
+use super::{db::Database, error::KronkError, exec::RowIterator, query::{SelectQuery, WherePredicate}};
+
+/// A select parsed and bound once, then run any number of times with
+/// different values for its `?` placeholders. Runs only parse the values.
+/// The plan is still made on each run, since which index is worth reading
+/// depends on them.
+pub struct PreparedStatement<'db> {
+    db: &'db Database,
+    query: SelectQuery<'db>,
+    /// The query's where clause with its placeholders still in it.
+    predicate: Option<WherePredicate<'db>>
+}
+
+impl<'db> PreparedStatement<'db> {
+    pub fn new(db: &'db Database, query: SelectQuery<'db>) -> PreparedStatement<'db> {
+        let predicate = query.where_predicate.clone();
+        PreparedStatement { db, query, predicate }
+    }
+
+    pub fn query(&self) -> &SelectQuery<'db> {
+        &self.query
+    }
+
+    /// Number of values each run takes.
+    pub fn parameter_count(&self) -> usize {
+        self.query.parameters.len()
+    }
+
+    /// Runs the statement with `values` in place of its placeholders, in the
+    /// order they're written.
+    pub fn execute(&mut self, values: &[&str]) -> Result<RowIterator<'_>, KronkError> {
+        if values.len() != self.parameter_count() {
+            return Err(KronkError::Parse(format!("Statement takes {} values, but {} were given", self.parameter_count(), values.len())));
+        }
+        let conditions = self.query.parameters.iter().zip(values)
+            .map(|(p, v)| p.with_value(v))
+            .collect::<Result<Vec<_>, KronkError>>()?;
+        self.query.where_predicate = self.predicate.as_ref().map(|p| p.with_parameters(&conditions));
+        Ok(self.db.query(&self.query)?)
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+/// How many rows a long-running operation processes between progress reports.
+pub const PROGRESS_REPORT_INTERVAL: u64 = 4096;
+
+/// A snapshot of a long-running operation, handed to progress callbacks.
+#[derive(Debug, Clone)]
+pub struct OperationProgress {
+    pub operation: &'static str,
+    /// The table worked on, or the database for backups.
+    pub table_name: String,
+    pub rows_processed: u64,
+    /// Known up front for operations that walk a whole table.
+    pub rows_total: Option<u64>,
+    /// Bytes written for operations that rewrite data, bytes read otherwise.
+    pub bytes_processed: u64,
+    /// Known up front for backups, which copy files rather than rows.
+    pub bytes_total: Option<u64>,
+    started: Instant
+}
+
+impl OperationProgress {
+    pub fn new(operation: &'static str, table_name: &str, rows_total: Option<u64>) -> OperationProgress {
+        OperationProgress {
+            operation,
+            table_name: table_name.to_owned(),
+            rows_processed: 0,
+            rows_total,
+            bytes_processed: 0,
+            bytes_total: None,
+            started: Instant::now()
+        }
+    }
+
+    /// Progress of copying `bytes_total` bytes of `db_name`'s files.
+    pub fn for_files(operation: &'static str, db_name: &str, bytes_total: u64) -> OperationProgress {
+        OperationProgress { bytes_total: Some(bytes_total), ..Self::new(operation, db_name, None) }
+    }
+
+    /// How much is done and how much there is in all, in rows, or in bytes
+    /// for operations that only know their total in bytes.
+    fn done_of_total(&self) -> Option<(u64, u64)> {
+        match (self.rows_total, self.bytes_total) {
+            (Some(total), _) => Some((self.rows_processed, total)),
+            (None, Some(total)) => Some((self.bytes_processed, total)),
+            (None, None) => None
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn fraction_done(&self) -> Option<f64> {
+        match self.done_of_total()? {
+            (_, 0) => Some(1.0),
+            (done, total) => Some((done as f64 / total as f64).min(1.0))
+        }
+    }
+
+    /// Estimated time left, extrapolated from the rate so far. `None` until
+    /// there is a total and some of it has been processed.
+    pub fn eta(&self) -> Option<Duration> {
+        let (done, total) = self.done_of_total()?;
+        if done == 0 { return None; }
+        let remaining = total.saturating_sub(done);
+        Some(self.elapsed().mul_f64(remaining as f64 / done as f64))
+    }
+
+    /// Counts one more row and reports every `PROGRESS_REPORT_INTERVAL` rows.
+    pub fn advance(&mut self, bytes: usize, on_progress: &mut dyn FnMut(&OperationProgress)) {
+        self.rows_processed += 1;
+        self.bytes_processed += bytes as u64;
+        if self.rows_processed.is_multiple_of(PROGRESS_REPORT_INTERVAL) {
+            on_progress(self);
+        }
+    }
+
+    /// Counts a file of `bytes` more copied and reports it.
+    pub fn copied(&mut self, bytes: u64, on_progress: &mut dyn FnMut(&OperationProgress)) {
+        self.bytes_processed += bytes;
+        on_progress(self);
+    }
+}
+
+impl std::fmt::Display for OperationProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} '{}': ", self.operation, self.table_name)?;
+        match self.bytes_total {
+            Some(total) => write!(f, "{} of {} bytes", self.bytes_processed, total)?,
+            None => {
+                write!(f, "{} rows", self.rows_processed)?;
+                if let Some(total) = self.rows_total {
+                    write!(f, " of {}", total)?;
+                }
+                write!(f, ", {} bytes", self.bytes_processed)?;
+            }
+        }
+        if let Some(eta) = self.eta() {
+            write!(f, ", about {}s left", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use super::{SelectQuery, types::{RawSelectQuery, RawSelectQueryColumn, RawSelectQueryColumnExpression, RawSelectColumnReference, RawSelectQueryWhereExpression, RawSelectQueryWhereComparison, RawSelectQueryWhereExpressionOperator, RawOrderBy}};
+use super::super::{db::Database, error::KronkError, value::{ResultSet, Value}};
+
+/// A select put together in code instead of parsed from text, started with
+/// `Database::select`. It's bound just like the parsed select it stands for,
+/// so both run the same way.
+pub struct SelectBuilder<'a> {
+    db: &'a Database,
+    query: RawSelectQuery
+}
+
+/// A where clause for `SelectBuilder::filter`. Combine them with `and`, `or`
+/// and `!`.
+#[derive(Debug)]
+pub struct Filter(RawSelectQueryWhereExpression);
+
+impl<'a> SelectBuilder<'a> {
+    /// A select of every column of `table_name`.
+    pub fn new(db: &'a Database, table_name: &str) -> SelectBuilder<'a> {
+        SelectBuilder {
+            db,
+            query: RawSelectQuery {
+                database_name: None,
+                table_name: Some(table_name.to_owned()),
+                table_identifier: None,
+                columns: vec![RawSelectQueryColumn { expression: RawSelectQueryColumnExpression::AllColumns, as_name: None }],
+                join: None,
+                where_expression: None,
+                group_by: Vec::new(),
+                order_by: Vec::new(),
+                limit: None
+            }
+        }
+    }
+
+    /// Selects just these columns, in this order.
+    pub fn columns(mut self, column_names: &[&str]) -> SelectBuilder<'a> {
+        self.query.columns = column_names.iter()
+            .map(|name| RawSelectQueryColumn { expression: RawSelectQueryColumnExpression::Column(column_reference(name)), as_name: None })
+            .collect();
+        self
+    }
+
+    /// Keeps only the rows matching `filter`, and any filters added before.
+    pub fn filter(mut self, filter: Filter) -> SelectBuilder<'a> {
+        self.query.where_expression = Some(match self.query.where_expression.take() {
+            Some(previous) => RawSelectQueryWhereExpression::And(Box::new(previous), Box::new(filter.0)),
+            None => filter.0
+        });
+        self
+    }
+
+    /// Orders the rows by `column_name`, after any columns ordered by before.
+    pub fn order_by(mut self, column_name: &str) -> SelectBuilder<'a> {
+        self.query.order_by.push(RawOrderBy { column: column_reference(column_name), descending: false });
+        self
+    }
+
+    /// Like `order_by`, largest first.
+    pub fn order_by_desc(mut self, column_name: &str) -> SelectBuilder<'a> {
+        self.query.order_by.push(RawOrderBy { column: column_reference(column_name), descending: true });
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> SelectBuilder<'a> {
+        self.query.limit = Some(limit);
+        self
+    }
+
+    /// Binds the select against the database, for `Database::query` and the
+    /// like.
+    pub fn build(self) -> Result<SelectQuery<'a>, KronkError> {
+        self.db.bind(&self.query)
+    }
+
+    /// Runs the select to the end.
+    pub fn run(self) -> Result<ResultSet, KronkError> {
+        let db = self.db;
+        Ok(db.query_result_set(&self.build()?)?)
+    }
+}
+
+impl Filter {
+    pub fn eq(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::EqualEqual, value.into())
+    }
+
+    pub fn ne(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::NotEqual, value.into())
+    }
+
+    pub fn lt(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::LessThan, value.into())
+    }
+
+    pub fn le(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::LessEqual, value.into())
+    }
+
+    pub fn gt(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::GreaterThan, value.into())
+    }
+
+    pub fn ge(column_name: &str, value: impl Into<Value>) -> Filter {
+        Filter::compare(column_name, RawSelectQueryWhereExpressionOperator::GreaterEqual, value.into())
+    }
+
+    pub fn and(self, other: Filter) -> Filter {
+        Filter(RawSelectQueryWhereExpression::And(Box::new(self.0), Box::new(other.0)))
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        Filter(RawSelectQueryWhereExpression::Or(Box::new(self.0), Box::new(other.0)))
+    }
+
+    /// The value is compared the way its literal would be in a where clause.
+    fn compare(column_name: &str, op: RawSelectQueryWhereExpressionOperator, value: Value) -> Filter {
+        Filter(RawSelectQueryWhereExpression::Single(RawSelectQueryWhereComparison { column: column_reference(column_name), op, value: Some(value.to_string()) }))
+    }
+}
+
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter(RawSelectQueryWhereExpression::Not(Box::new(self.0)))
+    }
+}
+
+/// `title`, or `b.title` for a column of the table identified as `b`.
+fn column_reference(name: &str) -> RawSelectColumnReference {
+    match name.split_once('.') {
+        Some((table_identifier, column_name)) => RawSelectColumnReference { column_name: column_name.to_owned(), table_identifier: Some(table_identifier.to_owned()) },
+        None => RawSelectColumnReference { column_name: name.to_owned(), table_identifier: None }
+    }
+}
@@ -1,5 +1,6 @@
-use std::{iter::{Peekable, Map}, cell::Cell, ops::Range, fmt::{Display, write}};
+use std::fmt::Display;
 use super::types::*;
+use super::super::literal;
 
 
 #[derive(Debug)]
@@ -14,9 +15,26 @@ pub enum KeywordToken {
     Select,
     From,
     Where,
+    Join,
     As,
     Insert,
-    Into
+    Into,
+    Use,
+    Create,
+    Index,
+    On,
+    Using,
+    Order,
+    Group,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Explain,
+    Analyze,
+    And,
+    Or,
+    Not
 }
 
 impl TryFrom<&str> for KeywordToken {
@@ -26,9 +44,26 @@ impl TryFrom<&str> for KeywordToken {
             "select" => Ok(Self::Select),
             "from" => Ok(Self::From),
             "where" => Ok(Self::Where),
+            "join" => Ok(Self::Join),
             "as" => Ok(Self::As),
             "insert" => Ok(Self::Insert),
             "into" => Ok(Self::Into),
+            "use" => Ok(Self::Use),
+            "create" => Ok(Self::Create),
+            "index" => Ok(Self::Index),
+            "on" => Ok(Self::On),
+            "using" => Ok(Self::Using),
+            "order" => Ok(Self::Order),
+            "group" => Ok(Self::Group),
+            "by" => Ok(Self::By),
+            "asc" => Ok(Self::Asc),
+            "desc" => Ok(Self::Desc),
+            "limit" => Ok(Self::Limit),
+            "explain" => Ok(Self::Explain),
+            "analyze" => Ok(Self::Analyze),
+            "and" => Ok(Self::And),
+            "or" => Ok(Self::Or),
+            "not" => Ok(Self::Not),
             _ => Err(())
         }
     }
@@ -41,15 +76,32 @@ impl ToStaticStr for KeywordToken {
             KeywordToken::From => "from",
             KeywordToken::Select => "select",
             KeywordToken::Where => "where",
+            KeywordToken::Join => "join",
             KeywordToken::Insert => "insert",
-            KeywordToken::Into => "into"
+            KeywordToken::Into => "into",
+            KeywordToken::Use => "use",
+            KeywordToken::Create => "create",
+            KeywordToken::Index => "index",
+            KeywordToken::On => "on",
+            KeywordToken::Using => "using",
+            KeywordToken::Order => "order",
+            KeywordToken::Group => "group",
+            KeywordToken::By => "by",
+            KeywordToken::Asc => "asc",
+            KeywordToken::Desc => "desc",
+            KeywordToken::Limit => "limit",
+            KeywordToken::Explain => "explain",
+            KeywordToken::Analyze => "analyze",
+            KeywordToken::And => "and",
+            KeywordToken::Or => "or",
+            KeywordToken::Not => "not"
         }
     }
 }
 
 impl Display for KeywordToken {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", Self::static_str(&self))
+        write!(f, "{}", Self::static_str(self))
     }
 }
 
@@ -67,7 +119,10 @@ pub enum CharacterToken {
     LessThan,
     LessEqual,
     EqualEqual,
-    NotEqual
+    NotEqual,
+    Star,
+    /// `?`, a placeholder for a value given when a prepared statement runs
+    Question
 }
 
 trait ToStaticStr {
@@ -90,13 +145,15 @@ impl ToStaticStr for CharacterToken {
             CharacterToken::RightParen => ")",
             CharacterToken::LeftBracket => "{",
             CharacterToken::RightBracket => "}",
+            CharacterToken::Star => "*",
+            CharacterToken::Question => "?",
         }
     }
 }
 
 impl Display for CharacterToken {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", Self::static_str(&self))
+        write!(f, "{}", Self::static_str(self))
     }
 }
 
@@ -120,7 +177,9 @@ impl TryFrom<CharacterToken> for RawSelectQueryWhereExpressionOperator {
 pub enum QueryToken {
     Character(CharacterToken),
     Keyword(KeywordToken),
-    String(String)
+    String(String),
+    Quoted(String),
+    Number(String)
 }
 
 impl std::fmt::Display for QueryToken {
@@ -128,31 +187,13 @@ impl std::fmt::Display for QueryToken {
         match self {
             Self::Character(c) => write!(f, "char({})", c),
             Self::Keyword(k) => write!(f, "kw({})", k),
-            Self::String(s) => write!(f, "string({})", s)
+            Self::String(s) => write!(f, "string({})", s),
+            Self::Quoted(s) => write!(f, "quoted({})", s),
+            Self::Number(n) => write!(f, "number({})", n)
         }
     }
 }
 
-impl QueryToken {
-    fn expect_keyword(&self, expected: KeywordToken) -> Result<(), ParsingError> {
-        if let QueryToken::Keyword(kw_token) = self {
-            if *kw_token == expected { return Ok(()); }
-        }
-        Err(ParsingError::UnexpectedToken(QueryToken::Keyword(expected), self.clone()))
-    }
-
-    fn expect_character(&self, expected: CharacterToken) -> Result<(), ParsingError> {
-        if let QueryToken::Character(c_token) = self {
-            if *c_token == expected { return Ok(()) }
-        }
-        Err(ParsingError::UnexpectedToken(QueryToken::Character(expected), self.clone()))
-    }
-
-    fn expect_string(&self) -> Result<(), ParsingError> {
-        if let QueryToken::String(_) = self { Ok(()) } else { Err(ParsingError::UnexpectedToken(QueryToken::String(String::from("")), self.clone()))}
-    }
-}
-
 impl From<KeywordToken> for QueryToken {
     fn from(kw: KeywordToken) -> Self {
         QueryToken::Keyword(kw)
@@ -162,11 +203,7 @@ impl From<KeywordToken> for QueryToken {
 
 impl<'a> TokenIterator<'a> {
     pub fn new(token_string: &'a str) -> TokenIterator<'a> {
-        TokenIterator { token_string: token_string, index: 0usize, err: None }
-    }
-
-    fn nth_char(&self, i: usize) -> Option<char> {
-        self.token_string.chars().nth(self.index + i)
+        TokenIterator { token_string, index: 0usize, err: None }
     }
 
     fn current_char(&self) -> Option<char> {
@@ -181,17 +218,6 @@ impl<'a> TokenIterator<'a> {
         self.token_string.len() - self.index
     }
 
-    fn matches_keyword(&self, match_str: &str) -> bool {
-        match_str.chars()
-            .enumerate()
-            .all(|(i, c)| if let Some(rc) = self.nth_char(i) { rc == c } else { false })
-        && (if let Some(c) = self.nth_char(match_str.len()) { c.is_whitespace() } else { false })
-    }
-
-    fn is_end_or_whitespace(&self) -> bool {
-        if let Some(c) = self.current_char() { c.is_whitespace() } else { true }
-    }
-
     fn advance(&mut self) {
         self.index += 1;
     }
@@ -200,14 +226,6 @@ impl<'a> TokenIterator<'a> {
         self.index += i;
     }
 
-    fn advance_until(&mut self, predicate: fn (char) -> bool) {
-        while self.index < self.token_string.len() {
-            let cc = self.current_char();
-            if cc.is_none() || predicate(cc.unwrap()) { break; }
-            self.index += 1;
-        }
-    }
-
     fn advance_while(&mut self, predicate: fn(char) -> bool) {
         loop {
             let cc = self.current_char();
@@ -216,35 +234,18 @@ impl<'a> TokenIterator<'a> {
         }
     }
 
-    fn range_while(&mut self, predicate: fn (char) -> bool) -> Range<usize> {
-        let start_idx = self.index;
-        self.advance_while(predicate);
-        Range { start: start_idx, end: self.index }
-    }
-
-    fn range_until(&mut self, predicate: fn (char) -> bool) -> Range<usize> {
-        let start_idx = self.index;
-        self.advance_until(predicate);
-        Range { start: start_idx, end: self.index }
-    }
-
-    fn slice(&self, i: usize) -> &str {
-        let ii = if i < self.token_string.len() { i } else { self.token_string.len() };
-        if self.index < ii { &self.token_string[self.index..ii] } else { &self.token_string[ii..self.index] }
-    }
-
     fn consume_in_string(&mut self) -> Result<QueryToken, LexingError> {
         let mut esc = false;
         let mut acc = String::new();
 
         while self.chars_left() > 0 {
             let oc = self.current_char();
-            if let None = oc { return Err(LexingError::UnexpectedEndOfInput) }
+            if oc.is_none() { return Err(LexingError::UnexpectedEndOfInput) }
             let c = oc.unwrap();
 
             if c == '"' && !esc {
                 self.advance();
-                return Ok(QueryToken::String(acc));
+                return Ok(QueryToken::Quoted(acc));
             }
 
             if esc {
@@ -268,7 +269,7 @@ impl<'a> TokenIterator<'a> {
             acc.push(c)
         }
 
-        return Err(LexingError::UnexpectedEndOfInput)
+        Err(LexingError::UnexpectedEndOfInput)
     }
 
     fn set_err(&mut self, err: LexingError) -> LexingError {
@@ -294,7 +295,7 @@ impl<'a> Iterator for TokenIterator<'a> {
     type Item = Result<QueryToken, LexingError>;
     fn next(&mut self) -> Option<Self::Item> {
 
-        if let Some(_) = self.err { return None }
+        if self.err.is_some() { return None }
 
         self.advance_while(|c| c.is_whitespace());
 
@@ -306,14 +307,16 @@ impl<'a> Iterator for TokenIterator<'a> {
                 Some(Ok(TryInto::<KeywordToken>::try_into(ss)
                     .map(|kw| kw.into())
                     .unwrap_or_else(|_| QueryToken::String(ss.to_string()))))
-            } else if fc.is_numeric() {
-                let r = self.range_while(|c| c.is_numeric());
-                Some(Ok(QueryToken::String(self.token_string[r].to_string())))
+            } else if literal::is_digit(fc) || (fc == '-' && self.next_char().is_some_and(literal::is_digit)) {
+                let start_idx = self.index;
+                self.advance();
+                self.advance_while(literal::is_digit);
+                Some(Ok(QueryToken::Number(self.token_string[start_idx..self.index].to_string())))
             } else {
                 match fc {
                     '"' => {
                         self.advance();
-                        return Some(self.consume_in_string());
+                        Some(self.consume_in_string())
                     },
                     '(' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::LeftParen))) },
                     ')' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::RightParen))) },
@@ -321,6 +324,8 @@ impl<'a> Iterator for TokenIterator<'a> {
                     ']' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::RightBracket))) },
                     '.' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::Dot))) },
                     ',' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::Comma))) },
+                    '*' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::Star))) },
+                    '?' => { self.advance(); Some(Ok(QueryToken::Character(CharacterToken::Question))) },
                     '=' | '<' | '>' | '!' => {
                         if self.next_char().is_none() { return Some(Err(LexingError::UnexpectedEndOfInput)) }
                         let sc = self.next_char().unwrap();
@@ -338,7 +343,7 @@ impl<'a> Iterator for TokenIterator<'a> {
                         if let Ok(c) = o {
                             self.advance_by(c.static_str().len())
                         }
-                        Some(o.map(|c| QueryToken::Character(c)))
+                        Some(o.map(QueryToken::Character))
                     },
                     _ => {
                         Some(Err(self.set_err(LexingError::UnexpectedCharacter(fc))))
@@ -346,7 +351,7 @@ impl<'a> Iterator for TokenIterator<'a> {
                 }
             }
         } else {
-            return None
+            None
         }
     }
 
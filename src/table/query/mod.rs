@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::{fmt::Display, ops::{Bound, RangeInclusive}, str::FromStr};
 
 use itertools::Itertools;
 use uuid::Uuid;
@@ -6,34 +6,312 @@ use uuid::Uuid;
 pub mod types;
 pub mod lex;
 pub mod parse;
+pub mod builder;
 
-use self::types::{RawSelectQuery, RawSelectQueryWhereExpression, RawDbCommand};
+use self::types::{RawSelectQuery, RawSelectQueryWhereExpressionOperator, RawSelectColumnReference, RawSelectQueryColumnExpression, RawSelectQueryWhereExpression, RawDbCommand, AggregateFunction};
 use self::parse::RawParse;
 
 use super::{
-    schema::{TableColumn, TableDescriptor, ColumnDataType, DatabaseDescriptor, GetTableDescriptor},
-    bytes::{FromSlice}
+    schema::{TableColumn, TableDescriptor, ColumnDataType, GetTableDescriptor},
+    bytes::{FromSlice},
+    value::{Value, ResultColumn},
+    catalog,
+    error::KronkError,
+    store::{index::KeyRange, version::ReadSnapshot},
+    literal
 };
 
 #[derive(Debug)]
 pub struct SelectQuery<'a> {
+    /// `None` for selects without a from clause, which run against a single
+    /// empty row
+    pub table: Option<&'a TableDescriptor>,
+    pub columns: Vec<SelectColumn<'a>>,
+    pub join: Option<Join<'a>>,
+    pub where_predicate: Option<WherePredicate<'a>>,
+    /// Columns whose distinct values each get a result row
+    pub group_by: Vec<&'a TableColumn>,
+    /// Empty when the rows can come back in any order
+    pub order_by: Vec<OrderBy<'a>>,
+    /// Most rows to return
+    pub limit: Option<u64>,
+    /// Where conditions compared against `?` placeholders, in the order
+    /// they're written. `where_predicate` has a `WherePredicate::Parameter`
+    /// in place of each until a prepared statement gives them values.
+    pub parameters: Vec<Parameter<'a>>,
+    /// Version of the database the query reads, from a `ReadSnapshot`.
+    /// `None` reads the latest.
+    pub snapshot: Option<u64>
+}
+
+/// A where condition whose value is a `?` placeholder.
+#[derive(Debug)]
+pub struct Parameter<'a> {
+    pub column: &'a TableColumn,
+    pub op: RawSelectQueryWhereExpressionOperator
+}
+
+impl<'a> Parameter<'a> {
+    /// The condition with `value` in place of the placeholder.
+    pub fn with_value(&self, value: &str) -> Result<WhereCondition<'a>, KronkError> {
+        WhereCondition::new(self.column, &self.op.to_string(), value).map_err(KronkError::Parse)
+    }
+}
+
+/// A second table whose rows are paired up with each row of the first, where
+/// a column of each compares true. Joined rows are a row of the first table
+/// followed by a row of the second.
+#[derive(Debug)]
+pub struct Join<'a> {
     pub table: &'a TableDescriptor,
-    pub columns: Vec<&'a TableColumn>,
-    pub where_predicate: Option<WherePredicate<'a>>
+    /// Column of the first table
+    pub left: &'a TableColumn,
+    /// Column of the joined table
+    pub right: &'a TableColumn,
+    operator: EqOrdOperator
 }
 
+impl Join<'_> {
+    /// True for joins on `==`, which can go through a hash table.
+    pub fn is_equality(&self) -> bool {
+        matches!(self.operator, EqOrdOperator::Eq(PartialEqOperator::Equal))
+    }
+
+    /// Whether a value of the left column and one of the right pair up.
+    pub fn matches(&self, left: &Value, right: &Value) -> bool {
+        self.operator.evaluate(left, right)
+    }
+}
+
+impl Display for Join<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}.{}", self.left.name, self.operator, self.table.table_name, self.right.name)
+    }
+}
+
+/// A table column to sort the result rows by, in `order by` order.
 #[derive(Debug)]
-pub struct WherePredicate<'a> {
-    pub conditions: Vec<WhereCondition<'a>>
+pub struct OrderBy<'a> {
+    pub column: &'a TableColumn,
+    pub descending: bool
 }
 
 #[derive(Debug)]
+pub struct SelectColumn<'a> {
+    pub name: String,
+    pub expression: SelectExpression<'a>
+}
+
+#[derive(Debug)]
+pub enum SelectExpression<'a> {
+    Column(&'a TableColumn),
+    /// A column of the joined table, which comes after the first table's
+    /// row in a joined row.
+    JoinedColumn(&'a TableColumn),
+    Constant(String),
+    CountAll,
+    Aggregate(AggregateFunction, &'a TableColumn)
+}
+
+impl SelectExpression<'_> {
+    /// True for expressions that fold every row into one value.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, SelectExpression::CountAll | SelectExpression::Aggregate(..))
+    }
+
+    /// What a column with this expression is called without an `as`.
+    pub fn default_name(&self) -> String {
+        match self {
+            SelectExpression::Column(c) | SelectExpression::JoinedColumn(c) => c.name.to_owned(),
+            SelectExpression::Constant(v) => v.to_owned(),
+            SelectExpression::CountAll => "count".to_owned(),
+            SelectExpression::Aggregate(f, _) => f.name().to_owned()
+        }
+    }
+}
+
+impl<'a> SelectColumn<'a> {
+    pub fn from_column(column: &'a TableColumn) -> SelectColumn<'a> {
+        SelectColumn { name: column.name.to_owned(), expression: SelectExpression::Column(column) }
+    }
+
+    /// The type of the values this column produces, if it has one.
+    pub fn datatype(&self) -> Option<&ColumnDataType> {
+        match &self.expression {
+            SelectExpression::Column(c) | SelectExpression::JoinedColumn(c) => Some(&c.datatype),
+            SelectExpression::Constant(_) => None,
+            SelectExpression::CountAll => Some(&ColumnDataType::UInt64),
+            SelectExpression::Aggregate(f, c) => match f {
+                AggregateFunction::Count => Some(&ColumnDataType::UInt64),
+                AggregateFunction::Sum if c.datatype.is_signed_integer() => Some(&ColumnDataType::Int64),
+                AggregateFunction::Sum => Some(&ColumnDataType::UInt64),
+                AggregateFunction::Min | AggregateFunction::Max => Some(&c.datatype),
+                // averages aren't a column type
+                AggregateFunction::Avg => None
+            }
+        }
+    }
+
+    /// What the column's values are, for reading results without the
+    /// query's tables. Only aggregates other than counts can be null, when
+    /// there are no rows to fold.
+    pub fn result_column(&self) -> ResultColumn {
+        let name = self.expression.default_name();
+        ResultColumn {
+            alias: (self.name != name).then(|| self.name.to_owned()),
+            name,
+            datatype: self.datatype().cloned(),
+            nullable: matches!(self.expression, SelectExpression::Aggregate(f, _) if f != AggregateFunction::Count)
+        }
+    }
+}
+
+/// A where clause, as a tree of conditions joined by `and`, `or` and `not`.
+#[derive(Debug, Clone)]
+pub enum WherePredicate<'a> {
+    Condition(WhereCondition<'a>),
+    /// The `n`th `?` placeholder of the query, counting from zero. Matches
+    /// no rows until a prepared statement puts a condition in its place.
+    Parameter(usize),
+    And(Box<WherePredicate<'a>>, Box<WherePredicate<'a>>),
+    Or(Box<WherePredicate<'a>>, Box<WherePredicate<'a>>),
+    Not(Box<WherePredicate<'a>>)
+}
+
+impl<'a> WherePredicate<'a> {
+    /// Rows matching both this predicate and `other`.
+    pub fn and(self, other: WherePredicate<'a>) -> WherePredicate<'a> {
+        WherePredicate::And(Box::new(self), Box::new(other))
+    }
+
+    /// Every condition in the predicate, wherever it is in the tree.
+    pub fn conditions(&self) -> Vec<&WhereCondition<'a>> {
+        match self {
+            WherePredicate::Condition(c) => vec![c],
+            WherePredicate::Parameter(_) => Vec::new(),
+            WherePredicate::And(a, b) | WherePredicate::Or(a, b) => a.conditions().into_iter().chain(b.conditions()).collect(),
+            WherePredicate::Not(p) => p.conditions()
+        }
+    }
+
+    /// Conditions every matching row meets, the ones joined to the rest of
+    /// the predicate by nothing but `and`. Indexes and estimates only go by
+    /// these.
+    pub fn required_conditions(&self) -> Vec<&WhereCondition<'a>> {
+        match self {
+            WherePredicate::Condition(c) => vec![c],
+            WherePredicate::And(a, b) => a.required_conditions().into_iter().chain(b.required_conditions()).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    /// The serial ids a row can have and still match, from the required
+    /// conditions on the table's serial id column. `None` if no condition
+    /// narrows it.
+    pub fn id_range(&self) -> Option<RangeInclusive<u64>> {
+        self.required_conditions().into_iter()
+            .filter_map(|c| c.comparison.id_range())
+            .reduce(|a, b| (*a.start()).max(*b.start())..=(*a.end()).min(*b.end()))
+    }
+
+    /// Whether a row matches.
+    pub fn is_true(&self, row: &[u8]) -> bool {
+        match self {
+            WherePredicate::Condition(c) => c.comparison.is_true(&row[c.column.offset..]),
+            WherePredicate::Parameter(_) => false,
+            WherePredicate::And(a, b) => a.is_true(row) && b.is_true(row),
+            WherePredicate::Or(a, b) => a.is_true(row) || b.is_true(row),
+            WherePredicate::Not(p) => !p.is_true(row)
+        }
+    }
+
+    /// The predicate with `conditions` in place of its placeholders, in the
+    /// order they're numbered.
+    pub fn with_parameters(&self, conditions: &[WhereCondition<'a>]) -> WherePredicate<'a> {
+        match self {
+            WherePredicate::Parameter(n) => conditions.get(*n).cloned().map_or(WherePredicate::Parameter(*n), WherePredicate::Condition),
+            WherePredicate::Condition(_) => self.clone(),
+            WherePredicate::And(a, b) => a.with_parameters(conditions).and(b.with_parameters(conditions)),
+            WherePredicate::Or(a, b) => WherePredicate::Or(Box::new(a.with_parameters(conditions)), Box::new(b.with_parameters(conditions))),
+            WherePredicate::Not(p) => WherePredicate::Not(Box::new(p.with_parameters(conditions)))
+        }
+    }
+
+    /// The predicate with its columns looked up by name in `table`, for a
+    /// predicate built against a copy of the table's descriptor.
+    pub fn against<'t>(&self, table: &'t TableDescriptor) -> Result<WherePredicate<'t>, String> {
+        Ok(match self {
+            WherePredicate::Condition(c) => match table.column_for_name(&c.column.name) {
+                Some(column) if column.datatype == c.column.datatype => WherePredicate::Condition(WhereCondition { column, comparison: c.comparison.clone() }),
+                _ => return Err(format!("Where condition on '{}' doesn't match a column of table '{}'", c.column.name, table.table_name))
+            },
+            WherePredicate::Parameter(n) => WherePredicate::Parameter(*n),
+            WherePredicate::And(a, b) => a.against(table)?.and(b.against(table)?),
+            WherePredicate::Or(a, b) => WherePredicate::Or(Box::new(a.against(table)?), Box::new(b.against(table)?)),
+            WherePredicate::Not(p) => WherePredicate::Not(Box::new(p.against(table)?))
+        })
+    }
+}
+
+impl Display for WherePredicate<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `and` binds tighter than `or`, so only an `or` under an `and` or a
+        // `not` needs parentheses
+        let grouped = |p: &WherePredicate| match p {
+            WherePredicate::Or(..) => format!("({})", p),
+            _ => p.to_string()
+        };
+        match self {
+            WherePredicate::Condition(c) => c.fmt(f),
+            WherePredicate::Parameter(_) => f.write_str("?"),
+            WherePredicate::And(a, b) => write!(f, "{} and {}", grouped(a), grouped(b)),
+            WherePredicate::Or(a, b) => write!(f, "{} or {}", a, b),
+            WherePredicate::Not(p) => match p.as_ref() {
+                WherePredicate::And(..) => write!(f, "not ({})", p),
+                _ => write!(f, "not {}", grouped(p))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct WhereCondition<'a> {
     pub column: &'a TableColumn,
     pub comparison: WhereComparison
 }
 
-#[derive(Debug)]
+impl<'a> WhereCondition<'a> {
+    /// Compares `column` against `value` with `op` (`==`, `!=`, `<`, `<=`,
+    /// `>`, `>=`), e.g. for a predicate built without going through a query.
+    pub fn new(column: &'a TableColumn, op: &str, value: &str) -> Result<WhereCondition<'a>, String> {
+        let comparison = column.datatype.parse_where_comparison(op, value)?;
+        Ok(WhereCondition { column, comparison })
+    }
+
+    /// The index keys (see `ColumnDataType::index_key`) a row's value can
+    /// have and still match. `None` for `!=`, which an index can't narrow.
+    pub fn key_range(&self) -> Option<KeyRange> {
+        let datatype = &self.column.datatype;
+        match &self.comparison {
+            WhereComparison::Int32(c) => c.operator.key_range(datatype.index_key(&c.value.to_le_bytes())),
+            WhereComparison::UInt32(c) => c.operator.key_range(datatype.index_key(&c.value.to_le_bytes())),
+            WhereComparison::Int64(c) => c.operator.key_range(datatype.index_key(&c.value.to_le_bytes())),
+            WhereComparison::UInt64(c) => c.operator.key_range(datatype.index_key(&c.value.to_le_bytes())),
+            WhereComparison::SerialId(c) => c.operator.key_range(datatype.index_key(&c.value.to_le_bytes())),
+            WhereComparison::UuidV4(c) => c.operator.key_range(datatype.index_key(c.value.as_bytes())),
+            WhereComparison::String(c) => c.operator.key_range(datatype.index_key(c.value.as_bytes())),
+            WhereComparison::Boolean(c) => c.operator.key_range(vec![c.value as u8])
+        }
+    }
+}
+
+impl Display for WhereCondition<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.column.name, self.comparison)
+    }
+}
+
+#[derive(Debug, Clone)]
 enum PartialOrdOperator {
     GreaterThan,
     GreaterEqual,
@@ -67,7 +345,18 @@ impl FromStr for PartialOrdOperator {
     }
 }
 
-#[derive(Debug)]
+impl Display for PartialOrdOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::GreaterThan => ">",
+            Self::GreaterEqual => ">=",
+            Self::LessThan => "<",
+            Self::LessEqual => "<="
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 enum PartialEqOperator {
     Equal,
     NotEqual
@@ -84,6 +373,15 @@ impl PartialEqOperator {
     }
 }
 
+impl PartialEqOperator {
+    fn key_range(&self, key: Vec<u8>) -> Option<KeyRange> {
+        match self {
+            PartialEqOperator::Equal => Some((Bound::Included(key.clone()), Bound::Included(key))),
+            PartialEqOperator::NotEqual => None
+        }
+    }
+}
+
 impl FromStr for PartialEqOperator {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -95,7 +393,16 @@ impl FromStr for PartialEqOperator {
     }
 }
 
-#[derive(Debug)]
+impl Display for PartialEqOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "==",
+            Self::NotEqual => "!="
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
 enum EqOrdOperator {
     Eq(PartialEqOperator),
     Ord(PartialOrdOperator)
@@ -113,6 +420,18 @@ impl EqOrdOperator {
 
 }
 
+impl EqOrdOperator {
+    fn key_range(&self, key: Vec<u8>) -> Option<KeyRange> {
+        match self {
+            Self::Eq(eqc) => eqc.key_range(key),
+            Self::Ord(PartialOrdOperator::GreaterThan) => Some((Bound::Excluded(key), Bound::Unbounded)),
+            Self::Ord(PartialOrdOperator::GreaterEqual) => Some((Bound::Included(key), Bound::Unbounded)),
+            Self::Ord(PartialOrdOperator::LessThan) => Some((Bound::Unbounded, Bound::Excluded(key))),
+            Self::Ord(PartialOrdOperator::LessEqual) => Some((Bound::Unbounded, Bound::Included(key)))
+        }
+    }
+}
+
 impl FromStr for EqOrdOperator {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -126,33 +445,28 @@ impl FromStr for EqOrdOperator {
     }
 }
 
-#[derive(Debug)]
+impl Display for EqOrdOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eq(op) => op.fmt(f),
+            Self::Ord(op) => op.fmt(f)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct EqOrdComparison<T> where T : PartialEq + PartialOrd {
     operator: EqOrdOperator,
     value: T
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EqComparison<T> where T : PartialEq {
     operator: PartialEqOperator,
     value: T
 }
 
-trait EvaluableComparison<T> {
-    fn evaluate(&self, other: &T) -> bool;
-}
-
-impl<T> EvaluableComparison<T> for EqComparison<T> where T : PartialEq {
-    fn evaluate(&self, other: &T) -> bool {
-        let s = self;
-        match s.operator {
-            PartialEqOperator::Equal => s.value == *other,
-            PartialEqOperator::NotEqual => s.value != *other
-        }
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum WhereComparison {
     Int32(EqOrdComparison<i32>),
     UInt32(EqOrdComparison<u32>),
@@ -169,8 +483,8 @@ impl ColumnDataType {
         let s = self;
         match s {
             Self::Boolean => {
-                let v = str::parse::<bool>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not a boolean value", value))?;
+                let v = literal::parse_boolean(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: PartialEqOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -179,8 +493,8 @@ impl ColumnDataType {
             },
 
             Self::SerialId => {
-                let v = str::parse::<u64>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not a serial id", value))?;
+                let v = literal::parse_integer::<u64>(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: EqOrdOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -189,8 +503,8 @@ impl ColumnDataType {
             },
 
             Self::Int32 => {
-                let v = str::parse::<i32>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not an int32 value", value))?;
+                let v = literal::parse_integer::<i32>(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: EqOrdOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -199,8 +513,8 @@ impl ColumnDataType {
             },
 
             Self::UInt32 => {
-                let v = str::parse::<u32>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not a u32 value", value))?;
+                let v = literal::parse_integer::<u32>(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: EqOrdOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -209,8 +523,8 @@ impl ColumnDataType {
             },
 
             Self::Int64 => {
-                let v = str::parse::<i64>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not an i64 value", value))?;
+                let v = literal::parse_integer::<i64>(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: EqOrdOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -218,9 +532,9 @@ impl ColumnDataType {
                 Ok(WhereComparison::Int64(EqOrdComparison { operator: parsed_op, value: v }))
             },
 
-            Self::UInt64 => {
-                let v = str::parse::<u64>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not a u64 value", value))?;
+            Self::UInt64 | Self::RowVersion => {
+                let v = literal::parse_integer::<u64>(value)
+                    .map_err(|e| format!("Invalid where expression: {}", e))?;
 
                 let parsed_op: EqOrdOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -230,7 +544,7 @@ impl ColumnDataType {
 
             Self::UuidV4 => {
                 let v = str::parse::<Uuid>(value)
-                    .map_err(|_| format!("Invalid where expression: '{}' is not a uuid value", value))?;
+                    .map_err(|e| format!("Invalid where expression: '{}' is not a uuid value: {}", value, e))?;
 
                 let parsed_op: PartialEqOperator = str::parse(op)
                     .map_err(|s| format!("Invalid where expression: {}", s))?;
@@ -249,6 +563,24 @@ impl ColumnDataType {
 }
 
 impl WhereComparison {
+    /// The serial ids this comparison lets through, for comparisons on a
+    /// serial id other than `!=`. An empty range matches nothing.
+    pub fn id_range(&self) -> Option<RangeInclusive<u64>> {
+        let c = match self {
+            Self::SerialId(c) => c,
+            _ => return None
+        };
+        let v = c.value;
+        match &c.operator {
+            EqOrdOperator::Eq(PartialEqOperator::Equal) => Some(v..=v),
+            EqOrdOperator::Eq(PartialEqOperator::NotEqual) => None,
+            EqOrdOperator::Ord(PartialOrdOperator::GreaterThan) => Some(v.checked_add(1).map_or(RangeInclusive::new(1, 0), |s| s..=u64::MAX)),
+            EqOrdOperator::Ord(PartialOrdOperator::GreaterEqual) => Some(v..=u64::MAX),
+            EqOrdOperator::Ord(PartialOrdOperator::LessThan) => Some(v.checked_sub(1).map_or(RangeInclusive::new(1, 0), |e| 0..=e)),
+            EqOrdOperator::Ord(PartialOrdOperator::LessEqual) => Some(0..=v)
+        }
+    }
+
     pub fn is_true(&self, buf: &[u8]) -> bool {
         let s = self;
         match s {
@@ -277,73 +609,281 @@ impl WhereComparison {
                 comparison.operator.evaluate(&b, &comparison.value)
             },
             Self::String(comparison) => {
-                let s = String::from_utf8(buf.into_iter().map(|b| *b).take_while(|b| *b != 0u8).collect()).unwrap();
-                comparison.operator.evaluate(&s, &comparison.value)
+                // compares the stored bytes up to their terminator in place;
+                // utf-8 bytes compare the same way the strings would
+                let end = buf.iter().position(|b| *b == 0u8).unwrap_or(buf.len());
+                comparison.operator.evaluate(&&buf[..end], &comparison.value.as_bytes())
             }
         }
     }
 }
 
+impl Display for WhereComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int32(c) => write!(f, "{} {}", c.operator, literal::format_integer(c.value)),
+            Self::UInt32(c) => write!(f, "{} {}", c.operator, literal::format_integer(c.value)),
+            Self::Int64(c) => write!(f, "{} {}", c.operator, literal::format_integer(c.value)),
+            Self::UInt64(c) | Self::SerialId(c) => write!(f, "{} {}", c.operator, literal::format_integer(c.value)),
+            Self::UuidV4(c) => write!(f, "{} {}", c.operator, c.value),
+            Self::String(c) => write!(f, "{} {}", c.operator, c.value),
+            Self::Boolean(c) => write!(f, "{} {}", c.operator, literal::format_boolean(c.value))
+        }
+    }
+}
+
+/// Binds a where expression's columns and values. Placeholders' conditions
+/// go in `parameters`, in the order they're written.
+fn bind_where<'a>(expression: &RawSelectQueryWhereExpression, column: &dyn Fn(&RawSelectColumnReference) -> Result<&'a TableColumn, String>, parameters: &mut Vec<Parameter<'a>>) -> Result<WherePredicate<'a>, String> {
+    Ok(match expression {
+        RawSelectQueryWhereExpression::Single(wc) => {
+            let column = column(&wc.column)?;
+            match &wc.value {
+                Some(value) => WherePredicate::Condition(WhereCondition { column, comparison: column.datatype.parse_where_comparison(&wc.op.to_string(), value)? }),
+                None => {
+                    parameters.push(Parameter { column, op: wc.op });
+                    WherePredicate::Parameter(parameters.len() - 1)
+                }
+            }
+        },
+        RawSelectQueryWhereExpression::And(a, b) => bind_where(a, column, parameters)?.and(bind_where(b, column, parameters)?),
+        RawSelectQueryWhereExpression::Or(a, b) => WherePredicate::Or(Box::new(bind_where(a, column, parameters)?), Box::new(bind_where(b, column, parameters)?)),
+        RawSelectQueryWhereExpression::Not(p) => WherePredicate::Not(Box::new(bind_where(p, column, parameters)?))
+    })
+}
+
 impl<'a> SelectQuery<'a> {
     pub fn parse_query_against_db(query: &RawSelectQuery, db_descriptor: &'a impl GetTableDescriptor) -> Result<SelectQuery<'a>, String> {
-        let table = db_descriptor.table_with_name(&query.table_name)
-            .ok_or_else(|| format!("Invalid query: no table '{}' exists", query.table_name))?;
+        let table = match &query.table_name {
+            Some(table_name) => Some(db_descriptor.table_with_name(table_name)
+                .ok_or_else(|| format!("Invalid query: no table '{}' exists", table_name))?),
+            None => None
+        };
 
-        let option_columns = query.columns[..].into_iter()
-            .map(|qc| table.column_for_name(&qc.column.column_name))
-            .collect::<Vec<_>>();
+        let joined_table = match (&query.join, table) {
+            (Some(j), Some(_)) => {
+                if j.database_name.is_some() && j.database_name != query.database_name {
+                    return Err("Invalid query: can't join tables of different databases".to_owned());
+                }
+                Some(db_descriptor.table_with_name(&j.table_name)
+                    .ok_or_else(|| format!("Invalid query: no table '{}' exists", j.table_name))?)
+            },
+            _ => None
+        };
 
-        for c in option_columns[..].into_iter() {
-            if let None = c { return Err("Missing column!".to_owned()) }
-        }
+        // finds the column a reference names, and whether it's in the joined
+        // table. Without a join, the reference's table identifier isn't checked.
+        let resolve = |c: &RawSelectColumnReference, t: &'a TableDescriptor| -> Result<Option<(&'a TableColumn, bool)>, String> {
+            let (join, joined) = match (&query.join, joined_table) {
+                (Some(join), Some(joined)) => (join, joined),
+                _ => return Ok(t.column_for_name(&c.column_name).map(|column| (column, false)))
+            };
+            let names = |table: &TableDescriptor, identifier: &Option<String>| c.table_identifier.as_ref()
+                .is_none_or(|i| Some(i) == identifier.as_ref() || *i == table.table_name);
+            let left = names(t, &query.table_identifier).then(|| t.column_for_name(&c.column_name)).flatten();
+            let right = names(joined, &join.table_identifier).then(|| joined.column_for_name(&c.column_name)).flatten();
+            match (left, right) {
+                (Some(_), Some(_)) => Err(format!("Invalid query: column '{}' could be in '{}' or '{}'", c.column_name, t.table_name, joined.table_name)),
+                (Some(column), None) => Ok(Some((column, false))),
+                (None, Some(column)) => Ok(Some((column, true))),
+                (None, None) => Ok(None)
+            }
+        };
+        // columns of the joined table can only be selected
+        let first_table_column = |column: Option<(&'a TableColumn, bool)>| match column {
+            Some((column, true)) => Err(format!("Invalid query: column '{}' of joined table '{}' can only be selected", column.name, joined_table.unwrap().table_name)),
+            Some((column, false)) => Ok(Some(column)),
+            None => Ok(None)
+        };
+
+        let columns = query.columns[..].iter()
+            .map(|qc| {
+                if let RawSelectQueryColumnExpression::AllColumns = qc.expression {
+                    let t = table.ok_or_else(|| "Invalid query: '*' used without a from clause".to_owned())?;
+                    if qc.as_name.is_some() {
+                        return Err("Invalid query: '*' can't be renamed with 'as'".to_owned());
+                    }
+                    let joined_columns = joined_table.into_iter()
+                        .flat_map(|j| j.columns.iter())
+                        .map(|c| SelectColumn { name: c.name.to_owned(), expression: SelectExpression::JoinedColumn(c) });
+                    return Ok(t.columns.iter()
+                        .map(|c| SelectColumn { name: c.name.to_owned(), expression: SelectExpression::Column(c) })
+                        .chain(joined_columns)
+                        .collect());
+                }
+                let expression = match &qc.expression {
+                    RawSelectQueryColumnExpression::Column(c) => {
+                        let t = table.ok_or_else(|| format!("Invalid query: column '{}' referenced without a from clause", c.column_name))?;
+                        match resolve(c, t)?.ok_or_else(|| "Missing column!".to_owned())? {
+                            (column, true) => SelectExpression::JoinedColumn(column),
+                            (column, false) => SelectExpression::Column(column)
+                        }
+                    },
+                    RawSelectQueryColumnExpression::Constant(v) => SelectExpression::Constant(v.to_owned()),
+                    RawSelectQueryColumnExpression::CountAll => SelectExpression::CountAll,
+                    RawSelectQueryColumnExpression::Aggregate(f, c) => {
+                        let t = table.ok_or_else(|| format!("Invalid query: column '{}' referenced without a from clause", c.column_name))?;
+                        let column = first_table_column(resolve(c, t)?)?.ok_or_else(|| "Missing column!".to_owned())?;
+                        if matches!(f, AggregateFunction::Sum | AggregateFunction::Avg) && !column.datatype.is_integer() {
+                            return Err(format!("Invalid query: can't {} column '{}', which isn't an integer", f.name(), column.name));
+                        }
+                        SelectExpression::Aggregate(*f, column)
+                    },
+                    RawSelectQueryColumnExpression::AllColumns => unreachable!()
+                };
+
+                let name = qc.as_name.clone().unwrap_or_else(|| expression.default_name());
+
+                Ok(vec![SelectColumn { name, expression }])
+            })
+            .collect::<Result<Vec<_>, String>>()?
+            .into_iter()
+            .flatten()
+            .collect_vec();
+
+        let group_by = query.group_by[..].iter()
+            .map(|c| {
+                let table = table.ok_or_else(|| "Invalid query: group by requires a from clause".to_owned())?;
+                first_table_column(resolve(c, table)?)?
+                    .ok_or_else(|| format!("Invalid query: no column '{}' to group by", c.column_name))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
 
-        let columns = option_columns[..].into_iter().map(|c| c.unwrap()).collect_vec();
+        let has_aggregate = columns[..].iter().any(|c| c.expression.is_aggregate());
+        if group_by.is_empty() {
+            let has_column = columns[..].iter().any(|c| matches!(c.expression, SelectExpression::Column(_) | SelectExpression::JoinedColumn(_)));
+            if has_aggregate && has_column {
+                return Err("Invalid query: cannot select columns alongside an aggregate".to_owned());
+            }
+        } else if let Some(c) = columns[..].iter().find_map(|c| match c.expression {
+            SelectExpression::Column(tc) if !group_by[..].iter().any(|g| g.name == tc.name) => Some(tc),
+            SelectExpression::JoinedColumn(tc) => Some(tc),
+            _ => None
+        }) {
+            return Err(format!("Invalid query: column '{}' is selected but not grouped by", c.name));
+        }
 
-        let where_predicate = if let Some(where_expr) = &query.where_expression {
-            match where_expr {
-                RawSelectQueryWhereExpression::Single(wc) => {
-                   let column = table.column_for_name(&wc.column.column_name)
-                        .ok_or_else(|| "no such column".to_owned())?;
+        let mut parameters = Vec::new();
+        let where_predicate = match &query.where_expression {
+            Some(where_expr) => {
+                let table = table.ok_or_else(|| "Invalid query: where clause requires a from clause".to_owned())?;
+                let column = |c: &RawSelectColumnReference| first_table_column(resolve(c, table)?)?
+                    .ok_or_else(|| format!("Invalid query: no column '{}' to compare", c.column_name));
+                Some(bind_where(where_expr, &column, &mut parameters)?)
+            },
+            None => None
+        };
 
-                    let comparison = column.datatype.parse_where_comparison(&wc.op.to_string(), &wc.value)?;
+        let order_by = query.order_by[..].iter()
+            .map(|o| {
+                let table = table.ok_or_else(|| "Invalid query: order by requires a from clause".to_owned())?;
+                let column = first_table_column(resolve(&o.column, table)?)?
+                    .ok_or_else(|| format!("Invalid query: no column '{}' to order by", o.column.column_name))?;
+                Ok(OrderBy { column, descending: o.descending })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if has_aggregate && !order_by.is_empty() {
+            return Err("Invalid query: cannot order by a column alongside an aggregate".to_owned());
+        }
+        if !group_by.is_empty() && !order_by.is_empty() {
+            return Err("Invalid query: cannot order by a column alongside a group by".to_owned());
+        }
 
-                    Some(WherePredicate {
-                        conditions: vec! [
-                            WhereCondition { 
-                                column,
-                                comparison
-                            }
-                        ]
-                    })
-                },
-                _ => None
-            }
-        } else { None };
+        let join = match (&query.join, table, joined_table) {
+            (Some(j), Some(t), Some(joined)) => {
+                if let Some(name) = [&t.table_name, &joined.table_name].into_iter().find(|n| catalog::is_virtual(n)) {
+                    return Err(format!("Invalid query: {} can't be joined", name));
+                }
+                let missing = |c: &RawSelectColumnReference| format!("Invalid query: no column '{}' to join on", c.column_name);
+                let left = resolve(&j.left, t)?.ok_or_else(|| missing(&j.left))?;
+                let right = resolve(&j.right, t)?.ok_or_else(|| missing(&j.right))?;
+                // `on b.x < a.y` is `on a.y > b.x`
+                let (left, right, op) = match (left, right) {
+                    ((left, false), (right, true)) => (left, right, j.op),
+                    ((right, true), (left, false)) => (left, right, j.op.flipped()),
+                    _ => return Err(format!("Invalid query: join should compare a column of '{}' with a column of '{}'", t.table_name, joined.table_name))
+                };
+                if !left.datatype.is_comparable_with(&right.datatype) {
+                    return Err(format!("Invalid query: can't join {:?} column '{}' on {:?} column '{}'", left.datatype, left.name, right.datatype, right.name));
+                }
+                Some(Join { table: joined, left, right, operator: str::parse(&op.to_string())? })
+            },
+            _ => None
+        };
 
         Ok(SelectQuery {
             table,
             columns,
-            where_predicate
+            join,
+            where_predicate,
+            group_by,
+            order_by,
+            limit: query.limit,
+            parameters,
+            snapshot: None
         })
     }
 
-    pub fn parse_raw_query_against_db(query: &str, db_descriptor: &'a impl GetTableDescriptor) -> Result<SelectQuery<'a>, String> {
-        let q = RawParse::parse(query).map_err(|_| "uh oh spagehtti ohs.")?;
-        if let RawDbCommand::Select(s) = q {
-            Self::parse_query_against_db(&s, db_descriptor)
+    /// Columns of the first table the query looks at, with repeats. The
+    /// serial id is always among them, since result rows carry it.
+    pub fn columns_used(&self) -> Vec<&'a TableColumn> {
+        let selected = self.columns[..].iter().filter_map(|c| match c.expression {
+            SelectExpression::Column(column) | SelectExpression::Aggregate(_, column) => Some(column),
+            _ => None
+        });
+        let conditions = self.where_predicate.iter().flat_map(|p| p.conditions()).map(|c| c.column);
+        selected
+            .chain(conditions)
+            .chain(self.order_by[..].iter().map(|o| o.column))
+            .chain(self.group_by.iter().copied())
+            .chain(self.join.as_ref().map(|j| j.left))
+            .chain(self.table.and_then(|t| t.id_column()))
+            .collect()
+    }
+
+    /// Columns of the joined table the query looks at, with repeats.
+    pub fn joined_columns_used(&self) -> Vec<&'a TableColumn> {
+        self.columns[..].iter()
+            .filter_map(|c| match c.expression {
+                SelectExpression::JoinedColumn(column) => Some(column),
+                _ => None
+            })
+            .chain(self.join.as_ref().map(|j| j.right))
+            .collect()
+    }
+
+    /// Reads the database as it was when `snapshot` was taken.
+    pub fn with_snapshot(mut self, snapshot: &ReadSnapshot) -> SelectQuery<'a> {
+        self.snapshot = Some(snapshot.version());
+        self
+    }
+
+    /// The result's columns, in select-list order.
+    pub fn result_columns(&self) -> Vec<ResultColumn> {
+        self.columns[..].iter().map(|c| c.result_column()).collect()
+    }
+
+    /// True when the query folds every row into a single output row.
+    pub fn is_aggregate(&self) -> bool {
+        self.columns[..].iter().any(|c| c.expression.is_aggregate())
+    }
+
+    pub fn parse_raw_query_against_db(query: &str, db_descriptor: &'a impl GetTableDescriptor) -> Result<SelectQuery<'a>, KronkError> {
+        if let RawDbCommand::Select(s) = RawParse::parse(query)? {
+            Self::parse_query_against_db(&s, db_descriptor).map_err(KronkError::Parse)
         } else {
-            Err("Database command was not a select statement".into())
+            Err(KronkError::Parse("Database command was not a select statement".to_owned()))
         }
     }
 
     pub fn parse_query_string(query: &str, db_descriptor: &'a impl GetTableDescriptor) -> Result<SelectQuery<'a>, String> {
-        let tokens = query.trim().split_whitespace().collect::<Vec<&str>>();
+        let tokens = query.split_whitespace().collect::<Vec<&str>>();
 
-        if tokens.len() == 0 { return Err("Query cannot be empty".to_owned()); }
+        if tokens.is_empty() { return Err("Query cannot be empty".to_owned()); }
 
         if tokens[0] != "select" { return Err("invalid query: the only allowed query command is 'select'".to_owned());}
 
-        let columns_ending_idx = tokens[..].into_iter()
+        let columns_ending_idx = tokens[..].iter()
             .take_while(|t| **t != "from")
             .count();
 
@@ -351,19 +891,17 @@ impl<'a> SelectQuery<'a> {
 
         let select_column_names = &tokens[1..columns_ending_idx];
 
-        let table_name = *tokens[..].into_iter()
-            .skip(columns_ending_idx + 1)
-            .next().unwrap();
+        let table_name = *tokens[..].get(columns_ending_idx + 1).unwrap();
 
         let table = db_descriptor.table_with_name(table_name)
             .ok_or_else(|| format!("Invalid query: no table '{}' exists", table_name))?;
 
-        let select_columns = select_column_names.into_iter()
+        let select_columns = select_column_names.iter()
             .map(|t| table.column_for_name(t))
             .collect::<Vec<Option<&TableColumn>>>();
 
-        for column in select_columns[..].into_iter() {
-            if let None = column { return Err("Missing column!".to_owned()) }
+        for column in select_columns[..].iter() {
+            if column.is_none() { return Err("Missing column!".to_owned()) }
         }
 
         let where_predicate = if select_columns.len() == tokens.len() - 1 { None } else {
@@ -385,19 +923,50 @@ impl<'a> SelectQuery<'a> {
                 })
                 .collect::<Vec<Result<WhereCondition, String>>>();
 
-            if let Some(err) = where_conditions[..].into_iter().find(|wc| wc.is_err()) {
+            if let Some(err) = where_conditions[..].iter().find(|wc| wc.is_err()) {
                 return Err(err.as_ref().unwrap_err().to_owned());
             }
 
-            Some(WherePredicate {
-                conditions: where_conditions.into_iter().filter_map(|r| r.ok()).collect_vec()
-            })
+            where_conditions.into_iter().filter_map(|r| r.ok())
+                .map(WherePredicate::Condition)
+                .reduce(WherePredicate::and)
         };
 
         Ok(SelectQuery {
-            table,
-            columns: select_columns.into_iter().map(|scn| scn.unwrap()).collect_vec(),
-            where_predicate
+            table: Some(table),
+            columns: select_columns.into_iter().map(|scn| SelectColumn::from_column(scn.unwrap())).collect_vec(),
+            join: None,
+            where_predicate,
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+            parameters: Vec::new(),
+            snapshot: None
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::types::ParsingError;
+    use crate::table::schema::DatabaseDescriptor;
+
+    fn catalog() -> DatabaseDescriptor {
+        let books = TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int32)]).unwrap();
+        DatabaseDescriptor::new("test_query", vec![books]).unwrap()
+    }
+
+    #[test]
+    fn syntax_errors_keep_the_parser_error() {
+        let catalog = catalog();
+        let e = SelectQuery::parse_raw_query_against_db("select year from", &catalog).unwrap_err();
+        assert!(matches!(e, KronkError::Syntax(ParsingError::UnexpectedEndOfInput)), "{:?}", e);
+    }
+
+    #[test]
+    fn bind_errors_say_what_is_wrong() {
+        let catalog = catalog();
+        let e = SelectQuery::parse_raw_query_against_db("select year from books where year > soon", &catalog).unwrap_err();
+        assert_eq!(e.to_string(), "Invalid where expression: 'soon' is not an integer literal");
+    }
+}
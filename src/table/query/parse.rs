@@ -1,18 +1,31 @@
 use std::iter::Peekable;
 
+use super::super::literal;
 use super::lex::{QueryToken, TokenIterator, KeywordToken, CharacterToken};
-use super::types::{RawSelectQuery, RawSelectColumnReference, RawSelectQueryColumn, RawSelectQueryWhereExpressionOperator, RawSelectQueryWhereComparison, RawSelectQueryWhereExpression, LexingError, ParsingError, RawInsertStatement, RawDbCommand};
+use super::types::{RawSelectQuery, RawSelectColumnReference, RawSelectQueryColumn, RawSelectQueryColumnExpression, RawSelectQueryWhereExpressionOperator, RawSelectQueryWhereComparison, RawSelectQueryWhereExpression, LexingError, ParsingError, RawInsertStatement, RawCreateIndexStatement, RawAnalyzeStatement, RawDbCommand, RawOrderBy, RawJoin, AggregateFunction};
 
 pub struct RawParse {}
 
 impl RawParse {
-    pub fn parse(cmd: &str) -> Result<RawDbCommand<'_>, ParsingError> {
+    pub fn parse(cmd: &str) -> Result<RawDbCommand, ParsingError> {
         let mut parser = TokenParser::new(cmd);
 
         if parser.is_a_keyword(KeywordToken::Select)? {
-            Self::parse_select(parser).map(|s| RawDbCommand::Select(s))
+            Self::parse_select(parser).map(RawDbCommand::Select)
+        } else if parser.is_a_keyword(KeywordToken::Explain)? {
+            parser.consume_a_keyword(KeywordToken::Explain)?;
+            Self::parse_select(parser).map(RawDbCommand::Explain)
         } else if parser.is_a_keyword(KeywordToken::Insert)? {
-            Self::parse_insert(parser).map(|i| RawDbCommand::Insert(i))
+            Self::parse_insert(parser).map(RawDbCommand::Insert)
+        } else if parser.is_a_keyword(KeywordToken::Use)? {
+            parser.consume_a_keyword(KeywordToken::Use)?;
+            parser.consume_string().map(RawDbCommand::Use)
+        } else if parser.is_a_keyword(KeywordToken::Create)? {
+            Self::parse_create_index(parser).map(RawDbCommand::CreateIndex)
+        } else if parser.is_a_keyword(KeywordToken::Analyze)? {
+            parser.consume_a_keyword(KeywordToken::Analyze)?;
+            let (database_name, table_name) = Self::parse_table_reference(&mut parser)?;
+            Ok(RawDbCommand::Analyze(RawAnalyzeStatement { database_name, table_name }))
         } else {
             Err(ParsingError::UnexpectedToken(QueryToken::Keyword(KeywordToken::Select), parser.expect_current_token()?))
         }
@@ -22,72 +35,217 @@ impl RawParse {
         parser.consume_a_keyword(KeywordToken::Insert)?;
         parser.consume_a_keyword(KeywordToken::Into)?;
         
-        let table_name = parser.consume_string()?;
+        let (database_name, table_name) = Self::parse_table_reference(&mut parser)?;
         
         let mut values: Vec<(String, String)> = vec![];
 
         while !parser.is_finished() {
             let column_name = parser.consume_string()?;
             parser.consume_a_character(CharacterToken::Equal)?;
-            let value = parser.consume_string()?;
+            let value = parser.consume_value()?;
             values.push((column_name, value));
         }
 
         Ok(RawInsertStatement {
+            database_name,
             table_name,
             values
         })
     }
 
-    fn parse_select(mut parser: TokenParser) -> Result<RawSelectQuery<'_>, ParsingError> {
+    /// Parses `create index on table (column) [using kind]`.
+    fn parse_create_index(mut parser: TokenParser) -> Result<RawCreateIndexStatement, ParsingError> {
+        parser.consume_a_keyword(KeywordToken::Create)?;
+        parser.consume_a_keyword(KeywordToken::Index)?;
+        parser.consume_a_keyword(KeywordToken::On)?;
+
+        let (database_name, table_name) = Self::parse_table_reference(&mut parser)?;
+
+        parser.consume_a_character(CharacterToken::LeftParen)?;
+        let column_name = parser.consume_string()?;
+        parser.consume_a_character(CharacterToken::RightParen)?;
+
+        let kind = if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Using)? {
+            Some(parser.consume_string()?)
+        } else {
+            None
+        };
+
+        Ok(RawCreateIndexStatement {
+            database_name,
+            table_name,
+            column_name,
+            kind
+        })
+    }
+
+    fn parse_select(mut parser: TokenParser) -> Result<RawSelectQuery, ParsingError> {
         parser.consume_a_keyword(KeywordToken::Select)?;
         let mut columns: Vec<RawSelectQueryColumn> = Vec::new();
 
-        while columns.len() == 0 || parser.maybe_consume_a_character(CharacterToken::Comma)? {
+        while columns.is_empty() || (!parser.is_finished() && parser.maybe_consume_a_character(CharacterToken::Comma)?) {
             columns.push(Self::parse_query_column(&mut parser)?);
         }
 
-        parser.consume_a_keyword(KeywordToken::From)?;
-
-        let table_name = parser.consume_string()?;
-        let table_identifier = if parser.is_finished() { None } else if parser.is_string()? { Some(parser.consume_string()?) } else { None };
-
-        if parser.is_finished() {
+        // constant selects like `select 1` have no from clause
+        if parser.is_finished() || parser.is_a_keyword(KeywordToken::Limit)? {
             return Ok(RawSelectQuery {
-                table_name,
-                table_identifier,
+                database_name: None,
+                table_name: None,
+                table_identifier: None,
                 columns,
-                where_expression: None
+                join: None,
+                where_expression: None,
+                group_by: Vec::new(),
+                order_by: Vec::new(),
+                limit: Self::parse_limit(&mut parser)?
             })
         }
 
-        let where_expression = if parser.maybe_consume_a_keyword(KeywordToken::Where)? {
-            let column = Self::parse_column_reference(&mut parser)?;
-            let op: RawSelectQueryWhereExpressionOperator = 
-                parser.consume_character().and_then(|c| c.try_into())?;
-            let value = parser.consume_string()?;
-            let ww = RawSelectQueryWhereComparison {
-                column,
-                op,
-                value
-            };
-
-            Some(RawSelectQueryWhereExpression::Single(ww))
+        parser.consume_a_keyword(KeywordToken::From)?;
+
+        let (database_name, table_name) = Self::parse_table_reference(&mut parser)?;
+        let table_name = Some(table_name);
+        let table_identifier = if parser.is_finished() { None } else if parser.is_string()? { Some(parser.consume_string()?) } else { None };
+
+        let join = if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Join)? {
+            Some(Self::parse_join(&mut parser)?)
+        } else {
+            None
+        };
+
+        let where_expression = if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Where)? {
+            Some(Self::parse_where_or(&mut parser)?)
         } else { 
             None
         };
 
+        let mut group_by = Vec::new();
+        if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Group)? {
+            parser.consume_a_keyword(KeywordToken::By)?;
+            while group_by.is_empty() || (!parser.is_finished() && parser.maybe_consume_a_character(CharacterToken::Comma)?) {
+                group_by.push(Self::parse_column_reference(&mut parser)?);
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Order)? {
+            parser.consume_a_keyword(KeywordToken::By)?;
+            while order_by.is_empty() || (!parser.is_finished() && parser.maybe_consume_a_character(CharacterToken::Comma)?) {
+                order_by.push(Self::parse_order_by(&mut parser)?);
+            }
+        }
+
+        let limit = Self::parse_limit(&mut parser)?;
+
         Ok(RawSelectQuery {
+            database_name,
             table_name,
             table_identifier,
             columns,
-            where_expression
+            join,
+            where_expression,
+            group_by,
+            order_by,
+            limit
         })
     }
 
-    fn parse_query_column(parser: &mut TokenParser<'_>) -> Result<RawSelectQueryColumn, ParsingError> {
+    /// Parses conditions joined by `or`, which binds loosest.
+    fn parse_where_or(parser: &mut TokenParser<'_>) -> Result<RawSelectQueryWhereExpression, ParsingError> {
+        let mut expression = Self::parse_where_and(parser)?;
+        while !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Or)? {
+            expression = RawSelectQueryWhereExpression::Or(Box::new(expression), Box::new(Self::parse_where_and(parser)?));
+        }
+        Ok(expression)
+    }
+
+    /// Parses conditions joined by `and`.
+    fn parse_where_and(parser: &mut TokenParser<'_>) -> Result<RawSelectQueryWhereExpression, ParsingError> {
+        let mut expression = Self::parse_where_term(parser)?;
+        while !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::And)? {
+            expression = RawSelectQueryWhereExpression::And(Box::new(expression), Box::new(Self::parse_where_term(parser)?));
+        }
+        Ok(expression)
+    }
+
+    /// Parses `not term`, `(expression)` or `column op value`, where the
+    /// value can be a `?` placeholder.
+    fn parse_where_term(parser: &mut TokenParser<'_>) -> Result<RawSelectQueryWhereExpression, ParsingError> {
+        if parser.maybe_consume_a_keyword(KeywordToken::Not)? {
+            return Ok(RawSelectQueryWhereExpression::Not(Box::new(Self::parse_where_term(parser)?)));
+        }
+        if parser.maybe_consume_a_character(CharacterToken::LeftParen)? {
+            let expression = Self::parse_where_or(parser)?;
+            parser.consume_a_character(CharacterToken::RightParen)?;
+            return Ok(expression);
+        }
+        let column = Self::parse_column_reference(parser)?;
+        let op: RawSelectQueryWhereExpressionOperator = parser.consume_character().and_then(|c| c.try_into())?;
+        let value = match parser.maybe_consume_a_character(CharacterToken::Question)? {
+            true => None,
+            false => Some(parser.consume_value()?)
+        };
+        Ok(RawSelectQueryWhereExpression::Single(RawSelectQueryWhereComparison { column, op, value }))
+    }
+
+    /// Parses `table [identifier] on column op column`, after the `join`.
+    fn parse_join(parser: &mut TokenParser<'_>) -> Result<RawJoin, ParsingError> {
+        let (database_name, table_name) = Self::parse_table_reference(parser)?;
+        let table_identifier = if parser.is_string()? { Some(parser.consume_string()?) } else { None };
+        parser.consume_a_keyword(KeywordToken::On)?;
+        let left = Self::parse_column_reference(parser)?;
+        let op: RawSelectQueryWhereExpressionOperator = parser.consume_character().and_then(|c| c.try_into())?;
+        let right = Self::parse_column_reference(parser)?;
+        Ok(RawJoin { database_name, table_name, table_identifier, left, op, right })
+    }
+
+    /// Parses an optional `limit n`.
+    fn parse_limit(parser: &mut TokenParser<'_>) -> Result<Option<u64>, ParsingError> {
+        if parser.is_finished() || !parser.maybe_consume_a_keyword(KeywordToken::Limit)? {
+            return Ok(None);
+        }
+        let n = parser.consume_value()?;
+        literal::parse_integer::<u64>(&n).map(Some).map_err(|_| ParsingError::InvalidSyntax)
+    }
+
+    /// Parses `column [asc | desc]`.
+    fn parse_order_by(parser: &mut TokenParser<'_>) -> Result<RawOrderBy, ParsingError> {
         let column = Self::parse_column_reference(parser)?;
-        let as_name = if parser.is_a_keyword(KeywordToken::As)? {
+        let descending = if !parser.is_finished() && parser.maybe_consume_a_keyword(KeywordToken::Desc)? {
+            true
+        } else {
+            if !parser.is_finished() { parser.maybe_consume_a_keyword(KeywordToken::Asc)?; }
+            false
+        };
+        Ok(RawOrderBy { column, descending })
+    }
+
+    /// Parses `table` or `database.table`.
+    fn parse_table_reference(parser: &mut TokenParser<'_>) -> Result<(Option<String>, String), ParsingError> {
+        let s1 = parser.consume_string()?;
+        if !parser.is_finished() && parser.maybe_consume_a_character(CharacterToken::Dot)? {
+            Ok((Some(s1), parser.consume_string()?))
+        } else {
+            Ok((None, s1))
+        }
+    }
+
+    fn parse_query_column(parser: &mut TokenParser<'_>) -> Result<RawSelectQueryColumn, ParsingError> {
+        let expression = if parser.maybe_consume_a_character(CharacterToken::Star)? {
+            RawSelectQueryColumnExpression::AllColumns
+        } else if parser.is_string()? {
+            let s1 = parser.consume_string()?;
+            if !parser.is_finished() && parser.maybe_consume_a_character(CharacterToken::LeftParen)? {
+                Self::parse_function_call(parser, s1)?
+            } else {
+                RawSelectQueryColumnExpression::Column(Self::parse_column_reference_from(parser, s1)?)
+            }
+        } else {
+            RawSelectQueryColumnExpression::Constant(parser.consume_value()?)
+        };
+
+        let as_name = if !parser.is_finished() && parser.is_a_keyword(KeywordToken::As)? {
             parser.consume_token()?;
             Some(parser.consume_string()?)
         } else {
@@ -95,14 +253,30 @@ impl RawParse {
         };
 
         Ok(RawSelectQueryColumn {
-            column,
+            expression,
             as_name
         })
     }
 
+    fn parse_function_call(parser: &mut TokenParser<'_>, function_name: String) -> Result<RawSelectQueryColumnExpression, ParsingError> {
+        let function = AggregateFunction::from_name(&function_name)
+            .ok_or(ParsingError::UnknownFunction(function_name))?;
+        let expression = if function == AggregateFunction::Count && parser.maybe_consume_a_character(CharacterToken::Star)? {
+            RawSelectQueryColumnExpression::CountAll
+        } else {
+            RawSelectQueryColumnExpression::Aggregate(function, Self::parse_column_reference(parser)?)
+        };
+        parser.consume_a_character(CharacterToken::RightParen)?;
+        Ok(expression)
+    }
+
     fn parse_column_reference(parser: &mut TokenParser<'_>) -> Result<RawSelectColumnReference, ParsingError> {
         let s1 = parser.consume_string()?;
-        let s2 = if parser.is_a_character(CharacterToken::Dot)? { 
+        Self::parse_column_reference_from(parser, s1)
+    }
+
+    fn parse_column_reference_from(parser: &mut TokenParser<'_>, s1: String) -> Result<RawSelectColumnReference, ParsingError> {
+        let s2 = if !parser.is_finished() && parser.is_a_character(CharacterToken::Dot)? { 
             parser.consume_token()?;
             Some(parser.consume_string()?) 
         } else {
@@ -123,21 +297,18 @@ struct TokenParser<'a> {
 
 impl<'a> TokenParser<'a> {
     pub fn new(query: &'a str) -> TokenParser<'a> {
-        let i = TokenIterator::new(query).into_iter().map(|r| r.map_err(|e| <LexingError as Into<ParsingError>>::into(e)));
+        let i = TokenIterator::new(query).map(|r| r.map_err(<LexingError as Into<ParsingError>>::into));
         let ib: Box<dyn Iterator<Item = Result<QueryToken, ParsingError>> + 'a> = Box::new(i);
-        return TokenParser { iterator: ib.peekable(), current_token: None };
+        TokenParser { iterator: ib.peekable(), current_token: None }
     }
 
     fn next(&mut self) {
         self.iterator.next();
-        self.current_token = self.iterator.peek().map(|r| r.clone());
+        self.current_token = self.iterator.peek().cloned();
     }
 
     pub fn is_finished(&mut self) -> bool {
-        match self.iterator.peek() {
-            Some(_) => false,
-            None => true
-        }
+        self.iterator.peek().is_none()
     }
 
     pub fn expect_current_token(&mut self) -> Result<QueryToken, ParsingError> {
@@ -153,24 +324,6 @@ impl<'a> TokenParser<'a> {
 
     // any keyword
 
-    fn match_is_keyword(&mut self) -> Result<(Option<KeywordToken>, QueryToken), ParsingError> {
-        let t = self.expect_current_token()?;
-        Ok((match t {
-            QueryToken::Keyword(c) => Some(c),
-            _ => None
-        }, t))
-
-    }
-
-    pub fn expect_is_keyword(&mut self) -> Result<KeywordToken, ParsingError> {
-        self.match_is_keyword()
-            .and_then(|(c, t)| c.ok_or_else(|| ParsingError::UnexpectedToken(QueryToken::Keyword(KeywordToken::Select), t)))
-    }
-
-    pub fn is_keyword(&mut self) -> Result<bool, ParsingError> {
-        self.match_is_keyword().map(|(c, _)| c.is_some())
-    }
-
     // a certain keyword
 
     fn match_is_a_keyword(&mut self, keyword: KeywordToken) -> Result<(Option<KeywordToken>, QueryToken), ParsingError> {
@@ -187,15 +340,15 @@ impl<'a> TokenParser<'a> {
 
     pub fn expect_is_a_keyword(&mut self, keyword: KeywordToken) -> Result<(), ParsingError> {
         self.match_is_a_keyword(keyword)
-            .and_then(|(c, t)| c.map(|_| ()).ok_or_else(|| ParsingError::UnexpectedToken(QueryToken::Keyword(keyword), t)))
+            .and_then(|(c, t)| c.map(|_| ()).ok_or(ParsingError::UnexpectedToken(QueryToken::Keyword(keyword), t)))
     }
 
     pub fn consume_a_keyword(&mut self, keyword: KeywordToken) -> Result<(), ParsingError> {
-        self.expect_is_a_keyword(keyword).and_then(|_| { self.consume_token()?; Ok(()) })
+        self.expect_is_a_keyword(keyword).map(|_| self.next())
     }
 
     pub fn maybe_consume_a_keyword(&mut self, keyword: KeywordToken) -> Result<bool, ParsingError> {
-        self.is_a_keyword(keyword).and_then(|v| { if v { self.consume_token()?; } Ok(v) })
+        self.is_a_keyword(keyword).inspect(|&v| { if v { self.next(); } })
     }
 
 
@@ -211,15 +364,11 @@ impl<'a> TokenParser<'a> {
 
     pub fn expect_is_character(&mut self) -> Result<CharacterToken, ParsingError> {
         self.match_is_character()
-            .and_then(|(c, t)| c.ok_or_else(|| ParsingError::UnexpectedToken(QueryToken::Character(CharacterToken::Comma), t)))
-    }
-
-    pub fn is_character(&mut self) -> Result<bool, ParsingError> {
-        self.match_is_character().map(|(c, _)| c.is_some())
+            .and_then(|(c, t)| c.ok_or(ParsingError::UnexpectedToken(QueryToken::Character(CharacterToken::Comma), t)))
     }
 
     pub fn consume_character(&mut self) -> Result<CharacterToken, ParsingError> {
-        self.expect_is_character().and_then(|c| { self.consume_token()?; Ok(c) })
+        self.expect_is_character().inspect(|_c| { self.next(); })
     }
 
 
@@ -239,15 +388,15 @@ impl<'a> TokenParser<'a> {
 
     pub fn expect_is_a_character(&mut self, character: CharacterToken) -> Result<(), ParsingError> {
         self.match_is_a_character(character)
-            .and_then(|(c, t)| c.map(|_| ()).ok_or_else(|| ParsingError::UnexpectedToken(QueryToken::Character(character), t)))
+            .and_then(|(c, t)| c.map(|_| ()).ok_or(ParsingError::UnexpectedToken(QueryToken::Character(character), t)))
     }
 
     pub fn consume_a_character(&mut self, character: CharacterToken) -> Result<(), ParsingError> {
-        self.expect_is_a_character(character).and_then(|_| { self.consume_token()?; Ok(()) })
+        self.expect_is_a_character(character).map(|_| self.next())
     }
 
     pub fn maybe_consume_a_character(&mut self, character: CharacterToken) -> Result<bool, ParsingError> {
-        self.is_a_character(character).and_then(|v| { if v { self.consume_token()?; } Ok(v) })
+        self.is_a_character(character).inspect(|&v| { if v { self.next(); } })
     }
 
     fn match_is_string(&mut self) -> Result<Option<String>, ParsingError> {
@@ -271,15 +420,24 @@ impl<'a> TokenParser<'a> {
     }
 
     pub fn consume_string(&mut self) -> Result<String, ParsingError> {
-        let exp = self.expect_string();
-        match self.expect_string() {
-            Ok(s) => { self.consume_token(); Ok(s) }
-            _ => exp
+        let s = self.expect_string()?;
+        self.consume_token()?;
+        Ok(s)
+    }
+
+    /// Consumes a literal value: a bare word, a quoted string, or a number.
+    pub fn consume_value(&mut self) -> Result<String, ParsingError> {
+        let t = self.expect_current_token()?;
+        match t {
+            QueryToken::String(s) | QueryToken::Quoted(s) | QueryToken::Number(s) => { self.consume_token()?; Ok(s) },
+            _ => Err(ParsingError::UnexpectedToken(QueryToken::Quoted(String::from("")), t))
         }
     }
 
+    /// Moves past the current token, returning it.
     pub fn consume_token(&mut self) -> Result<QueryToken, ParsingError> {
+        let t = self.expect_current_token()?;
         self.next();
-        self.expect_current_token()
+        Ok(t)
     }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
-use std::error;
+
+use std::fmt::Display;
 
 use thiserror::Error;
 
@@ -15,6 +16,9 @@ pub enum ParsingError {
     #[error("Unexpected end of input")]
     UnexpectedEndOfInput,
 
+    #[error("Unknown function {0}")]
+    UnknownFunction(String),
+
     #[error("Invalid syntax")]
     InvalidSyntax
 }
@@ -34,22 +38,65 @@ pub enum LexingError {
     InvalidEscapeCharacter(char)
 }
 
-pub enum RawDbCommand<'a> {
+pub enum RawDbCommand {
     Insert(RawInsertStatement),
-    Select(RawSelectQuery<'a>)
+    Select(RawSelectQuery),
+    /// `explain select ...`, for the plan the select would run.
+    Explain(RawSelectQuery),
+    Use(String),
+    CreateIndex(RawCreateIndexStatement),
+    /// `analyze table`, gathering the table's histograms.
+    Analyze(RawAnalyzeStatement)
+}
+
+pub struct RawCreateIndexStatement {
+    pub database_name: Option<String>,
+    pub table_name: String,
+    pub column_name: String,
+    /// From `using <kind>`, if given.
+    pub kind: Option<String>
+}
+
+pub struct RawAnalyzeStatement {
+    pub database_name: Option<String>,
+    pub table_name: String
 }
 
 pub struct RawInsertStatement {
+    pub database_name: Option<String>,
     pub table_name: String,
     pub values: Vec<(String, String)>
 }
 
 #[derive(Debug)]
-pub struct RawSelectQuery<'a> {
-    pub table_name: String,
+pub struct RawSelectQuery {
+    pub database_name: Option<String>,
+    pub table_name: Option<String>,
     pub table_identifier: Option<String>,
     pub columns: Vec<RawSelectQueryColumn>,
-    pub where_expression: Option<RawSelectQueryWhereExpression<'a>>
+    pub join: Option<RawJoin>,
+    pub where_expression: Option<RawSelectQueryWhereExpression>,
+    pub group_by: Vec<RawSelectColumnReference>,
+    pub order_by: Vec<RawOrderBy>,
+    pub limit: Option<u64>
+}
+
+/// A `join table [identifier] on left op right` clause.
+#[derive(Debug)]
+pub struct RawJoin {
+    pub database_name: Option<String>,
+    pub table_name: String,
+    pub table_identifier: Option<String>,
+    pub left: RawSelectColumnReference,
+    pub op: RawSelectQueryWhereExpressionOperator,
+    pub right: RawSelectColumnReference
+}
+
+/// One column of an `order by` clause.
+#[derive(Debug)]
+pub struct RawOrderBy {
+    pub column: RawSelectColumnReference,
+    pub descending: bool
 }
 
 #[derive(Debug)]
@@ -58,25 +105,69 @@ pub struct RawSelectColumnReference {
     pub table_identifier: Option<String>
 }
 
+#[derive(Debug)]
+pub enum RawSelectQueryColumnExpression {
+    Column(RawSelectColumnReference),
+    Constant(String),
+    CountAll,
+    Aggregate(AggregateFunction, RawSelectColumnReference),
+    /// `*`, every column of the table in order.
+    AllColumns
+}
+
+/// A function folding a column's values over many rows into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg
+}
+
+impl AggregateFunction {
+    pub fn from_name(name: &str) -> Option<AggregateFunction> {
+        match name {
+            "count" => Some(Self::Count),
+            "sum" => Some(Self::Sum),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            "avg" => Some(Self::Avg),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Count => "count",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Avg => "avg"
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RawSelectQueryColumn {
-    pub column: RawSelectColumnReference,
+    pub expression: RawSelectQueryColumnExpression,
     pub as_name: Option<String>
 }
 
 #[derive(Debug)]
-pub enum RawSelectQueryWhereExpression<'a> {
+pub enum RawSelectQueryWhereExpression {
     Single(RawSelectQueryWhereComparison),
-    And(&'a RawSelectQueryWhereExpression<'a>, &'a RawSelectQueryWhereExpression<'a>),
-    Or(&'a RawSelectQueryWhereExpression<'a>, &'a RawSelectQueryWhereExpression<'a>),
-    Not(&'a RawSelectQueryWhereExpression<'a>)
+    And(Box<RawSelectQueryWhereExpression>, Box<RawSelectQueryWhereExpression>),
+    Or(Box<RawSelectQueryWhereExpression>, Box<RawSelectQueryWhereExpression>),
+    Not(Box<RawSelectQueryWhereExpression>)
 }
 
 #[derive(Debug)]
 pub struct RawSelectQueryWhereComparison {
     pub column: RawSelectColumnReference,
     pub op: RawSelectQueryWhereExpressionOperator,
-    pub value: String
+    /// `None` for a `?` placeholder
+    pub value: Option<String>
 }
 
 
@@ -90,15 +181,28 @@ pub enum RawSelectQueryWhereExpressionOperator {
     NotEqual
 }
 
-impl ToString for RawSelectQueryWhereExpressionOperator {
-    fn to_string(&self) -> String {
-        (match self {
+impl RawSelectQueryWhereExpressionOperator {
+    /// The operator with its sides swapped, so `a < b` becomes `b > a`.
+    pub fn flipped(self) -> RawSelectQueryWhereExpressionOperator {
+        match self {
+            Self::GreaterThan => Self::LessThan,
+            Self::GreaterEqual => Self::LessEqual,
+            Self::LessThan => Self::GreaterThan,
+            Self::LessEqual => Self::GreaterEqual,
+            Self::EqualEqual | Self::NotEqual => self
+        }
+    }
+}
+
+impl Display for RawSelectQueryWhereExpressionOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
             Self::GreaterThan => ">",
             Self::GreaterEqual => ">=",
             Self::LessThan => "<",
             Self::LessEqual => "<=",
             Self::EqualEqual => "==",
             Self::NotEqual => "!="
-        }).to_owned()
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,96 @@
+use uuid::Uuid;
+
+use super::{error::KronkError, literal, schema::{ColumnDataType, TableDescriptor}, value::{Row, FromValue}};
+
+/// A struct kept as the rows of a table, one field per column. Usually
+/// derived with `#[derive(KronkTable)]` (the `derive` feature) rather than
+/// written out:
+///
+/// - the table is named after the struct in snake case, or
+///   `#[kronk(table = "name")]`
+/// - each field is a column of the same name, typed after the field, see
+///   `ColumnValue`
+/// - `#[kronk(serial_id)]` makes a `u64` field the table's serial id and
+///   `#[kronk(row_version)]` its row version. Neither is inserted
+/// - `#[kronk(size = n)]` sets the length of a `String` column
+pub trait KronkTable: Sized {
+    const TABLE_NAME: &'static str;
+
+    fn table_descriptor() -> Result<TableDescriptor, KronkError>;
+
+    /// The values to insert the struct with, by column, as the strings
+    /// `insert_columns` takes.
+    fn to_columns(&self) -> Vec<(&'static str, String)>;
+
+    /// Reads the struct back from a query result selecting all of its
+    /// columns.
+    fn from_row(row: &Row) -> Result<Self, KronkError>;
+}
+
+/// A type a `KronkTable` field can have.
+pub trait ColumnValue: FromValue {
+    /// The column type fields of this type get, unless the field says
+    /// otherwise.
+    const DATATYPE: ColumnDataType;
+
+    fn to_column(&self) -> String;
+}
+
+/// The length strings get when their field doesn't set one, in bytes.
+pub const DEFAULT_STRING_SIZE: usize = 64;
+
+impl ColumnValue for i32 {
+    const DATATYPE: ColumnDataType = ColumnDataType::Int32;
+
+    fn to_column(&self) -> String {
+        literal::format_integer(self)
+    }
+}
+
+impl ColumnValue for u32 {
+    const DATATYPE: ColumnDataType = ColumnDataType::UInt32;
+
+    fn to_column(&self) -> String {
+        literal::format_integer(self)
+    }
+}
+
+impl ColumnValue for i64 {
+    const DATATYPE: ColumnDataType = ColumnDataType::Int64;
+
+    fn to_column(&self) -> String {
+        literal::format_integer(self)
+    }
+}
+
+impl ColumnValue for u64 {
+    const DATATYPE: ColumnDataType = ColumnDataType::UInt64;
+
+    fn to_column(&self) -> String {
+        literal::format_integer(self)
+    }
+}
+
+impl ColumnValue for bool {
+    const DATATYPE: ColumnDataType = ColumnDataType::Boolean;
+
+    fn to_column(&self) -> String {
+        literal::format_boolean(*self).to_owned()
+    }
+}
+
+impl ColumnValue for Uuid {
+    const DATATYPE: ColumnDataType = ColumnDataType::UuidV4;
+
+    fn to_column(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ColumnValue for String {
+    const DATATYPE: ColumnDataType = ColumnDataType::Byte(DEFAULT_STRING_SIZE);
+
+    fn to_column(&self) -> String {
+        self.clone()
+    }
+}
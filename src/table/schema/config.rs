@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use super::super::error::KronkError;
+
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+pub const DEFAULT_BUFFER_POOL_MEMORY_CAP: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// sync after every write
+    EveryWrite,
+    /// sync at most once per interval
+    Interval(Duration),
+    /// only sync when the database is closed
+    OnClose
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CachePriority {
+    Low,
+    Normal,
+    High
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// reads go through the buffer pool
+    File,
+    /// the table file is memory-mapped and rows are read straight from it
+    Mmap
+}
+
+/// What to do with a row that fails its checksum when read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// fail the read
+    Error,
+    /// leave the row out of results
+    Skip,
+    /// restore the row from the write-ahead log if it still holds it,
+    /// otherwise fail the read
+    Repair
+}
+
+/// Storage settings in effect for a table: the database-wide defaults with
+/// any per-table overrides applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageSettings {
+    pub page_size: usize,
+    pub durability: Durability,
+    pub compression: Compression,
+    pub cache_priority: CachePriority,
+    pub backend: StorageBackend,
+    pub on_corruption: CorruptionPolicy,
+    /// Largest a table's active segment may grow to, in bytes, before it is
+    /// sealed and a new one started. Without one, every row stays in the
+    /// table file.
+    pub segment_size: Option<u64>
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            page_size: DEFAULT_PAGE_SIZE,
+            durability: Durability::OnClose,
+            compression: Compression::None,
+            cache_priority: CachePriority::Normal,
+            backend: StorageBackend::File,
+            on_corruption: CorruptionPolicy::Error,
+            segment_size: None
+        }
+    }
+}
+
+impl StorageSettings {
+    pub fn with_overrides(&self, overrides: &TableOverrides) -> StorageSettings {
+        StorageSettings {
+            page_size: overrides.page_size.unwrap_or(self.page_size),
+            durability: overrides.durability.unwrap_or(self.durability),
+            compression: overrides.compression.unwrap_or(self.compression),
+            cache_priority: overrides.cache_priority.unwrap_or(self.cache_priority),
+            backend: overrides.backend.unwrap_or(self.backend),
+            on_corruption: overrides.on_corruption.unwrap_or(self.on_corruption),
+            segment_size: overrides.segment_size.or(self.segment_size)
+        }
+    }
+}
+
+/// Per-table settings that take precedence over the database defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableOverrides {
+    pub page_size: Option<usize>,
+    pub durability: Option<Durability>,
+    pub compression: Option<Compression>,
+    pub cache_priority: Option<CachePriority>,
+    pub backend: Option<StorageBackend>,
+    pub on_corruption: Option<CorruptionPolicy>,
+    pub segment_size: Option<u64>
+}
+
+impl TableOverrides {
+    pub fn validate(&self) -> Result<(), KronkError> {
+        if let Some(page_size) = self.page_size {
+            if !page_size.is_power_of_two() || !(512..=65536).contains(&page_size) {
+                return Err(KronkError::Schema(format!("Invalid page size {}: must be a power of two between 512 and 65536", page_size)));
+            }
+        }
+        if self.segment_size == Some(0) {
+            return Err(KronkError::Schema("Invalid segment size 0: must be at least one byte".to_owned()));
+        }
+        Ok(())
+    }
+}
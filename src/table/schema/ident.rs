@@ -0,0 +1,56 @@
+use super::super::query::lex::KeywordToken;
+use super::super::error::KronkError;
+
+/// Longest name accepted for a database, table or column, in bytes.
+pub const MAX_IDENTIFIER_LENGTH: usize = 64;
+
+/// Identifiers are case-insensitive. Names are stored in this form and every
+/// lookup goes through it, so `Books`, `BOOKS` and `books` are one table.
+pub fn canonical(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+/// Checks a name before it is used in a schema, returning its canonical
+/// form. Names must start with a letter or underscore, contain only ASCII
+/// letters, digits and underscores, and can't be a keyword. Since table and
+/// database names become file names, this also keeps them safe on disk.
+/// `kind` names what is being validated in the error, e.g. "table".
+pub fn validate(kind: &str, name: &str) -> Result<String, KronkError> {
+    if name.is_empty() {
+        return Err(KronkError::Schema(format!("Invalid {} name: name is empty", kind)));
+    }
+    if name.len() > MAX_IDENTIFIER_LENGTH {
+        return Err(KronkError::Schema(format!("Invalid {} name '{}': longer than {} characters", kind, name, MAX_IDENTIFIER_LENGTH)));
+    }
+    let starts_ok = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+    if !starts_ok || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(KronkError::Schema(format!("Invalid {} name '{}': use letters, digits and underscores, starting with a letter or underscore", kind, name)));
+    }
+
+    let name = canonical(name);
+    if KeywordToken::try_from(name.as_str()).is_ok() {
+        return Err(KronkError::Schema(format!("Invalid {} name '{}': it is a reserved keyword", kind, name)));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_folded_to_lower_case() {
+        assert_eq!(validate("table", "Books").unwrap(), "books");
+        assert_eq!(validate("column", "_year_2").unwrap(), "_year_2");
+        assert_eq!(canonical("BOOKS"), canonical("books"));
+    }
+
+    #[test]
+    fn unusable_names_are_refused() {
+        for name in ["", "2books", "book shelf", "books.db", "../books", "bøger", "select", "SELECT"] {
+            assert!(matches!(validate("table", name), Err(KronkError::Schema(_))), "{:?}", name);
+        }
+        assert!(validate("table", &"b".repeat(MAX_IDENTIFIER_LENGTH)).is_ok());
+        assert!(validate("table", &"b".repeat(MAX_IDENTIFIER_LENGTH + 1)).is_err());
+    }
+}
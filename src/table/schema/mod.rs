@@ -1,8 +1,17 @@
 use std::any::type_name;
 
 use itertools::Itertools;
-use uuid::{Uuid, uuid};
-use super::bytes::{FromSlice};
+use uuid::Uuid;
+use super::bytes::{FromSlice, ToBytes};
+use super::error::KronkError;
+use super::literal;
+use super::catalog;
+use super::value::Value;
+
+pub mod config;
+pub mod ident;
+
+use self::config::{StorageSettings, TableOverrides};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum ColumnDataType {
@@ -13,10 +22,52 @@ pub enum ColumnDataType {
     UInt32,
     Int64,
     UInt64,
-    UuidV4
+    UuidV4,
+    /// Starts at 0 and is bumped by every update of the row. It can't be
+    /// set, so a reader can tell whether the row changed since they read
+    /// it, see `Database::update_versioned`.
+    RowVersion
+}
+
+/// The type's name in snake case, with a byte column's length, e.g. `int32`
+/// or `byte(64)`.
+impl std::fmt::Display for ColumnDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnDataType::SerialId => write!(f, "serial_id"),
+            ColumnDataType::Byte(length) => write!(f, "byte({})", length),
+            ColumnDataType::Boolean => write!(f, "boolean"),
+            ColumnDataType::Int32 => write!(f, "int32"),
+            ColumnDataType::UInt32 => write!(f, "uint32"),
+            ColumnDataType::Int64 => write!(f, "int64"),
+            ColumnDataType::UInt64 => write!(f, "uint64"),
+            ColumnDataType::UuidV4 => write!(f, "uuid_v4"),
+            ColumnDataType::RowVersion => write!(f, "row_version")
+        }
+    }
 }
 
 impl ColumnDataType {
+    /// True for the integer types, serial ids included.
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Self::SerialId | Self::Int32 | Self::UInt32 | Self::Int64 | Self::UInt64 | Self::RowVersion)
+    }
+
+    pub fn is_signed_integer(&self) -> bool {
+        matches!(self, Self::Int32 | Self::Int64)
+    }
+
+    /// True when values of the two types decode to the same kind of value,
+    /// so they can be compared, e.g. serial ids and row versions with
+    /// `UInt64`s and strings of any length with each other.
+    pub fn is_comparable_with(&self, other: &ColumnDataType) -> bool {
+        match (self, other) {
+            (Self::SerialId | Self::UInt64 | Self::RowVersion, Self::SerialId | Self::UInt64 | Self::RowVersion) => true,
+            (Self::Byte(_), Self::Byte(_)) => true,
+            (a, b) => a == b
+        }
+    }
+
     pub fn size_in_bytes(&self) -> usize {
         let s = self;
         match s {
@@ -27,76 +78,82 @@ impl ColumnDataType {
             Self::UInt32 => 4,
             Self::Int64 => 8,
             Self::UInt64 => 8,
-            Self::UuidV4 => 128
+            Self::UuidV4 => 16,
+            Self::RowVersion => 8
         }
     }
 
-    pub fn parse_string<'a>(&self, s: &str) -> Result<Vec<u8>, String> {
+    pub fn parse_string(&self, s: &str) -> Result<Vec<u8>, KronkError> {
         let expected = self;
         match expected {
-            Self::SerialId => Err("Cannot provide an argument for serial ids".to_owned()),
-            Self::Boolean => match s {
-                "true" => Ok(vec![1u8]),
-                "false" => Ok(vec![0u8]),
-                _ => Err(format!("Could not parse {} to a boolean", s))
-            },
-            Self::Int32 => str::parse::<i32>(s)
-                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>())
-                .map_err(|_| format!("Could not parse {} to an {}", s, type_name::<i32>())),
-            Self::UInt32 => str::parse::<u32>(s)
-                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>())
-                .map_err(|_| format!("Could not parse {} to an {}", s, type_name::<u32>())),
-            Self::Int64 => str::parse::<i64>(s)
-                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>())
-                .map_err(|_| format!("Could not parse {} to an {}", s, type_name::<i64>())),
-            Self::UInt64 => str::parse::<u64>(s)
-                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>())
-                .map_err(|_| format!("Could not parse {} to an {}", s, type_name::<u64>())),
+            Self::SerialId => Err(KronkError::Schema("Cannot provide an argument for serial ids".to_owned())),
+            Self::RowVersion => Err(KronkError::Schema("Cannot provide an argument for row versions".to_owned())),
+            Self::Boolean => literal::parse_boolean(s)
+                .map(|b| vec![b as u8]),
+            Self::Int32 => literal::parse_integer::<i32>(s)
+                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>()),
+            Self::UInt32 => literal::parse_integer::<u32>(s)
+                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>()),
+            Self::Int64 => literal::parse_integer::<i64>(s)
+                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>()),
+            Self::UInt64 => literal::parse_integer::<u64>(s)
+                .map(|i| i.to_le_bytes().into_iter().collect::<Vec<_>>()),
 
             Self::UuidV4 => str::parse::<uuid::Uuid>(s)
-                .map(|i| i.as_bytes().into_iter().map(|b| *b).collect::<Vec<_>>())
-                .map_err(|_| format!("Could not parse {} to a {}", s, type_name::<Uuid>())),
+                .map(|i| i.as_bytes().to_vec())
+                .map_err(|e| KronkError::Schema(format!("Could not parse {} to a {}: {}", s, type_name::<Uuid>(), e))),
 
-            Self::Byte(i) => {
-                let s_bytes_len = s.as_bytes().len();
-                if s_bytes_len >= (*i - 1) { Err(format!("Could not add string as Byte({}) because it's too long! ({})", i, s_bytes_len)) }
-                else { Ok(s.as_bytes().into_iter().map(|b| *b).chain(std::iter::repeat(0u8).take(i - s_bytes_len)).collect::<Vec<_>>()) }
-            }
+            Self::Byte(i) => Self::encode_str(*i, s)
         }
     }
 
-    fn from_bytes_to_string<T>(buf: &[u8]) -> Result<String, String>
-    where T: FromSlice + ToString {
-        T::from_slice(buf)
-            .map(|t| t.to_string())
-            .map_err(|_| format!("Could not parse byte buffer to {}", type_name::<T>()))
+    /// Serializes a natively typed value for a column of this type. Integers
+    /// can go in any integer column they fit in.
+    pub fn encode_value(&self, value: &Value) -> Result<Vec<u8>, KronkError> {
+        let out_of_range = || KronkError::Schema(format!("Value {} is out of range for {:?}", value, self));
+        let integer = match value {
+            Value::Int32(i) => Some(*i as i128),
+            Value::UInt32(u) => Some(*u as i128),
+            Value::Int64(i) => Some(*i as i128),
+            Value::UInt64(u) => Some(*u as i128),
+            _ => None
+        };
+        match (self, value, integer) {
+            (Self::SerialId, _, _) => Err(KronkError::Schema("Cannot provide an argument for serial ids".to_owned())),
+            (Self::RowVersion, _, _) => Err(KronkError::Schema("Cannot provide an argument for row versions".to_owned())),
+            (Self::Int32, _, Some(i)) => Ok(i32::try_from(i).map_err(|_| out_of_range())?.to_bytes()),
+            (Self::UInt32, _, Some(i)) => Ok(u32::try_from(i).map_err(|_| out_of_range())?.to_bytes()),
+            (Self::Int64, _, Some(i)) => Ok(i64::try_from(i).map_err(|_| out_of_range())?.to_bytes()),
+            (Self::UInt64, _, Some(i)) => Ok(u64::try_from(i).map_err(|_| out_of_range())?.to_bytes()),
+            (Self::Boolean, Value::Bool(b), _) => Ok(vec![*b as u8]),
+            (Self::UuidV4, Value::Uuid(u), _) => Ok(u.as_bytes().to_vec()),
+            (Self::Byte(i), Value::Str(s), _) => Self::encode_str(*i, s),
+            _ => Err(KronkError::Schema(format!("Could not store {:?} as {:?}", value, self)))
+        }
     }
 
-    pub fn parse_bytes(&self, bytes: &[u8]) -> Result<String, String> {
-        match self {
-            Self::SerialId => Self::from_bytes_to_string::<u64>(bytes),
-            Self::UuidV4 => {
-                let sized_bytes: [u8; 16] = bytes[..16].try_into().map_err(|_| "Byte buffer not long enough for uuid")?;
-                Ok(Uuid::from_bytes(sized_bytes).to_string())
-            },
-            Self::Int32 => Self::from_bytes_to_string::<i32>(bytes),
-            Self::UInt32 => Self::from_bytes_to_string::<u32>(bytes),
-            Self::Int64 => Self::from_bytes_to_string::<i64>(bytes),
-            Self::UInt64 => Self::from_bytes_to_string::<u64>(bytes),
-            Self::Boolean => {
-                let sized_bytes: [u8; 1] = bytes[..1].try_into()
-                    .map_err(|_| "Insufficient byte buffer size for u8".to_string())?;
-
-                Ok((sized_bytes[0] != 0).to_string())
-            },
-            Self::Byte(max_length) => {
-                if bytes.len() < *max_length { return Err("Insufficient byte buffer size".to_string())}
-                
-                let s = String::from_utf8(bytes.into_iter().map(|b| *b).take_while(|b| *b != 0u8).collect_vec())
-                    .map_err(|_| "could not parse byte buffer to a valid utf-8 string")?;
+    fn encode_str(size: usize, s: &str) -> Result<Vec<u8>, KronkError> {
+        let s_bytes_len = s.len();
+        if s_bytes_len >= (size - 1) { Err(KronkError::Schema(format!("Could not add string as Byte({}) because it's too long! ({})", size, s_bytes_len))) }
+        else { Ok(s.as_bytes().iter().copied().chain(std::iter::repeat_n(0u8, size - s_bytes_len)).collect::<Vec<_>>()) }
+    }
 
-                Ok(s)
-            }
+    pub fn parse_bytes(&self, bytes: &[u8]) -> Result<String, KronkError> {
+        Value::from_bytes(self, bytes).map(|v| v.to_string())
+    }
+
+    /// A value's stored bytes rearranged so that comparing keys byte by byte
+    /// orders them the same way as the values, for use as an index key.
+    pub fn index_key(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Self::SerialId | Self::UInt64 | Self::RowVersion => u64::from_slice(bytes).unwrap().to_be_bytes().to_vec(),
+            Self::UInt32 => u32::from_slice(bytes).unwrap().to_be_bytes().to_vec(),
+            // flipping the sign bit puts negative numbers before positive ones
+            Self::Int32 => ((i32::from_slice(bytes).unwrap() as u32) ^ (1 << 31)).to_be_bytes().to_vec(),
+            Self::Int64 => ((i64::from_slice(bytes).unwrap() as u64) ^ (1 << 63)).to_be_bytes().to_vec(),
+            Self::Boolean => vec![(bytes[0] != 0) as u8],
+            Self::UuidV4 => bytes[..16].to_vec(),
+            Self::Byte(max_length) => bytes.iter().take(*max_length).copied().take_while(|b| *b != 0u8).collect_vec()
         }
     }
 }
@@ -108,33 +165,110 @@ pub struct TableColumn {
     pub offset: usize
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDelete {
+    Restrict,
+    Cascade
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableConstraint {
+    Unique(String),
+    /// `column` holds the serial id of a row in `references`
+    ForeignKey { column: String, references: String, on_delete: OnDelete }
+}
+
+#[derive(Debug, Clone)]
 pub struct TableDescriptor {
     pub table_name: String,
-    pub columns: Vec<TableColumn>
+    pub columns: Vec<TableColumn>,
+    pub primary_key: Vec<String>,
+    pub constraints: Vec<TableConstraint>,
+    pub overrides: TableOverrides
+}
+
+#[derive(Debug, Clone)]
+pub enum AlterTableOperation {
+    AddColumn(String, ColumnDataType),
+    DropColumn(String),
+    AlterColumnType(String, ColumnDataType),
+    RenameColumn(String, String)
 }
 
 #[derive(Debug)]
 pub struct DatabaseDescriptor {
     pub db_name: String,
-    pub tables: Vec<TableDescriptor>
+    pub tables: Vec<TableDescriptor>,
+    pub settings: StorageSettings
 }
 
 impl DatabaseDescriptor {
-    pub fn new(name: &str, tables: Vec<TableDescriptor>) -> Result<DatabaseDescriptor, String> {
+    pub fn new(name: &str, tables: Vec<TableDescriptor>) -> Result<DatabaseDescriptor, KronkError> {
         // TODO: assert unique table names
         Ok(DatabaseDescriptor {
-            db_name: name.to_owned(),
-            tables
+            db_name: ident::validate("database", name)?,
+            tables,
+            settings: StorageSettings::default()
         })
     }
 
-    pub fn add_table(&mut self, table: TableDescriptor) -> Result<(), String> {
+    /// The storage settings for a table, with its overrides applied to the
+    /// database defaults.
+    pub fn settings_for(&self, table_name: &str) -> Option<StorageSettings> {
+        self.table_with_name(table_name).map(|t| self.settings.with_overrides(&t.overrides))
+    }
+
+    pub fn add_table(&mut self, table: TableDescriptor) -> Result<(), KronkError> {
+        self.validate_new_table(&table)?;
+        self.tables.push(table);
+        Ok(())
+    }
+
+    /// Removes a table, refusing while another table's foreign key references it.
+    pub fn remove_table(&mut self, table_name: &str) -> Result<TableDescriptor, KronkError> {
+        let table_name = ident::canonical(table_name);
+        let idx = self.tables.iter().position(|t| t.table_name == table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table_name)))?;
+
+        let referencing = self.tables.iter()
+            .filter(|t| t.table_name != table_name)
+            .find(|t| t.foreign_keys().any(|(_, references, _)| *references == table_name));
+        if let Some(t) = referencing {
+            return Err(KronkError::Schema(format!("Cannot drop table '{}': table '{}' has a foreign key referencing it", table_name, t.table_name)));
+        }
+
+        Ok(self.tables.remove(idx))
+    }
+
+    /// Swaps in a new descriptor for an existing table of the same name.
+    pub fn replace_table(&mut self, table: TableDescriptor) -> Result<(), KronkError> {
+        let existing = self.tables.iter_mut()
+            .find(|t| t.table_name == table.table_name)
+            .ok_or_else(|| KronkError::Schema(format!("No table '{}' exists", table.table_name)))?;
+        *existing = table;
+        Ok(())
+    }
+
+    /// Checks that a table could be added to this database without adding it.
+    pub fn validate_new_table(&self, table: &TableDescriptor) -> Result<(), KronkError> {
         let t = &self.tables;
-        if t.into_iter().any(|t| t.table_name == table.table_name) {
-            return Err(format!("Cannot add table with duplicate name '{}'", table.table_name));
+        if t.iter().any(|t| t.table_name == table.table_name) {
+            return Err(KronkError::Schema(format!("Cannot add table with duplicate name '{}'", table.table_name)));
+        }
+        if catalog::is_virtual(&table.table_name) {
+            return Err(KronkError::Schema(format!("Cannot add table '{}': the name is reserved for a virtual table", table.table_name)));
+        }
+        if table.primary_key.is_empty() {
+            return Err(KronkError::Schema(format!("Table '{}' requires a serial id or a declared primary key", table.table_name)));
+        }
+        for (column, references, _) in table.foreign_keys() {
+            let referenced = if references == table.table_name { Some(table) } else { self.table_with_name(references) };
+            match referenced {
+                None => return Err(KronkError::Constraint(format!("Foreign key '{}' references missing table '{}'", column.name, references))),
+                Some(r) if r.id_column().is_none() => return Err(KronkError::Constraint(format!("Foreign key '{}' references table '{}', which has no serial id", column.name, references))),
+                _ => {}
+            }
         }
-        self.tables.push(table);
         Ok(())
     }
 }
@@ -145,67 +279,355 @@ pub trait GetTableDescriptor {
 
 impl GetTableDescriptor for DatabaseDescriptor {
     fn table_with_name<'a>(&'a self, table_name: &str) -> Option<&'a TableDescriptor> {
-        (&self.tables).into_iter().find(|t| t.table_name == table_name)
+        let table_name = ident::canonical(table_name);
+        self.tables.iter().find(|t| t.table_name == table_name)
     }
 }
 
 impl TableDescriptor {
-    pub fn new(name: &str, columns: Vec<(&str, ColumnDataType)>) -> Result<TableDescriptor, String> {
+    pub fn new(name: &str, columns: Vec<(&str, ColumnDataType)>) -> Result<TableDescriptor, KronkError> {
         let mut offset = 0usize;
 
-        if columns[..].into_iter().filter(|c| c.1 == ColumnDataType::SerialId).count() != 1 {
-            return Err("Table descriptor requires exactly 1 serial id".to_string());
+        if columns[..].iter().filter(|c| c.1 == ColumnDataType::SerialId).count() > 1 {
+            return Err(KronkError::Schema("Table descriptor allows at most 1 serial id".to_string()));
+        }
+        if columns[..].iter().filter(|c| c.1 == ColumnDataType::RowVersion).count() > 1 {
+            return Err(KronkError::Schema("Table descriptor allows at most 1 row version".to_string()));
         }
 
+        let table_name = ident::validate("table", name)?;
+
         let cols: Vec<TableColumn> = columns.into_iter()
             .map(|c| {
-                let tc = TableColumn { name: c.0.to_owned(), offset: offset, datatype: c.1 };
+                let tc = TableColumn { name: ident::validate("column", c.0)?, offset, datatype: c.1 };
                 offset += tc.datatype.size_in_bytes();
 
-                tc
-            }).collect();
+                Ok(tc)
+            }).collect::<Result<_, KronkError>>()?;
+
+        if let Some(c) = cols.iter().duplicates_by(|c| &c.name).next() {
+            return Err(KronkError::Schema(format!("Table '{}' has more than one column named '{}'", table_name, c.name)));
+        }
 
-        Ok(TableDescriptor { table_name: name.to_owned(), columns: cols })
+        let primary_key = cols.iter()
+            .filter(|c| c.datatype == ColumnDataType::SerialId)
+            .map(|c| c.name.to_owned())
+            .collect_vec();
+
+        Ok(TableDescriptor { table_name, columns: cols, primary_key, constraints: Vec::new(), overrides: TableOverrides::default() })
+    }
+
+    /// Declares the primary key, replacing the default of the serial id
+    /// column. Several columns make a composite key.
+    pub fn with_primary_key(mut self, column_names: &[&str]) -> Result<TableDescriptor, KronkError> {
+        if column_names.is_empty() {
+            return Err(KronkError::Schema("Primary key requires at least one column".to_owned()));
+        }
+        let column_names = column_names.iter().map(|n| ident::canonical(n)).collect_vec();
+        for (i, name) in column_names.iter().enumerate() {
+            self.column_for_name(name)
+                .ok_or_else(|| KronkError::Schema(format!("Cannot use '{}' in primary key: no such column", name)))?;
+            if column_names[..i].contains(name) {
+                return Err(KronkError::Schema(format!("Column '{}' appears twice in primary key", name)));
+            }
+        }
+        self.primary_key = column_names;
+        Ok(self)
+    }
+
+    pub fn primary_key_columns<'a>(&'a self) -> impl Iterator<Item = &'a TableColumn> + 'a {
+        self.primary_key.iter().filter_map(|n| self.column_for_name(n))
+    }
+
+    pub fn with_overrides(mut self, overrides: TableOverrides) -> Result<TableDescriptor, KronkError> {
+        overrides.validate()?;
+        self.overrides = overrides;
+        Ok(self)
+    }
+
+    pub fn with_constraint(mut self, constraint: TableConstraint) -> Result<TableDescriptor, KronkError> {
+        let constraint = match constraint {
+            TableConstraint::Unique(column_name) => TableConstraint::Unique(ident::canonical(&column_name)),
+            TableConstraint::ForeignKey { column, references, on_delete } =>
+                TableConstraint::ForeignKey { column: ident::canonical(&column), references: ident::canonical(&references), on_delete }
+        };
+        match &constraint {
+            TableConstraint::Unique(column_name) => {
+                self.column_for_name(column_name)
+                    .ok_or_else(|| KronkError::Schema(format!("Cannot add unique constraint: no column '{}' exists", column_name)))?;
+            },
+            TableConstraint::ForeignKey { column, .. } => {
+                let c = self.column_for_name(column)
+                    .ok_or_else(|| KronkError::Schema(format!("Cannot add foreign key: no column '{}' exists", column)))?;
+                if c.datatype != ColumnDataType::UInt64 {
+                    return Err(KronkError::Schema(format!("Cannot add foreign key: column '{}' must be a UInt64 to hold a serial id", column)));
+                }
+            }
+        }
+
+        if !self.constraints.contains(&constraint) {
+            self.constraints.push(constraint);
+        }
+        Ok(self)
+    }
+
+    pub fn unique_columns<'a>(&'a self) -> impl Iterator<Item = &'a TableColumn> + 'a {
+        self.constraints.iter()
+            .filter_map(|c| match c {
+                TableConstraint::Unique(column_name) => self.column_for_name(column_name),
+                _ => None
+            })
+    }
+
+    /// Foreign keys declared on this table, as (column, referenced table, on delete).
+    pub fn foreign_keys<'a>(&'a self) -> impl Iterator<Item = (&'a TableColumn, &'a str, OnDelete)> + 'a {
+        self.constraints.iter()
+            .filter_map(|c| match c {
+                TableConstraint::ForeignKey { column, references, on_delete } =>
+                    self.column_for_name(column).map(|tc| (tc, references.as_str(), *on_delete)),
+                _ => None
+            })
+    }
+
+    /// Applies the operations to a copy of this descriptor. Also returns, for
+    /// each column of the new layout, the name of the column in this layout
+    /// its values come from (`None` for added columns).
+    pub fn altered(&self, operations: &[AlterTableOperation]) -> Result<(TableDescriptor, Vec<Option<String>>), KronkError> {
+        let mut columns: Vec<(String, ColumnDataType, Option<String>)> = self.columns.iter()
+            .map(|c| (c.name.to_owned(), c.datatype.clone(), Some(c.name.to_owned())))
+            .collect_vec();
+        let mut primary_key = self.primary_key.clone();
+        let mut constraints = self.constraints.clone();
+
+        for op in operations {
+            let op = match op {
+                AlterTableOperation::AddColumn(name, datatype) => AlterTableOperation::AddColumn(ident::validate("column", name)?, datatype.clone()),
+                AlterTableOperation::DropColumn(name) => AlterTableOperation::DropColumn(ident::canonical(name)),
+                AlterTableOperation::AlterColumnType(name, datatype) => AlterTableOperation::AlterColumnType(ident::canonical(name), datatype.clone()),
+                AlterTableOperation::RenameColumn(from, to) => AlterTableOperation::RenameColumn(ident::canonical(from), ident::validate("column", to)?)
+            };
+            match &op {
+                AlterTableOperation::AddColumn(name, datatype) => {
+                    if columns[..].iter().any(|c| c.0 == *name) {
+                        return Err(KronkError::Schema(format!("Cannot add column '{}': it already exists", name)));
+                    }
+                    columns.push((name.to_owned(), datatype.clone(), None));
+                },
+                AlterTableOperation::DropColumn(name) => {
+                    let idx = columns[..].iter().position(|c| c.0 == *name)
+                        .ok_or_else(|| KronkError::Schema(format!("Cannot drop column '{}': no such column", name)))?;
+                    if primary_key.contains(name) || constraints.iter().any(|c| match c {
+                        TableConstraint::Unique(c) => c == name,
+                        TableConstraint::ForeignKey { column, .. } => column == name
+                    }) {
+                        return Err(KronkError::Schema(format!("Cannot drop column '{}': it is used by a key or constraint", name)));
+                    }
+                    columns.remove(idx);
+                },
+                AlterTableOperation::AlterColumnType(name, datatype) => {
+                    let column = columns.iter_mut().find(|c| c.0 == *name)
+                        .ok_or_else(|| KronkError::Schema(format!("Cannot alter column '{}': no such column", name)))?;
+                    if column.1 == ColumnDataType::SerialId || *datatype == ColumnDataType::SerialId {
+                        return Err(KronkError::Schema(format!("Cannot alter column '{}': serial ids cannot change type", name)));
+                    }
+                    column.1 = datatype.clone();
+                },
+                AlterTableOperation::RenameColumn(from, to) => {
+                    if columns[..].iter().any(|c| c.0 == *to) {
+                        return Err(KronkError::Schema(format!("Cannot rename column '{}' to '{}': it already exists", from, to)));
+                    }
+                    let column = columns.iter_mut().find(|c| c.0 == *from)
+                        .ok_or_else(|| KronkError::Schema(format!("Cannot rename column '{}': no such column", from)))?;
+                    column.0 = to.to_owned();
+
+                    for pk in primary_key.iter_mut() { if pk == from { *pk = to.to_owned(); } }
+                    for c in constraints.iter_mut() {
+                        match c {
+                            TableConstraint::Unique(c) if c == from => { *c = to.to_owned(); },
+                            TableConstraint::ForeignKey { column, .. } if column == from => { *column = to.to_owned(); },
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        let sources = columns[..].iter().map(|c| c.2.clone()).collect_vec();
+        let named_columns = columns[..].iter().map(|c| (c.0.as_str(), c.1.clone())).collect_vec();
+        let mut altered = TableDescriptor::new(&self.table_name, named_columns)?;
+        altered.primary_key = primary_key;
+        altered.overrides = self.overrides;
+        for c in constraints {
+            altered = altered.with_constraint(c)?;
+        }
+
+        Ok((altered, sources))
+    }
+
+    /// Converts a row stored in the `from` layout into this layout. Columns
+    /// that kept their type are copied as-is, changed types go through their
+    /// string form, and added columns get the same defaults as an insert
+    /// that omits them.
+    pub fn convert_row_from(&self, from: &TableDescriptor, sources: &[Option<String>], row: &[u8]) -> Result<Vec<u8>, KronkError> {
+        let mut o: Vec<u8> = Vec::with_capacity(self.total_row_size());
+
+        for (column, source) in self.columns.iter().zip(sources) {
+            match source.as_ref().and_then(|s| from.column_for_name(s)) {
+                Some(old) if old.datatype == column.datatype => {
+                    o.extend(&row[old.offset..(old.offset + old.datatype.size_in_bytes())]);
+                },
+                Some(old) => {
+                    let value = old.datatype.parse_bytes(&row[old.offset..])?;
+                    let converted = column.datatype.parse_string(&value)
+                        .map_err(|e| KronkError::Schema(format!("Cannot convert column '{}': {}", column.name, e)))?;
+                    o.extend(converted);
+                },
+                None if column.datatype == ColumnDataType::UuidV4 => o.extend(Uuid::new_v4().as_bytes()),
+                None => o.extend(std::iter::repeat_n(0u8, column.datatype.size_in_bytes()))
+            }
+        }
+
+        Ok(o)
     }
 
     pub fn total_row_size(&self) -> usize {
         let cols = &self.columns;
-        cols.into_iter().map(|c| c.datatype.size_in_bytes()).sum()
+        cols.iter().map(|c| c.datatype.size_in_bytes()).sum()
+    }
+
+    /// The names of the table's columns, in row order.
+    pub fn column_names(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|c| c.name.as_str())
     }
 
-    pub fn id_column<'a>(&'a self) -> &'a TableColumn {
+    pub fn id_column(&self) -> Option<&TableColumn> {
         let columns = &self.columns;
-        columns.into_iter().find(|c| c.datatype == ColumnDataType::SerialId).unwrap()
+        columns.iter().find(|c| c.datatype == ColumnDataType::SerialId)
+    }
+
+    pub fn version_column(&self) -> Option<&TableColumn> {
+        self.columns.iter().find(|c| c.datatype == ColumnDataType::RowVersion)
+    }
+
+    /// The id reported for a row: its serial id, or its position in the
+    /// table for tables without one.
+    pub fn row_id(&self, bytes: &[u8], row_number: u64) -> u64 {
+        match self.id_column() {
+            Some(id_column) => u64::from_slice(&bytes[id_column.offset..]).unwrap(),
+            None => row_number
+        }
     }
 
     pub fn column_for_name<'a>(&'a self, name: &str) -> Option<&'a TableColumn> {
         let columns = &self.columns;
-        columns.into_iter().find(|c| c.name == name)
+        let name = ident::canonical(name);
+        columns.iter().find(|c| c.name == name)
     }
 
-    pub fn get_insertion_bytes(&self, id: u64, columns: &[(&str, &str)]) -> Result<Vec<u8>, String> {
-        let mut o: Vec<u8> = Vec::new();
+    /// Decodes a full row into its id and every column's value.
+    pub fn parse_row(&self, bytes: &[u8], row_number: u64) -> Result<(u64, Vec<(String, String)>), KronkError> {
+        let row_id = self.row_id(bytes, row_number);
+
+        let columns = self.columns.iter()
+            .map(|c| Ok((c.name.to_owned(), c.datatype.parse_bytes(&bytes[c.offset..])?)))
+            .collect::<Result<Vec<_>, KronkError>>()?;
+
+        Ok((row_id, columns))
+    }
+
+    /// Serializes the values of a row to insert, given as literals like
+    /// `1932` or `true`. Serial ids, row versions and columns not in the
+    /// table are ignored.
+    pub fn encode_literals(&self, columns: &[(&str, &str)]) -> Result<EncodedRow, KronkError> {
+        self.encode_row(columns, |datatype, value| datatype.parse_string(value))
+    }
+
+    /// Like `encode_literals`, for natively typed values.
+    pub fn encode_values(&self, columns: &[(&str, Value)]) -> Result<EncodedRow, KronkError> {
+        self.encode_row(columns, |datatype, value| datatype.encode_value(value))
+    }
+
+    fn encode_row<T>(&self, columns: &[(&str, T)], encode: impl Fn(&ColumnDataType, &T) -> Result<Vec<u8>, KronkError>) -> Result<EncodedRow, KronkError> {
+        let mut values = Vec::new();
+        for dtc in self.columns.iter().filter(|c| c.datatype != ColumnDataType::SerialId && c.datatype != ColumnDataType::RowVersion) {
+            if let Some((_, arg)) = columns.iter().find(|cc| ident::canonical(cc.0) == dtc.name) {
+                values.push((dtc.name.to_owned(), encode(&dtc.datatype, arg)?));
+            }
+        }
+        Ok(EncodedRow { values })
+    }
 
-        let dtc_columns = &self.columns;
-        let mm = dtc_columns.into_iter()
-            .map(|c| (c, columns.into_iter().find(|cc| cc.0 == c.name)));
+    pub fn get_insertion_bytes(&self, id: u64, row: &EncodedRow) -> Vec<u8> {
+        let mut o: Vec<u8> = Vec::new();
 
-        for (dtc, arg_c) in mm {
+        for dtc in &self.columns {
             if dtc.datatype == ColumnDataType::SerialId {
                 o.extend(id.to_le_bytes());
+            } else if dtc.datatype == ColumnDataType::RowVersion {
+                o.extend(0u64.to_le_bytes());
             } else {
-                match arg_c {
-                    Some((_, arg)) => {
-                        let parsed = dtc.datatype.parse_string(arg)?;
-                        o.extend(parsed);
+                match row.get(&dtc.name) {
+                    Some(bytes) => o.extend(bytes),
+                    None if dtc.datatype == ColumnDataType::UuidV4 => {
+                        o.extend(Uuid::new_v4().as_bytes())
                     },
                     None => {
-                        o.extend(std::iter::repeat(0u8).take(dtc.datatype.size_in_bytes())) 
+                        o.extend(std::iter::repeat_n(0u8, dtc.datatype.size_in_bytes()))
                     }
                 }
             }
         }
 
-        Ok(o)
+        o
     }
-}
\ No newline at end of file
+}
+
+/// A row to insert, with the values it was given already serialized. The
+/// other columns are generated or zeroed when it's stored, see
+/// `TableDescriptor::get_insertion_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedRow {
+    values: Vec<(String, Vec<u8>)>
+}
+
+impl EncodedRow {
+    /// The serialized value given for the column named `column_name`.
+    pub fn get(&self, column_name: &str) -> Option<&[u8]> {
+        self.values.iter().find(|(n, _)| n == column_name).map(|(_, v)| v.as_slice())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_literals_render_as_written() {
+        let literals = [
+            (ColumnDataType::Boolean, vec!["true", "false"]),
+            (ColumnDataType::Int32, vec!["0", "-2147483648", "2147483647"]),
+            (ColumnDataType::UInt32, vec!["4294967295"]),
+            (ColumnDataType::Int64, vec!["-9223372036854775808", "1950"]),
+            (ColumnDataType::UInt64, vec!["18446744073709551615"]),
+            (ColumnDataType::Byte(16), vec!["", "Frogs, 1,000"]),
+            (ColumnDataType::UuidV4, vec!["67e55044-10b1-426f-9247-bb680e5fe0c8"])
+        ];
+        for (datatype, literals) in literals {
+            for literal in literals {
+                assert_eq!(datatype.parse_bytes(&datatype.parse_string(literal).unwrap()).unwrap(), literal);
+            }
+        }
+        for (datatype, literal) in [(ColumnDataType::Int32, "2147483648"), (ColumnDataType::UInt32, "-1"), (ColumnDataType::Int64, "1,000"), (ColumnDataType::Boolean, "TRUE"), (ColumnDataType::Byte(4), "Frogs")] {
+            assert!(datatype.parse_string(literal).is_err(), "{} {}", datatype, literal);
+        }
+    }
+
+    #[test]
+    fn index_keys_sort_like_their_values() {
+        for (datatype, sorted) in [(ColumnDataType::Int32, ["-300", "-2", "0", "7", "256"]), (ColumnDataType::Int64, ["-9223372036854775808", "-1", "0", "1", "9223372036854775807"]), (ColumnDataType::UInt32, ["0", "1", "255", "256", "65536"])] {
+            let keys = sorted.iter().map(|v| datatype.index_key(&datatype.parse_string(v).unwrap())).collect_vec();
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{}", datatype);
+        }
+        let titles = ColumnDataType::Byte(8);
+        assert!(titles.index_key(&titles.parse_string("Bee").unwrap()) < titles.index_key(&titles.parse_string("Bees").unwrap()));
+    }
+}
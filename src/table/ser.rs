@@ -0,0 +1,47 @@
+//! Writing query results out through serde, with the `serde` feature, e.g.
+//! as json with `serde_json::to_string`.
+
+use serde::ser::{Serialize, Serializer, SerializeMap, SerializeSeq};
+
+use super::value::{ResultSet, Row, Value};
+
+/// Integers, booleans and strings serialize as themselves, uuids as their
+/// hyphenated text and nulls as `None`.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Int32(i) => serializer.serialize_i32(*i),
+            Value::UInt32(u) => serializer.serialize_u32(*u),
+            Value::Int64(i) => serializer.serialize_i64(*i),
+            Value::UInt64(u) => serializer.serialize_u64(*u),
+            Value::Float64(x) => serializer.serialize_f64(*x),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Uuid(u) => serializer.collect_str(u),
+            Value::Str(s) => serializer.serialize_str(s),
+            Value::Null => serializer.serialize_none()
+        }
+    }
+}
+
+/// A map of each column's name to its value, in select-list order. The
+/// row's id isn't included unless it was selected.
+impl Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (name, value) in &self.columns {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// A sequence of the rows, each as a map. The column types are left out.
+impl Serialize for ResultSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in &self.rows {
+            seq.serialize_element(row)?;
+        }
+        seq.end()
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use itertools::Itertools;
+
+use super::{db::Database, instance::Instance, lock::Transaction, query::{parse::RawParse, types::RawDbCommand}, schema::{ident, ColumnDataType, GetTableDescriptor}, store::index::IndexKind, value::ResultSet, error::KronkError};
+
+/// What a command run in a session came back with.
+#[derive(Debug)]
+pub enum CommandOutput {
+    /// A select's rows.
+    Rows(ResultSet),
+    /// The row an insert stored, as each column's name, type and value.
+    Inserted(Vec<(String, ColumnDataType, String)>),
+    /// An explain's plan, a line each.
+    Plan(Vec<String>),
+    /// What a command with nothing else to show did.
+    Message(String)
+}
+
+/// One client's state: the database it's using, the statements it has
+/// prepared and its open transaction. The databases themselves belong to
+/// the `Instance` each call is given, which any number of sessions can
+/// take turns with.
+#[derive(Debug, Default)]
+pub struct Session {
+    current: Option<String>,
+    /// The database and select text of each prepared statement, by name.
+    /// A bound statement borrows its database, so it's bound again on
+    /// each run.
+    prepared: HashMap<String, (String, String)>,
+    /// The database the open transaction is on, and the transaction.
+    transaction: Option<(String, Transaction)>
+}
+
+impl Session {
+    /// A session using the instance's current database, if it has one.
+    pub fn new(instance: &Instance) -> Session {
+        Session { current: instance.current_name().map(|n| n.to_owned()), ..Session::default() }
+    }
+
+    pub fn current_name(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Switches the session to another of the instance's databases. Other
+    /// sessions stay on theirs.
+    pub fn use_database(&mut self, instance: &Instance, db_name: &str) -> Result<(), KronkError> {
+        let db_name = ident::canonical(db_name);
+        if instance.database(&db_name).is_none() {
+            return Err(KronkError::Schema(format!("No database '{}' exists", db_name)));
+        }
+        self.current = Some(db_name);
+        Ok(())
+    }
+
+    fn resolve_name(&self, db_name: Option<&str>) -> Result<String, KronkError> {
+        match db_name {
+            Some(n) => Ok(ident::canonical(n)),
+            None => self.current.clone().ok_or_else(|| KronkError::Schema("No database selected: run 'use <db>' first".to_owned()))
+        }
+    }
+
+    /// The database a (possibly database-qualified) table reference made in
+    /// this session points at.
+    pub fn resolve<'i>(&self, instance: &'i Instance, db_name: Option<&str>) -> Result<&'i Database, KronkError> {
+        let n = self.resolve_name(db_name)?;
+        instance.database(&n).ok_or_else(|| KronkError::Schema(format!("No database '{}' exists", n)))
+    }
+
+    pub fn resolve_mut<'i>(&self, instance: &'i mut Instance, db_name: Option<&str>) -> Result<&'i mut Database, KronkError> {
+        let n = self.resolve_name(db_name)?;
+        instance.database_mut(&n).ok_or_else(|| KronkError::Schema(format!("No database '{}' exists", n)))
+    }
+
+    /// Parses and runs one command.
+    pub fn execute(&mut self, instance: &mut Instance, sql: &str) -> Result<CommandOutput, KronkError> {
+        match RawParse::parse(sql)? {
+            RawDbCommand::Insert(i) => {
+                let mapped_args = i.values.iter()
+                    .map(|(c, v)| (c.as_str(), v.as_str()))
+                    .collect_vec();
+                let db = self.resolve_mut(instance, i.database_name.as_deref())?;
+                let (_, row) = db.insert_returning(&i.table_name, mapped_args.as_slice())?;
+                let table = db.table_with_name(&i.table_name).unwrap();
+                Ok(CommandOutput::Inserted(table.columns.iter().zip(row)
+                    .map(|(column, (name, value))| (name, column.datatype.clone(), value))
+                    .collect()))
+            },
+            RawDbCommand::Select(s) => {
+                let db = self.resolve(instance, s.database_name.as_deref())?;
+                Ok(CommandOutput::Rows(db.query_result_set(&db.bind(&s)?)?))
+            },
+            RawDbCommand::Explain(s) => {
+                let db = self.resolve(instance, s.database_name.as_deref())?;
+                Ok(CommandOutput::Plan(db.explain(&db.bind(&s)?)?))
+            },
+            RawDbCommand::Use(db_name) => {
+                self.use_database(instance, &db_name)?;
+                Ok(CommandOutput::Message(format!("using {}", ident::canonical(&db_name))))
+            },
+            RawDbCommand::CreateIndex(c) => {
+                let db = self.resolve_mut(instance, c.database_name.as_deref())?;
+                let kind = match &c.kind {
+                    Some(k) => str::parse(k).map_err(KronkError::Parse)?,
+                    None => IndexKind::BTree
+                };
+                db.create_index(&c.table_name, &c.column_name, kind)?;
+                Ok(CommandOutput::Message(format!("created index on {}({})", c.table_name, c.column_name)))
+            },
+            RawDbCommand::Analyze(a) => {
+                let db = self.resolve_mut(instance, a.database_name.as_deref())?;
+                db.analyze(&a.table_name)?;
+                Ok(CommandOutput::Message(format!("analyzed {}", a.table_name)))
+            }
+        }
+    }
+
+    /// Prepares a select on the session's current database under `name`,
+    /// replacing any statement prepared under it before. Returns the
+    /// number of values each run takes.
+    pub fn prepare(&mut self, instance: &Instance, name: &str, sql: &str) -> Result<usize, KronkError> {
+        let db_name = self.resolve_name(None)?;
+        let parameter_count = self.resolve(instance, Some(&db_name))?.prepare(sql)?.parameter_count();
+        self.prepared.insert(name.to_owned(), (db_name, sql.to_owned()));
+        Ok(parameter_count)
+    }
+
+    /// Runs the statement prepared under `name` to the end, with `values`
+    /// in place of its placeholders.
+    pub fn execute_prepared(&self, instance: &Instance, name: &str, values: &[&str]) -> Result<ResultSet, KronkError> {
+        let (db_name, sql) = self.prepared.get(name)
+            .ok_or_else(|| KronkError::Schema(format!("No statement prepared as '{}'", name)))?;
+        let mut statement = self.resolve(instance, Some(db_name))?.prepare(sql)?;
+        let rows = statement.execute(values)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(ResultSet { columns: statement.query().result_columns(), rows })
+    }
+
+    /// Forgets the statement prepared under `name`, returning whether there
+    /// was one.
+    pub fn deallocate(&mut self, name: &str) -> bool {
+        self.prepared.remove(name).is_some()
+    }
+
+    /// Opens a transaction on the session's current database, for
+    /// `update_columns_in` and `delete_where_in`. A session has one open at
+    /// a time.
+    pub fn begin(&mut self, instance: &Instance) -> Result<&Transaction, KronkError> {
+        if let Some((db_name, _)) = &self.transaction {
+            return Err(KronkError::Schema(format!("A transaction is already open on '{}'", db_name)));
+        }
+        let db_name = self.resolve_name(None)?;
+        let transaction = self.resolve(instance, Some(&db_name))?.begin_transaction();
+        Ok(&self.transaction.insert((db_name, transaction)).1)
+    }
+
+    /// The open transaction, if there is one, and the database it's on.
+    pub fn transaction(&self) -> Option<(&str, &Transaction)> {
+        self.transaction.as_ref().map(|(db_name, transaction)| (db_name.as_str(), transaction))
+    }
+
+    /// Ends the open transaction, letting go of the rows it locked.
+    pub fn end_transaction(&mut self) -> Result<(), KronkError> {
+        match self.transaction.take() {
+            Some(_) => Ok(()),
+            None => Err(KronkError::Schema("No transaction is open".to_owned()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{schema::TableDescriptor, testing::ScratchDir};
+
+    #[test]
+    fn sessions_keep_their_own_state() {
+        let _dir = ScratchDir::new("test_session_state");
+        let mut archive = Database::new("test_session_state");
+        archive.add_table(TableDescriptor::new("shelves", vec![("id", ColumnDataType::SerialId), ("label", ColumnDataType::Byte(16))]).unwrap()).unwrap();
+        let mut instance = Instance::new();
+        instance.add_database(archive).unwrap();
+        let mut first = Session::new(&instance);
+        let mut second = Session::new(&instance);
+
+        assert!(matches!(first.execute(&mut instance, "insert into shelves label = \"a\"").unwrap(), CommandOutput::Inserted(_)));
+        assert!(second.execute(&mut instance, "use nowhere").is_err());
+        assert_eq!(second.current_name(), Some("test_session_state"));
+        // prepared statements and transactions are each session's own
+        assert_eq!(first.prepare(&instance, "by_label", "select id from shelves where label == ?").unwrap(), 1);
+        assert_eq!(first.execute_prepared(&instance, "by_label", &["a"]).unwrap().rows.len(), 1);
+        assert!(second.execute_prepared(&instance, "by_label", &["a"]).is_err());
+        first.begin(&instance).unwrap();
+        assert!(first.begin(&instance).is_err());
+        second.begin(&instance).unwrap();
+        first.end_transaction().unwrap();
+        assert!(first.end_transaction().is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::{cmp::Ordering, io::{BufReader, BufWriter, Read, Write}};
+
+use super::{exec::QueryError, query::OrderBy, spill::SpillFile, value::Value};
+
+/// Memory a sort can hold rows in before it spills them to disk.
+pub const DEFAULT_SORT_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Most sorted runs merged at once. Past this, runs are merged into longer
+/// runs first, so a merge never holds too many files open.
+const MERGE_FAN_IN: usize = 64;
+
+type SortInput<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<u8>), QueryError>> + 'a>;
+
+/// A row waiting to be sorted, with its sort key decoded.
+struct SortEntry {
+    key: Vec<Value>,
+    row_number: u64,
+    bytes: Vec<u8>
+}
+
+impl SortEntry {
+    fn new(order_by: &[OrderBy], row_number: u64, bytes: Vec<u8>) -> Result<SortEntry, QueryError> {
+        let key = order_by.iter()
+            .map(|o| Value::from_bytes(&o.column.datatype, &bytes[o.column.offset..])
+                .map_err(|e| QueryError::Decode { column_name: o.column.name.to_owned(), source: Box::new(e) }))
+            .collect::<Result<Vec<_>, QueryError>>()?;
+        Ok(SortEntry { key, row_number, bytes })
+    }
+
+    /// Rough number of bytes the entry takes up, for the memory budget.
+    fn size(&self) -> usize {
+        std::mem::size_of::<SortEntry>() + self.bytes.len() + self.key.len() * std::mem::size_of::<Value>()
+    }
+
+    /// Orders by the key, and rows with equal keys in the order a scan would
+    /// read them.
+    fn compare(&self, other: &SortEntry, order_by: &[OrderBy]) -> Ordering {
+        self.key.iter().zip(&other.key).zip(order_by)
+            .map(|((a, b), o)| match o.descending {
+                true => b.cmp(a),
+                false => a.cmp(b)
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| self.row_number.cmp(&other.row_number))
+    }
+}
+
+/// Rows sorted for an `order by`. The input is read in full on the first
+/// call to `next`, sorting it in memory as long as it fits in the memory
+/// budget. Past that, rows are sorted a budget's worth at a time and written
+/// out as runs to temporary files, which are then merged, so a result larger
+/// than memory can still be sorted.
+pub struct SortedRows<'a> {
+    order_by: &'a [OrderBy<'a>],
+    row_size: usize,
+    memory: usize,
+    state: SortState<'a>
+}
+
+enum SortState<'a> {
+    Unsorted(SortInput<'a>),
+    InMemory(std::vec::IntoIter<SortEntry>),
+    Merging(Merge),
+    Done
+}
+
+impl<'a> SortedRows<'a> {
+    /// Sorts `rows`, each of `row_size` bytes, holding at most about `memory`
+    /// bytes of them in memory.
+    pub fn new(rows: SortInput<'a>, order_by: &'a [OrderBy<'a>], row_size: usize, memory: usize) -> SortedRows<'a> {
+        SortedRows { order_by, row_size, memory, state: SortState::Unsorted(rows) }
+    }
+
+    fn sort(&mut self, mut rows: SortInput<'a>) -> Result<SortState<'a>, QueryError> {
+        let mut runs: Vec<SpillRun> = Vec::new();
+        let mut entries: Vec<SortEntry> = Vec::new();
+        let mut held = 0;
+        for row in &mut rows {
+            let (row_number, bytes) = row?;
+            let entry = SortEntry::new(self.order_by, row_number, bytes)?;
+            held += entry.size();
+            entries.push(entry);
+            if held > self.memory {
+                entries.sort_by(|a, b| a.compare(b, self.order_by));
+                runs.push(SpillRun::write(entries.drain(..), self.row_size)?);
+                held = 0;
+            }
+        }
+
+        entries.sort_by(|a, b| a.compare(b, self.order_by));
+        if runs.is_empty() {
+            return Ok(SortState::InMemory(entries.into_iter()));
+        }
+        if !entries.is_empty() {
+            runs.push(SpillRun::write(entries.into_iter(), self.row_size)?);
+        }
+
+        while runs.len() > MERGE_FAN_IN {
+            let merged = Merge::new(runs.drain(..MERGE_FAN_IN).collect(), self.order_by, self.row_size)?;
+            let mut failed = None;
+            let run = SpillRun::write(merged.entries(self.order_by).map_while(|e| e.map_err(|e| failed = Some(e)).ok()), self.row_size)?;
+            if let Some(e) = failed {
+                return Err(e);
+            }
+            runs.push(run);
+        }
+        Ok(SortState::Merging(Merge::new(runs, self.order_by, self.row_size)?))
+    }
+}
+
+impl Iterator for SortedRows<'_> {
+    type Item = Result<(u64, Vec<u8>), QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let SortState::Unsorted(_) = self.state {
+            let SortState::Unsorted(rows) = std::mem::replace(&mut self.state, SortState::Done) else { unreachable!() };
+            match self.sort(rows) {
+                Ok(state) => self.state = state,
+                Err(e) => return Some(Err(e))
+            }
+        }
+        let entry = match &mut self.state {
+            SortState::InMemory(entries) => entries.next().map(Ok),
+            SortState::Merging(merge) => merge.next(self.order_by),
+            SortState::Unsorted(_) | SortState::Done => None
+        };
+        if matches!(entry, Some(Err(_))) {
+            self.state = SortState::Done;
+        }
+        entry.map(|e| e.map(|e| (e.row_number, e.bytes)))
+    }
+}
+
+/// A sorted run of rows in a spill file, each as its row number in little
+/// endian followed by its bytes.
+struct SpillRun {
+    reader: BufReader<SpillFile>
+}
+
+impl SpillRun {
+    fn write(entries: impl Iterator<Item = SortEntry>, row_size: usize) -> Result<SpillRun, QueryError> {
+        let write = || -> std::io::Result<SpillFile> {
+            let mut out = BufWriter::new(SpillFile::create("kronk-sort")?);
+            for entry in entries {
+                debug_assert_eq!(entry.bytes.len(), row_size);
+                out.write_all(&entry.row_number.to_le_bytes())?;
+                out.write_all(&entry.bytes)?;
+            }
+            let mut file = out.into_inner().map_err(|e| e.into_error())?;
+            std::io::Seek::rewind(&mut file)?;
+            Ok(file)
+        };
+        Ok(SpillRun { reader: BufReader::new(write().map_err(QueryError::Spill)?) })
+    }
+
+    fn next(&mut self, order_by: &[OrderBy], row_size: usize) -> Result<Option<SortEntry>, QueryError> {
+        let mut row_number = [0u8; 8];
+        match self.reader.read_exact(&mut row_number) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(QueryError::Spill(e))
+        }
+        let mut bytes = vec![0u8; row_size];
+        self.reader.read_exact(&mut bytes).map_err(QueryError::Spill)?;
+        SortEntry::new(order_by, u64::from_le_bytes(row_number), bytes).map(Some)
+    }
+}
+
+/// Merges sorted runs by repeatedly taking the smallest of their first rows.
+struct Merge {
+    runs: Vec<SpillRun>,
+    heads: Vec<Option<SortEntry>>,
+    row_size: usize
+}
+
+impl Merge {
+    fn new(mut runs: Vec<SpillRun>, order_by: &[OrderBy], row_size: usize) -> Result<Merge, QueryError> {
+        let heads = runs.iter_mut()
+            .map(|run| run.next(order_by, row_size))
+            .collect::<Result<Vec<_>, QueryError>>()?;
+        Ok(Merge { runs, heads, row_size })
+    }
+
+    fn next(&mut self, order_by: &[OrderBy]) -> Option<Result<SortEntry, QueryError>> {
+        let smallest = self.heads.iter().enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|h| (i, h)))
+            .min_by(|(_, a), (_, b)| a.compare(b, order_by))
+            .map(|(i, _)| i)?;
+        let next = match self.runs[smallest].next(order_by, self.row_size) {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e))
+        };
+        std::mem::replace(&mut self.heads[smallest], next).map(Ok)
+    }
+
+    fn entries<'m>(mut self, order_by: &'m [OrderBy<'m>]) -> impl Iterator<Item = Result<SortEntry, QueryError>> + 'm {
+        std::iter::from_fn(move || self.next(order_by))
+    }
+}
@@ -0,0 +1,65 @@
+//! Where sorts and groups put rows that don't fit in their memory budget.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "file")]
+use std::{fs::File, path::PathBuf, sync::atomic::{AtomicU64, Ordering}};
+
+#[cfg(feature = "file")]
+static NEXT_SPILL: AtomicU64 = AtomicU64::new(0);
+
+/// Scratch space written once and then read back from the start. With the
+/// `file` feature it's a file in the temporary directory, removed on drop.
+/// Without it, spilled rows are kept in memory, so a sort or group holds
+/// its whole input however its budget is set.
+pub struct SpillFile {
+    #[cfg(feature = "file")]
+    path: PathBuf,
+    #[cfg(feature = "file")]
+    inner: File,
+    #[cfg(not(feature = "file"))]
+    inner: std::io::Cursor<Vec<u8>>
+}
+
+impl SpillFile {
+    /// A new, empty spill file, named with `prefix` when it's on disk.
+    #[cfg(feature = "file")]
+    pub fn create(prefix: &str) -> std::io::Result<SpillFile> {
+        let path = std::env::temp_dir().join(format!("{}-{}-{}", prefix, std::process::id(), NEXT_SPILL.fetch_add(1, Ordering::Relaxed)));
+        let inner = File::options().read(true).write(true).create_new(true).open(&path)?;
+        Ok(SpillFile { path, inner })
+    }
+
+    #[cfg(not(feature = "file"))]
+    pub fn create(_prefix: &str) -> std::io::Result<SpillFile> {
+        Ok(SpillFile { inner: std::io::Cursor::new(Vec::new()) })
+    }
+}
+
+impl Read for SpillFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for SpillFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for SpillFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(feature = "file")]
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
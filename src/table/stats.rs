@@ -0,0 +1,129 @@
+use std::ops::{Bound, RangeBounds};
+
+use itertools::Itertools;
+
+use super::{schema::{ColumnDataType, TableDescriptor, ident::MAX_IDENTIFIER_LENGTH}, store::{ByteStore, index::KeyRange}};
+
+/// Name of the virtual table that `select`s see a `TableStats` row per
+/// table through. No real table can take it.
+pub const STATS_TABLE_NAME: &str = "kronk_stats";
+
+/// Rows of a table that histograms are built from. Bigger tables are
+/// sampled evenly down to about this many rows.
+pub const HISTOGRAM_SAMPLE_ROWS: u64 = 10_000;
+
+/// Buckets in each column's histogram.
+const HISTOGRAM_BUCKETS: usize = 64;
+
+/// Storage statistics for one table, from `Database::table_stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+    pub table_name: String,
+    /// Rows stored, deleted ones included.
+    pub row_count: u64,
+    pub live_rows: u64,
+    /// Deleted rows whose slots are still taken up.
+    pub dead_rows: u64,
+    /// Bytes of everything but indexes.
+    pub file_bytes: u64,
+    pub index_bytes: u64
+}
+
+impl TableStats {
+    pub fn for_store(table_name: &str, store: &dyn ByteStore) -> std::io::Result<TableStats> {
+        let row_count = store.row_count()?;
+        let dead_rows = store.deleted_count();
+        let size = store.storage_size()?;
+        Ok(TableStats {
+            table_name: table_name.to_owned(),
+            row_count,
+            live_rows: row_count - dead_rows,
+            dead_rows,
+            file_bytes: size.data_bytes,
+            index_bytes: size.index_bytes
+        })
+    }
+
+    /// Layout of the `kronk_stats` table.
+    pub fn descriptor() -> TableDescriptor {
+        TableDescriptor::new(STATS_TABLE_NAME, vec![
+            // byte columns hold strings at least two bytes shorter than they are
+            ("table_name", ColumnDataType::Byte(MAX_IDENTIFIER_LENGTH + 2)),
+            ("row_count", ColumnDataType::UInt64),
+            ("live_rows", ColumnDataType::UInt64),
+            ("dead_rows", ColumnDataType::UInt64),
+            ("file_bytes", ColumnDataType::UInt64),
+            ("index_bytes", ColumnDataType::UInt64)
+        ]).expect("kronk_stats descriptor should be valid")
+    }
+
+    /// Column values for this table's row of `kronk_stats`.
+    pub fn columns(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("table_name", self.table_name.to_owned()),
+            ("row_count", self.row_count.to_string()),
+            ("live_rows", self.live_rows.to_string()),
+            ("dead_rows", self.dead_rows.to_string()),
+            ("file_bytes", self.file_bytes.to_string()),
+            ("index_bytes", self.index_bytes.to_string())
+        ]
+    }
+}
+
+/// How one column's values are spread out, from a sample of the table's
+/// rows, for estimating how many rows a where condition lets through. Values
+/// are kept as index keys (see `ColumnDataType::index_key`), so they compare
+/// the way the values do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnHistogram {
+    pub column_name: String,
+    /// Highest key of each bucket, lowest bucket first. Each bucket holds
+    /// about the same number of sampled rows.
+    pub bounds: Vec<Vec<u8>>,
+    /// Distinct values in the sample.
+    pub distinct: u64
+}
+
+impl ColumnHistogram {
+    pub fn new(column_name: &str, mut keys: Vec<Vec<u8>>) -> ColumnHistogram {
+        keys.sort_unstable();
+        let buckets = HISTOGRAM_BUCKETS.min(keys.len());
+        ColumnHistogram {
+            column_name: column_name.to_owned(),
+            bounds: (1..=buckets).map(|b| keys[b * keys.len() / buckets - 1].clone()).collect(),
+            distinct: keys.iter().dedup().count() as u64
+        }
+    }
+
+    /// A histogram for every column of a table, sampling every so many rows
+    /// so that about `HISTOGRAM_SAMPLE_ROWS` are looked at.
+    pub fn for_store(table: &TableDescriptor, store: &dyn ByteStore) -> std::io::Result<Vec<ColumnHistogram>> {
+        let every = store.row_count()?.div_ceil(HISTOGRAM_SAMPLE_ROWS).max(1);
+        let mut keys: Vec<Vec<Vec<u8>>> = vec![Vec::new(); table.columns.len()];
+        store.scan_rows(&mut |row_number, row| {
+            if row_number % every == 0 {
+                for (column, keys) in table.columns.iter().zip(&mut keys) {
+                    keys.push(column.datatype.index_key(&row[column.offset..]));
+                }
+            }
+            true
+        })?;
+        Ok(table.columns.iter().zip(keys).map(|(c, keys)| ColumnHistogram::new(&c.name, keys)).collect())
+    }
+
+    /// Estimated share of rows, from 0 to 1, whose key falls in `keys`. A
+    /// bucket counts as in the range when its highest key is. A single value
+    /// that doesn't fill a bucket is taken to be as common as any other.
+    pub fn selectivity(&self, keys: &KeyRange) -> f64 {
+        if self.bounds.is_empty() {
+            return 0.0;
+        }
+        let buckets = self.bounds.len() as f64;
+        let inside = self.bounds.iter().filter(|k| keys.contains(*k)).count() as f64;
+        match keys {
+            (Bound::Included(low), Bound::Included(high)) if low == high => (inside / buckets).max(1.0 / self.distinct as f64),
+            // the range likely covers part of a bucket it doesn't count
+            _ => ((inside + 0.5) / buckets).min(1.0)
+        }
+    }
+}
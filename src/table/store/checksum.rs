@@ -0,0 +1,141 @@
+#[cfg(feature = "file")]
+use std::{fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+#[cfg(feature = "file")]
+use super::ByteStore;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE), the same checksum zip and png use.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_continue(0, bytes)
+}
+
+/// Extends `crc`, the CRC-32 of some bytes, to cover `bytes` after them, for
+/// checksumming data that's read a piece at a time.
+pub fn crc32_continue(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |c, b| CRC_TABLE[((c ^ *b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+/// What a row read back from storage turned out to be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowCheck {
+    Intact,
+    /// The row was corrupt and has been restored; use these bytes instead.
+    Repaired(Vec<u8>),
+    /// The row was corrupt and should be left out.
+    Skip
+}
+
+/// A CRC-32 per row of a table, kept in a file beside the table as little
+/// endian u32s in row order and overwritten in place as rows change.
+#[cfg(feature = "file")]
+#[derive(Debug)]
+pub struct RowChecksums {
+    path: PathBuf,
+    pub row_size: usize,
+    sums: Vec<u32>
+}
+
+#[cfg(feature = "file")]
+impl RowChecksums {
+    /// Loads the checksums at `path`, checksumming any rows of `store` the
+    /// file doesn't cover yet, e.g. a table that predates checksums or a
+    /// crash between writing a row and its checksum.
+    pub fn open(path: &Path, store: &dyn ByteStore) -> std::io::Result<RowChecksums> {
+        let mut sums = Vec::new();
+        match File::open(path) {
+            Ok(mut f) => {
+                let mut raw = Vec::new();
+                f.read_to_end(&mut raw)?;
+                sums.extend(raw.chunks_exact(4).map(|s| u32::from_le_bytes(s.try_into().unwrap())));
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e)
+        }
+
+        let row_count = store.row_count()?;
+        let covered = sums.len() as u64;
+        if covered != row_count {
+            // deleted rows are never read, so their checksums don't matter
+            sums.resize(row_count as usize, 0);
+            store.scan(covered.min(row_count)..row_count, &mut |row_number, row| {
+                sums[row_number as usize] = crc32(row);
+                true
+            })?;
+
+            let mut f = File::create(path)?;
+            f.write_all(&sums.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>())?;
+            f.sync_all()?;
+        }
+        Ok(RowChecksums { path: path.to_owned(), row_size: store.row_size(), sums })
+    }
+
+    /// Checksums every row of `store` from scratch, e.g. after its rows were
+    /// rewritten in a new layout.
+    pub fn rebuild(path: &Path, store: &dyn ByteStore) -> std::io::Result<RowChecksums> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Self::open(path, store)
+    }
+
+    /// Records the checksums of rows that were just written, given back to
+    /// back in `rows` starting at `first_row`.
+    pub fn set(&mut self, first_row: u64, rows: &[u8]) -> std::io::Result<()> {
+        let sums = rows.chunks_exact(self.row_size).map(crc32).collect::<Vec<_>>();
+        let mut f = OpenOptions::new().write(true).create(true).truncate(false).open(&self.path)?;
+        f.seek(SeekFrom::Start(first_row * 4))?;
+        f.write_all(&sums.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>())?;
+
+        let start = first_row as usize;
+        if start + sums.len() > self.sums.len() {
+            self.sums.resize(start + sums.len(), 0);
+        }
+        self.sums[start..(start + sums.len())].copy_from_slice(&sums);
+        Ok(())
+    }
+
+    /// Whether `row` is what was written at `row_number`. Rows without a
+    /// recorded checksum pass.
+    pub fn matches(&self, row_number: u64, row: &[u8]) -> bool {
+        self.expected(row_number).is_none_or(|sum| sum == crc32(row))
+    }
+
+    pub fn expected(&self, row_number: u64) -> Option<u32> {
+        self.sums.get(row_number as usize).copied()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crcs_match_the_ieee_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32_continue(crc32(b"1234"), b"56789"), crc32(b"123456789"));
+        assert_ne!(crc32(b"Frogs"), crc32(b"Frogz"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{collections::HashMap, fs::{File, OpenOptions}, io::{BufReader, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+
+use super::super::schema::config::Compression;
+use super::super::error::KronkError;
+
+// The compressed page file is a sequence of frames, each:
+//   page number (u64), codec tag (u8), uncompressed length (u32),
+//   compressed length (u32), compressed bytes
+// A page that is written again gets a new frame; the last frame for a page
+// wins. Frames for pages past the table header's cold page count are left
+// over from an interrupted move and are ignored.
+
+const FRAME_HEAD_SIZE: usize = 17;
+
+fn codec_tag(codec: Compression) -> u8 {
+    match codec {
+        Compression::None => 0,
+        Compression::Lz4 => 1,
+        Compression::Zstd => 2
+    }
+}
+
+fn codec_for_tag(tag: u8) -> Option<Compression> {
+    match tag {
+        0 => Some(Compression::None),
+        1 => Some(Compression::Lz4),
+        2 => Some(Compression::Zstd),
+        _ => None
+    }
+}
+
+fn unsupported(codec: Compression) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, format!("kronk was built without {:?} compression; enable its cargo feature", codec))
+}
+
+/// Fails for codecs whose cargo feature isn't enabled in this build.
+pub fn check_supported(codec: Compression) -> Result<(), KronkError> {
+    match codec {
+        Compression::None => Ok(()),
+        Compression::Lz4 if cfg!(feature = "lz4") => Ok(()),
+        Compression::Zstd if cfg!(feature = "zstd") => Ok(()),
+        _ => Err(KronkError::Storage(unsupported(codec).to_string()))
+    }
+}
+
+pub fn compress(codec: Compression, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match codec {
+        Compression::None => Ok(data.to_vec()),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::compress(data, 0),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(codec))
+    }
+}
+
+pub fn decompress(codec: Compression, data: &[u8], len: usize) -> std::io::Result<Vec<u8>> {
+    let page = match codec {
+        Compression::None => data.to_vec(),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => lz4_flex::block::decompress(data, len)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::decompress(data, len)?,
+        #[allow(unreachable_patterns)]
+        _ => return Err(unsupported(codec))
+    };
+    if page.len() != len {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "compressed page decompressed to the wrong length"));
+    }
+    Ok(page)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    offset: u64,
+    codec: Compression,
+    len: u32,
+    compressed_len: u32
+}
+
+/// The leading pages of a table's row data, moved out of the table file and
+/// stored compressed once no more rows will be appended to them. Pages are
+/// a fixed size, so a position in the row data still maps straight to its
+/// page.
+#[derive(Debug)]
+pub struct ColdPages {
+    path: PathBuf,
+    file: File,
+    pub page_size: u64,
+    /// Pages `0..count` are cold.
+    pub count: u64,
+    frames: HashMap<u64, Frame>
+}
+
+impl ColdPages {
+    /// Opens the page file at `path`, creating it if needed. Fails unless it
+    /// holds each of the first `count` pages.
+    pub fn open(path: &Path, count: u64, page_size: u64) -> std::io::Result<ColdPages> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut frames = HashMap::new();
+        let mut intact_len = 0u64;
+
+        let mut reader = BufReader::new(File::open(path)?);
+        loop {
+            let mut head = [0u8; FRAME_HEAD_SIZE];
+            if reader.read_exact(&mut head).is_err() { break; }
+            let page_number = u64::from_le_bytes(head[0..8].try_into().unwrap());
+            let codec = match codec_for_tag(head[8]) {
+                Some(codec) => codec,
+                None => break
+            };
+            let len = u32::from_le_bytes(head[9..13].try_into().unwrap());
+            let compressed_len = u32::from_le_bytes(head[13..17].try_into().unwrap());
+            if reader.seek_relative(compressed_len as i64).is_err() { break; }
+
+            let frame_end = intact_len + (FRAME_HEAD_SIZE as u64) + compressed_len as u64;
+            if frame_end > file.metadata()?.len() { break; }
+            frames.insert(page_number, Frame { offset: intact_len + FRAME_HEAD_SIZE as u64, codec, len, compressed_len });
+            intact_len = frame_end;
+        }
+        // don't leave a torn frame where the next one gets appended
+        if file.metadata()?.len() > intact_len {
+            file.set_len(intact_len)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+
+        if let Some(missing) = (0..count).find(|n| !frames.contains_key(n)) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: compressed page {} is missing", path.display(), missing)));
+        }
+        Ok(ColdPages { path: path.to_owned(), file, page_size, count, frames })
+    }
+
+    /// Bytes of row data held here.
+    pub fn len(&self) -> u64 {
+        self.count * self.page_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn read_page(&self, page_number: u64) -> std::io::Result<Vec<u8>> {
+        let frame = self.frames.get(&page_number)
+            .filter(|_| page_number < self.count)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("page {} isn't compressed", page_number)))?;
+        let mut data = vec![0u8; frame.compressed_len as usize];
+        let mut f = File::open(&self.path)?;
+        f.seek(SeekFrom::Start(frame.offset))?;
+        f.read_exact(&mut data)?;
+        decompress(frame.codec, &data, frame.len as usize)
+    }
+
+    /// Stores pages, replacing any earlier copies, and syncs them. Pages
+    /// past `count` only become readable once `count` is raised.
+    pub fn write_pages(&mut self, pages: &[(u64, &[u8])], codec: Compression) -> std::io::Result<()> {
+        let mut offset = self.file.metadata()?.len();
+        let mut written = Vec::with_capacity(pages.len());
+        let mut out: Vec<u8> = Vec::new();
+        for (page_number, data) in pages {
+            let compressed = compress(codec, data)?;
+            out.extend(page_number.to_le_bytes());
+            out.push(codec_tag(codec));
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend((compressed.len() as u32).to_le_bytes());
+            offset += FRAME_HEAD_SIZE as u64;
+            written.push((*page_number, Frame { offset, codec, len: data.len() as u32, compressed_len: compressed.len() as u32 }));
+            offset += compressed.len() as u64;
+            out.extend(compressed);
+        }
+        self.file.write_all(&out)?;
+        self.file.sync_data()?;
+        self.frames.extend(written);
+        Ok(())
+    }
+
+    /// The codec page `page_number` was last written with.
+    pub fn codec_of(&self, page_number: u64) -> Option<Compression> {
+        self.frames.get(&page_number).map(|f| f.codec)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{store::database_dir, testing::ScratchDir};
+
+    /// The codecs this build supports.
+    fn codecs() -> Vec<Compression> {
+        [Compression::None, Compression::Lz4, Compression::Zstd].into_iter()
+            .filter(|codec| check_supported(*codec).is_ok())
+            .collect()
+    }
+
+    fn page_file(db_name: &str) -> PathBuf {
+        let dir = database_dir(db_name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("cold")
+    }
+
+    #[test]
+    fn pages_read_back_as_last_written() {
+        let _dir = ScratchDir::new("test_compress_frames");
+        let path = page_file("test_compress_frames");
+        let pages = (0..3u8).map(|n| vec![n; 64]).collect::<Vec<_>>();
+        let mut cold = ColdPages::open(&path, 0, 64).unwrap();
+        for codec in codecs() {
+            cold.write_pages(&[(0, &pages[0]), (1, &pages[1])], codec).unwrap();
+        }
+        cold.write_pages(&[(1, &pages[2])], Compression::None).unwrap();
+        assert!(cold.read_page(0).is_err());
+        cold.count = 2;
+        assert_eq!((cold.read_page(0).unwrap(), cold.read_page(1).unwrap()), (pages[0].clone(), pages[2].clone()));
+
+        let cold = ColdPages::open(&path, 2, 64).unwrap();
+        assert_eq!(cold.codec_of(0), codecs().last().copied());
+        assert_eq!((cold.read_page(0).unwrap(), cold.read_page(1).unwrap()), (pages[0].clone(), pages[2].clone()));
+        assert_eq!(ColdPages::open(&path, 3, 64).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn torn_frames_are_dropped_on_open() {
+        let _dir = ScratchDir::new("test_compress_torn");
+        let path = page_file("test_compress_torn");
+        let mut cold = ColdPages::open(&path, 0, 64).unwrap();
+        cold.write_pages(&[(0, &[7u8; 64])], Compression::None).unwrap();
+        let intact_len = std::fs::metadata(&path).unwrap().len();
+        // a frame cut off partway through its bytes
+        cold.file.write_all(&[&1u64.to_le_bytes()[..], &[0], &64u32.to_le_bytes(), &64u32.to_le_bytes(), &[7u8; 10]].concat()).unwrap();
+        drop(cold);
+
+        let mut cold = ColdPages::open(&path, 1, 64).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact_len);
+        cold.write_pages(&[(1, &[8u8; 64])], Compression::None).unwrap();
+        cold.count = 2;
+        assert_eq!(cold.read_page(1).unwrap(), vec![8u8; 64]);
+    }
+
+    #[test]
+    fn pages_must_decompress_to_their_length() {
+        for codec in codecs() {
+            let compressed = compress(codec, &[1u8; 100]).unwrap();
+            assert_eq!(decompress(codec, &compressed, 100).unwrap(), vec![1u8; 100]);
+            assert!(decompress(codec, &compressed, 99).is_err());
+        }
+    }
+}
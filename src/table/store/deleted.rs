@@ -0,0 +1,85 @@
+use std::{collections::BTreeSet, fs::{File, OpenOptions}, io::{Read, Write}, path::{Path, PathBuf}};
+
+/// Set on an entry for a row that was restored.
+const RESTORED: u64 = 1 << 63;
+
+/// The rows deleted from a table. A deleted row's bytes stay where they are
+/// and its slot isn't reused; its row number is appended to a file of
+/// little endian u64s beside the table, and scans and indexes pass over it
+/// from then on. Restoring the row appends its number again with the top
+/// bit set.
+#[derive(Debug)]
+pub struct DeletedRows {
+    path: PathBuf,
+    rows: BTreeSet<u64>
+}
+
+impl DeletedRows {
+    /// Loads the deleted rows recorded at `path`, if there are any.
+    pub fn open(path: &Path) -> std::io::Result<DeletedRows> {
+        let mut rows = BTreeSet::new();
+        match File::open(path) {
+            Ok(mut f) => {
+                let mut raw = Vec::new();
+                f.read_to_end(&mut raw)?;
+                for row_number in raw.chunks_exact(8).map(|r| u64::from_le_bytes(r.try_into().unwrap())) {
+                    match row_number & RESTORED != 0 {
+                        true => rows.remove(&(row_number & !RESTORED)),
+                        false => rows.insert(row_number)
+                    };
+                }
+                // don't leave a torn entry where the next one gets appended
+                if raw.len() % 8 != 0 {
+                    OpenOptions::new().write(true).open(path)?.set_len((raw.len() - raw.len() % 8) as u64)?;
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e)
+        }
+        Ok(DeletedRows { path: path.to_owned(), rows })
+    }
+
+    pub fn insert(&mut self, row_number: u64) -> std::io::Result<()> {
+        self.append(row_number)?;
+        self.rows.insert(row_number);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, row_number: u64) -> std::io::Result<()> {
+        self.append(row_number | RESTORED)?;
+        self.rows.remove(&row_number);
+        Ok(())
+    }
+
+    fn append(&self, entry: u64) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut f = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        f.write_all(&entry.to_le_bytes())
+    }
+
+    pub fn contains(&self, row_number: u64) -> bool {
+        self.rows.contains(&row_number)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.rows.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sync(&self) -> std::io::Result<()> {
+        match File::open(&self.path) {
+            Ok(f) => f.sync_all(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e)
+        }
+    }
+}
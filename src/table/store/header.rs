@@ -0,0 +1,180 @@
+use std::io::{Read, Write};
+
+use super::super::{schema::{ColumnDataType, TableDescriptor}, bytes::ToNativeType};
+
+// Table files start with a fixed 64-byte prelude followed by the table's
+// column layout. Rows begin right after the layout.
+//
+// prelude:
+//   0..8    id counter (u64)
+//   8..12   magic, b"KRNK"
+//   12..14  format version (u16)
+//   16..20  length of the layout block that follows (u32)
+//   20..28  number of leading pages moved to the compressed page file (u64)
+//   28..32  size of those pages (u32)
+//   32..40  number of sealed segment files holding the rows after those (u64)
+//   40..48  bytes of row data in each sealed segment (u64)
+//
+// layout block:
+//   column count (u16), then per column: name length (u16), name bytes,
+//   type tag (u8), type width (u32)
+//
+// All integers are little endian.
+
+pub const PRELUDE_SIZE: usize = 64;
+const MAGIC: &[u8; 4] = b"KRNK";
+const FORMAT_VERSION: u16 = 1;
+
+fn type_tag(datatype: &ColumnDataType) -> (u8, u32) {
+    match datatype {
+        ColumnDataType::SerialId => (1, 0),
+        ColumnDataType::Byte(w) => (2, *w as u32),
+        ColumnDataType::Boolean => (3, 0),
+        ColumnDataType::Int32 => (4, 0),
+        ColumnDataType::UInt32 => (5, 0),
+        ColumnDataType::Int64 => (6, 0),
+        ColumnDataType::UInt64 => (7, 0),
+        ColumnDataType::UuidV4 => (8, 0),
+        ColumnDataType::RowVersion => (9, 0)
+    }
+}
+
+fn datatype_for_tag(tag: u8, width: u32) -> Option<ColumnDataType> {
+    match tag {
+        1 => Some(ColumnDataType::SerialId),
+        2 => Some(ColumnDataType::Byte(width as usize)),
+        3 => Some(ColumnDataType::Boolean),
+        4 => Some(ColumnDataType::Int32),
+        5 => Some(ColumnDataType::UInt32),
+        6 => Some(ColumnDataType::Int64),
+        7 => Some(ColumnDataType::UInt64),
+        8 => Some(ColumnDataType::UuidV4),
+        9 => Some(ColumnDataType::RowVersion),
+        _ => None
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableFileHeader {
+    pub id_counter: u64,
+    pub columns: Vec<(String, ColumnDataType)>,
+    /// Pages of row data kept compressed in a separate file. The rows in
+    /// this file start right after them.
+    pub cold_pages: u64,
+    pub cold_page_size: u32,
+    /// Sealed segments of row data, each in its own file, that come after
+    /// any compressed pages and before the rows in this file.
+    pub segments: u64,
+    pub segment_len: u64
+}
+
+impl TableFileHeader {
+    pub fn for_descriptor(descriptor: &TableDescriptor, id_counter: u64) -> TableFileHeader {
+        TableFileHeader {
+            id_counter,
+            columns: descriptor.columns.iter().map(|c| (c.name.to_owned(), c.datatype.clone())).collect(),
+            cold_pages: 0,
+            cold_page_size: 0,
+            segments: 0,
+            segment_len: 0
+        }
+    }
+
+    /// Where rows start in a file with this header.
+    pub fn data_offset(&self) -> u64 {
+        (PRELUDE_SIZE + self.layout_bytes().len()) as u64
+    }
+
+    fn layout_bytes(&self) -> Vec<u8> {
+        let mut o: Vec<u8> = Vec::new();
+        o.extend((self.columns.len() as u16).to_le_bytes());
+        for (name, datatype) in &self.columns {
+            let (tag, width) = type_tag(datatype);
+            o.extend((name.len() as u16).to_le_bytes());
+            o.extend(name.as_bytes());
+            o.push(tag);
+            o.extend(width.to_le_bytes());
+        }
+        o
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let layout = self.layout_bytes();
+        let mut prelude = [0u8; PRELUDE_SIZE];
+        prelude[0..8].copy_from_slice(&self.id_counter.to_le_bytes());
+        prelude[8..12].copy_from_slice(MAGIC);
+        prelude[12..14].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        prelude[16..20].copy_from_slice(&(layout.len() as u32).to_le_bytes());
+        prelude[20..28].copy_from_slice(&self.cold_pages.to_le_bytes());
+        prelude[28..32].copy_from_slice(&self.cold_page_size.to_le_bytes());
+        prelude[32..40].copy_from_slice(&self.segments.to_le_bytes());
+        prelude[40..48].copy_from_slice(&self.segment_len.to_le_bytes());
+        w.write_all(&prelude)?;
+        w.write_all(&layout)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> std::io::Result<TableFileHeader> {
+        let mut prelude = [0u8; PRELUDE_SIZE];
+        r.read_exact(&mut prelude)?;
+
+        if &prelude[8..12] != MAGIC {
+            return Err(invalid("table file has no embedded schema; it was written by an older version of kronk".to_owned()));
+        }
+        let version = u16::from_le_bytes([prelude[12], prelude[13]]);
+        if version != FORMAT_VERSION {
+            return Err(invalid(format!("unsupported table file format version {}", version)));
+        }
+
+        let id_counter: u64 = prelude[0..8].to_native_type().unwrap();
+        let layout_len: u32 = prelude[16..20].to_native_type().unwrap();
+        let cold_pages: u64 = prelude[20..28].to_native_type().unwrap();
+        let cold_page_size: u32 = prelude[28..32].to_native_type().unwrap();
+        let segments: u64 = prelude[32..40].to_native_type().unwrap();
+        let segment_len: u64 = prelude[40..48].to_native_type().unwrap();
+        let mut layout = vec![0u8; layout_len as usize];
+        r.read_exact(&mut layout)?;
+
+        let truncated = || invalid("table file schema is truncated".to_owned());
+        let mut pos = 0usize;
+        let mut take = |n: usize| -> std::io::Result<&[u8]> {
+            let s = layout.get(pos..(pos + n)).ok_or_else(truncated)?;
+            pos += n;
+            Ok(s)
+        };
+
+        let count = u16::from_le_bytes(take(2)?.try_into().unwrap());
+        let mut columns = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take(2)?.try_into().unwrap());
+            let name = String::from_utf8(take(name_len as usize)?.to_vec())
+                .map_err(|_| invalid("table file schema has a column name that isn't utf-8".to_owned()))?;
+            let tag = take(1)?[0];
+            let width: u32 = take(4)?.to_native_type().unwrap();
+            let datatype = datatype_for_tag(tag, width)
+                .ok_or_else(|| invalid(format!("table file schema has unknown type tag {}", tag)))?;
+            columns.push((name, datatype));
+        }
+
+        Ok(TableFileHeader { id_counter, columns, cold_pages, cold_page_size, segments, segment_len })
+    }
+
+    /// Fails unless the embedded layout matches the descriptor column for column.
+    pub fn validate(&self, descriptor: &TableDescriptor) -> std::io::Result<()> {
+        let expected = TableFileHeader::for_descriptor(descriptor, self.id_counter);
+        if expected.columns != self.columns {
+            let describe = |cols: &Vec<(String, ColumnDataType)>| cols.iter()
+                .map(|(n, d)| format!("{} {:?}", n, d))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(invalid(format!(
+                "table file for '{}' has columns ({}) but the descriptor has ({})",
+                descriptor.table_name, describe(&self.columns), describe(&expected.columns)
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+use std::ops::Bound;
+#[cfg(feature = "file")]
+use std::{collections::{BTreeMap, HashMap}, fs::{File, OpenOptions}, io::{BufReader, BufWriter, Read, Write}, ops::RangeInclusive, path::{Path, PathBuf}};
+
+#[cfg(feature = "file")]
+use super::{ByteStore, super::schema::{ColumnDataType, TableColumn}};
+
+/// A range of index keys, as made by `ColumnDataType::index_key`.
+pub type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Maps a table's serial ids to the rows holding them, so lookups and range
+/// scans on the id don't have to read the whole table.
+///
+/// The ids live in a B-tree in memory. On disk the index is a file of
+/// (id, row number) pairs, both little endian u64s, appended to as rows are
+/// inserted and read back into the tree when the table is opened. A pair
+/// whose row number has the top bit set removes the id, for deleted rows.
+#[cfg(feature = "file")]
+#[derive(Debug)]
+pub struct IdIndex {
+    path: PathBuf,
+    /// Where the serial id sits in a row.
+    pub id_offset: usize,
+    rows: BTreeMap<u64, u64>
+}
+
+#[cfg(feature = "file")]
+const ENTRY_SIZE: usize = 16;
+
+#[cfg(feature = "file")]
+impl IdIndex {
+    /// Loads the index at `path`. Returns `None` when there is no usable
+    /// index there, e.g. it was never built, was cut short by a crash, or
+    /// doesn't hold an id for each of the table's `live_rows`.
+    pub fn load(path: &Path, id_offset: usize, live_rows: u64) -> std::io::Result<Option<IdIndex>> {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        if f.metadata()?.len() % ENTRY_SIZE as u64 != 0 {
+            return Ok(None);
+        }
+
+        let mut raw = Vec::new();
+        BufReader::new(f).read_to_end(&mut raw)?;
+        let mut rows = BTreeMap::new();
+        for entry in raw.chunks_exact(ENTRY_SIZE) {
+            let id = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            let row_number = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+            if row_number & REMOVED != 0 {
+                rows.remove(&id);
+            } else {
+                rows.insert(id, row_number);
+            }
+        }
+        if rows.len() as u64 != live_rows {
+            return Ok(None);
+        }
+        Ok(Some(IdIndex { path: path.to_owned(), id_offset, rows }))
+    }
+
+    /// Writes out a fresh index from every (id, row number) in the table.
+    pub fn build(path: &Path, id_offset: usize, entries: impl Iterator<Item = (u64, u64)>) -> std::io::Result<IdIndex> {
+        let rows: BTreeMap<u64, u64> = entries.collect();
+        let mut f = BufWriter::new(File::create(path)?);
+        for (id, row_number) in &rows {
+            f.write_all(&id.to_le_bytes())?;
+            f.write_all(&row_number.to_le_bytes())?;
+        }
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(IdIndex { path: path.to_owned(), id_offset, rows })
+    }
+
+    /// Adds (id, row number) entries for newly inserted rows.
+    pub fn insert(&mut self, entries: &[(u64, u64)]) -> std::io::Result<()> {
+        let mut f = OpenOptions::new().append(true).open(&self.path)?;
+        let mut out: Vec<u8> = Vec::with_capacity(entries.len() * ENTRY_SIZE);
+        for (id, row_number) in entries {
+            out.extend(id.to_le_bytes());
+            out.extend(row_number.to_le_bytes());
+        }
+        f.write_all(&out)?;
+        self.rows.extend(entries.iter().copied());
+        Ok(())
+    }
+
+    /// Drops the id of a deleted row.
+    pub fn remove(&mut self, id: u64, row_number: u64) -> std::io::Result<()> {
+        let mut f = OpenOptions::new().append(true).open(&self.path)?;
+        let mut entry: Vec<u8> = Vec::with_capacity(ENTRY_SIZE);
+        entry.extend(id.to_le_bytes());
+        entry.extend((row_number | REMOVED).to_le_bytes());
+        f.write_all(&entry)?;
+        self.rows.remove(&id);
+        Ok(())
+    }
+
+    /// Row numbers of the rows whose ids fall in `ids`, in id order.
+    pub fn rows_in_range(&self, ids: RangeInclusive<u64>) -> Vec<u64> {
+        if ids.is_empty() { return Vec::new(); }
+        self.rows.range(ids).map(|(_, row_number)| *row_number).collect()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// Ordered, serves both equality and range comparisons.
+    BTree,
+    /// Unordered and cheaper to keep up to date, but only serves `==`.
+    Hash
+}
+
+impl IndexKind {
+    pub const ALL: [IndexKind; 2] = [IndexKind::BTree, IndexKind::Hash];
+
+    #[cfg(feature = "file")]
+    fn extension(&self) -> &'static str {
+        match self {
+            IndexKind::BTree => "idx",
+            IndexKind::Hash => "hidx"
+        }
+    }
+}
+
+impl std::str::FromStr for IndexKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "btree" => Ok(Self::BTree),
+            "hash" => Ok(Self::Hash),
+            _ => Err(format!("Unknown index kind '{}': expected btree or hash", s))
+        }
+    }
+}
+
+#[cfg(feature = "file")]
+#[derive(Debug)]
+enum IndexEntries {
+    BTree(BTreeMap<Vec<u8>, Vec<u64>>),
+    Hash(HashMap<Vec<u8>, Vec<u64>>)
+}
+
+#[cfg(feature = "file")]
+impl IndexEntries {
+    fn insert(&mut self, key: Vec<u8>, row_number: u64) {
+        match self {
+            IndexEntries::BTree(m) => m.entry(key).or_default().push(row_number),
+            IndexEntries::Hash(m) => m.entry(key).or_default().push(row_number)
+        }
+    }
+
+    fn remove(&mut self, key: &[u8], row_number: u64) {
+        let rows = match self {
+            IndexEntries::BTree(m) => m.get_mut(key),
+            IndexEntries::Hash(m) => m.get_mut(key)
+        };
+        if let Some(rows) = rows {
+            rows.retain(|r| *r != row_number);
+        }
+    }
+}
+
+/// Set on an entry's row number to mark it as removing that row's key.
+#[cfg(feature = "file")]
+const REMOVED: u64 = 1 << 63;
+
+/// Index on any one column, created with `create index`. Maps the column's
+/// index keys to the rows holding them, kept in memory in a B-tree or a
+/// hash table depending on its kind.
+///
+/// On disk it is a file of entries appended as rows are written, each the
+/// row number (u64), key length (u16) and key. An entry whose row number has
+/// the top bit set removes the key from that row instead, for rows that were
+/// overwritten with a different value. The file is named after the
+/// table and column, with an extension for the kind, which is how the index
+/// is found again on open.
+#[cfg(feature = "file")]
+#[derive(Debug)]
+pub struct ColumnIndex {
+    path: PathBuf,
+    pub kind: IndexKind,
+    pub column_name: String,
+    pub offset: usize,
+    pub datatype: ColumnDataType,
+    rows: IndexEntries
+}
+
+#[cfg(feature = "file")]
+impl ColumnIndex {
+    pub fn file_name(table_name: &str, column_name: &str, kind: IndexKind) -> String {
+        format!("{}.{}.{}", table_name, column_name, kind.extension())
+    }
+
+    fn empty(path: &Path, column: &TableColumn, kind: IndexKind) -> ColumnIndex {
+        ColumnIndex {
+            path: path.to_owned(),
+            kind,
+            column_name: column.name.to_owned(),
+            offset: column.offset,
+            datatype: column.datatype.clone(),
+            rows: match kind {
+                IndexKind::BTree => IndexEntries::BTree(BTreeMap::new()),
+                IndexKind::Hash => IndexEntries::Hash(HashMap::new())
+            }
+        }
+    }
+
+    fn encode_entry(key: &[u8], row_number: u64) -> Vec<u8> {
+        let mut o: Vec<u8> = Vec::with_capacity(10 + key.len());
+        o.extend(row_number.to_le_bytes());
+        o.extend((key.len() as u16).to_le_bytes());
+        o.extend(key);
+        o
+    }
+
+    /// Loads the index at `path`. Returns `None` when it doesn't hold an
+    /// entry for each of the table's `live_rows`, e.g. after a crash
+    /// between writing a row and its index entry.
+    pub fn load(path: &Path, column: &TableColumn, kind: IndexKind, live_rows: u64) -> std::io::Result<Option<ColumnIndex>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut index = Self::empty(path, column, kind);
+
+        let mut entries = 0i64;
+        let mut head = [0u8; 10];
+        loop {
+            match reader.read_exact(&mut head) {
+                Ok(()) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e)
+            }
+            let row_number = u64::from_le_bytes(head[0..8].try_into().unwrap());
+            let mut key = vec![0u8; u16::from_le_bytes(head[8..10].try_into().unwrap()) as usize];
+            if reader.read_exact(&mut key).is_err() { return Ok(None); }
+            if row_number & REMOVED != 0 {
+                index.rows.remove(&key, row_number & !REMOVED);
+                entries -= 1;
+            } else {
+                index.rows.insert(key, row_number);
+                entries += 1;
+            }
+        }
+
+        Ok(if entries == live_rows as i64 { Some(index) } else { None })
+    }
+
+    /// Writes out a fresh index from every row in `store`.
+    pub fn build(path: &Path, column: &TableColumn, kind: IndexKind, store: &dyn ByteStore) -> std::io::Result<ColumnIndex> {
+        let mut index = Self::empty(path, column, kind);
+        let mut f = BufWriter::new(File::create(path)?);
+        let mut written = Ok(());
+        store.scan_rows(&mut |row_number, row| {
+            let key = index.key_for(row);
+            written = f.write_all(&Self::encode_entry(&key, row_number));
+            index.rows.insert(key, row_number);
+            written.is_ok()
+        })?;
+        written?;
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(index)
+    }
+
+    fn key_for(&self, row: &[u8]) -> Vec<u8> {
+        self.datatype.index_key(&row[self.offset..])
+    }
+
+    /// Adds entries for newly inserted rows, given back to back in `rows`
+    /// starting at `first_row`.
+    pub fn insert(&mut self, rows: &[u8], row_size: usize, first_row: u64) -> std::io::Result<()> {
+        let keys = rows.chunks_exact(row_size).map(|row| self.key_for(row)).collect::<Vec<_>>();
+        let mut f = OpenOptions::new().append(true).open(&self.path)?;
+        let out = keys.iter().enumerate()
+            .flat_map(|(i, key)| Self::encode_entry(key, first_row + i as u64))
+            .collect::<Vec<_>>();
+        f.write_all(&out)?;
+        for (i, key) in keys.into_iter().enumerate() {
+            self.rows.insert(key, first_row + i as u64);
+        }
+        Ok(())
+    }
+
+    /// Moves a row to its new key after it was overwritten.
+    pub fn update(&mut self, old_row: &[u8], new_row: &[u8], row_number: u64) -> std::io::Result<()> {
+        let old_key = self.key_for(old_row);
+        let new_key = self.key_for(new_row);
+        if old_key == new_key { return Ok(()); }
+
+        let mut f = OpenOptions::new().append(true).open(&self.path)?;
+        let mut entries = Self::encode_entry(&old_key, row_number | REMOVED);
+        entries.extend(Self::encode_entry(&new_key, row_number));
+        f.write_all(&entries)?;
+        self.rows.remove(&old_key, row_number);
+        self.rows.insert(new_key, row_number);
+        Ok(())
+    }
+
+    /// Drops the entry for a deleted row.
+    pub fn remove(&mut self, row: &[u8], row_number: u64) -> std::io::Result<()> {
+        let key = self.key_for(row);
+        let mut f = OpenOptions::new().append(true).open(&self.path)?;
+        f.write_all(&Self::encode_entry(&key, row_number | REMOVED))?;
+        self.rows.remove(&key, row_number);
+        Ok(())
+    }
+
+    /// Row numbers of the rows whose keys fall in `keys`. `None` if this
+    /// index can't answer it: hash indexes only look up single keys.
+    pub fn rows_in_range(&self, keys: KeyRange) -> Option<Vec<u64>> {
+        match &self.rows {
+            IndexEntries::BTree(m) => {
+                // BTreeMap::range panics on a range that ends before it starts
+                let inverted = match (&keys.0, &keys.1) {
+                    (Bound::Included(s), Bound::Included(e)) => s > e,
+                    (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => s >= e,
+                    _ => false
+                };
+                if inverted { return Some(Vec::new()); }
+                Some(m.range(keys).flat_map(|(_, rows)| rows.iter().copied()).collect())
+            },
+            IndexEntries::Hash(m) => match &keys {
+                (Bound::Included(s), Bound::Included(e)) if s == e => Some(m.get(s).cloned().unwrap_or_default()),
+                _ => None
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(all(test, feature = "file"))]
+mod tests {
+    use super::*;
+    use crate::table::{schema::TableDescriptor, store::{InMemoryByteStore, database_dir}, testing::ScratchDir};
+
+    fn index_path(db_name: &str, file_name: &str) -> PathBuf {
+        let dir = database_dir(db_name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(file_name)
+    }
+
+    fn key(datatype: &ColumnDataType, value: &str) -> Vec<u8> {
+        datatype.index_key(&datatype.parse_string(value).unwrap())
+    }
+
+    fn equal(key: Vec<u8>) -> KeyRange {
+        (Bound::Included(key.clone()), Bound::Included(key))
+    }
+
+    /// A store of `years`, one row each, and its `year` column.
+    fn years(years: &[&str]) -> (InMemoryByteStore, TableColumn) {
+        let table = TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int32)]).unwrap();
+        let mut store = InMemoryByteStore::new(&table);
+        for year in years {
+            store.insert(&table, &table.encode_literals(&[("year", year)]).unwrap()).unwrap();
+        }
+        (store, table.column_for_name("year").unwrap().clone())
+    }
+
+    #[test]
+    fn id_indexes_read_back_as_written() {
+        let _dir = ScratchDir::new("test_index_ids");
+        let path = index_path("test_index_ids", "books.id");
+        let mut index = IdIndex::build(&path, 0, [(0, 0), (1, 1), (2, 2)].into_iter()).unwrap();
+        index.insert(&[(5, 3), (6, 4)]).unwrap();
+        index.remove(1, 1).unwrap();
+        assert_eq!(index.rows_in_range(0..=5), vec![0, 2, 3]);
+        assert_eq!(index.rows_in_range(3..=4), Vec::<u64>::new());
+        assert!(index.rows_in_range(RangeInclusive::new(6, 2)).is_empty());
+
+        let loaded = IdIndex::load(&path, 0, 4).unwrap().unwrap();
+        assert_eq!(loaded.rows_in_range(0..=u64::MAX), vec![0, 2, 3, 4]);
+        // an index missing a row's id, or cut short, is rebuilt instead
+        assert!(IdIndex::load(&path, 0, 5).unwrap().is_none());
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[1, 2, 3]).unwrap();
+        assert!(IdIndex::load(&path, 0, 4).unwrap().is_none());
+        assert!(IdIndex::load(&index_path("test_index_ids", "pages.id"), 0, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn btree_indexes_answer_ranges() {
+        let _dir = ScratchDir::new("test_index_btree");
+        let (store, year) = years(&["1950", "-20", "1960", "1950", "0"]);
+        let path = index_path("test_index_btree", &ColumnIndex::file_name("books", "year", IndexKind::BTree));
+        let mut index = ColumnIndex::build(&path, &year, IndexKind::BTree, &store).unwrap();
+        let datatype = &year.datatype;
+
+        assert_eq!(index.rows_in_range(equal(key(datatype, "1950"))), Some(vec![0, 3]));
+        // negative keys sort before positive ones
+        assert_eq!(index.rows_in_range((Bound::Unbounded, Bound::Excluded(key(datatype, "1950")))), Some(vec![1, 4]));
+        assert_eq!(index.rows_in_range((Bound::Excluded(key(datatype, "0")), Bound::Unbounded)), Some(vec![0, 3, 2]));
+        assert_eq!(index.rows_in_range((Bound::Included(key(datatype, "1960")), Bound::Excluded(key(datatype, "1950")))), Some(vec![]));
+
+        let row = |year: &str| {
+            let mut row = vec![0u8; 12];
+            row[8..].copy_from_slice(&datatype.parse_string(year).unwrap());
+            row
+        };
+        index.update(&row("1950"), &row("1970"), 3).unwrap();
+        index.remove(&row("-20"), 1).unwrap();
+        index.insert(&[row("1970"), row("1940")].concat(), 12, 5).unwrap();
+        assert_eq!(index.rows_in_range(equal(key(datatype, "1970"))), Some(vec![3, 5]));
+        assert_eq!(index.rows_in_range((Bound::Unbounded, Bound::Included(key(datatype, "1950")))), Some(vec![4, 6, 0]));
+
+        let loaded = ColumnIndex::load(&path, &year, IndexKind::BTree, 6).unwrap().unwrap();
+        assert_eq!(loaded.rows_in_range((Bound::Unbounded, Bound::Unbounded)), index.rows_in_range((Bound::Unbounded, Bound::Unbounded)));
+        assert!(ColumnIndex::load(&path, &year, IndexKind::BTree, 7).unwrap().is_none());
+    }
+
+    #[test]
+    fn hash_indexes_only_look_up_single_keys() {
+        let _dir = ScratchDir::new("test_index_hash");
+        let (store, year) = years(&["1950", "1960", "1950"]);
+        let path = index_path("test_index_hash", &ColumnIndex::file_name("books", "year", IndexKind::Hash));
+        let index = ColumnIndex::build(&path, &year, IndexKind::Hash, &store).unwrap();
+        let datatype = &year.datatype;
+
+        assert_eq!(index.rows_in_range(equal(key(datatype, "1950"))), Some(vec![0, 2]));
+        assert_eq!(index.rows_in_range(equal(key(datatype, "1970"))), Some(vec![]));
+        assert_eq!(index.rows_in_range((Bound::Unbounded, Bound::Included(key(datatype, "1950")))), None);
+        assert_eq!(ColumnIndex::load(&path, &year, IndexKind::Hash, 3).unwrap().unwrap().rows_in_range(equal(key(datatype, "1960"))), Some(vec![1]));
+        assert_eq!("hash".parse::<IndexKind>(), Ok(IndexKind::Hash));
+        assert!("bitmap".parse::<IndexKind>().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::{io::Read, ops::{Range, RangeInclusive}};
+
+use memmap2::Mmap;
+
+use super::{ByteStore, ConvertRow, FileByteStore, StorageSize, deleted_row, index::{IndexKind, KeyRange}, checksum::RowCheck, version::RowVersions, super::schema::{TableDescriptor, EncodedRow, config::{StorageSettings, Compression}}, super::stats::ColumnHistogram, super::error::KronkError};
+
+/// Table store that memory-maps the table file, so scans and row reads come
+/// straight out of the OS page cache instead of being copied through read
+/// buffers. Writes, logging and indexes are handled by the wrapped
+/// `FileByteStore`, and the file is re-mapped after each one so reads see it.
+pub struct MmapByteStore {
+    inner: FileByteStore,
+    map: Mmap
+}
+
+impl MmapByteStore {
+    /// Wraps an opened store. It shouldn't have a page cache; the mapping
+    /// takes its place. Tables with compressed pages or sealed segments
+    /// can't be mapped.
+    pub fn new(inner: FileByteStore) -> std::io::Result<MmapByteStore> {
+        if inner.cold.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "table has compressed pages, which can't be memory-mapped"));
+        }
+        if !inner.segments.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "table is split into segments, which can't be memory-mapped together"));
+        }
+        let map = Self::map_file(&inner)?;
+        Ok(MmapByteStore { inner, map })
+    }
+
+    fn map_file(inner: &FileByteStore) -> std::io::Result<Mmap> {
+        // Safety: the map is read-only and the file is only written through
+        // `inner`, which re-maps afterwards. Nothing truncates a table file
+        // while it is open; rewrites rename a new file over it instead.
+        unsafe { Mmap::map(&inner.file) }
+    }
+
+    fn remap(&mut self) -> Result<(), KronkError> {
+        self.map = Self::map_file(&self.inner).map_err(|e| KronkError::storage_io("failed mapping table file", e))?;
+        Ok(())
+    }
+
+    /// The mapped row data, past the file header.
+    fn rows(&self) -> &[u8] {
+        self.map.get(self.inner.data_offset as usize..).unwrap_or_default()
+    }
+}
+
+impl ByteStore for MmapByteStore {
+    fn insert(&mut self, descriptor: &TableDescriptor, row: &EncodedRow) -> Result<(u64, Vec<u8>), KronkError> {
+        let inserted = self.inner.insert(descriptor, row)?;
+        self.remap()?;
+        Ok(inserted)
+    }
+
+    fn insert_many(&mut self, descriptor: &TableDescriptor, rows: &[EncodedRow]) -> Result<Vec<(u64, Vec<u8>)>, KronkError> {
+        let inserted = self.inner.insert_many(descriptor, rows)?;
+        self.remap()?;
+        Ok(inserted)
+    }
+
+    fn get_reader<'a>(&'a self) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(self.rows()))
+    }
+
+    fn mapped_rows(&self) -> Option<&[u8]> {
+        Some(self.rows())
+    }
+
+    fn write_row(&mut self, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        self.inner.write_row(row_number, bytes)?;
+        self.remap()
+    }
+
+    fn row_size(&self) -> usize {
+        self.inner.row_size
+    }
+
+    fn is_deleted(&self, row_number: u64) -> bool {
+        self.inner.is_deleted(row_number)
+    }
+
+    fn deleted_count(&self) -> u64 {
+        self.inner.deleted_count()
+    }
+
+    fn delete_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        self.inner.delete_row(row_number)
+    }
+
+    fn restore_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        self.inner.restore_row(row_number)
+    }
+
+    fn rewrite_rows(&mut self, new_descriptor: &TableDescriptor, convert: &mut ConvertRow<'_>) -> Result<(), KronkError> {
+        self.inner.rewrite_rows(new_descriptor, convert)?;
+        self.remap()
+    }
+
+    fn release(self: Box<Self>, keep_backup: bool) -> Result<(), KronkError> {
+        let MmapByteStore { inner, map } = *self;
+        drop(map);
+        Box::new(inner).release(keep_backup)
+    }
+
+    fn scan(&self, rows: Range<u64>, f: &mut dyn FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+        let mapped = self.rows().chunks_exact(self.inner.row_size).enumerate()
+            .map(|(i, row)| (i as u64, row))
+            .skip(rows.start as usize)
+            .take_while(|(row_number, _)| *row_number < rows.end);
+        for (row_number, row) in mapped {
+            if self.inner.is_deleted(row_number) { continue; }
+            if !f(row_number, row) { break; }
+        }
+        Ok(())
+    }
+
+    fn rows_in_id_range(&self, ids: RangeInclusive<u64>) -> Option<Vec<u64>> {
+        self.inner.rows_in_id_range(ids)
+    }
+
+    fn rows_in_key_range(&self, column_name: &str, keys: KeyRange) -> Option<Vec<u64>> {
+        self.inner.rows_in_key_range(column_name, keys)
+    }
+
+    fn create_index(&mut self, descriptor: &TableDescriptor, column_name: &str, kind: IndexKind) -> Result<(), KronkError> {
+        self.inner.create_index(descriptor, column_name, kind)
+    }
+
+    fn histogram(&self, column_name: &str) -> Option<&ColumnHistogram> {
+        self.inner.histogram(column_name)
+    }
+
+    fn set_histograms(&mut self, histograms: Vec<ColumnHistogram>) -> Result<(), KronkError> {
+        self.inner.set_histograms(histograms)
+    }
+
+    fn row_versions(&self) -> Option<&RowVersions> {
+        self.inner.row_versions()
+    }
+
+    fn row_versions_mut(&mut self) -> Option<&mut RowVersions> {
+        self.inner.row_versions_mut()
+    }
+
+    fn read_row(&self, row_number: u64) -> std::io::Result<Vec<u8>> {
+        if self.inner.is_deleted(row_number) {
+            return Err(deleted_row(row_number));
+        }
+        let row_size = self.inner.row_size;
+        let start = row_number as usize * row_size;
+        self.rows().get(start..(start + row_size))
+            .map(|row| row.to_vec())
+            .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn verify_row(&self, row_number: u64, row: &[u8]) -> std::io::Result<RowCheck> {
+        self.inner.verify_row(row_number, row)
+    }
+
+    fn data_len(&self) -> std::io::Result<u64> {
+        Ok(self.rows().len() as u64)
+    }
+
+    fn storage_size(&self) -> std::io::Result<StorageSize> {
+        self.inner.storage_size()
+    }
+
+    fn apply_settings(&mut self, settings: &StorageSettings) {
+        // compressed pages and segments can't be mapped, so never start either
+        self.inner.apply_settings(&StorageSettings { compression: Compression::None, segment_size: None, ..*settings })
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.inner.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{schema::ColumnDataType, testing::ScratchDir};
+
+    fn titles(store: &dyn ByteStore) -> Vec<String> {
+        let mut titles = Vec::new();
+        store.scan_rows(&mut |_, row| {
+            titles.push(ColumnDataType::Byte(16).parse_bytes(&row[8..]).unwrap());
+            true
+        }).unwrap();
+        titles
+    }
+
+    #[test]
+    fn reads_see_every_write() {
+        let _dir = ScratchDir::new("test_mmap");
+        let table = TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap();
+        let mut store = MmapByteStore::new(FileByteStore::new("test_mmap", &table).unwrap()).unwrap();
+        assert_eq!(store.mapped_rows(), Some(&[][..]));
+
+        store.insert(&table, &table.encode_literals(&[("title", "Bees")]).unwrap()).unwrap();
+        store.insert_many(&table, &[table.encode_literals(&[("title", "Frogs")]).unwrap(), table.encode_literals(&[("title", "Lumps")]).unwrap()]).unwrap();
+        assert_eq!(store.mapped_rows().unwrap().len(), 3 * table.total_row_size());
+        assert_eq!(titles(&store), vec!["Bees", "Frogs", "Lumps"]);
+
+        let mut moths = store.read_row(1).unwrap();
+        moths[8..].copy_from_slice(&ColumnDataType::Byte(16).parse_string("Moths").unwrap());
+        store.write_row(1, &moths).unwrap();
+        store.delete_row(0).unwrap();
+        assert_eq!(titles(&store), vec!["Moths", "Lumps"]);
+        assert!(store.read_row(0).is_err());
+        let mut rows = Vec::new();
+        store.scan(2..3, &mut |row_number, _| { rows.push(row_number); true }).unwrap();
+        assert_eq!(rows, vec![2]);
+
+        // the file underneath holds the same rows
+        drop(store);
+        assert_eq!(titles(&FileByteStore::new("test_mmap", &table).unwrap()), vec!["Moths", "Lumps"]);
+    }
+}
@@ -1,122 +1,1533 @@
-use std::{fs::{File, OpenOptions, ReadDir}, path::{Path, PathBuf}, io::{Write, BufReader}, io::prelude::*};
+use std::{collections::BTreeSet, path::{Path, PathBuf}, io::prelude::*, ops::{Range, RangeInclusive}};
+#[cfg(feature = "file")]
+use std::{fs::{File, OpenOptions}, io::{Write, BufReader}, sync::{Arc, Mutex}};
 
-use super::{schema::TableDescriptor, bytes::ToNativeType};
+use super::{schema::{TableDescriptor, EncodedRow, config::StorageSettings}, stats::ColumnHistogram, error::KronkError};
+#[cfg(feature = "file")]
+use super::schema::config::{Durability, CorruptionPolicy, Compression, DEFAULT_PAGE_SIZE};
+
+#[cfg(feature = "file")]
+pub mod pool;
+#[cfg(feature = "file")]
+pub mod header;
+#[cfg(feature = "file")]
+pub mod wal;
+pub mod index;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod checksum;
+#[cfg(feature = "file")]
+pub mod compress;
+#[cfg(feature = "file")]
+pub mod snapshot;
+#[cfg(feature = "file")]
+pub mod deleted;
+#[cfg(feature = "file")]
+pub mod object;
+pub mod version;
+
+use self::{index::{IndexKind, KeyRange}, checksum::RowCheck, version::RowVersions};
+#[cfg(feature = "file")]
+use self::{header::TableFileHeader, pool::{PageCache, PagedReader}, wal::{WalRecord, WriteAheadLog}, index::{IdIndex, ColumnIndex}, checksum::RowChecksums, compress::ColdPages, deleted::DeletedRows, object::{ObjectStore, RemoteSegments}};
 
 const KRONKSTORE_DIRECTORY: &str = "./.kronkstore";
-const KRONKSTORE_TABLES_DIR: &str = "./.kronkstore/tables";
 
+/// Each database keeps its files under `.kronkstore/<db_name>`, so databases
+/// with identically named tables don't share storage.
+pub fn database_dir(db_name: &str) -> PathBuf {
+    Path::new(KRONKSTORE_DIRECTORY).join(db_name)
+}
+
+pub fn table_path(db_name: &str, table_name: &str) -> PathBuf {
+    database_dir(db_name).join("tables").join(table_name)
+}
+
+/// File in a database's directory that whoever has the database open holds
+/// a lock on.
+#[cfg(feature = "file")]
+pub const LOCK_FILE_NAME: &str = "lock";
+
+/// Takes the lock on the database in `database_dir`, creating the directory
+/// if needed. The lock is held until the returned file is closed. Fails with
+/// `WouldBlock` when the database is already open, whether in another
+/// process or this one. The lock is advisory: it only keeps out other
+/// processes that take it too.
+#[cfg(feature = "file")]
+pub fn lock_database(database_dir: &Path) -> std::io::Result<File> {
+    std::fs::create_dir_all(database_dir)?;
+    let f = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(database_dir.join(LOCK_FILE_NAME))?;
+    match f.try_lock() {
+        Ok(()) => Ok(f),
+        Err(std::fs::TryLockError::WouldBlock) => Err(std::io::ErrorKind::WouldBlock.into()),
+        Err(std::fs::TryLockError::Error(e)) => Err(e)
+    }
+}
+
+
+/// Bytes a table takes up in storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageSize {
+    /// Everything but indexes: the table file, compressed pages, segments,
+    /// checksums and the list of deleted rows.
+    pub data_bytes: u64,
+    pub index_bytes: u64
+}
+
+/// Length of the file at `path`, or 0 if there is none.
+#[cfg(feature = "file")]
+fn file_len(path: &Path) -> std::io::Result<u64> {
+    match std::fs::metadata(path) {
+        Ok(m) => Ok(m.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e)
+    }
+}
 
 #[derive(Debug)]
 pub struct InMemoryByteStore {
     pub table_name: String,
     pub id_counter: u64,
-    pub mem: Vec<u8>
+    pub row_size: usize,
+    pub mem: Vec<u8>,
+    pub deleted: BTreeSet<u64>,
+    /// Rows' earlier versions, for snapshots taken before they were written.
+    pub versions: RowVersions
 }
 
 impl InMemoryByteStore {
     pub fn new(table_descriptor: &TableDescriptor) -> InMemoryByteStore {
         InMemoryByteStore {
             table_name: table_descriptor.table_name.to_string(),
-            id_counter: 1,
-            mem: Vec::new()
+            id_counter: 0,
+            row_size: table_descriptor.total_row_size(),
+            mem: Vec::new(),
+            deleted: BTreeSet::new(),
+            versions: RowVersions::default()
         }
     } 
 }
 
-pub trait ByteStore {
-    fn insert(&mut self, descriptor: &TableDescriptor, columns: &[(&str, &str)]) -> Result<(), String>;
+/// Reads the rows numbered `rows` from `reader`, which must be positioned at
+/// the first of them, passing the ones that aren't deleted to `f`.
+fn scan_reader(store: &(impl ByteStore + ?Sized), mut reader: impl Read, rows: Range<u64>, f: &mut dyn FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+    let mut row = vec![0u8; store.row_size()];
+    for row_number in rows {
+        match reader.read_exact(row.as_mut_slice()) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e)
+        }
+        if store.is_deleted(row_number) { continue; }
+        if !f(row_number, row.as_slice()) { return Ok(()); }
+    }
+    Ok(())
+}
 
-    fn get_reader<'a>(&'a self) -> Box<dyn Read + 'a>;
+fn deleted_row(row_number: u64) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("row {} was deleted", row_number))
+}
+
+/// Converts a row for `ByteStore::rewrite_rows`.
+pub type ConvertRow<'a> = dyn FnMut(&[u8]) -> Result<Vec<u8>, KronkError> + 'a;
+
+/// Storage for one table's rows. Rows all have the table's row size and are
+/// numbered from 0 in the order they were inserted, so a row number is
+/// enough to find a row.
+pub trait ByteStore: Send + Sync {
+    /// Appends a row and returns its position in the table along with the
+    /// bytes that were written, including generated values like the serial id.
+    fn insert(&mut self, descriptor: &TableDescriptor, row: &EncodedRow) -> Result<(u64, Vec<u8>), KronkError>;
+
+    /// Appends several rows at once, returning each like `insert` does.
+    /// Either every row is appended or, if one of them can't be, none are.
+    /// Stores that can should write the batch in one go.
+    fn insert_many(&mut self, descriptor: &TableDescriptor, rows: &[EncodedRow]) -> Result<Vec<(u64, Vec<u8>)>, KronkError> {
+        rows.iter().map(|row| self.insert(descriptor, row)).collect()
+    }
+
+    /// Reads the stored row data from the start, deleted rows included.
+    fn get_reader<'a>(&'a self) -> std::io::Result<Box<dyn Read + 'a>>;
+
+    /// Size of a row in the table's current layout.
+    fn row_size(&self) -> usize;
+
+    /// Number of rows stored, deleted ones included, so rows are numbered
+    /// from 0 up to this.
+    fn row_count(&self) -> std::io::Result<u64> {
+        Ok(self.data_len()? / self.row_size() as u64)
+    }
+
+    fn is_deleted(&self, _row_number: u64) -> bool {
+        false
+    }
+
+    /// Number of rows that have been deleted.
+    fn deleted_count(&self) -> u64 {
+        0
+    }
+
+    /// Overwrites the row at `row_number` in place. `bytes` must be a whole
+    /// row in the table's current layout.
+    fn write_row(&mut self, row_number: u64, bytes: &[u8]) -> Result<(), KronkError>;
+
+    /// Deletes the row at `row_number`. Scans and indexes pass over it from
+    /// then on, and reading it fails; the rows after it keep their numbers.
+    fn delete_row(&mut self, _row_number: u64) -> Result<(), KronkError> {
+        Err(KronkError::Storage("This table's storage doesn't support deleting rows".to_owned()))
+    }
+
+    /// Brings back a row `delete_row` deleted, as it was when deleted.
+    fn restore_row(&mut self, _row_number: u64) -> Result<(), KronkError> {
+        Err(KronkError::Storage("This table's storage doesn't support restoring rows".to_owned()))
+    }
+
+    /// Rebuilds the table with every row passed through `convert`, replacing
+    /// the old contents only once all rows converted successfully.
+    /// `new_descriptor` is the layout the converted rows are in.
+    fn rewrite_rows(&mut self, new_descriptor: &TableDescriptor, convert: &mut ConvertRow<'_>) -> Result<(), KronkError>;
+
+    /// Closes the store and frees its storage. With `keep_backup`, the data
+    /// is archived instead of deleted.
+    fn release(self: Box<Self>, keep_backup: bool) -> Result<(), KronkError>;
+
+    /// Calls `f` with the number and bytes of each row in `rows` that isn't
+    /// deleted, in order, stopping early once it returns false.
+    fn scan(&self, rows: Range<u64>, f: &mut dyn FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+        let mut reader = self.get_reader()?;
+        std::io::copy(&mut (&mut reader).take(rows.start * self.row_size() as u64), &mut std::io::sink())?;
+        scan_reader(self, reader, rows, f)
+    }
+
+    /// The stored rows, deleted ones included, for stores that can hand
+    /// them out without reading them, such as by mapping the file. Scans of
+    /// such stores only touch the bytes they look at.
+    fn mapped_rows(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Like `scan`, over every row.
+    fn scan_rows(&self, f: &mut dyn FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+        self.scan(0..self.row_count()?, f)
+    }
+
+    /// Row numbers of the rows with serial ids in `ids`, in id order, for
+    /// stores that index their ids. `None` means the caller has to scan.
+    fn rows_in_id_range(&self, _ids: RangeInclusive<u64>) -> Option<Vec<u64>> {
+        None
+    }
+
+    /// Row numbers of the rows whose `column` value falls in `keys`, for
+    /// stores with an index on that column. `None` means the caller has to
+    /// scan.
+    fn rows_in_key_range(&self, _column_name: &str, _keys: KeyRange) -> Option<Vec<u64>> {
+        None
+    }
+
+    /// Builds an index on a column and keeps it up to date from then on.
+    fn create_index(&mut self, _descriptor: &TableDescriptor, _column_name: &str, _kind: IndexKind) -> Result<(), KronkError> {
+        Err(KronkError::Storage("This table's storage doesn't support indexes".to_owned()))
+    }
+
+    /// A column's histogram from the last time the table was analyzed, if
+    /// it was.
+    fn histogram(&self, _column_name: &str) -> Option<&ColumnHistogram> {
+        None
+    }
+
+    /// Keeps histograms for the planner to estimate with, in place of any
+    /// from before.
+    fn set_histograms(&mut self, _histograms: Vec<ColumnHistogram>) -> Result<(), KronkError> {
+        Err(KronkError::Storage("This table's storage doesn't keep statistics".to_owned()))
+    }
+
+    /// Earlier versions of rows written while snapshots were open, for
+    /// stores that keep them. Stores without them can't be read at a
+    /// snapshot.
+    fn row_versions(&self) -> Option<&RowVersions> {
+        None
+    }
+
+    fn row_versions_mut(&mut self) -> Option<&mut RowVersions> {
+        None
+    }
+
+    /// Reads the row at `row_number`. Fails with `NotFound` if it was
+    /// deleted.
+    fn read_row(&self, row_number: u64) -> std::io::Result<Vec<u8>> {
+        if self.is_deleted(row_number) {
+            return Err(deleted_row(row_number));
+        }
+        let mut reader = self.get_reader()?;
+        std::io::copy(&mut (&mut reader).take(row_number * self.row_size() as u64), &mut std::io::sink())?;
+        let mut row = vec![0u8; self.row_size()];
+        reader.read_exact(&mut row)?;
+        Ok(row)
+    }
+
+    /// Like `read_row`, into `row`, which has to be a row long. Lets a
+    /// caller reading many rows reuse one buffer.
+    fn read_row_into(&self, row_number: u64, row: &mut [u8]) -> std::io::Result<()> {
+        row.copy_from_slice(&self.read_row(row_number)?);
+        Ok(())
+    }
+
+    /// Looks up a row by serial id. Tables without a serial id never match.
+    fn find_row_by_id(&self, descriptor: &TableDescriptor, id: u64) -> std::io::Result<Option<Vec<u8>>> {
+        let id_offset = match descriptor.id_column() {
+            Some(c) => c.offset,
+            None => return Ok(None)
+        };
+        if let Some(rows) = self.rows_in_id_range(id..=id) {
+            return rows.first().map(|r| self.read_row(*r)).transpose();
+        }
+        let mut found = None;
+        self.scan_rows(&mut |_, row| {
+            if row[id_offset..(id_offset + 8)] == id.to_le_bytes() {
+                found = Some(row.to_vec());
+                false
+            } else { true }
+        })?;
+        Ok(found)
+    }
+
+    /// Checks a row just read against what was written at `row_number`,
+    /// following the store's corruption policy when it doesn't match. Stores
+    /// without checksums take every row as intact.
+    fn verify_row(&self, _row_number: u64, _row: &[u8]) -> std::io::Result<RowCheck> {
+        Ok(RowCheck::Intact)
+    }
+
+    /// Size of the stored rows in bytes, not counting any file header.
+    fn data_len(&self) -> std::io::Result<u64>;
+
+    /// Bytes the table takes up in storage.
+    fn storage_size(&self) -> std::io::Result<StorageSize> {
+        Ok(StorageSize { data_bytes: self.data_len()?, index_bytes: 0 })
+    }
+
+    /// Picks up changed storage settings. Stores that don't cache pages
+    /// ignore this.
+    fn apply_settings(&mut self, _settings: &StorageSettings) {}
+
+    /// Flushes written rows to durable storage.
+    fn sync(&self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl ByteStore for InMemoryByteStore {
-    fn insert(&mut self, descriptor: &TableDescriptor, columns: &[(&str, &str)]) -> Result<(), String> {
-        let id = self.id_counter;
-        let bytes = descriptor.get_insertion_bytes(id, columns)?;
-        self.id_counter += 1;
+    fn insert(&mut self, descriptor: &TableDescriptor, row: &EncodedRow) -> Result<(u64, Vec<u8>), KronkError> {
+        self.insert_many(descriptor, std::slice::from_ref(row)).map(|mut rows| rows.remove(0))
+    }
 
-        if bytes.len() != descriptor.total_row_size() {
-            Err("invalid table insertion".to_owned())
-        } else {
-            self.mem.extend(bytes);
-            Ok(())
+    /// Serializes the whole batch before appending any of it.
+    fn insert_many(&mut self, descriptor: &TableDescriptor, rows: &[EncodedRow]) -> Result<Vec<(u64, Vec<u8>)>, KronkError> {
+        let first_row = (self.mem.len() / self.row_size) as u64;
+        let inserted = rows.iter().enumerate()
+            .map(|(i, row)| {
+                let bytes = descriptor.get_insertion_bytes(self.id_counter + i as u64, row);
+                match bytes.len() == self.row_size {
+                    true => Ok((first_row + i as u64, bytes)),
+                    false => Err(KronkError::Storage("invalid table insertion".to_owned()))
+                }
+            })
+            .collect::<Result<Vec<_>, KronkError>>()?;
+        self.id_counter += rows.len() as u64;
+        for (_, bytes) in &inserted {
+            self.mem.extend(bytes.as_slice());
+        }
+        Ok(inserted)
+    }
+
+    fn get_reader<'a>(&'a self) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(std::io::BufReader::new(self.mem.as_slice())))
+    }
+
+    fn mapped_rows(&self) -> Option<&[u8]> {
+        Some(&self.mem)
+    }
+
+    fn row_size(&self) -> usize {
+        self.row_size
+    }
+
+    fn is_deleted(&self, row_number: u64) -> bool {
+        self.deleted.contains(&row_number)
+    }
+
+    fn deleted_count(&self) -> u64 {
+        self.deleted.len() as u64
+    }
+
+    fn write_row(&mut self, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        let start = row_number as usize * bytes.len();
+        let row = self.mem.get_mut(start..(start + bytes.len()))
+            .filter(|_| !self.deleted.contains(&row_number))
+            .ok_or_else(|| KronkError::Schema(format!("No row {} to overwrite", row_number)))?;
+        row.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn delete_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        if row_number >= self.row_count().unwrap() || !self.deleted.insert(row_number) {
+            return Err(KronkError::Schema(format!("No row {} to delete", row_number)));
+        }
+        Ok(())
+    }
+
+    fn restore_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        if !self.deleted.remove(&row_number) {
+            return Err(KronkError::Schema(format!("No deleted row {} to restore", row_number)));
         }
+        Ok(())
     }
 
-    fn get_reader<'a>(&'a self) -> Box<dyn Read + 'a> {
-        Box::new(std::io::BufReader::new(self.mem.as_slice()))
+    fn data_len(&self) -> std::io::Result<u64> {
+        Ok(self.mem.len() as u64)
+    }
+
+    fn row_versions(&self) -> Option<&RowVersions> {
+        Some(&self.versions)
+    }
+
+    fn row_versions_mut(&mut self) -> Option<&mut RowVersions> {
+        Some(&mut self.versions)
+    }
+
+    fn release(self: Box<Self>, _keep_backup: bool) -> Result<(), KronkError> {
+        // nothing outlives the process, so there is nowhere to keep a backup
+        Ok(())
+    }
+
+    fn rewrite_rows(&mut self, new_descriptor: &TableDescriptor, convert: &mut ConvertRow<'_>) -> Result<(), KronkError> {
+        let new_row_size = new_descriptor.total_row_size();
+        let mut rewritten: Vec<u8> = Vec::new();
+        for (row_number, row) in self.mem.chunks_exact(self.row_size).enumerate() {
+            match self.deleted.contains(&(row_number as u64)) {
+                true => rewritten.extend(vec![0u8; new_row_size]),
+                false => rewritten.extend(convert(row)?)
+            }
+        }
+        self.mem = rewritten;
+        self.row_size = new_row_size;
+        // earlier versions of rows are in the old layout
+        self.versions = RowVersions::default();
+        Ok(())
+    }
+}
+
+/// Reads exactly `buf.len()` bytes at `offset` without touching the file's
+/// cursor, so reads and writes through a shared handle can't interfere.
+#[cfg(feature = "file")]
+fn read_exact_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    { std::os::unix::fs::FileExt::read_exact_at(f, buf, offset) }
+    #[cfg(windows)]
+    {
+        let mut done = 0usize;
+        while done < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(f, &mut buf[done..], offset + done as u64)? {
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                n => done += n
+            }
+        }
+        Ok(())
     }
 }
 
+/// Writes all of `buf` at `offset` without touching the file's cursor.
+#[cfg(feature = "file")]
+fn write_all_at(f: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    { std::os::unix::fs::FileExt::write_all_at(f, buf, offset) }
+    #[cfg(windows)]
+    {
+        let mut done = 0usize;
+        while done < buf.len() {
+            done += std::os::windows::fs::FileExt::seek_write(f, &buf[done..], offset + done as u64)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "file")]
 pub struct FileByteStore {
     pub table_name: String,
+    pub database_dir: PathBuf,
     pub table_path: PathBuf,
-    pub id_counter: u64
+    /// The table file, held open for the life of the store. Reads and writes
+    /// through it are positioned, so they never depend on its cursor.
+    file: File,
+    /// The next serial id. Kept here and written to the file header on
+    /// `sync` and when the store is dropped; the write-ahead log covers
+    /// anything in between.
+    pub id_counter: u64,
+    pub row_size: usize,
+    /// Where rows start, just past the header and embedded schema.
+    pub data_offset: u64,
+    /// Reads go through this buffer pool when set.
+    pub cache: Option<PageCache>,
+    /// Writes are logged here before they touch the table file when set.
+    pub wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    pub durability: Durability,
+    pub id_index: Option<IdIndex>,
+    pub column_indexes: Vec<ColumnIndex>,
+    /// From `Database::analyze`. Only held in memory, and not kept up to
+    /// date as rows change.
+    pub histograms: Vec<ColumnHistogram>,
+    /// Rows' earlier versions, for snapshots taken before they were written.
+    pub versions: RowVersions,
+    pub checksums: Option<RowChecksums>,
+    pub on_corruption: CorruptionPolicy,
+    /// Leading pages of row data that were moved out of the table file and
+    /// compressed, if any were.
+    pub cold: Option<Mutex<ColdPages>>,
+    /// Codec and page size that full pages are compressed with as the table
+    /// grows. Nothing is compressed with `Compression::None`.
+    pub compression: Compression,
+    pub compression_page_size: usize,
+    /// Sealed segments of row data, oldest first. Rows are never appended to
+    /// them again; only overwrites of rows already in them touch them.
+    segments: Vec<File>,
+    /// Bytes of row data in each sealed segment.
+    segment_len: u64,
+    /// Size the rows in the table file may reach before they are sealed into
+    /// a new segment. Without one, every row stays in the table file.
+    pub segment_size: Option<u64>,
+    /// Object store that sealed segments are uploaded to, if there is one.
+    pub remote: Option<RemoteSegments>,
+    pub deleted: DeletedRows
 }
 
+/// How many full pages at the end of a compressed table stay uncompressed,
+/// since recently inserted rows are the likeliest to be read or updated.
+#[cfg(feature = "file")]
+pub const HOT_PAGE_COUNT: u64 = 4;
+
+#[cfg(feature = "file")]
 impl FileByteStore {
-    pub fn new(table_descriptor: &TableDescriptor) -> std::io::Result<FileByteStore> {
-        std::fs::create_dir_all(KRONKSTORE_TABLES_DIR).or_else(|e| match e.kind() {
+    /// Opens the table's file, creating it if needed. An existing file must
+    /// carry a schema matching `table_descriptor`, otherwise this fails with
+    /// `InvalidData` rather than misreading its rows.
+    pub fn new(db_name: &str, table_descriptor: &TableDescriptor) -> std::io::Result<FileByteStore> {
+        let database_dir = database_dir(db_name);
+        std::fs::create_dir_all(database_dir.join("tables")).or_else(|e| match e.kind() {
             std::io::ErrorKind::AlreadyExists => Ok(()),
             _ => Err(e)
         })?;
-        let table_path = Path::new(KRONKSTORE_TABLES_DIR).join(table_descriptor.table_name.as_str());
-        dbg!(&table_path);
+        let table_path = table_path(db_name, &table_descriptor.table_name);
 
-        if !table_path.exists() {
-            let mut f = OpenOptions::new().write(true).create(true).open(&table_path)?;
+        let header = if !table_path.exists() {
+            let mut f = OpenOptions::new().write(true).create(true).truncate(true).open(&table_path)?;
+            let header = TableFileHeader::for_descriptor(table_descriptor, 0);
+            header.write_to(&mut f)?;
+            header
+        } else {
+            let mut f = BufReader::new(File::open(&table_path)?);
+            let header = TableFileHeader::read_from(&mut f)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", table_path.display(), e)))?;
+            header.validate(table_descriptor)?;
+            header
+        };
+        let file = OpenOptions::new().read(true).write(true).open(&table_path)?;
 
-            // write out the 64-byte header section, all zeroed out
-            let b = [0u8; 64];
-            f.write(&b)?;
-        }
+        let cold = if header.cold_pages > 0 {
+            let path = database_dir.join("cold").join(table_descriptor.table_name.as_str());
+            Some(Mutex::new(ColdPages::open(&path, header.cold_pages, header.cold_page_size as u64)?))
+        } else { None };
+        let segments = (0..header.segments).map(|n| {
+            let path = Self::segment_path(&database_dir, &table_descriptor.table_name, n);
+            let f = OpenOptions::new().read(true).write(true).open(&path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            if f.metadata()?.len() != header.segment_len {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: segment is cut short", path.display())));
+            }
+            Ok(f)
+        }).collect::<std::io::Result<Vec<_>>>()?;
+        let deleted = DeletedRows::open(&database_dir.join("deleted").join(table_descriptor.table_name.as_str()))?;
 
-        Ok(FileByteStore {
+        let mut store = FileByteStore {
             table_name: table_descriptor.table_name.to_string(),
+            database_dir,
             table_path,
-            id_counter: 0
-        })
+            file,
+            id_counter: header.id_counter,
+            row_size: table_descriptor.total_row_size(),
+            data_offset: header.data_offset(),
+            cache: None,
+            wal: None,
+            durability: Durability::OnClose,
+            id_index: None,
+            column_indexes: Vec::new(),
+            histograms: Vec::new(),
+            versions: RowVersions::default(),
+            checksums: None,
+            on_corruption: CorruptionPolicy::Error,
+            cold,
+            compression: Compression::None,
+            compression_page_size: DEFAULT_PAGE_SIZE,
+            segments,
+            segment_len: header.segment_len,
+            segment_size: None,
+            remote: None,
+            deleted
+        };
+        store.catch_up_id_counter(table_descriptor)?;
+        Ok(store)
     }
 
-    pub fn get_file(&self, options: &OpenOptions) -> std::io::Result<File> {
-        options.open(&self.table_path)
+    /// Moves the id counter past the last row's serial id. The counter is
+    /// only written to the header when the table is synced, so a process
+    /// killed mid-insert can leave it behind rows it wrote.
+    fn catch_up_id_counter(&mut self, table_descriptor: &TableDescriptor) -> std::io::Result<()> {
+        let Some(id_column) = table_descriptor.id_column() else { return Ok(()) };
+        let row_count = self.row_count()?;
+        if row_count == 0 {
+            return Ok(());
+        }
+        let last = self.read_slot(row_count - 1)?;
+        let last_id = u64::from_le_bytes(last[id_column.offset..(id_column.offset + 8)].try_into().unwrap());
+        self.id_counter = self.id_counter.max(last_id.saturating_add(1));
+        Ok(())
+    }
+
+    /// Serves this table's reads from a buffer pool, caching pages of the
+    /// row data as they are read.
+    pub fn with_page_cache(mut self, cache: PageCache) -> FileByteStore {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Logs every write to `wal` before applying it, syncing the log as
+    /// `durability` asks. Call `recover` before using the store to replay
+    /// writes a previous run logged but may not have finished.
+    pub fn with_wal(mut self, wal: Arc<Mutex<WriteAheadLog>>, durability: Durability) -> FileByteStore {
+        self.wal = Some(wal);
+        self.durability = durability;
+        self
+    }
+
+    /// Indexes the table's serial ids, loading the index file or rebuilding
+    /// it if it is missing or doesn't cover every row. Tables without a
+    /// serial id are left as they are.
+    pub fn with_id_index(mut self, descriptor: &TableDescriptor) -> std::io::Result<FileByteStore> {
+        let id_offset = match descriptor.id_column() {
+            Some(c) => c.offset,
+            None => return Ok(self)
+        };
+        let indexes_dir = self.indexes_dir();
+        std::fs::create_dir_all(&indexes_dir)?;
+        let path = indexes_dir.join(format!("{}.id", self.table_name));
+
+        let index = match IdIndex::load(&path, id_offset, self.live_row_count()?)? {
+            Some(index) => index,
+            None => {
+                let mut entries: Vec<(u64, u64)> = Vec::new();
+                self.scan_rows(&mut |row_number, row| {
+                    entries.push((u64::from_le_bytes(row[id_offset..(id_offset + 8)].try_into().unwrap()), row_number));
+                    true
+                })?;
+                IdIndex::build(&path, id_offset, entries.into_iter())?
+            }
+        };
+        self.id_index = Some(index);
+        Ok(self)
+    }
+
+    /// Compresses full pages of row data with `codec` as the table grows,
+    /// keeping the last few pages uncompressed. Pages compressed earlier are
+    /// read back whatever this is set to.
+    pub fn with_compression(mut self, codec: Compression, page_size: usize) -> std::io::Result<FileByteStore> {
+        compress::check_supported(codec).map_err(|e| std::io::Error::new(std::io::ErrorKind::Unsupported, e))?;
+        if codec != Compression::None && !self.segments.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "a table split into segments can't be compressed"));
+        }
+        self.compression = codec;
+        self.compression_page_size = page_size;
+        Ok(self)
+    }
+
+    /// Seals the rows in the table file into a new segment file whenever
+    /// they reach `segment_size` bytes. Can't be combined with compression.
+    pub fn with_segments(mut self, segment_size: Option<u64>) -> std::io::Result<FileByteStore> {
+        if segment_size.is_some() && (self.cold.is_some() || self.compression != Compression::None) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "a compressed table can't be split into segments"));
+        }
+        self.segment_size = segment_size;
+        Ok(self)
+    }
+
+    /// Uploads sealed segments to `objects` from now on, along with any
+    /// sealed earlier that aren't there yet. Segments missing from disk have
+    /// to be downloaded with `RemoteSegments::download_missing` before the
+    /// store is opened.
+    pub fn with_object_store(mut self, objects: Option<Arc<dyn ObjectStore>>) -> std::io::Result<FileByteStore> {
+        let objects = match objects {
+            Some(objects) => objects,
+            None => return Ok(self)
+        };
+        let stored = RemoteSegments::stored(objects.as_ref(), &self.table_name)?;
+        let remote = RemoteSegments::new(objects, &self.database_dir, &self.table_name);
+        for n in (0..self.segments.len() as u64).filter(|n| !stored.contains(n)) {
+            remote.changed(n);
+        }
+        self.remote = Some(remote);
+        Ok(self)
+    }
+
+    fn segment_path(database_dir: &Path, table_name: &str, n: u64) -> PathBuf {
+        database_dir.join("segments").join(format!("{}.{}", table_name, n))
+    }
+
+    fn cold_len(&self) -> u64 {
+        self.cold.as_ref().map_or(0, |cold| cold.lock().unwrap().len())
     }
 
-    pub fn get_id_counter(&self, table_file: &mut File) -> std::io::Result<u64> {
-        table_file.rewind()?;
-        let mut id_buf = [0u8; 8];
-        table_file.read_exact(id_buf.as_mut_slice())?;
-        Ok(id_buf.to_native_type().unwrap())
+    fn live_row_count(&self) -> std::io::Result<u64> {
+        Ok(self.row_count()? - self.deleted.len())
     }
 
-    pub fn set_id_counter(&self, table_file: &mut File, id: u64) -> std::io::Result<()> {
-        table_file.rewind()?;
-        let b = id.to_le_bytes();
-        table_file.write(b.as_slice())?;
+    fn sealed_len(&self) -> u64 {
+        self.segments.len() as u64 * self.segment_len
+    }
+
+    /// Where the row data at `hot_pos`, a position past any compressed
+    /// pages, is stored: the file, the offset in it, and how many bytes
+    /// from there on are in the same file.
+    fn locate(&self, hot_pos: u64) -> (&File, u64, u64) {
+        let sealed_len = self.sealed_len();
+        if hot_pos < sealed_len {
+            let offset = hot_pos % self.segment_len;
+            (&self.segments[(hot_pos / self.segment_len) as usize], offset, self.segment_len - offset)
+        } else {
+            (&self.file, self.data_offset + hot_pos - sealed_len, u64::MAX)
+        }
+    }
+
+    /// Reads row data starting at `start` from the compressed pages, sealed
+    /// segments and the table file.
+    pub fn read_data(&self, start: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let mut filled = 0usize;
+        let mut cold_len = 0u64;
+        if let Some(cold) = &self.cold {
+            let cold = cold.lock().unwrap();
+            cold_len = cold.len();
+            while filled < buf.len() && start + (filled as u64) < cold_len {
+                let pos = start + filled as u64;
+                let page_number = pos / cold.page_size;
+                let page = cold.read_page(page_number)?;
+                let in_page = (pos - page_number * cold.page_size) as usize;
+                let n = (buf.len() - filled).min(page.len() - in_page);
+                buf[filled..(filled + n)].copy_from_slice(&page[in_page..(in_page + n)]);
+                filled += n;
+            }
+        }
+        while filled < buf.len() {
+            let (f, offset, available) = self.locate(start + filled as u64 - cold_len);
+            let n = ((buf.len() - filled) as u64).min(available) as usize;
+            read_exact_at(f, &mut buf[filled..(filled + n)], offset)?;
+            filled += n;
+        }
         Ok(())
     }
+
+    /// Moves full pages at the front of the table file into the compressed
+    /// page file once enough have built up, leaving `HOT_PAGE_COUNT` behind.
+    /// The pages are written and synced first, then the table file is
+    /// rewritten without them and renamed into place, so an interruption
+    /// at any point leaves every row readable.
+    fn compress_cold_pages(&mut self) -> std::io::Result<()> {
+        if self.compression == Compression::None || !self.segments.is_empty() { return Ok(()); }
+        let page_size = match &self.cold {
+            Some(cold) => cold.lock().unwrap().page_size,
+            None => self.compression_page_size as u64
+        };
+        let hot_len = self.file.metadata()?.len() - self.data_offset;
+        let full_pages = hot_len / page_size;
+        if full_pages < 2 * HOT_PAGE_COUNT { return Ok(()); }
+        let moving = full_pages - HOT_PAGE_COUNT;
+
+        let mut src = File::open(&self.table_path)?;
+        let mut header = TableFileHeader::read_from(&mut src)?;
+        header.id_counter = self.id_counter;
+        let first_page = header.cold_pages;
+        let mut moved = vec![0u8; (moving * page_size) as usize];
+        src.read_exact(&mut moved)?;
+
+        if self.cold.is_none() {
+            let dir = self.database_dir.join("cold");
+            std::fs::create_dir_all(&dir)?;
+            self.cold = Some(Mutex::new(ColdPages::open(&dir.join(&self.table_name), 0, page_size)?));
+        }
+        let cold = self.cold.as_ref().unwrap();
+        let pages = moved.chunks_exact(page_size as usize).enumerate()
+            .map(|(i, page)| (first_page + i as u64, page))
+            .collect::<Vec<_>>();
+        cold.lock().unwrap().write_pages(&pages, self.compression)?;
+
+        header.cold_pages += moving;
+        header.cold_page_size = page_size as u32;
+        self.swap_in_remainder(&header, &mut src)?;
+        self.cold.as_ref().unwrap().lock().unwrap().count = header.cold_pages;
+        Ok(())
+    }
+
+    /// Moves rows from the front of the table file into new sealed segments
+    /// for as long as it holds a segment's worth. As with compressed pages,
+    /// the segments are written and synced before the table file is
+    /// rewritten without their rows.
+    fn seal_segments(&mut self, row_size: usize) -> std::io::Result<()> {
+        let segment_size = match self.segment_size {
+            Some(s) if self.cold.is_none() => s,
+            _ => return Ok(())
+        };
+        // segments hold whole rows and are all the same length, so a
+        // position maps straight to its segment
+        let segment_len = match self.segments.is_empty() {
+            true => (segment_size / row_size as u64).max(1) * row_size as u64,
+            false => self.segment_len
+        };
+        let hot_len = self.file.metadata()?.len() - self.data_offset;
+        let sealing = hot_len / segment_len;
+        if sealing == 0 { return Ok(()); }
+
+        let mut src = BufReader::new(File::open(&self.table_path)?);
+        let mut header = TableFileHeader::read_from(&mut src)?;
+        header.id_counter = self.id_counter;
+        std::fs::create_dir_all(self.database_dir.join("segments"))?;
+        let mut sealed = Vec::new();
+        for n in header.segments..(header.segments + sealing) {
+            let path = Self::segment_path(&self.database_dir, &self.table_name, n);
+            let mut f = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+            std::io::copy(&mut (&mut src).take(segment_len), &mut f)?;
+            f.sync_all()?;
+            sealed.push(f);
+        }
+
+        header.segments += sealing;
+        header.segment_len = segment_len;
+        self.swap_in_remainder(&header, &mut src)?;
+        if let Some(remote) = &self.remote {
+            for n in self.segments.len() as u64..header.segments {
+                remote.changed(n);
+            }
+        }
+        self.segments.extend(sealed);
+        self.segment_len = segment_len;
+        Ok(())
+    }
+
+    /// Replaces the table file with `header` followed by whatever is left to
+    /// read from `src`, for once leading rows have been moved elsewhere.
+    fn swap_in_remainder(&mut self, header: &TableFileHeader, src: &mut impl Read) -> std::io::Result<()> {
+        let rewrite_path = self.table_path.with_extension("compact");
+        let mut dst = std::io::BufWriter::new(File::create(&rewrite_path)?);
+        header.write_to(&mut dst)?;
+        std::io::copy(src, &mut dst)?;
+        dst.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&rewrite_path, &self.table_path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.table_path)?;
+        Ok(())
+    }
+
+    /// Keeps a checksum of every row, verified as rows are read. Attach
+    /// before `recover` so replayed rows get their checksums updated.
+    pub fn with_checksums(mut self, on_corruption: CorruptionPolicy) -> std::io::Result<FileByteStore> {
+        let checksums_dir = self.database_dir.join("checksums");
+        std::fs::create_dir_all(&checksums_dir)?;
+        let path = checksums_dir.join(&self.table_name);
+        self.checksums = Some(RowChecksums::open(&path, &self)?);
+        self.on_corruption = on_corruption;
+        Ok(self)
+    }
+
+    /// Looks through the write-ahead log for the last logged copy of a row
+    /// that matches its checksum, and writes it back over the corrupt one.
+    fn repair_row(&self, row_number: u64) -> std::io::Result<Option<Vec<u8>>> {
+        let (wal, checksums) = match (&self.wal, &self.checksums) {
+            (Some(wal), Some(checksums)) => (wal, checksums),
+            _ => return Ok(None)
+        };
+        let expected = match checksums.expected(row_number) {
+            Some(sum) => sum,
+            None => return Ok(None)
+        };
+        let row_size = checksums.row_size as u64;
+        let start = row_number * row_size;
+
+        let records = wal.lock().unwrap().records()?;
+        let logged = records.into_iter().rev()
+            .filter(|r| r.table_name == self.table_name && r.position <= start && start + row_size <= r.position + r.bytes.len() as u64)
+            .map(|r| r.bytes[((start - r.position) as usize)..((start - r.position + row_size) as usize)].to_vec())
+            .find(|row| checksum::crc32(row) == expected);
+
+        if let Some(row) = &logged {
+            self.write_at(start, row)?;
+        }
+        Ok(logged)
+    }
+
+    fn indexes_dir(&self) -> PathBuf {
+        self.database_dir.join("indexes")
+    }
+
+    /// Opens the column indexes previously created on this table, rebuilding
+    /// any that don't cover every row.
+    pub fn with_column_indexes(mut self, descriptor: &TableDescriptor) -> std::io::Result<FileByteStore> {
+        let row_count = self.live_row_count()?;
+        for column in &descriptor.columns {
+            for kind in IndexKind::ALL {
+                let path = self.indexes_dir().join(ColumnIndex::file_name(&self.table_name, &column.name, kind));
+                if !path.exists() { continue; }
+                let index = match ColumnIndex::load(&path, column, kind, row_count)? {
+                    Some(index) => index,
+                    None => ColumnIndex::build(&path, column, kind, &self)?
+                };
+                self.column_indexes.push(index);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Re-applies this table's records from the write-ahead log. Returns the
+    /// number of records replayed.
+    pub fn recover(&mut self) -> std::io::Result<usize> {
+        let wal = match &self.wal {
+            Some(wal) => wal.clone(),
+            None => return Ok(0)
+        };
+        let records = wal.lock().unwrap().records()?.into_iter()
+            .filter(|r| r.table_name == self.table_name)
+            .collect::<Vec<_>>();
+        if records.is_empty() { return Ok(0); }
+
+        for r in &records {
+            self.write_at(r.position, &r.bytes)?;
+            self.id_counter = self.id_counter.max(r.id_counter);
+        }
+        self.sync()?;
+
+        if let Some(mut checksums) = self.checksums.take() {
+            let row_size = checksums.row_size as u64;
+            for r in &records {
+                let rows = (r.position / row_size)..(r.position + r.bytes.len() as u64).div_ceil(row_size);
+                for row_number in rows {
+                    checksums.set(row_number, &self.read_slot(row_number)?)?;
+                }
+            }
+            self.checksums = Some(checksums);
+        }
+        Ok(records.len())
+    }
+
+    /// Writes into the row data at `position` and drops any cached pages
+    /// the write touched. Writes to compressed pages recompress the page.
+    fn write_at(&self, position: u64, bytes: &[u8]) -> std::io::Result<()> {
+        let mut written = 0usize;
+        let mut cold_len = 0u64;
+        if let Some(cold) = &self.cold {
+            let mut cold = cold.lock().unwrap();
+            cold_len = cold.len();
+            while written < bytes.len() && position + (written as u64) < cold_len {
+                let pos = position + written as u64;
+                let page_number = pos / cold.page_size;
+                let mut page = cold.read_page(page_number)?;
+                let in_page = (pos - page_number * cold.page_size) as usize;
+                let n = (bytes.len() - written).min(page.len() - in_page);
+                page[in_page..(in_page + n)].copy_from_slice(&bytes[written..(written + n)]);
+                let codec = cold.codec_of(page_number).unwrap_or(self.compression);
+                cold.write_pages(&[(page_number, &page)], codec)?;
+                written += n;
+            }
+        }
+        while written < bytes.len() {
+            let hot_pos = position + written as u64 - cold_len;
+            if let Some(remote) = self.remote.as_ref().filter(|_| hot_pos < self.sealed_len()) {
+                remote.changed(hot_pos / self.segment_len);
+            }
+            let (f, offset, available) = self.locate(hot_pos);
+            let n = ((bytes.len() - written) as u64).min(available) as usize;
+            write_all_at(f, &bytes[written..(written + n)], offset)?;
+            written += n;
+        }
+        if let Some(cache) = &self.cache {
+            let mut pool = cache.pool.lock().unwrap();
+            for page_number in cache.pages_for(position, bytes.len() as u64) {
+                pool.invalidate(&(self.table_name.to_owned(), page_number));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the row at `row_number` whether or not it was deleted.
+    fn read_slot(&self, row_number: u64) -> std::io::Result<Vec<u8>> {
+        let mut row = vec![0u8; self.row_size];
+        self.read_slot_into(row_number, &mut row)?;
+        Ok(row)
+    }
+
+    fn read_slot_into(&self, row_number: u64, row: &mut [u8]) -> std::io::Result<()> {
+        let position = row_number * self.row_size as u64;
+        if self.cache.is_some() {
+            let mut reader = PagedReader::new(self, self.data_len()?);
+            reader.seek(std::io::SeekFrom::Start(position))?;
+            reader.read_exact(row)
+        } else {
+            self.read_data(position, row)
+        }
+    }
+
+    /// A reader over the row data starting at `position`.
+    fn reader_at(&self, position: u64) -> std::io::Result<Box<dyn Read + '_>> {
+        if self.cache.is_some() || self.cold.is_some() || !self.segments.is_empty() {
+            let mut reader = PagedReader::new(self, self.data_len()?);
+            reader.seek(std::io::SeekFrom::Start(position))?;
+            return Ok(Box::new(reader));
+        }
+        let mut f = File::open(&self.table_path)?;
+        f.seek(std::io::SeekFrom::Start(self.data_offset + position))?;
+        Ok(Box::new(BufReader::new(f)))
+    }
+
+    fn flush_id_counter(&self) -> std::io::Result<()> {
+        write_all_at(&self.file, &self.id_counter.to_le_bytes(), 0)
+    }
+}
+
+#[cfg(feature = "file")]
+impl Drop for FileByteStore {
+    fn drop(&mut self) {
+        let _ = self.flush_id_counter();
+        if let Some(remote) = &self.remote {
+            let _ = remote.upload();
+        }
+    }
 }
 
+#[cfg(feature = "file")]
 impl ByteStore for FileByteStore {
-    fn insert(&mut self, descriptor: &TableDescriptor, columns: &[(&str, &str)]) -> Result<(), String> {
-        let mut f = self.get_file(OpenOptions::new().read(true).write(true)).map_err(|_| "failed opening table file!".to_owned())?;
-        let id = self.get_id_counter(&mut f).map_err(|_| "could not get id".to_owned())?;
+    fn insert(&mut self, descriptor: &TableDescriptor, row: &EncodedRow) -> Result<(u64, Vec<u8>), KronkError> {
+        self.insert_many(descriptor, std::slice::from_ref(row)).map(|mut rows| rows.remove(0))
+    }
+
+    /// Serializes the whole batch up front, then logs it as one record and
+    /// writes it with a single seek and write, updating the id counter,
+    /// checksums and indexes once for all of it.
+    fn insert_many(&mut self, descriptor: &TableDescriptor, rows: &[EncodedRow]) -> Result<Vec<(u64, Vec<u8>)>, KronkError> {
+        if rows.is_empty() { return Ok(Vec::new()); }
+        let row_size = descriptor.total_row_size();
+        let id = self.id_counter;
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(rows.len() * row_size);
+        for (i, row) in rows.iter().enumerate() {
+            let row = descriptor.get_insertion_bytes(id + i as u64, row);
+            if row.len() != row_size {
+                return Err(KronkError::Storage("invalid table insertion".to_owned()));
+            }
+            bytes.extend(row);
+        }
+        let next_id = id + rows.len() as u64;
+
+        let end = self.file.metadata().map_err(|e| KronkError::storage_io("failed reading table file", e))?.len();
+        let position = end - self.data_offset + self.cold_len() + self.sealed_len();
+        let first_row = position / row_size as u64;
+
+        if let Some(wal) = &self.wal {
+            let record = WalRecord { table_name: self.table_name.to_owned(), position, id_counter: next_id, bytes: bytes.clone() };
+            wal.lock().unwrap().append(&record, self.durability).map_err(|e| KronkError::storage_io("failed writing to the write-ahead log", e))?;
+        }
+
+        self.write_at(position, &bytes).map_err(|e| KronkError::storage_io("failed writing row to file", e))?;
+        self.id_counter = next_id;
+        if let Some(checksums) = &mut self.checksums {
+            checksums.set(first_row, &bytes).map_err(|e| KronkError::storage_io("failed updating row checksum", e))?;
+        }
+        if let Some(index) = &mut self.id_index {
+            let entries = (0..rows.len() as u64).map(|i| (id + i, first_row + i)).collect::<Vec<_>>();
+            index.insert(&entries).map_err(|e| KronkError::storage_io("failed updating id index", e))?;
+        }
+        for index in self.column_indexes.iter_mut() {
+            index.insert(&bytes, row_size, first_row).map_err(|e| KronkError::storage_io(format!("failed updating index on '{}'", index.column_name), e))?;
+        }
+        self.compress_cold_pages().map_err(|e| KronkError::storage_io("failed compressing pages", e))?;
+        self.seal_segments(row_size).map_err(|e| KronkError::storage_io("failed sealing segment", e))?;
+
+        Ok(bytes.chunks_exact(row_size).enumerate()
+            .map(|(i, row)| (first_row + i as u64, row.to_vec()))
+            .collect())
+    }
+
+    /// Logs the new row, writes it over the old one, and moves it in any
+    /// column index whose key changed. Serial ids can't be overwritten.
+    fn write_row(&mut self, row_number: u64, bytes: &[u8]) -> Result<(), KronkError> {
+        if bytes.len() != self.row_size {
+            return Err(KronkError::Storage("invalid row size for this table".to_owned()));
+        }
+        let position = row_number * bytes.len() as u64;
+        if row_number >= self.row_count().map_err(|e| KronkError::storage_io("failed reading table file", e))? || self.deleted.contains(row_number) {
+            return Err(KronkError::Schema(format!("No row {} to overwrite", row_number)));
+        }
+        let old = self.read_slot(row_number).map_err(|e| KronkError::storage_io("failed reading row to overwrite", e))?;
+
+        if let Some(index) = &self.id_index {
+            let id = index.id_offset..(index.id_offset + 8);
+            if old[id.clone()] != bytes[id] {
+                return Err(KronkError::Constraint("A row's serial id can't be changed".to_owned()));
+            }
+        }
+
+        if let Some(wal) = &self.wal {
+            let record = WalRecord { table_name: self.table_name.to_owned(), position, id_counter: self.id_counter, bytes: bytes.to_vec() };
+            wal.lock().unwrap().append(&record, self.durability).map_err(|e| KronkError::storage_io("failed writing to the write-ahead log", e))?;
+        }
+        self.write_at(position, bytes).map_err(|e| KronkError::storage_io("failed writing row to file", e))?;
+        if let Some(checksums) = &mut self.checksums {
+            checksums.set(row_number, bytes).map_err(|e| KronkError::storage_io("failed updating row checksum", e))?;
+        }
 
-        let bytes = descriptor.get_insertion_bytes(id, columns)?;
+        for index in self.column_indexes.iter_mut() {
+            index.update(&old, bytes, row_number).map_err(|e| KronkError::storage_io(format!("failed updating index on '{}'", index.column_name), e))?;
+        }
+        Ok(())
+    }
 
-        if bytes.len() != descriptor.total_row_size() {
-            return Err("invalid table insertion".to_owned());
+    /// Marks the row deleted, then takes it out of the indexes. An index
+    /// left behind by a crash in between no longer matches the live row
+    /// count, so it gets rebuilt on open.
+    fn delete_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        let row = self.read_row(row_number).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => KronkError::Schema(format!("No row {} to delete", row_number)),
+            _ => KronkError::storage_io("failed reading row to delete", e)
+        })?;
+        self.deleted.insert(row_number).map_err(|e| KronkError::storage_io("failed recording deleted row", e))?;
+        if let Some(index) = &mut self.id_index {
+            let id = u64::from_le_bytes(row[index.id_offset..(index.id_offset + 8)].try_into().unwrap());
+            index.remove(id, row_number).map_err(|e| KronkError::storage_io("failed updating id index", e))?;
+        }
+        for index in self.column_indexes.iter_mut() {
+            index.remove(&row, row_number).map_err(|e| KronkError::storage_io(format!("failed updating index on '{}'", index.column_name), e))?;
         }
+        Ok(())
+    }
 
-        f.seek(std::io::SeekFrom::End(0)).map_err(|_| "could not seek to end for appending")?;
-        f.write_all(bytes.as_slice()).map_err(|_| "failed writing row to file".to_owned())?;
-        self.set_id_counter(&mut f, id + 1);
+    /// Takes the row off the deleted list, then puts it back in the
+    /// indexes, the same way round as `delete_row`.
+    fn restore_row(&mut self, row_number: u64) -> Result<(), KronkError> {
+        if !self.deleted.contains(row_number) {
+            return Err(KronkError::Schema(format!("No deleted row {} to restore", row_number)));
+        }
+        let row = self.read_slot(row_number).map_err(|e| KronkError::storage_io("failed reading row to restore", e))?;
+        self.deleted.remove(row_number).map_err(|e| KronkError::storage_io("failed recording restored row", e))?;
+        if let Some(index) = &mut self.id_index {
+            let id = u64::from_le_bytes(row[index.id_offset..(index.id_offset + 8)].try_into().unwrap());
+            index.insert(&[(id, row_number)]).map_err(|e| KronkError::storage_io("failed updating id index", e))?;
+        }
+        for index in self.column_indexes.iter_mut() {
+            index.insert(&row, row.len(), row_number).map_err(|e| KronkError::storage_io(format!("failed updating index on '{}'", index.column_name), e))?;
+        }
         Ok(())
     }
 
-    fn get_reader(&self) -> Box<dyn Read> {
-        let mut f = File::open(&self.table_path).unwrap();
-        f.seek(std::io::SeekFrom::Start(64)).unwrap();
-        Box::new(BufReader::new(f))
+    fn get_reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        self.reader_at(0)
+    }
+
+    fn row_size(&self) -> usize {
+        self.row_size
+    }
+
+    fn is_deleted(&self, row_number: u64) -> bool {
+        self.deleted.contains(row_number)
+    }
+
+    fn deleted_count(&self) -> u64 {
+        self.deleted.len()
+    }
+
+    fn scan(&self, rows: Range<u64>, f: &mut dyn FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+        let reader = self.reader_at(rows.start * self.row_size as u64)?;
+        scan_reader(self, reader, rows, f)
+    }
+
+    fn data_len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len().saturating_sub(self.data_offset) + self.cold_len() + self.sealed_len())
     }
-}
\ No newline at end of file
+
+    fn storage_size(&self) -> std::io::Result<StorageSize> {
+        let mut data_bytes = self.file.metadata()?.len() + self.sealed_len() + file_len(self.deleted.path())?;
+        if let Some(cold) = &self.cold {
+            data_bytes += file_len(cold.lock().unwrap().path())?;
+        }
+        if let Some(checksums) = &self.checksums {
+            data_bytes += file_len(checksums.path())?;
+        }
+        let mut index_bytes = 0;
+        if let Some(index) = &self.id_index {
+            index_bytes += file_len(index.path())?;
+        }
+        for index in &self.column_indexes {
+            index_bytes += file_len(index.path())?;
+        }
+        Ok(StorageSize { data_bytes, index_bytes })
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.flush_id_counter()?;
+        self.deleted.sync()?;
+        self.file.sync_all()?;
+        match &self.remote {
+            Some(remote) => remote.upload(),
+            None => Ok(())
+        }
+    }
+
+    fn rows_in_id_range(&self, ids: RangeInclusive<u64>) -> Option<Vec<u64>> {
+        self.id_index.as_ref().map(|index| index.rows_in_range(ids))
+    }
+
+    fn rows_in_key_range(&self, column_name: &str, keys: KeyRange) -> Option<Vec<u64>> {
+        self.column_indexes.iter()
+            .find(|index| index.column_name == column_name)
+            .and_then(|index| index.rows_in_range(keys))
+    }
+
+    fn create_index(&mut self, descriptor: &TableDescriptor, column_name: &str, kind: IndexKind) -> Result<(), KronkError> {
+        let column = descriptor.column_for_name(column_name)
+            .ok_or_else(|| KronkError::Schema(format!("Cannot create index: no column '{}' exists", column_name)))?;
+        if self.column_indexes.iter().any(|index| index.column_name == column.name) {
+            return Err(KronkError::Schema(format!("Column '{}' is already indexed", column.name)));
+        }
+
+        std::fs::create_dir_all(self.indexes_dir()).map_err(|e| KronkError::storage_io("failed creating index directory", e))?;
+        let path = self.indexes_dir().join(ColumnIndex::file_name(&self.table_name, &column.name, kind));
+        let index = ColumnIndex::build(&path, column, kind, self)
+            .map_err(|e| KronkError::storage_io(format!("failed building index on '{}'", column.name), e))?;
+        self.column_indexes.push(index);
+        Ok(())
+    }
+
+    fn histogram(&self, column_name: &str) -> Option<&ColumnHistogram> {
+        self.histograms.iter().find(|h| h.column_name == column_name)
+    }
+
+    fn set_histograms(&mut self, histograms: Vec<ColumnHistogram>) -> Result<(), KronkError> {
+        self.histograms = histograms;
+        Ok(())
+    }
+
+    fn row_versions(&self) -> Option<&RowVersions> {
+        Some(&self.versions)
+    }
+
+    fn row_versions_mut(&mut self) -> Option<&mut RowVersions> {
+        Some(&mut self.versions)
+    }
+
+    fn read_row(&self, row_number: u64) -> std::io::Result<Vec<u8>> {
+        if self.deleted.contains(row_number) {
+            return Err(deleted_row(row_number));
+        }
+        self.read_slot(row_number)
+    }
+
+    fn read_row_into(&self, row_number: u64, row: &mut [u8]) -> std::io::Result<()> {
+        if self.deleted.contains(row_number) {
+            return Err(deleted_row(row_number));
+        }
+        self.read_slot_into(row_number, row)
+    }
+
+    fn verify_row(&self, row_number: u64, row: &[u8]) -> std::io::Result<RowCheck> {
+        match &self.checksums {
+            Some(checksums) if !checksums.matches(row_number, row) => {},
+            _ => return Ok(RowCheck::Intact)
+        }
+        let corrupt = || std::io::Error::new(std::io::ErrorKind::InvalidData, format!("row {} of table '{}' failed its checksum", row_number, self.table_name));
+        match self.on_corruption {
+            CorruptionPolicy::Error => Err(corrupt()),
+            CorruptionPolicy::Skip => Ok(RowCheck::Skip),
+            CorruptionPolicy::Repair => self.repair_row(row_number)?.map(RowCheck::Repaired).ok_or_else(corrupt)
+        }
+    }
+
+    fn apply_settings(&mut self, settings: &StorageSettings) {
+        self.durability = settings.durability;
+        self.on_corruption = settings.on_corruption;
+        // compression and segments can't be combined; segments already
+        // sealed keep a table from being compressed and vice versa
+        if compress::check_supported(settings.compression).is_ok() && (settings.compression == Compression::None || self.segments.is_empty()) {
+            self.compression = settings.compression;
+            self.compression_page_size = settings.page_size;
+        }
+        self.segment_size = settings.segment_size.filter(|_| self.cold.is_none() && self.compression == Compression::None);
+        if let Some(cache) = &mut self.cache {
+            // pages cached at the old size no longer line up
+            cache.pool.lock().unwrap().invalidate_table(&self.table_name);
+            *cache = PageCache::new(cache.pool.clone(), settings);
+        }
+    }
+
+    /// Deletes the table file, or with `keep_backup` moves it under the
+    /// database's `archive` directory with the time it was dropped appended.
+    fn release(self: Box<Self>, keep_backup: bool) -> Result<(), KronkError> {
+        // indexes are rebuilt from the table if it is ever restored
+        if let Some(index) = &self.id_index {
+            let _ = std::fs::remove_file(index.path());
+        }
+        for index in &self.column_indexes {
+            let _ = std::fs::remove_file(index.path());
+        }
+        if let Some(checksums) = &self.checksums {
+            let _ = std::fs::remove_file(checksums.path());
+        }
+        let deleted_path = Some(self.deleted.path().to_owned()).filter(|p| p.exists());
+        let cold_path = self.cold.as_ref().map(|cold| cold.lock().unwrap().path().to_owned());
+        let segment_paths = (0..self.segments.len() as u64)
+            .map(|n| Self::segment_path(&self.database_dir, &self.table_name, n))
+            .collect::<Vec<_>>();
+        // an archived table keeps its segments on disk only
+        if let Some(remote) = &self.remote {
+            remote.remove_from(0).map_err(|e| KronkError::storage_io("failed deleting stored segments", e))?;
+        }
+        if keep_backup {
+            let archive_dir = self.database_dir.join("archive");
+            std::fs::create_dir_all(&archive_dir).map_err(|e| KronkError::storage_io("failed creating archive directory", e))?;
+            let dropped_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let archive_path = archive_dir.join(format!("{}.{}", self.table_name, dropped_at));
+            // the table file needs its compressed pages and segments to be complete
+            if let Some(cold_path) = cold_path {
+                std::fs::rename(cold_path, archive_path.with_extension(format!("{}.cold", dropped_at)))
+                    .map_err(|e| KronkError::storage_io("failed archiving compressed pages", e))?;
+            }
+            for (n, segment_path) in segment_paths.into_iter().enumerate() {
+                std::fs::rename(segment_path, archive_path.with_extension(format!("{}.{}.seg", dropped_at, n)))
+                    .map_err(|e| KronkError::storage_io("failed archiving table segment", e))?;
+            }
+            if let Some(deleted_path) = deleted_path {
+                std::fs::rename(deleted_path, archive_path.with_extension(format!("{}.deleted", dropped_at)))
+                    .map_err(|e| KronkError::storage_io("failed archiving deleted rows", e))?;
+            }
+            std::fs::rename(&self.table_path, archive_path).map_err(|e| KronkError::storage_io("failed archiving table file", e))
+        } else {
+            if let Some(cold_path) = cold_path {
+                let _ = std::fs::remove_file(cold_path);
+            }
+            for segment_path in segment_paths {
+                let _ = std::fs::remove_file(segment_path);
+            }
+            if let Some(deleted_path) = deleted_path {
+                let _ = std::fs::remove_file(deleted_path);
+            }
+            std::fs::remove_file(&self.table_path).map_err(|e| KronkError::storage_io("failed deleting table file", e))
+        }
+    }
+
+    /// Writes the converted rows to a sibling file and renames it over the
+    /// table file, so a failed or interrupted rewrite leaves the original intact.
+    /// The new file's header carries `new_descriptor`'s schema.
+    /// Deleted rows are written out zeroed rather than converted, so they
+    /// keep their slots.
+    fn rewrite_rows(&mut self, new_descriptor: &TableDescriptor, convert: &mut ConvertRow<'_>) -> Result<(), KronkError> {
+        let rewrite_path = self.table_path.with_extension("rewrite");
+
+        let result = (|| {
+            let mut src = BufReader::new(File::open(&self.table_path).map_err(|e| KronkError::storage_io("failed opening table file!", e))?);
+            let mut dst = std::io::BufWriter::new(File::create(&rewrite_path).map_err(|e| KronkError::storage_io("failed creating rewrite file", e))?);
+
+            TableFileHeader::read_from(&mut src).map_err(|e| KronkError::storage_io("failed reading table header", e))?;
+            let header = TableFileHeader::for_descriptor(new_descriptor, self.id_counter);
+            header.write_to(&mut dst).map_err(|e| KronkError::storage_io("failed writing rewrite file", e))?;
+
+            // compressed pages are read back too; the rewritten file starts
+            // out uncompressed
+            let mut rows = self.get_reader().map_err(|e| KronkError::storage_io("failed reading table file", e))?;
+            let mut row = vec![0u8; self.row_size];
+            let deleted_row = vec![0u8; new_descriptor.total_row_size()];
+            for row_number in 0.. {
+                match rows.read_exact(row.as_mut_slice()) {
+                    Ok(()) => {},
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(KronkError::storage_io("failed reading table file", e))
+                }
+                let converted = match self.deleted.contains(row_number) {
+                    true => deleted_row.clone(),
+                    false => convert(row.as_slice())?
+                };
+                dst.write_all(converted.as_slice()).map_err(|e| KronkError::storage_io("failed writing rewrite file", e))?;
+            }
+            drop(rows);
+
+            let f = dst.into_inner().map_err(|e| KronkError::storage_io("failed writing rewrite file", e.into_error()))?;
+            f.sync_all().map_err(|e| KronkError::storage_io("failed syncing rewrite file", e))?;
+            std::fs::rename(&rewrite_path, &self.table_path).map_err(|e| KronkError::storage_io("failed swapping in rewritten table file", e))?;
+            self.file = OpenOptions::new().read(true).write(true).open(&self.table_path).map_err(|e| KronkError::storage_io("failed opening table file!", e))?;
+            self.data_offset = header.data_offset();
+            self.row_size = new_descriptor.total_row_size();
+            // cached pages hold rows in the old layout
+            if let Some(cache) = &self.cache {
+                cache.pool.lock().unwrap().invalidate_table(&self.table_name);
+            }
+            if let Some(cold) = self.cold.take() {
+                let _ = std::fs::remove_file(cold.into_inner().unwrap().path());
+            }
+            for n in 0..self.segments.len() as u64 {
+                let _ = std::fs::remove_file(Self::segment_path(&self.database_dir, &self.table_name, n));
+            }
+            self.segments.clear();
+            // the serial id moves along with the columns before it
+            match new_descriptor.id_column() {
+                Some(id) => if let Some(index) = &mut self.id_index {
+                    index.id_offset = id.offset;
+                },
+                None => if let Some(index) = self.id_index.take() {
+                    let _ = std::fs::remove_file(index.path());
+                }
+            }
+            // rows keep their positions, so an index stays valid as long as
+            // its column is still there with the same type. Indexes on
+            // dropped, renamed or retyped columns go away.
+            let mut kept = Vec::new();
+            for mut index in self.column_indexes.drain(..) {
+                match new_descriptor.column_for_name(&index.column_name) {
+                    Some(c) if c.datatype == index.datatype => {
+                        index.offset = c.offset;
+                        kept.push(index);
+                    },
+                    _ => { let _ = std::fs::remove_file(index.path()); }
+                }
+            }
+            self.column_indexes = kept;
+            // values may have changed type, so the next analyze starts over
+            self.histograms.clear();
+            // and earlier versions of rows are in the old layout
+            self.versions = RowVersions::default();
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&rewrite_path);
+            return result;
+        }
+        if let Some(checksums) = self.checksums.take() {
+            let rebuilt = RowChecksums::rebuild(checksums.path(), self)
+                .map_err(|e| KronkError::storage_io("failed rebuilding row checksums", e))?;
+            self.checksums = Some(rebuilt);
+        }
+        self.compress_cold_pages().map_err(|e| KronkError::storage_io("failed compressing pages", e))?;
+        self.seal_segments(new_descriptor.total_row_size()).map_err(|e| KronkError::storage_io("failed sealing segment", e))?;
+        match &self.remote {
+            Some(remote) => remote.remove_from(self.segments.len() as u64).map_err(|e| KronkError::storage_io("failed deleting stored segments", e)),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "file"))]
+mod tests {
+    use super::*;
+    use crate::table::{schema::ColumnDataType, testing::ScratchDir};
+
+    fn books() -> TableDescriptor {
+        TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap()
+    }
+
+    fn insert_titles(store: &mut FileByteStore, titles: &[String]) {
+        let table = books();
+        let rows = titles.iter().map(|title| table.encode_literals(&[("title", title)]).unwrap()).collect::<Vec<_>>();
+        store.insert_many(&table, &rows).unwrap();
+    }
+
+    fn titles(store: &dyn ByteStore) -> Vec<String> {
+        let mut titles = Vec::new();
+        store.scan_rows(&mut |_, row| {
+            titles.push(ColumnDataType::Byte(16).parse_bytes(&row[8..]).unwrap());
+            true
+        }).unwrap();
+        titles
+    }
+
+    fn retitle(store: &mut FileByteStore, row_number: u64, title: &str) {
+        let mut row = store.read_row(row_number).unwrap();
+        row[8..].copy_from_slice(&ColumnDataType::Byte(16).parse_string(title).unwrap());
+        store.write_row(row_number, &row).unwrap();
+    }
+
+    #[test]
+    fn databases_are_locked_by_one_opener_at_a_time() {
+        let _dir = ScratchDir::new("test_store_lock");
+        let dir = database_dir("test_store_lock");
+        let lock = lock_database(&dir).unwrap();
+        assert_eq!(lock_database(&dir).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+        drop(lock);
+        lock_database(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_segments_are_sealed_and_read_back() {
+        let _dir = ScratchDir::new("test_store_segments");
+        let written = (0..7).map(|i| format!("book {}", i)).collect::<Vec<_>>();
+        let mut store = FileByteStore::new("test_store_segments", &books()).unwrap().with_segments(Some(60)).unwrap();
+        insert_titles(&mut store, &written[..3]);
+        insert_titles(&mut store, &written[3..]);
+        // 60 bytes hold two whole rows, so six rows are sealed
+        assert_eq!(store.segments.len(), 3);
+        assert!(FileByteStore::segment_path(&database_dir("test_store_segments"), "books", 2).exists());
+        assert_eq!(titles(&store), written);
+
+        // sealed rows can still be overwritten and deleted
+        retitle(&mut store, 1, "moths");
+        store.delete_row(4).unwrap();
+        drop(store);
+        let store = FileByteStore::new("test_store_segments", &books()).unwrap().with_segments(Some(60)).unwrap();
+        assert_eq!(store.row_count().unwrap(), 7);
+        assert_eq!(titles(&store), ["book 0", "moths", "book 2", "book 3", "book 5", "book 6"]);
+    }
+
+    #[test]
+    fn compressed_pages_read_back_as_written() {
+        let codecs = [Compression::Lz4, Compression::Zstd].into_iter().filter(|codec| compress::check_supported(*codec).is_ok());
+        for codec in codecs {
+            let db_name = format!("test_store_compressed_{:?}", codec).to_lowercase();
+            let _dir = ScratchDir::new(&db_name);
+            let written = (0..400).map(|i| format!("book {}", i)).collect::<Vec<_>>();
+            let mut store = FileByteStore::new(&db_name, &books()).unwrap().with_compression(codec, 512).unwrap();
+            insert_titles(&mut store, &written);
+            assert!(store.cold.is_some());
+            assert!(std::fs::metadata(&store.table_path).unwrap().len() < store.data_len().unwrap());
+            assert_eq!(titles(&store), written);
+
+            retitle(&mut store, 0, "moths");
+            drop(store);
+            let store = FileByteStore::new(&db_name, &books()).unwrap().with_compression(codec, 512).unwrap();
+            let read = titles(&store);
+            assert_eq!((read.len(), read[0].as_str(), read[399].as_str()), (400, "moths", "book 399"));
+        }
+    }
+
+    #[test]
+    fn logged_writes_are_replayed_after_a_crash() {
+        let _dir = ScratchDir::new("test_store_recover");
+        let wal = Arc::new(Mutex::new(WriteAheadLog::open(&database_dir("test_store_recover")).unwrap()));
+        let open = || FileByteStore::new("test_store_recover", &books()).unwrap().with_wal(wal.clone(), Durability::EveryWrite);
+        let mut store = open();
+        insert_titles(&mut store, &["bees".to_owned(), "frogs".to_owned()]);
+        retitle(&mut store, 1, "moths");
+        let data_offset = store.data_offset;
+        drop(store);
+        // a crash that lost everything written to the table file
+        OpenOptions::new().write(true).open(table_path("test_store_recover", "books")).unwrap().set_len(data_offset).unwrap();
+
+        let mut store = open();
+        assert_eq!(store.row_count().unwrap(), 0);
+        assert_eq!(store.recover().unwrap(), 2);
+        assert_eq!(titles(&store), ["bees", "moths"]);
+        let (id, _) = store.insert(&books(), &books().encode_literals(&[("title", "lumps")]).unwrap()).unwrap();
+        assert_eq!(id, 2);
+
+        // once checkpointed there's nothing left to replay
+        wal.lock().unwrap().checkpoint(&["books"]).unwrap();
+        assert_eq!(open().recover().unwrap(), 0);
+    }
+}
@@ -0,0 +1,316 @@
+use std::{collections::BTreeSet, fmt::Debug, fs::File, io::Write, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use super::FileByteStore;
+
+// A table's sealed segments can be kept in an object store as well as on
+// disk, under the key `segments/<table>.<n>`. The segment files on disk then
+// act as a cache of the objects: any that are missing when the table is
+// opened are downloaded again. Segments are uploaded on `sync` and when the
+// store is dropped, after being sealed or having rows in them overwritten.
+// The table file, write-ahead log and indexes always stay on disk.
+
+/// A flat namespace of byte objects addressed by `/` separated keys.
+pub trait ObjectStore: Debug + Send + Sync {
+    /// Fails with `NotFound` when there is no object at `key`.
+    fn get(&self, key: &str) -> std::io::Result<Vec<u8>>;
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+    /// Deleting a missing object isn't an error.
+    fn delete(&self, key: &str) -> std::io::Result<()>;
+    /// Keys of the objects whose keys start with `prefix`.
+    fn list(&self, prefix: &str) -> std::io::Result<Vec<String>>;
+}
+
+/// Keeps objects as files under a directory, e.g. a mounted bucket or a
+/// network share.
+#[derive(Debug)]
+pub struct DirObjectStore {
+    root: PathBuf
+}
+
+impl DirObjectStore {
+    pub fn new(root: &Path) -> DirObjectStore {
+        DirObjectStore { root: root.to_owned() }
+    }
+}
+
+impl ObjectStore for DirObjectStore {
+    fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(key))
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let path = self.root.join(key);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // readers never see a partly written object
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        let mut f = File::create(&partial)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+        std::fs::rename(partial, path)
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(())
+        }
+    }
+
+    fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let (dir, name_prefix) = match prefix.rsplit_once('/') {
+            Some((dir, name)) => (self.root.join(dir), name),
+            None => (self.root.clone(), prefix)
+        };
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+        };
+        let mut keys = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with(name_prefix) && !name.ends_with(".partial") {
+                keys.push(format!("{}{}", &prefix[..(prefix.len() - name_prefix.len())], name));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// An S3 bucket or Google Cloud Storage bucket, through the `object_store`
+/// crate. Credentials and region come from the usual environment
+/// variables for each service. Needs the `s3` or `gcs` cargo feature.
+#[cfg(any(feature = "s3", feature = "gcs"))]
+#[derive(Debug)]
+pub struct CloudObjectStore {
+    store: Box<dyn object_store::ObjectStore>,
+    prefix: String,
+    runtime: tokio::runtime::Runtime
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+impl CloudObjectStore {
+    /// Keeps objects in the S3 bucket `bucket`, under `prefix`.
+    #[cfg(feature = "s3")]
+    pub fn s3(bucket: &str, prefix: &str) -> std::io::Result<CloudObjectStore> {
+        let store = object_store::aws::AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(cloud_error)?;
+        Self::new(Box::new(store), prefix)
+    }
+
+    /// Keeps objects in the Google Cloud Storage bucket `bucket`, under `prefix`.
+    #[cfg(feature = "gcs")]
+    pub fn gcs(bucket: &str, prefix: &str) -> std::io::Result<CloudObjectStore> {
+        let store = object_store::gcp::GoogleCloudStorageBuilder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(cloud_error)?;
+        Self::new(Box::new(store), prefix)
+    }
+
+    fn new(store: Box<dyn object_store::ObjectStore>, prefix: &str) -> std::io::Result<CloudObjectStore> {
+        // the object_store crate is async; kronk isn't, so every call blocks
+        // on a runtime of its own
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(CloudObjectStore { store, prefix: prefix.trim_matches('/').to_owned(), runtime })
+    }
+
+    fn location(&self, key: &str) -> object_store::path::Path {
+        match self.prefix.is_empty() {
+            true => object_store::path::Path::from(key),
+            false => object_store::path::Path::from(format!("{}/{}", self.prefix, key))
+        }
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+fn cloud_error(e: object_store::Error) -> std::io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()),
+        _ => std::io::Error::other(e.to_string())
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+impl ObjectStore for CloudObjectStore {
+    fn get(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        self.runtime.block_on(async {
+            let object = self.store.get(&self.location(key)).await.map_err(cloud_error)?;
+            object.bytes().await.map(|b| b.to_vec()).map_err(cloud_error)
+        })
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        self.runtime.block_on(self.store.put(&self.location(key), bytes.to_vec().into()))
+            .map(|_| ())
+            .map_err(cloud_error)
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        match self.runtime.block_on(self.store.delete(&self.location(key))) {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(cloud_error(e))
+        }
+    }
+
+    fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let location = self.location(prefix);
+        let (dir, name_prefix) = match location.as_ref().rsplit_once('/') {
+            Some((dir, name)) => (Some(object_store::path::Path::from(dir)), name.to_owned()),
+            None => (None, location.as_ref().to_owned())
+        };
+        let listed = self.runtime.block_on(self.store.list_with_delimiter(dir.as_ref())).map_err(cloud_error)?;
+        let strip = match self.prefix.is_empty() {
+            true => 0,
+            false => self.prefix.len() + 1
+        };
+        Ok(listed.objects.into_iter()
+            .filter(|o| o.location.filename().is_some_and(|name| name.starts_with(name_prefix.as_str())))
+            .map(|o| o.location.as_ref()[strip..].to_owned())
+            .collect())
+    }
+}
+
+/// Keeps a table's sealed segments in an object store, tracking which ones
+/// have changed since they were last uploaded.
+#[derive(Debug)]
+pub struct RemoteSegments {
+    objects: Arc<dyn ObjectStore>,
+    database_dir: PathBuf,
+    table_name: String,
+    pending: Mutex<BTreeSet<u64>>
+}
+
+impl RemoteSegments {
+    pub fn new(objects: Arc<dyn ObjectStore>, database_dir: &Path, table_name: &str) -> RemoteSegments {
+        RemoteSegments { objects, database_dir: database_dir.to_owned(), table_name: table_name.to_owned(), pending: Mutex::new(BTreeSet::new()) }
+    }
+
+    fn key(table_name: &str, n: u64) -> String {
+        format!("segments/{}.{}", table_name, n)
+    }
+
+    /// Numbers of the segments stored for `table_name`.
+    pub fn stored(objects: &dyn ObjectStore, table_name: &str) -> std::io::Result<Vec<u64>> {
+        let prefix = format!("segments/{}.", table_name);
+        Ok(objects.list(&prefix)?.into_iter()
+            .filter_map(|key| key[prefix.len()..].parse::<u64>().ok())
+            .collect())
+    }
+
+    /// Downloads the segments of `table_name` that aren't on disk, e.g. when
+    /// the database is opened on a new machine. Returns how many there were.
+    pub fn download_missing(objects: &dyn ObjectStore, database_dir: &Path, table_name: &str) -> std::io::Result<usize> {
+        let mut downloaded = 0;
+        for n in Self::stored(objects, table_name)? {
+            let path = FileByteStore::segment_path(database_dir, table_name, n);
+            if path.exists() { continue; }
+            std::fs::create_dir_all(database_dir.join("segments"))?;
+            let partial = path.with_extension(format!("{}.partial", n));
+            let mut f = File::create(&partial)?;
+            f.write_all(&objects.get(&Self::key(table_name, n))?)?;
+            f.sync_all()?;
+            std::fs::rename(partial, path)?;
+            downloaded += 1;
+        }
+        Ok(downloaded)
+    }
+
+    /// Marks segment `n` as needing to be uploaded.
+    pub fn changed(&self, n: u64) {
+        self.pending.lock().unwrap().insert(n);
+    }
+
+    /// Uploads every changed segment from its file on disk.
+    pub fn upload(&self) -> std::io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        while let Some(n) = pending.first().copied() {
+            let bytes = std::fs::read(FileByteStore::segment_path(&self.database_dir, &self.table_name, n))?;
+            self.objects.put(&Self::key(&self.table_name, n), &bytes)?;
+            pending.remove(&n);
+        }
+        Ok(())
+    }
+
+    /// Deletes the stored segments numbered `from` and up, e.g. ones left
+    /// over after a rewrite sealed fewer segments, or all of them when the
+    /// table is dropped.
+    pub fn remove_from(&self, from: u64) -> std::io::Result<()> {
+        self.pending.lock().unwrap().retain(|n| *n < from);
+        for n in Self::stored(self.objects.as_ref(), &self.table_name)? {
+            if n >= from {
+                self.objects.delete(&Self::key(&self.table_name, n))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{db::Database, query::SelectQuery, schema::{ColumnDataType, TableDescriptor, config::TableOverrides}, store::database_dir, testing::ScratchDir};
+
+    #[test]
+    fn dir_object_stores_list_keys_by_prefix() {
+        let _dir = ScratchDir::new("test_objects_dir");
+        let objects = DirObjectStore::new(&database_dir("test_objects_dir"));
+        objects.put("segments/books.0", b"bees").unwrap();
+        objects.put("segments/books.1", b"frogs").unwrap();
+        objects.put("segments/bookends.0", b"lumps").unwrap();
+        objects.put("segments/books.1", b"moths").unwrap();
+
+        assert_eq!(objects.get("segments/books.1").unwrap(), b"moths");
+        assert_eq!(objects.get("segments/books.2").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        let mut keys = objects.list("segments/books.").unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["segments/books.0", "segments/books.1"]);
+        assert_eq!(RemoteSegments::stored(&objects, "books").unwrap().len(), 2);
+        assert!(objects.list("pages/").unwrap().is_empty());
+
+        objects.delete("segments/books.0").unwrap();
+        objects.delete("segments/books.0").unwrap();
+        assert_eq!(objects.list("segments/books.").unwrap(), vec!["segments/books.1"]);
+    }
+
+    fn open(db_name: &str, objects: &Arc<DirObjectStore>) -> Database {
+        let mut db = Database::new(db_name);
+        db.set_object_store(objects.clone());
+        let overrides = TableOverrides { segment_size: Some(64), ..TableOverrides::default() };
+        db.add_table(TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("title", ColumnDataType::Byte(16))]).unwrap().with_overrides(overrides).unwrap()).unwrap();
+        db
+    }
+
+    fn titles(db: &Database) -> Vec<String> {
+        let query = SelectQuery::parse_raw_query_against_db("select title from books", db).unwrap();
+        db.query(&query).unwrap().map(|row| row.unwrap().get::<String>("title").unwrap()).collect()
+    }
+
+    #[test]
+    fn sealed_segments_are_downloaded_when_missing() {
+        let _bucket = ScratchDir::new("test_objects_bucket");
+        let _dir = ScratchDir::new("test_objects_segments");
+        let objects = Arc::new(DirObjectStore::new(&database_dir("test_objects_bucket")));
+        let written = ["Bees", "Frogs", "Lumps", "Moths", "Ants"];
+
+        let mut db = open("test_objects_segments", &objects);
+        db.insert_rows("books", &written.iter().map(|title| vec![("title", *title)]).collect::<Vec<_>>()).unwrap();
+        db.close().unwrap();
+        // two rows to a segment, with the fifth row left in the table file
+        let mut stored = RemoteSegments::stored(objects.as_ref(), "books").unwrap();
+        stored.sort();
+        assert_eq!(stored, vec![0, 1]);
+
+        std::fs::remove_dir_all(database_dir("test_objects_segments").join("segments")).unwrap();
+        let mut db = open("test_objects_segments", &objects);
+        assert_eq!(titles(&db), written);
+
+        db.drop_table("books", false).unwrap();
+        assert!(RemoteSegments::stored(objects.as_ref(), "books").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+use std::{collections::{BTreeMap, HashMap}, io::{Read, Seek, SeekFrom}, sync::{Arc, Mutex}};
+
+use super::{FileByteStore, super::schema::config::{CachePriority, StorageSettings, DEFAULT_PAGE_SIZE}};
+
+/// A page is identified by the table it belongs to and its page number.
+pub type PageKey = (String, u64);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub resident_pages: usize,
+    pub resident_bytes: usize
+}
+
+impl BufferPoolStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    data: Arc<Vec<u8>>,
+    pins: u32,
+    priority: CachePriority,
+    last_used: u64
+}
+
+/// Page cache with a soft memory cap. When the cap is exceeded, unpinned
+/// pages are evicted lowest cache priority first and least recently used
+/// within a priority. Pinned pages are never evicted, so the pool may sit
+/// above its cap until they are unpinned.
+#[derive(Debug)]
+pub struct BufferPool {
+    memory_cap: usize,
+    used: usize,
+    tick: u64,
+    frames: HashMap<PageKey, Frame>,
+    /// The unpinned frames, in the order they're evicted in.
+    evictable: BTreeMap<(CachePriority, u64), PageKey>,
+    stats: BufferPoolStats
+}
+
+impl BufferPool {
+    pub fn new(memory_cap: usize) -> BufferPool {
+        BufferPool {
+            memory_cap,
+            used: 0,
+            tick: 0,
+            frames: HashMap::new(),
+            evictable: BTreeMap::new(),
+            stats: BufferPoolStats::default()
+        }
+    }
+
+    pub fn memory_cap(&self) -> usize {
+        self.memory_cap
+    }
+
+    pub fn set_memory_cap(&mut self, memory_cap: usize) {
+        self.memory_cap = memory_cap;
+        self.evict_to_cap();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    pub fn get(&mut self, key: &PageKey) -> Option<Arc<Vec<u8>>> {
+        let tick = self.next_tick();
+        match self.frames.get_mut(key) {
+            Some(frame) => {
+                if frame.pins == 0 {
+                    self.evictable.remove(&(frame.priority, frame.last_used));
+                    self.evictable.insert((frame.priority, tick), key.clone());
+                }
+                frame.last_used = tick;
+                self.stats.hits += 1;
+                Some(frame.data.clone())
+            },
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: PageKey, data: Vec<u8>, priority: CachePriority) -> Arc<Vec<u8>> {
+        self.insert_with_pins(key, data, priority, 0)
+    }
+
+    /// Like `insert`, pinning the page before anything is evicted to make
+    /// room for it, so it's there until unpinned.
+    pub fn insert_pinned(&mut self, key: PageKey, data: Vec<u8>, priority: CachePriority) -> Arc<Vec<u8>> {
+        self.insert_with_pins(key, data, priority, 1)
+    }
+
+    fn insert_with_pins(&mut self, key: PageKey, data: Vec<u8>, priority: CachePriority, pins: u32) -> Arc<Vec<u8>> {
+        let tick = self.next_tick();
+        let data = Arc::new(data);
+        let pins = pins + self.remove_frame(&key).map_or(0, |old| old.pins);
+
+        self.used += data.len();
+        if pins == 0 {
+            self.evictable.insert((priority, tick), key.clone());
+        }
+        self.frames.insert(key, Frame { data: data.clone(), pins, priority, last_used: tick });
+        self.evict_to_cap();
+        data
+    }
+
+    fn remove_frame(&mut self, key: &PageKey) -> Option<Frame> {
+        let frame = self.frames.remove(key)?;
+        self.used -= frame.data.len();
+        if frame.pins == 0 {
+            self.evictable.remove(&(frame.priority, frame.last_used));
+        }
+        Some(frame)
+    }
+
+    /// Drops a page without counting it as an eviction, e.g. after the page
+    /// was rewritten on disk.
+    pub fn invalidate(&mut self, key: &PageKey) {
+        self.remove_frame(key);
+    }
+
+    pub fn invalidate_table(&mut self, table_name: &str) {
+        let keys = self.frames.iter()
+            .filter(|(k, _)| k.0 == table_name)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for k in keys { self.invalidate(&k); }
+    }
+
+    /// Keeps a page from being evicted until it's unpinned as many times.
+    /// Returns false if the page isn't in the pool.
+    pub fn pin(&mut self, key: &PageKey) -> bool {
+        match self.frames.get_mut(key) {
+            Some(frame) => {
+                if frame.pins == 0 {
+                    self.evictable.remove(&(frame.priority, frame.last_used));
+                }
+                frame.pins += 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn unpin(&mut self, key: &PageKey) {
+        if let Some(frame) = self.frames.get_mut(key) {
+            if frame.pins == 1 {
+                self.evictable.insert((frame.priority, frame.last_used), key.clone());
+            }
+            frame.pins = frame.pins.saturating_sub(1);
+        }
+        self.evict_to_cap();
+    }
+
+    fn evict_to_cap(&mut self) {
+        while self.used > self.memory_cap {
+            let Some((_, victim)) = self.evictable.pop_first() else { break };
+            if let Some(frame) = self.frames.remove(&victim) {
+                self.used -= frame.data.len();
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            resident_pages: self.frames.len(),
+            resident_bytes: self.used,
+            ..self.stats
+        }
+    }
+}
+
+/// How a table store reads through a shared buffer pool: the pool itself and
+/// the page size and priority its pages are cached with.
+#[derive(Debug, Clone)]
+pub struct PageCache {
+    pub pool: Arc<Mutex<BufferPool>>,
+    pub page_size: usize,
+    pub priority: CachePriority
+}
+
+impl PageCache {
+    pub fn new(pool: Arc<Mutex<BufferPool>>, settings: &StorageSettings) -> PageCache {
+        PageCache { pool, page_size: settings.page_size, priority: settings.cache_priority }
+    }
+
+    /// Pages holding any of the `len` bytes starting at `start`.
+    pub fn pages_for(&self, start: u64, len: u64) -> std::ops::RangeInclusive<u64> {
+        let page_size = self.page_size as u64;
+        (start / page_size)..=((start + len.max(1) - 1) / page_size)
+    }
+}
+
+/// Reads a table's rows page by page. Pages are served from the store's
+/// buffer pool when it has one, only going to disk on a miss, and come from
+/// the compressed page file or the table file as the store lays them out.
+/// Page numbers count from the start of the row data, past the file header.
+/// The page being read is pinned in the pool until the reader moves off it
+/// or is dropped.
+pub struct PagedReader<'a> {
+    store: &'a FileByteStore,
+    page_size: u64,
+    data_len: u64,
+    pos: u64,
+    page: Option<(u64, Arc<Vec<u8>>)>
+}
+
+impl<'a> PagedReader<'a> {
+    pub fn new(store: &'a FileByteStore, data_len: u64) -> PagedReader<'a> {
+        let page_size = store.cache.as_ref().map_or(DEFAULT_PAGE_SIZE, |c| c.page_size) as u64;
+        PagedReader { store, page_size, data_len, pos: 0, page: None }
+    }
+
+    fn load_page(&mut self, page_number: u64) -> std::io::Result<Arc<Vec<u8>>> {
+        let start = page_number * self.page_size;
+        let len = self.page_size.min(self.data_len - start);
+
+        // a cached last page can be shorter than it is now if rows were
+        // appended since; read it again in that case
+        let key = (self.store.table_name.to_owned(), page_number);
+        if let Some(cache) = &self.store.cache {
+            let mut pool = cache.pool.lock().unwrap();
+            if let Some(page) = pool.get(&key).filter(|page| page.len() as u64 == len) {
+                pool.pin(&key);
+                return Ok(page);
+            }
+        }
+        let mut data = vec![0u8; len as usize];
+        self.store.read_data(start, &mut data)?;
+
+        Ok(match &self.store.cache {
+            Some(cache) => cache.pool.lock().unwrap().insert_pinned(key, data, cache.priority),
+            None => Arc::new(data)
+        })
+    }
+
+    /// Lets the pool evict the page the reader was on.
+    fn unpin_page(&mut self) {
+        if let (Some((page_number, _)), Some(cache)) = (self.page.take(), &self.store.cache) {
+            cache.pool.lock().unwrap().unpin(&(self.store.table_name.to_owned(), page_number));
+        }
+    }
+}
+
+impl Drop for PagedReader<'_> {
+    fn drop(&mut self) {
+        self.unpin_page();
+    }
+}
+
+impl Seek for PagedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.data_len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n)
+        };
+        self.pos = target.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start of table data"))?;
+        Ok(self.pos)
+    }
+}
+
+impl Read for PagedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.data_len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let page_size = self.page_size;
+        let page_number = self.pos / page_size;
+        let page = match &self.page {
+            Some((n, page)) if *n == page_number => page.clone(),
+            _ => {
+                self.unpin_page();
+                let page = self.load_page(page_number)?;
+                self.page = Some((page_number, page.clone()));
+                page
+            }
+        };
+
+        let in_page = (self.pos - page_number * page_size) as usize;
+        let n = buf.len().min(page.len() - in_page);
+        buf[..n].copy_from_slice(&page[in_page..(in_page + n)]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::table::{schema::{ColumnDataType, TableDescriptor}, store::ByteStore, testing::ScratchDir};
+
+    fn page(table_name: &str, page_number: u64) -> PageKey {
+        (table_name.to_owned(), page_number)
+    }
+
+    #[test]
+    fn evicts_lowest_priority_then_least_recently_used() {
+        let mut pool = BufferPool::new(30);
+        pool.insert(page("t", 0), vec![0; 10], CachePriority::Normal);
+        pool.insert(page("t", 1), vec![0; 10], CachePriority::High);
+        pool.insert(page("t", 2), vec![0; 10], CachePriority::Normal);
+        pool.get(&page("t", 0));
+
+        pool.insert(page("t", 3), vec![0; 10], CachePriority::Normal);
+        assert!(!pool.frames.contains_key(&page("t", 2)));
+        pool.insert(page("t", 4), vec![0; 10], CachePriority::Low);
+        assert!(!pool.frames.contains_key(&page("t", 4)));
+        pool.set_memory_cap(10);
+        assert_eq!(pool.frames.keys().collect::<Vec<_>>(), vec![&page("t", 1)]);
+        assert_eq!(pool.stats().evictions, 4);
+    }
+
+    #[test]
+    fn pinned_pages_stay_until_unpinned() {
+        let mut pool = BufferPool::new(10);
+        pool.insert_pinned(page("t", 0), vec![0; 10], CachePriority::Low);
+        pool.insert(page("t", 1), vec![0; 10], CachePriority::High);
+        pool.set_memory_cap(0);
+        assert_eq!(pool.stats().resident_pages, 1);
+        assert!(pool.pin(&page("t", 0)));
+        pool.unpin(&page("t", 0));
+        assert_eq!(pool.stats().resident_pages, 1);
+        pool.unpin(&page("t", 0));
+        assert_eq!((pool.stats().resident_pages, pool.stats().resident_bytes), (0, 0));
+        assert!(pool.evictable.is_empty());
+    }
+
+    #[test]
+    fn readers_pin_the_page_they_are_on() {
+        let _dir = ScratchDir::new("test_pool_reader");
+        let descriptor = TableDescriptor::new("books", vec![("id", ColumnDataType::SerialId), ("year", ColumnDataType::Int64)]).unwrap();
+        let pool = Arc::new(Mutex::new(BufferPool::new(usize::MAX)));
+        let settings = StorageSettings { page_size: 16, ..StorageSettings::default() };
+        let mut store = FileByteStore::new("test_pool_reader", &descriptor).unwrap()
+            .with_page_cache(PageCache::new(pool.clone(), &settings));
+        let rows = (0..8).map(|year| descriptor.encode_literals(&[("year", &year.to_string())]).unwrap()).collect::<Vec<_>>();
+        store.insert_many(&descriptor, &rows).unwrap();
+
+        let mut reader = PagedReader::new(&store, store.data_len().unwrap());
+        reader.read_exact(&mut [0u8; 1]).unwrap();
+        pool.lock().unwrap().set_memory_cap(0);
+        assert_eq!(pool.lock().unwrap().stats().resident_pages, 1);
+        // pages it moved off of go as it leaves them
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(pool.lock().unwrap().stats().resident_pages, 1);
+        drop(reader);
+        assert_eq!(pool.lock().unwrap().stats().resident_pages, 0);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{fs::File, io::{Read, Write}, path::{Path, PathBuf}};
+
+use super::{LOCK_FILE_NAME, checksum::crc32_continue};
+
+// A snapshot is a copy of a database's directory plus a manifest file,
+// `snapshot`, listing every file copied:
+//   kronk snapshot <format version>
+//   <crc32 as 8 hex digits> <length> <path relative to the snapshot>
+// Archived tables, the leftovers of interrupted rewrites and the database's
+// lock file aren't copied.
+
+pub const MANIFEST_NAME: &str = "snapshot";
+const FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub len: u64,
+    pub crc: u32
+}
+
+fn is_leftover(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("compact" | "rewrite" | "checkpoint" | "partial"))
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".");
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// Copies every file under `database_dir` into a new directory at `dest`,
+/// with a manifest of their checksums. The copy is built beside `dest` and
+/// renamed into place once complete, so `dest` either holds a whole
+/// snapshot or doesn't exist. The files mustn't change until this returns.
+/// `on_copied` is called after each file is copied.
+pub fn write(database_dir: &Path, dest: &Path, on_copied: &mut dyn FnMut(&ManifestEntry)) -> std::io::Result<Vec<ManifestEntry>> {
+    if dest.exists() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", dest.display())));
+    }
+    if std::path::absolute(dest)?.starts_with(std::path::absolute(database_dir)?) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "a snapshot can't go inside the database's own directory"));
+    }
+    let partial = sibling(dest, "partial");
+    if partial.exists() {
+        std::fs::remove_dir_all(&partial)?;
+    }
+
+    let result = (|| {
+        std::fs::create_dir_all(&partial)?;
+        let mut entries = Vec::new();
+        walk(database_dir, Path::new(""), &mut |path| {
+            let dst = partial.join(path);
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let entry = copy_file(&database_dir.join(path), &dst, path)?;
+            on_copied(&entry);
+            entries.push(entry);
+            Ok(())
+        })?;
+        let mut manifest = File::create(partial.join(MANIFEST_NAME))?;
+        manifest.write_all(&manifest_bytes(&entries))?;
+        manifest.sync_all()?;
+        Ok(entries)
+    })();
+
+    match result {
+        Ok(entries) => {
+            std::fs::rename(&partial, dest)?;
+            Ok(entries)
+        },
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&partial);
+            Err(e)
+        }
+    }
+}
+
+fn manifest_bytes(entries: &[ManifestEntry]) -> Vec<u8> {
+    let mut o = format!("kronk snapshot {}\n", FORMAT_VERSION);
+    for entry in entries {
+        o.push_str(&format!("{:08x} {} {}\n", entry.crc, entry.len, entry.path.display()));
+    }
+    o.into_bytes()
+}
+
+/// Calls `f` with the path, relative to `root`, of each file under `rel`
+/// that goes in a snapshot, in name order.
+fn walk(root: &Path, rel: &Path, f: &mut dyn FnMut(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    let mut children = std::fs::read_dir(root.join(rel))?.collect::<std::io::Result<Vec<_>>>()?;
+    children.sort_by_key(|c| c.file_name());
+    for child in children {
+        let path = rel.join(child.file_name());
+        let file_type = child.file_type()?;
+        if file_type.is_dir() && path != Path::new("archive") {
+            walk(root, &path, f)?;
+        } else if file_type.is_file() && !is_leftover(&path) && path != Path::new(LOCK_FILE_NAME) {
+            f(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// How many bytes of files a snapshot of `database_dir` copies.
+pub fn size(database_dir: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    walk(database_dir, Path::new(""), &mut |path| {
+        size += std::fs::metadata(database_dir.join(path))?.len();
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Reads a file through, checksumming it and copying it to `dst` if given.
+fn checksum_file(src: &Path, rel: &Path, mut dst: Option<&mut File>) -> std::io::Result<ManifestEntry> {
+    let mut r = File::open(src).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", src.display(), e)))?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let (mut len, mut crc) = (0u64, 0u32);
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 { break; }
+        crc = crc32_continue(crc, &buf[..n]);
+        if let Some(w) = &mut dst {
+            w.write_all(&buf[..n])?;
+        }
+        len += n as u64;
+    }
+    Ok(ManifestEntry { path: rel.to_owned(), len, crc })
+}
+
+fn copy_file(src: &Path, dst: &Path, rel: &Path) -> std::io::Result<ManifestEntry> {
+    let mut w = File::create(dst)?;
+    let entry = checksum_file(src, rel, Some(&mut w))?;
+    w.sync_all()?;
+    Ok(entry)
+}
+
+/// The files the snapshot at `src` lists, from its manifest.
+pub fn read_manifest(src: &Path) -> std::io::Result<Vec<ManifestEntry>> {
+    let text = std::fs::read_to_string(src.join(MANIFEST_NAME))
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{} has no snapshot manifest: {}", src.display(), e)))?;
+    let mut lines = text.lines();
+    let version = lines.next()
+        .and_then(|l| l.strip_prefix("kronk snapshot "))
+        .ok_or_else(|| invalid(format!("{} isn't a kronk snapshot", src.display())))?;
+    if version.parse::<u16>().ok() != Some(FORMAT_VERSION) {
+        return Err(invalid(format!("unsupported snapshot format version {}", version)));
+    }
+
+    lines.map(|line| {
+        let malformed = || invalid(format!("snapshot manifest has a malformed line '{}'", line));
+        let mut fields = line.splitn(3, ' ');
+        let crc = fields.next().and_then(|f| u32::from_str_radix(f, 16).ok()).ok_or_else(malformed)?;
+        let len = fields.next().and_then(|f| f.parse::<u64>().ok()).ok_or_else(malformed)?;
+        let path = PathBuf::from(fields.next().ok_or_else(malformed)?);
+        // every file has to land inside the database directory
+        if !path.components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+            return Err(malformed());
+        }
+        Ok(ManifestEntry { path, len, crc })
+    }).collect()
+}
+
+/// Checks the snapshot at `src`: every file its manifest lists in `entries`
+/// has to be there with the same length and checksum. `on_checked` is
+/// called after each file checks out.
+pub fn verify(src: &Path, entries: &[ManifestEntry], on_checked: &mut dyn FnMut(&ManifestEntry)) -> std::io::Result<()> {
+    for entry in entries {
+        if checksum_file(&src.join(&entry.path), &entry.path, None)? != *entry {
+            return Err(invalid(format!("{} in the snapshot is corrupt", entry.path.display())));
+        }
+        on_checked(entry);
+    }
+    Ok(())
+}
+
+/// Replaces `database_dir` with a copy of the verified snapshot at `src`.
+/// The copy is built beside `database_dir` and swapped in with renames, so
+/// a failure before the swap leaves the database as it was. `on_copied` is
+/// called after each file is copied.
+pub fn install(src: &Path, entries: &[ManifestEntry], database_dir: &Path, on_copied: &mut dyn FnMut(&ManifestEntry)) -> std::io::Result<()> {
+    let staged = sibling(database_dir, "restore");
+    if staged.exists() {
+        std::fs::remove_dir_all(&staged)?;
+    }
+    let result = (|| {
+        std::fs::create_dir_all(&staged)?;
+        for entry in entries {
+            let dst = staged.join(&entry.path);
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if copy_file(&src.join(&entry.path), &dst, &entry.path)? != *entry {
+                return Err(invalid(format!("{} changed while the snapshot was being restored", entry.path.display())));
+            }
+            on_copied(entry);
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&staged);
+        return Err(e);
+    }
+
+    let replaced = sibling(database_dir, "replaced");
+    if database_dir.exists() {
+        if replaced.exists() {
+            std::fs::remove_dir_all(&replaced)?;
+        }
+        std::fs::rename(database_dir, &replaced)?;
+    }
+    std::fs::rename(&staged, database_dir)?;
+    let _ = std::fs::remove_dir_all(&replaced);
+    Ok(())
+}
+
+/// Whether `database_dir` holds any tables or logged writes.
+pub fn has_data(database_dir: &Path) -> std::io::Result<bool> {
+    let has_tables = match std::fs::read_dir(database_dir.join("tables")) {
+        Ok(mut tables) => tables.next().is_some(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e)
+    };
+    let has_log = match std::fs::metadata(database_dir.join("wal")) {
+        Ok(m) => m.len() > 0,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e)
+    };
+    Ok(has_tables || has_log)
+}
@@ -0,0 +1,189 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}};
+
+/// Numbers the database's writes, and keeps count of the snapshots open at
+/// each version so row history no snapshot can see any more is let go.
+/// Every insert, update or delete is one version, however many rows it
+/// changes.
+#[derive(Debug, Default)]
+pub struct VersionClock {
+    current: u64,
+    open: BTreeMap<u64, usize>
+}
+
+impl VersionClock {
+    /// Starts a write, returning its version and the version of the oldest
+    /// open snapshot. The rows it changes only need their history kept when
+    /// there is one.
+    pub fn advance(&mut self) -> (u64, Option<u64>) {
+        self.current += 1;
+        (self.current, self.open.keys().next().copied())
+    }
+}
+
+/// A consistent view of the database as of the last write before it was
+/// taken. Queries run with it see every row as it was then, whatever has
+/// been written since. The rows' earlier versions are kept for as long as
+/// it's open.
+#[derive(Debug)]
+pub struct ReadSnapshot {
+    version: u64,
+    clock: Arc<Mutex<VersionClock>>
+}
+
+impl ReadSnapshot {
+    pub fn new(clock: &Arc<Mutex<VersionClock>>) -> ReadSnapshot {
+        let mut locked = clock.lock().unwrap();
+        let version = locked.current;
+        *locked.open.entry(version).or_default() += 1;
+        ReadSnapshot { version, clock: clock.clone() }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl Drop for ReadSnapshot {
+    fn drop(&mut self) {
+        let mut clock = self.clock.lock().unwrap();
+        if let Some(count) = clock.open.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                clock.open.remove(&self.version);
+            }
+        }
+    }
+}
+
+/// How a row looks to a snapshot.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RowVisibility<'v> {
+    /// As it's stored now, unless it's deleted.
+    Current,
+    /// Not at all: it was inserted after the snapshot was taken.
+    Hidden,
+    /// As these bytes, since it was updated or deleted after the snapshot
+    /// was taken.
+    Earlier(&'v [u8])
+}
+
+/// Earlier versions of a table's rows, kept in memory for open snapshots.
+/// Only writes made while a snapshot is open are recorded.
+#[derive(Debug, Default)]
+pub struct RowVersions {
+    /// Version each row was inserted at.
+    inserted: HashMap<u64, u64>,
+    /// Each row's bytes before the writes that replaced them, as the
+    /// version of the write and the bytes, oldest first.
+    replaced: HashMap<u64, Vec<(u64, Vec<u8>)>>
+}
+
+impl RowVersions {
+    pub fn record_insert(&mut self, row_number: u64, version: u64) {
+        self.inserted.insert(row_number, version);
+    }
+
+    /// Records the bytes an update or delete at `version` replaced.
+    pub fn record_replace(&mut self, row_number: u64, version: u64, bytes: Vec<u8>) {
+        self.replaced.entry(row_number).or_default().push((version, bytes));
+    }
+
+    /// Lets go of history no snapshot at `oldest` or later can see: every
+    /// one of them sees rows inserted before then, and none of them the
+    /// bytes replaced before then. With no snapshot open, all of it goes.
+    pub fn prune(&mut self, oldest: Option<u64>) {
+        let oldest = match oldest {
+            Some(oldest) => oldest,
+            None => {
+                self.inserted.clear();
+                self.replaced.clear();
+                return;
+            }
+        };
+        self.inserted.retain(|_, version| *version > oldest);
+        self.replaced.retain(|_, history| {
+            history.retain(|(version, _)| *version > oldest);
+            !history.is_empty()
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.replaced.is_empty()
+    }
+
+    pub fn visibility(&self, row_number: u64, snapshot: u64) -> RowVisibility<'_> {
+        if self.inserted.get(&row_number).is_some_and(|version| *version > snapshot) {
+            return RowVisibility::Hidden;
+        }
+        // the bytes the first write after the snapshot replaced
+        match self.replaced.get(&row_number).and_then(|history| history.iter().find(|(version, _)| *version > snapshot)) {
+            Some((_, bytes)) => RowVisibility::Earlier(bytes),
+            None => RowVisibility::Current
+        }
+    }
+
+    /// Rows that were updated or deleted since some snapshot was taken, and
+    /// so can look different to it than the indexes say.
+    pub fn replaced_rows(&self) -> impl Iterator<Item = u64> + '_ {
+        self.replaced.keys().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_see_the_first_bytes_replaced_after_them() {
+        let mut versions = RowVersions::default();
+        versions.record_replace(0, 3, b"as of 2".to_vec());
+        versions.record_replace(0, 5, b"as of 4".to_vec());
+        versions.record_insert(1, 4);
+
+        assert_eq!(versions.visibility(0, 2), RowVisibility::Earlier(b"as of 2"));
+        assert_eq!(versions.visibility(0, 3), RowVisibility::Earlier(b"as of 4"));
+        assert_eq!(versions.visibility(0, 5), RowVisibility::Current);
+        assert_eq!(versions.visibility(1, 3), RowVisibility::Hidden);
+        assert_eq!(versions.visibility(1, 4), RowVisibility::Current);
+        assert_eq!(versions.visibility(2, 0), RowVisibility::Current);
+    }
+
+    #[test]
+    fn pruning_keeps_what_open_snapshots_can_see() {
+        let mut versions = RowVersions::default();
+        versions.record_replace(0, 3, b"as of 2".to_vec());
+        versions.record_replace(0, 5, b"as of 4".to_vec());
+        versions.record_insert(1, 2);
+        versions.record_insert(2, 6);
+
+        versions.prune(Some(4));
+        assert_eq!(versions.visibility(0, 4), RowVisibility::Earlier(b"as of 4"));
+        assert_eq!(versions.visibility(1, 1), RowVisibility::Current);
+        assert_eq!(versions.visibility(2, 4), RowVisibility::Hidden);
+        assert_eq!(versions.replaced_rows().collect::<Vec<_>>(), vec![0]);
+
+        versions.prune(Some(5));
+        assert_eq!(versions.replaced_rows().count(), 0);
+        assert_eq!(versions.visibility(2, 5), RowVisibility::Hidden);
+        versions.prune(None);
+        assert!(versions.is_empty());
+    }
+
+    #[test]
+    fn the_clock_reports_the_oldest_open_snapshot() {
+        let clock = Arc::new(Mutex::new(VersionClock::default()));
+        assert_eq!(clock.lock().unwrap().advance(), (1, None));
+        let first = ReadSnapshot::new(&clock);
+        let again = ReadSnapshot::new(&clock);
+        clock.lock().unwrap().advance();
+        let second = ReadSnapshot::new(&clock);
+        assert_eq!((first.version(), second.version()), (1, 2));
+        assert_eq!(clock.lock().unwrap().advance(), (3, Some(1)));
+        drop(first);
+        assert_eq!(clock.lock().unwrap().advance(), (4, Some(1)));
+        drop(again);
+        assert_eq!(clock.lock().unwrap().advance(), (5, Some(2)));
+        drop(second);
+        assert_eq!(clock.lock().unwrap().advance(), (6, None));
+    }
+}
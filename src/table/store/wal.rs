@@ -0,0 +1,284 @@
+use std::{fs::{File, OpenOptions}, io::{BufReader, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread::Thread, time::Instant};
+
+use super::super::schema::config::Durability;
+
+// Each record is framed as: payload length (u32), checksum of the payload
+// (u32), payload. The payload is:
+//   kind (u8), table name length (u16), table name, position in the row
+//   data (u64), id counter after the write (u64), byte count (u32), bytes
+// A record whose frame or checksum doesn't check out marks the torn tail of
+// the log; it and anything after it are discarded.
+
+const RECORD_WRITE: u8 = 1;
+
+/// Log records are physical: they say which bytes go where in a table's row
+/// data, so replaying one that was already applied is harmless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    pub table_name: String,
+    pub position: u64,
+    pub id_counter: u64,
+    pub bytes: Vec<u8>
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    // FNV-1a, plenty to catch a torn or half-written record
+    bytes.iter().fold(0x811c9dc5u32, |h, b| (h ^ *b as u32).wrapping_mul(0x01000193))
+}
+
+impl WalRecord {
+    fn encode(&self) -> Vec<u8> {
+        let mut payload: Vec<u8> = Vec::with_capacity(27 + self.table_name.len() + self.bytes.len());
+        payload.push(RECORD_WRITE);
+        payload.extend((self.table_name.len() as u16).to_le_bytes());
+        payload.extend(self.table_name.as_bytes());
+        payload.extend(self.position.to_le_bytes());
+        payload.extend(self.id_counter.to_le_bytes());
+        payload.extend((self.bytes.len() as u32).to_le_bytes());
+        payload.extend(&self.bytes);
+
+        let mut o: Vec<u8> = Vec::with_capacity(8 + payload.len());
+        o.extend((payload.len() as u32).to_le_bytes());
+        o.extend(checksum(&payload).to_le_bytes());
+        o.extend(payload);
+        o
+    }
+
+    fn decode(payload: &[u8]) -> Option<WalRecord> {
+        let mut pos = 0usize;
+        let mut take = |n: usize| {
+            let s = payload.get(pos..(pos + n))?;
+            pos += n;
+            Some(s)
+        };
+
+        if take(1)?[0] != RECORD_WRITE { return None; }
+        let name_len = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+        let table_name = String::from_utf8(take(name_len)?.to_vec()).ok()?;
+        let position = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        let id_counter = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        let len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+        let bytes = take(len)?.to_vec();
+        Some(WalRecord { table_name, position, id_counter, bytes })
+    }
+}
+
+/// Write-ahead log shared by a database's tables. Writes are appended here
+/// before they touch a table file, and replayed into the table files when
+/// they are next opened, so a crash mid-write can't leave a table torn.
+///
+/// Syncing follows the durability of the table being written: `EveryWrite`
+/// syncs right away, `Interval` syncs within the interval of the write, and
+/// `OnClose` leaves it to the next checkpoint. Every sync covers all records
+/// appended before it, so records logged between syncs are committed
+/// together.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+    unsynced: usize,
+    last_sync: Instant,
+    /// When the records appended under `Interval` durability have to be
+    /// synced by, if any are waiting.
+    sync_deadline: Option<Instant>,
+    /// Thread started by `start_flusher`, woken when a deadline is set.
+    flusher: Option<Thread>
+}
+
+impl WriteAheadLog {
+    pub fn open(database_dir: &Path) -> std::io::Result<WriteAheadLog> {
+        std::fs::create_dir_all(database_dir)?;
+        let path = database_dir.join("wal");
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        Ok(WriteAheadLog { path, file, unsynced: 0, last_sync: Instant::now(), sync_deadline: None, flusher: None })
+    }
+
+    /// Starts a thread that syncs `wal` when records logged under `Interval`
+    /// durability reach their deadline without another write coming along
+    /// to sync them. It stops once the log is dropped.
+    pub fn start_flusher(wal: &Arc<Mutex<WriteAheadLog>>) -> std::io::Result<()> {
+        let weak = Arc::downgrade(wal);
+        let handle = std::thread::Builder::new().name("kronk-wal-flusher".to_owned()).spawn(move || {
+            loop {
+                let wait = match weak.upgrade() {
+                    Some(wal) => {
+                        let mut wal = wal.lock().unwrap();
+                        if wal.sync_deadline.is_some_and(|d| d <= Instant::now()) {
+                            // a failed sync is retried by the next write or checkpoint
+                            let _ = wal.sync();
+                        }
+                        wal.sync_deadline.map(|d| d.saturating_duration_since(Instant::now()))
+                    },
+                    None => return
+                };
+                match wait {
+                    Some(wait) => std::thread::park_timeout(wait),
+                    None => std::thread::park()
+                }
+            }
+        })?;
+        wal.lock().unwrap().flusher = Some(handle.thread().clone());
+        Ok(())
+    }
+
+    pub fn append(&mut self, record: &WalRecord, durability: Durability) -> std::io::Result<()> {
+        self.file.write_all(&record.encode())?;
+        self.unsynced += 1;
+
+        match durability {
+            Durability::EveryWrite => self.sync()?,
+            Durability::Interval(interval) if self.last_sync.elapsed() >= interval => self.sync()?,
+            Durability::Interval(interval) => {
+                let deadline = self.last_sync + interval;
+                if self.sync_deadline.is_none_or(|d| deadline < d) {
+                    self.sync_deadline = Some(deadline);
+                    if let Some(flusher) = &self.flusher {
+                        flusher.unpark();
+                    }
+                }
+            },
+            Durability::OnClose => {}
+        }
+        Ok(())
+    }
+
+    /// Opens the log file again, dropping anything waiting to be synced, for
+    /// when the database's files have been replaced underneath it.
+    pub fn reopen(&mut self) -> std::io::Result<()> {
+        self.file = OpenOptions::new().read(true).append(true).create(true).open(&self.path)?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        self.sync_deadline = None;
+        Ok(())
+    }
+
+    /// Current size of the log file in bytes.
+    pub fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    pub fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        if self.unsynced > 0 {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        self.last_sync = Instant::now();
+        self.sync_deadline = None;
+        Ok(())
+    }
+
+    /// Every intact record in the log, oldest first. A torn tail is dropped
+    /// from the file so new records aren't appended after garbage.
+    pub fn records(&mut self) -> std::io::Result<Vec<WalRecord>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut records = Vec::new();
+        let mut intact_len = 0u64;
+
+        loop {
+            let mut frame = [0u8; 8];
+            if reader.read_exact(&mut frame).is_err() { break; }
+            let len = u32::from_le_bytes(frame[0..4].try_into().unwrap()) as usize;
+            let sum = u32::from_le_bytes(frame[4..8].try_into().unwrap());
+
+            let mut payload = vec![0u8; len];
+            if reader.read_exact(&mut payload).is_err() || checksum(&payload) != sum { break; }
+            match WalRecord::decode(&payload) {
+                Some(r) => records.push(r),
+                None => break
+            }
+            intact_len += 8 + len as u64;
+        }
+
+        if self.file.metadata()?.len() > intact_len {
+            self.file.set_len(intact_len)?;
+            self.file.seek(SeekFrom::End(0))?;
+        }
+        Ok(records)
+    }
+
+    /// Drops the records of the given tables, once their table files are
+    /// synced and the records are no longer needed. Records for other tables
+    /// are kept for when those tables are opened.
+    pub fn checkpoint(&mut self, table_names: &[&str]) -> std::io::Result<()> {
+        self.sync()?;
+        let kept = self.records()?.into_iter()
+            .filter(|r| !table_names.contains(&r.table_name.as_str()))
+            .collect::<Vec<_>>();
+
+        let rewrite_path = self.path.with_extension("checkpoint");
+        let mut f = File::create(&rewrite_path)?;
+        for r in &kept {
+            f.write_all(&r.encode())?;
+        }
+        f.sync_all()?;
+        std::fs::rename(&rewrite_path, &self.path)?;
+
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for WriteAheadLog {
+    fn drop(&mut self) {
+        // lets the flusher see the log is gone and stop
+        if let Some(flusher) = &self.flusher {
+            flusher.unpark();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{store::database_dir, testing::ScratchDir};
+
+    fn record(table_name: &str, position: u64) -> WalRecord {
+        WalRecord { table_name: table_name.to_owned(), position, id_counter: position + 1, bytes: vec![position as u8; 16] }
+    }
+
+    #[test]
+    fn torn_tails_are_dropped() {
+        let _dir = ScratchDir::new("test_wal_torn_tail");
+        let mut wal = WriteAheadLog::open(&database_dir("test_wal_torn_tail")).unwrap();
+        wal.append(&record("books", 0), Durability::EveryWrite).unwrap();
+        wal.append(&record("books", 16), Durability::EveryWrite).unwrap();
+        let intact_len = wal.len().unwrap();
+        // a crash partway through appending the next one
+        let torn = record("books", 32).encode();
+        wal.file.write_all(&torn[..torn.len() - 3]).unwrap();
+
+        assert_eq!(wal.records().unwrap(), vec![record("books", 0), record("books", 16)]);
+        assert_eq!(wal.len().unwrap(), intact_len);
+        wal.append(&record("books", 32), Durability::EveryWrite).unwrap();
+        assert_eq!(wal.records().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn records_after_a_corrupt_one_are_dropped() {
+        let _dir = ScratchDir::new("test_wal_corrupt");
+        let mut wal = WriteAheadLog::open(&database_dir("test_wal_corrupt")).unwrap();
+        wal.append(&record("books", 0), Durability::EveryWrite).unwrap();
+        let mut corrupt = record("books", 16).encode();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        wal.file.write_all(&corrupt).unwrap();
+        wal.append(&record("books", 32), Durability::EveryWrite).unwrap();
+
+        assert_eq!(wal.records().unwrap(), vec![record("books", 0)]);
+    }
+
+    #[test]
+    fn checkpoints_keep_other_tables_records() {
+        let _dir = ScratchDir::new("test_wal_checkpoint");
+        let mut wal = WriteAheadLog::open(&database_dir("test_wal_checkpoint")).unwrap();
+        wal.append(&record("books", 0), Durability::OnClose).unwrap();
+        wal.append(&record("reviews", 0), Durability::OnClose).unwrap();
+        wal.checkpoint(&["books"]).unwrap();
+        assert_eq!(wal.records().unwrap(), vec![record("reviews", 0)]);
+        wal.checkpoint(&["reviews"]).unwrap();
+        assert!(wal.is_empty().unwrap());
+    }
+}
@@ -0,0 +1,21 @@
+//! Helpers shared by the unit tests.
+
+use super::store::database_dir;
+
+/// Clears a database's directory when made and again when dropped, so a
+/// test's database starts out empty and leaves nothing behind. Make it
+/// before the database, so the database is dropped first.
+pub struct ScratchDir(String);
+
+impl ScratchDir {
+    pub fn new(db_name: &str) -> ScratchDir {
+        let _ = std::fs::remove_dir_all(database_dir(db_name));
+        ScratchDir(db_name.to_owned())
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(database_dir(&self.0));
+    }
+}
@@ -0,0 +1,350 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use uuid::Uuid;
+
+use super::{bytes::FromSlice, error::KronkError, literal, schema::ColumnDataType};
+
+/// A single value in a query result, typed after the column it came from.
+/// Values of the same type order the way the type does.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int32(i32),
+    UInt32(u32),
+    Int64(i64),
+    /// Also serial ids and counts.
+    UInt64(u64),
+    /// Averages. Unlike `f64` itself these compare equal to themselves, NaN
+    /// included, so values have a total order.
+    Float64(f64),
+    Bool(bool),
+    Uuid(Uuid),
+    Str(String),
+    /// No value, e.g. the minimum of no rows.
+    Null
+}
+
+impl Value {
+    /// Decodes a value of type `datatype` stored at the start of `bytes`.
+    pub fn from_bytes(datatype: &ColumnDataType, bytes: &[u8]) -> Result<Value, KronkError> {
+        let short = || KronkError::Storage(format!("Byte buffer too short for {}", datatype));
+        Ok(match datatype {
+            ColumnDataType::SerialId | ColumnDataType::UInt64 | ColumnDataType::RowVersion => Value::UInt64(u64::from_slice(bytes).map_err(|_| short())?),
+            ColumnDataType::Int32 => Value::Int32(i32::from_slice(bytes).map_err(|_| short())?),
+            ColumnDataType::UInt32 => Value::UInt32(u32::from_slice(bytes).map_err(|_| short())?),
+            ColumnDataType::Int64 => Value::Int64(i64::from_slice(bytes).map_err(|_| short())?),
+            ColumnDataType::Boolean => Value::Bool(*bytes.first().ok_or_else(short)? != 0),
+            ColumnDataType::UuidV4 => Value::Uuid(Uuid::from_bytes(bytes.get(..16).ok_or_else(short)?.try_into().unwrap())),
+            ColumnDataType::Byte(max_length) => {
+                let stored = bytes.get(..*max_length).ok_or_else(short)?;
+                let length = stored.iter().position(|b| *b == 0u8).unwrap_or(stored.len());
+                let s = std::str::from_utf8(&stored[..length])?;
+                Value::Str(s.to_owned())
+            }
+        })
+    }
+
+    /// A constant from a select list. Integers come back as `Int64`, or
+    /// `UInt64` past its range, and anything else as a string.
+    pub fn from_literal(s: &str) -> Value {
+        if literal::is_integer(s) {
+            if let Ok(i) = literal::parse_integer::<i64>(s) {
+                return Value::Int64(i);
+            }
+            if let Ok(u) = literal::parse_integer::<u64>(s) {
+                return Value::UInt64(u);
+            }
+        }
+        Value::Str(s.to_owned())
+    }
+
+    /// The value as an `i64`, for any integer that fits in one.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int32(i) => Some(*i as i64),
+            Value::UInt32(u) => Some(*u as i64),
+            Value::Int64(i) => Some(*i),
+            Value::UInt64(u) => i64::try_from(*u).ok(),
+            _ => None
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s.as_str()),
+            _ => None
+        }
+    }
+}
+
+impl Value {
+    /// Position of the variant, for ordering values of different types.
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Int32(_) => 0,
+            Value::UInt32(_) => 1,
+            Value::Int64(_) => 2,
+            Value::UInt64(_) => 3,
+            Value::Float64(_) => 4,
+            Value::Bool(_) => 5,
+            Value::Uuid(_) => 6,
+            Value::Str(_) => 7,
+            Value::Null => 8
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Int32(a), Value::Int32(b)) => a.cmp(b),
+            (Value::UInt32(a), Value::UInt32(b)) => a.cmp(b),
+            (Value::Int64(a), Value::Int64(b)) => a.cmp(b),
+            (Value::UInt64(a), Value::UInt64(b)) => a.cmp(b),
+            (Value::Float64(a), Value::Float64(b)) => a.total_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Uuid(a), Value::Uuid(b)) => a.cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank())
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Value {
+        Value::Int32(i)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(u: u32) -> Value {
+        Value::UInt32(u)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Int64(i)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(u: u64) -> Value {
+        Value::UInt64(u)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<Uuid> for Value {
+    fn from(u: Uuid) -> Value {
+        Value::Uuid(u)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Str(s.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int32(i) => write!(f, "{}", literal::format_integer(i)),
+            Value::UInt32(u) => write!(f, "{}", literal::format_integer(u)),
+            Value::Int64(i) => write!(f, "{}", literal::format_integer(i)),
+            Value::UInt64(u) => write!(f, "{}", literal::format_integer(u)),
+            Value::Bool(b) => write!(f, "{}", literal::format_boolean(*b)),
+            Value::Uuid(u) => write!(f, "{}", u),
+            Value::Float64(x) => write!(f, "{}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Null => write!(f, "null")
+        }
+    }
+}
+
+/// One row of a query result: the row's id, and a value per selected
+/// column in select-list order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub id: u64,
+    pub columns: Vec<(String, Value)>
+}
+
+impl Row {
+    /// The value of the first column named `name`.
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.columns.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// The value of the first column named `name`, as a `T`. Fails if
+    /// there's no such column or its value isn't a `T`, nulls included.
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, KronkError> {
+        let value = self.value(name).ok_or_else(|| KronkError::Schema(format!("No column '{}' in the row", name)))?;
+        T::from_value(value).ok_or_else(|| KronkError::Schema(format!("Column '{}' holds {:?}, not a {}", name, value, std::any::type_name::<T>())))
+    }
+
+    /// Like `get`, reading a null value as `None`.
+    pub fn get_opt<T: FromValue>(&self, name: &str) -> Result<Option<T>, KronkError> {
+        match self.value(name) {
+            Some(Value::Null) => Ok(None),
+            _ => self.get(name).map(Some)
+        }
+    }
+}
+
+/// A type a row's values can be read as, see `Row::get`. Integers read as
+/// any integer type they fit in.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl Value {
+    fn as_i128(&self) -> Option<i128> {
+        match self {
+            Value::Int32(i) => Some(*i as i128),
+            Value::UInt32(u) => Some(*u as i128),
+            Value::Int64(i) => Some(*i as i128),
+            Value::UInt64(u) => Some(*u as i128),
+            _ => None
+        }
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Option<i32> {
+        value.as_i128().and_then(|i| i32::try_from(i).ok())
+    }
+}
+
+impl FromValue for u32 {
+    fn from_value(value: &Value) -> Option<u32> {
+        value.as_i128().and_then(|i| u32::try_from(i).ok())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<i64> {
+        value.as_i128().and_then(|i| i64::try_from(i).ok())
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: &Value) -> Option<u64> {
+        value.as_i128().and_then(|i| u64::try_from(i).ok())
+    }
+}
+
+/// Averages, and integers.
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<f64> {
+        match value {
+            Value::Float64(x) => Some(*x),
+            _ => value.as_i128().map(|i| i as f64)
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<bool> {
+        value.as_bool()
+    }
+}
+
+impl FromValue for Uuid {
+    fn from_value(value: &Value) -> Option<Uuid> {
+        match value {
+            Value::Uuid(u) => Some(*u),
+            _ => None
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<String> {
+        match value {
+            Value::Str(s) => Some(s.clone()),
+            _ => None
+        }
+    }
+}
+
+/// One column of a query result. `name` is the column or function the values
+/// come from, and `alias` what the select list renamed it to with `as`, if
+/// anything. Constants and averages have no `datatype`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultColumn {
+    pub name: String,
+    pub alias: Option<String>,
+    pub datatype: Option<ColumnDataType>,
+    /// Whether the column's values can be `Value::Null`.
+    pub nullable: bool
+}
+
+impl ResultColumn {
+    /// The name the column comes back under in each row.
+    pub fn label(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// A query's rows along with what their columns are, so they can be read
+/// without the tables they came from. Iterating it goes over the rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultSet {
+    pub columns: Vec<ResultColumn>,
+    pub rows: Vec<Row>
+}
+
+impl IntoIterator for ResultSet {
+    type Item = Row;
+    type IntoIter = std::vec::IntoIter<Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ResultSet {
+    type Item = &'a Row;
+    type IntoIter = std::slice::Iter<'a, Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}